@@ -0,0 +1,137 @@
+//! Encoding of the messages of the websocket stream
+//!
+//! Clients can request a binary encoding using the websocket subprotocol, which is a lot cheaper
+//! to decode for large snapshots than JSON. Without requesting one, messages are sent as JSON.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// The encoding of messages, negotiated using the websocket subprotocol
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON, sent as text frames
+    #[default]
+    Json,
+    /// MessagePack, sent as binary frames
+    MessagePack,
+}
+
+/// An encoded message, along with the type of frame to send it as
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// Failed to encode or decode JSON
+    Json(serde_json::Error),
+    /// Failed to encode MessagePack
+    Encode(rmp_serde::encode::Error),
+    /// Failed to decode MessagePack
+    Decode(rmp_serde::decode::Error),
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(err) => write!(f, "JSON: {err}"),
+            Self::Encode(err) => write!(f, "Failed to encode MessagePack: {err}"),
+            Self::Decode(err) => write!(f, "Failed to decode MessagePack: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl Encoding {
+    /// All encodings, in the order of preference of the client
+    pub const ALL: [Self; 2] = [Self::MessagePack, Self::Json];
+
+    /// The websocket subprotocol requesting the encoding
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Self::Json => "bommer.v1.json",
+            Self::MessagePack => "bommer.v1.msgpack",
+        }
+    }
+
+    /// Pick the first supported encoding of the subprotocols requested by the client
+    ///
+    /// The protocols are the comma separated value of the `Sec-WebSocket-Protocol` header.
+    pub fn negotiate(protocols: &str) -> Option<Self> {
+        protocols.split(',').find_map(|protocol| {
+            Self::ALL
+                .into_iter()
+                .find(|encoding| encoding.protocol() == protocol.trim())
+        })
+    }
+
+    /// Encode a message
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Frame, CodecError> {
+        Ok(match self {
+            Self::Json => Frame::Text(serde_json::to_string(value).map_err(CodecError::Json)?),
+            // fields are encoded by name, as optional fields get skipped
+            Self::MessagePack => {
+                Frame::Binary(rmp_serde::to_vec_named(value).map_err(CodecError::Encode)?)
+            }
+        })
+    }
+}
+
+impl Frame {
+    /// Decode a message, using the encoding matching the type of frame
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, CodecError> {
+        match self {
+            Self::Text(text) => serde_json::from_str(text).map_err(CodecError::Json),
+            Self::Binary(data) => rmp_serde::from_slice(data).map_err(CodecError::Decode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{Event, Image, ImageRef, PodRef, RevisionedEvent, SbomState};
+    use std::collections::HashSet;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(
+            Encoding::negotiate("foo, bommer.v1.msgpack, bommer.v1.json"),
+            Some(Encoding::MessagePack)
+        );
+        assert_eq!(Encoding::negotiate("bommer.v1.json"), Some(Encoding::Json));
+        assert_eq!(Encoding::negotiate("foo"), None);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let image: ImageRef = "quay.io/foo/bar:1".parse().unwrap();
+        let state = Image {
+            pods: HashSet::from([PodRef {
+                cluster: None,
+                namespace: "default".to_string(),
+                name: "pod".to_string(),
+                node: None,
+                workload: None,
+            }]),
+            ..Image::new(SbomState::Scheduled)
+        };
+        let evt = RevisionedEvent {
+            revision: 42,
+            event: Event::Restart(im::HashMap::from(vec![(image.clone(), state.clone())])),
+        };
+
+        for encoding in Encoding::ALL {
+            let decoded: RevisionedEvent<ImageRef, Image> =
+                encoding.encode(&evt).unwrap().decode().unwrap();
+            assert_eq!(decoded.revision, 42);
+            assert!(
+                matches!(decoded.event, Event::Restart(images) if images.get(&image) == Some(&state))
+            );
+        }
+    }
+}
@@ -1,61 +1,762 @@
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Image {
     pub pods: HashSet<PodRef>,
+    /// The image is only declared in the spec of the pods, but none of their containers runs it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub declared: bool,
+    /// The containers using the image, by name, kind, and namespace
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<ContainerUsage>,
+    /// Batch workloads (jobs and cron jobs) using the image in their pod template, if tracked
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub jobs: HashSet<JobRef>,
+    /// Nodes having the image present, if tracked
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub nodes: HashSet<NodeRef>,
+    /// The package URL the SBOM and vulnerabilities are looked up by, if one can be created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purl: Option<String>,
     pub sbom: SbomState,
+    /// Retry information, when the last attempt to retrieve the SBOM failed or found none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryState>,
+    /// Vulnerabilities affecting the image, when an SBOM was found and they could be looked up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vulnerabilities: Option<Vulnerabilities>,
 }
 
+impl Image {
+    /// Create an image in the given SBOM state, without any usage or scan results
+    ///
+    /// The usage (pods, containers, …) and purl are expected to be filled in afterwards, using
+    /// the struct update syntax.
+    pub fn new(sbom: SbomState) -> Self {
+        Self {
+            pods: Default::default(),
+            declared: false,
+            containers: vec![],
+            jobs: Default::default(),
+            nodes: Default::default(),
+            purl: None,
+            sbom,
+            retry: None,
+            vulnerabilities: None,
+        }
+    }
+
+    /// If an image used by these containers is only declared, with none of them running it
+    pub fn declared_by(containers: &[ContainerUsage]) -> bool {
+        !containers.is_empty() && !containers.iter().any(|container| container.running)
+    }
+}
+
+/// Number of vulnerabilities, by severity
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vulnerabilities {
+    pub critical: usize,
+    pub high: usize,
+    pub medium: usize,
+    pub low: usize,
+    /// Vulnerabilities without a score
+    pub unknown: usize,
+}
+
+impl Vulnerabilities {
+    /// count a vulnerability, by its CVSS score
+    pub fn add(&mut self, score: Option<f64>) {
+        match score {
+            Some(score) if score >= 9.0 => self.critical += 1,
+            Some(score) if score >= 7.0 => self.high += 1,
+            Some(score) if score >= 4.0 => self.medium += 1,
+            Some(score) if score > 0.0 => self.low += 1,
+            _ => self.unknown += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.critical + self.high + self.medium + self.low + self.unknown
+    }
+}
+
+/// State of retrying a failed operation
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct RetryState {
+    /// Number of failed attempts
+    pub attempts: u32,
+    /// Time the next attempt is due
+    pub next: DateTime<Utc>,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+// most images end up with an SBOM, so boxing the summary wouldn't save anything
+#[allow(clippy::large_enum_variant)]
 pub enum SbomState {
     Scheduled,
-    Err(String),
+    Err(LookupError),
     Missing,
-    Found(SBOM),
+    Found(SbomSummary),
+}
+
+/// An error looking up an SBOM
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupError {
+    pub kind: LookupErrorKind,
+    pub message: String,
+    /// The status code returned by the SBOM source, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+impl LookupError {
+    /// Check if the error is temporary, and the lookup should be re-tried soon
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            LookupErrorKind::Transport | LookupErrorKind::Server
+        )
+    }
+}
+
+impl Display for LookupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LookupErrorKind {
+    /// Failed to communicate with the SBOM source
+    Transport,
+    /// The SBOM source reported an internal error
+    Server,
+    /// The SBOM source responded with an unexpected status
+    UnexpectedStatus,
+    /// The image reference can't be used to look up an SBOM
+    InvalidReference,
+    /// The SBOM returned by the source couldn't be parsed
+    InvalidSbom,
+    /// The SBOM returned by the source exceeds the size limit
+    TooLarge,
+}
+
+/// Summary of an SBOM, independent of its format
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-pub struct SBOM {
-    pub data: String,
+#[serde(rename_all = "camelCase")]
+pub struct SbomSummary {
+    /// The format of the SBOM
+    pub format: SbomFormat,
+    /// The version of the format, e.g. `SPDX-2.3` or `1.4`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The name of the SBOM document, or its main component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Number of packages (components) contained in the SBOM
+    pub packages: usize,
+    /// Licenses of the top-level packages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub licenses: Vec<String>,
+    /// Tools which created the SBOM
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    /// Time the SBOM was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    /// The package URL the SBOM was found by, if the source looks up SBOMs by package URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purl: Option<String>,
+    /// The instance of the SBOM source the SBOM was found at, if the source knows several
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The digest of the document itself (`sha256:…`), if the source provides the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Digests of the components the document describes, as stated by the document
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+    /// One of the described components has the digest of the image
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verified: bool,
+    /// Identifiers of the licenses of all packages, taken from their license expressions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub license_ids: Vec<String>,
+    /// The quality of the SBOM, if the source provides the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Box<SbomQuality>>,
+}
+
+/// The quality of an SBOM, checking for the NTIA minimum elements
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SbomQuality {
+    /// Overall score, from 0 to 100
+    pub score: u8,
+    pub grade: QualityGrade,
+    pub checks: Vec<QualityCheck>,
+}
+
+/// Grade of an SBOM, based on its quality score
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub enum QualityGrade {
+    /// A score of at least 90
+    A,
+    /// A score of at least 75
+    B,
+    /// A score of at least 60
+    C,
+    /// A score of at least 40
+    D,
+    F,
+}
+
+impl QualityGrade {
+    pub fn from_score(score: u8) -> Self {
+        match score {
+            90.. => Self::A,
+            75.. => Self::B,
+            60.. => Self::C,
+            40.. => Self::D,
+            _ => Self::F,
+        }
+    }
+}
+
+impl Display for QualityGrade {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+/// The outcome of checking an SBOM for one of the minimum elements
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityCheck {
+    pub criterion: QualityCriterion,
+    /// Share of the packages meeting the criterion, from 0 to 100. Either 0 or 100 for criteria of the document
+    pub score: u8,
+}
+
+/// A minimum element an SBOM should provide
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QualityCriterion {
+    /// Packages name their supplier
+    Supplier,
+    /// Packages have a version
+    Version,
+    /// Packages have a unique identifier, a package URL or CPE
+    Identifier,
+    /// The document states the dependencies between packages
+    Dependencies,
+    /// The document names its author, a person, organization, or tool
+    Author,
+    /// The document has a creation timestamp
+    Timestamp,
+}
+
+impl Display for QualityCriterion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Supplier => f.write_str("supplier"),
+            Self::Version => f.write_str("version"),
+            Self::Identifier => f.write_str("identifier"),
+            Self::Dependencies => f.write_str("dependencies"),
+            Self::Author => f.write_str("author"),
+            Self::Timestamp => f.write_str("timestamp"),
+        }
+    }
+}
+
+/// The history of SBOM lookups of an image
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanHistory {
+    /// Time of the last lookup which found an SBOM, even if it's no longer part of the attempts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_found: Option<DateTime<Utc>>,
+    /// The most recent lookups, latest first
+    pub attempts: Vec<ScanAttempt>,
+}
+
+/// A single lookup of the SBOM of an image
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanAttempt {
+    pub timestamp: DateTime<Utc>,
+    pub outcome: ScanOutcome,
+    /// The error, if the lookup failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<LookupError>,
+    /// The result was taken from the cache, instead of the SBOM source
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+/// The outcome of looking up an SBOM
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanOutcome {
+    Found,
+    Missing,
+    Err,
+}
+
+/// The details of an SBOM: its summary, along with the packages it contains
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SbomDetails {
+    pub summary: SbomSummary,
+    pub packages: Vec<SbomPackage>,
+}
+
+/// A package (component) of an SBOM
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SbomPackage {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purl: Option<String>,
+    /// License expressions of the package
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub licenses: Vec<String>,
+    /// If the package is one the SBOM describes, and not only contained in it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub top_level: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SbomFormat {
+    Spdx,
+    CycloneDx,
+    /// The source only provides metadata about the SBOM, not the document itself
+    Unknown,
+}
+
+impl Display for SbomFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spdx => f.write_str("SPDX"),
+            Self::CycloneDx => f.write_str("CycloneDX"),
+            Self::Unknown => f.write_str("Unknown"),
+        }
+    }
 }
 
 /// A reference to a pod
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(
     Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Serialize, serde::Deserialize,
 )]
 #[serde(rename_all = "camelCase")]
 pub struct PodRef {
+    /// The cluster the pod is located in, `None` when running against a single cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    pub namespace: String,
+    pub name: String,
+    /// The node the pod is scheduled on, if tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// The top-level workload (e.g. a deployment) controlling the pod, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workload: Option<WorkloadRef>,
+}
+
+/// The kind of a container in a pod
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Hash,
+    Ord,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum ContainerKind {
+    #[default]
+    Container,
+    Init,
+    Ephemeral,
+}
+
+impl Display for ContainerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Container => f.write_str("container"),
+            Self::Init => f.write_str("init"),
+            Self::Ephemeral => f.write_str("ephemeral"),
+        }
+    }
+}
+
+/// Containers using an image, which share the same name and kind, in the pods of a namespace
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerUsage {
+    pub name: String,
+    #[serde(default)]
+    pub kind: ContainerKind,
+    #[serde(default)]
+    pub namespace: String,
+    /// Number of pods having such a container
+    pub pods: usize,
+    /// Any of the containers runs the image, rather than only declaring it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub running: bool,
+}
+
+/// A pod, along with the images used by its containers
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodImages {
+    pub pod: PodRef,
+    pub containers: Vec<PodContainer>,
+}
+
+/// A container of a pod, and the state of the SBOM lookup of its image
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodContainer {
+    pub name: String,
+    #[serde(default)]
+    pub kind: ContainerKind,
+    pub image: ImageRef,
+    /// If the container only declares the image, without running it yet
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub declared: bool,
+    /// The state of the SBOM lookup, `None` if the image isn't part of the workload (yet)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sbom: Option<SbomState>,
+}
+
+/// A license used by an image
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageLicense {
+    /// The license identifier
+    pub license: String,
+    /// The license is configured as forbidden
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forbidden: bool,
+}
+
+/// A license used by images of the workload
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LicenseUsage {
+    /// The license identifier
+    pub license: String,
+    /// The license is configured as forbidden
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forbidden: bool,
+    /// The images containing packages using the license
+    pub images: Vec<ImageRef>,
+    /// The namespaces the images are used in
+    pub namespaces: BTreeSet<String>,
+}
+
+/// An image containing a package which was searched for
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageMatch {
+    pub image: ImageRef,
+    /// The packages of the image matching the search
+    pub packages: Vec<SbomPackage>,
+    /// The namespaces the image is used in
+    pub namespaces: BTreeSet<String>,
+    /// The pods using the image
+    pub pods: Vec<PodRef>,
+}
+
+/// A reference to a batch workload, a job or a cron job
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(
+    Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRef {
+    /// The cluster the job is located in, `None` when running against a single cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    /// The kind, `Job` or `CronJob`
+    pub kind: String,
     pub namespace: String,
     pub name: String,
 }
 
+impl Display for JobRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{}", self.kind, self.namespace, self.name)
+    }
+}
+
+/// A reference to a node
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(
+    Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeRef {
+    /// The cluster the node belongs to, `None` when running against a single cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    pub name: String,
+}
+
+/// A reference to a workload controlling pods, like a deployment
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(
+    Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkloadRef {
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+}
+
+impl Display for WorkloadRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{}", self.kind, self.namespace, self.name)
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Clone, Debug, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageState {
     pub pods: HashSet<PodRef>,
 }
 
+/// A reference to an image
+///
+/// The reference is canonicalized when being parsed, adding the implicit Docker Hub registry and
+/// `library/` namespace. It is serialized in its string form, e.g.
+/// `docker.io/library/nginx:1.25@sha256:…`. When the architecture is known, it is appended as
+/// qualifier, like in a purl: `docker.io/library/nginx@sha256:…?arch=arm64`.
 #[derive(
     Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Deserialize, serde::Serialize,
 )]
-pub struct ImageRef(pub String);
+#[serde(into = "String", try_from = "String")]
+pub struct ImageRef {
+    /// The registry host, including an optional port
+    pub registry: String,
+    /// The repository inside the registry
+    pub repository: String,
+    /// The tag, e.g. `1.25`
+    pub tag: Option<String>,
+    /// The digest, e.g. `sha256:…`
+    pub digest: Option<String>,
+    /// The architecture of the node running the image, if tracked
+    pub arch: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageRefError {
+    /// The reference is empty
+    Empty,
+    /// The reference is only a digest, missing the repository
+    MissingRepository,
+}
+
+impl Display for ImageRefError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty image reference"),
+            Self::MissingRepository => f.write_str("Image reference is missing the repository"),
+        }
+    }
+}
+
+impl std::error::Error for ImageRefError {}
+
+const DOCKER_HUB: &str = "docker.io";
+
+impl ImageRef {
+    /// The name of the image, consisting of the registry and the repository
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    /// A copy of the reference, only using the digest
+    pub fn with_digest(&self, digest: impl Into<String>) -> Self {
+        Self {
+            registry: self.registry.clone(),
+            repository: self.repository.clone(),
+            tag: None,
+            digest: Some(digest.into()),
+            arch: self.arch.clone(),
+        }
+    }
+}
+
+impl FromStr for ImageRef {
+    type Err = ImageRefError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ImageRefError::Empty);
+        }
+
+        let (s, arch) = match s.split_once('?') {
+            Some((s, qualifiers)) => (
+                s,
+                qualifiers
+                    .split('&')
+                    .find_map(|q| q.strip_prefix("arch="))
+                    .map(ToString::to_string),
+            ),
+            None => (s, None),
+        };
+
+        let (name, digest) = match s.rsplit_once('@') {
+            Some((name, digest)) => (name, Some(digest.to_string())),
+            None => (s, None),
+        };
+
+        // a bare digest, as reported by some runtimes
+        if name.is_empty() || (digest.is_none() && is_digest(name)) {
+            return Err(ImageRefError::MissingRepository);
+        }
+
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+            _ => (name, None),
+        };
+
+        // the first segment is a registry if it looks like a host name
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, rest))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host, rest)
+            }
+            _ => (DOCKER_HUB, name),
+        };
+
+        let registry = match registry {
+            "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB,
+            registry => registry,
+        };
+
+        // official images on Docker Hub live in the "library" namespace
+        let repository = match registry == DOCKER_HUB && !repository.contains('/') {
+            true => format!("library/{repository}"),
+            false => repository.to_string(),
+        };
+
+        Ok(Self {
+            registry: registry.to_string(),
+            repository,
+            tag,
+            digest,
+            arch,
+        })
+    }
+}
+
+/// check if the value is a bare digest, like `sha256:…`
+fn is_digest(value: &str) -> bool {
+    value.split_once(':').is_some_and(|(algorithm, hex)| {
+        algorithm.starts_with("sha")
+            && hex.len() >= 32
+            && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
 
 impl Display for ImageRef {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self.0, f)
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        if let Some(arch) = &self.arch {
+            write!(f, "?arch={arch}")?;
+        }
+        Ok(())
+    }
+}
+
+// serialized as a string, which the derive can't express
+#[cfg(feature = "openapi")]
+impl<'s> utoipa::ToSchema<'s> for ImageRef {
+    fn schema() -> (
+        &'s str,
+        utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>,
+    ) {
+        (
+            "ImageRef",
+            utoipa::openapi::ObjectBuilder::new()
+                .schema_type(utoipa::openapi::SchemaType::String)
+                .description(Some("A reference to an image"))
+                .example(Some("docker.io/library/nginx@sha256:…".into()))
+                .into(),
+        )
     }
 }
 
-impl Deref for ImageRef {
-    type Target = String;
+impl From<ImageRef> for String {
+    fn from(value: ImageRef) -> Self {
+        value.to_string()
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl TryFrom<String> for ImageRef {
+    type Error = ImageRefError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
     }
 }
 
@@ -69,5 +770,358 @@ where
     Added(K, V),
     Modified(K, V),
     Removed(K),
-    Restart(HashMap<K, V>),
+    /// The full state, replacing everything known before
+    ///
+    /// As a persistent map, it shares its entries with the state it was taken from, so that
+    /// taking it is cheap even for large states.
+    Restart(im::HashMap<K, V>),
+}
+
+/// An event, along with the revision of the state it leads to
+///
+/// Clients can use the revision to resume the stream of events after reconnecting.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionedEvent<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    pub revision: u64,
+    #[serde(flatten)]
+    pub event: Event<K, V>,
+}
+
+/// An event of the recent history, along with the time it happened
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    pub revision: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event<K, V>,
+}
+
+/// The most recent events, for clients polling for changes
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentEvents<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    /// The current revision of the state
+    pub revision: u64,
+    /// Events since the requested revision got lost, and clients need to fetch the full state
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expired: bool,
+    /// The events, oldest first
+    pub events: Vec<RecordedEvent<K, V>>,
+}
+
+/// The state of the workload, by image
+///
+/// Consumers of the event stream can keep it up to date by applying the events they receive.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Workload(pub HashMap<ImageRef, Image>);
+
+impl Workload {
+    /// Apply an event, received from the stream of changes
+    pub fn apply(&mut self, event: Event<ImageRef, Image>) {
+        match event {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                self.0.insert(image, state);
+            }
+            Event::Removed(image) => {
+                self.0.remove(&image);
+            }
+            Event::Restart(state) => self.0 = state.into_iter().collect(),
+        }
+    }
+
+    /// Apply a batch of events, in the order they were received
+    pub fn apply_all(&mut self, events: impl IntoIterator<Item = Event<ImageRef, Image>>) {
+        for event in events {
+            self.apply(event);
+        }
+    }
+}
+
+impl Deref for Workload {
+    type Target = HashMap<ImageRef, Image>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Workload {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Number of images by the state of their SBOM lookup
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SbomStats {
+    pub images: usize,
+    pub found: usize,
+    pub missing: usize,
+    pub failed: usize,
+    pub scheduled: usize,
+}
+
+impl SbomStats {
+    /// Count an image, having this state
+    pub fn add(&mut self, sbom: &SbomState) {
+        self.images += 1;
+        match sbom {
+            SbomState::Found(_) => self.found += 1,
+            SbomState::Missing => self.missing += 1,
+            SbomState::Err(_) => self.failed += 1,
+            SbomState::Scheduled => self.scheduled += 1,
+        }
+    }
+}
+
+/// The SBOM coverage of the workload at a point in time
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageSample {
+    pub timestamp: DateTime<Utc>,
+    pub coverage: SbomStats,
+}
+
+/// The state of the initial synchronization with the clusters
+///
+/// Until it completed, the workload is incomplete, and might even be empty.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// All watchers processed their first full list
+    pub synced: bool,
+    /// When the initial synchronization completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<DateTime<Utc>>,
+    /// The number of watchers which didn't process their first full list yet
+    pub pending: usize,
+}
+
+/// Statistics of the workload, in total and broken down by namespace and registry
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkloadStats {
+    pub total: SbomStats,
+    /// The images used in each namespace, by pods or jobs
+    pub namespaces: BTreeMap<String, SbomStats>,
+    /// The images of each registry
+    pub registries: BTreeMap<String, SbomStats>,
+}
+
+impl WorkloadStats {
+    /// Count an image of the workload
+    pub fn add(&mut self, image: &ImageRef, state: &Image) {
+        self.total.add(&state.sbom);
+
+        self.registries
+            .entry(image.registry.clone())
+            .or_default()
+            .add(&state.sbom);
+
+        let used_in = state
+            .pods
+            .iter()
+            .map(|pod| &pod.namespace)
+            .chain(state.jobs.iter().map(|job| &job.namespace))
+            .collect::<BTreeSet<_>>();
+        for namespace in used_in {
+            self.namespaces
+                .entry(namespace.clone())
+                .or_default()
+                .add(&state.sbom);
+        }
+    }
+}
+
+/// The details of a failed request, as defined by RFC 7807 (`application/problem+json`)
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    /// A URI identifying the kind of problem, derived from its code
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// A short summary of the kind of problem
+    pub title: String,
+    /// The HTTP status code
+    pub status: u16,
+    /// What went wrong with this request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// A stable code identifying the kind of problem, e.g. `image-not-found`
+    pub code: String,
+}
+
+impl Problem {
+    /// The media type of problem details
+    pub const CONTENT_TYPE: &'static str = "application/problem+json";
+
+    pub fn new(status: u16, code: &str, title: &str, detail: Option<String>) -> Self {
+        Self {
+            r#type: format!("urn:bommer:problem:{code}"),
+            title: title.to_string(),
+            status,
+            detail,
+            code: code.to_string(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} ({}): {detail}", self.title, self.code),
+            None => write!(f, "{} ({})", self.title, self.code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:34e8724e0f47e31eb2ec3279ac398b657db5f60f167426ee73138e2e84af6486";
+
+    fn image_ref(
+        registry: &str,
+        repository: &str,
+        tag: Option<&str>,
+        digest: Option<&str>,
+    ) -> ImageRef {
+        ImageRef {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            tag: tag.map(ToString::to_string),
+            digest: digest.map(ToString::to_string),
+            arch: None,
+        }
+    }
+
+    #[test]
+    fn parse_docker_hub() {
+        assert_eq!(
+            "nginx".parse(),
+            Ok(image_ref("docker.io", "library/nginx", None, None))
+        );
+        assert_eq!(
+            "index.docker.io/foo/bar:1".parse(),
+            Ok(image_ref("docker.io", "foo/bar", Some("1"), None))
+        );
+    }
+
+    #[test]
+    fn parse_registry_with_port() {
+        assert_eq!(
+            format!("localhost:5000/foo/bar:1.0@{DIGEST}").parse(),
+            Ok(image_ref(
+                "localhost:5000",
+                "foo/bar",
+                Some("1.0"),
+                Some(DIGEST)
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!("".parse::<ImageRef>(), Err(ImageRefError::Empty));
+        assert_eq!(
+            DIGEST.parse::<ImageRef>(),
+            Err(ImageRefError::MissingRepository)
+        );
+    }
+
+    #[test]
+    fn parse_arch() {
+        let image = format!("quay.io/foo/bar@{DIGEST}?arch=arm64");
+        assert_eq!(
+            image.parse(),
+            Ok(ImageRef {
+                arch: Some("arm64".into()),
+                ..image_ref("quay.io", "foo/bar", None, Some(DIGEST))
+            })
+        );
+        assert_eq!(image.parse::<ImageRef>().unwrap().to_string(), image);
+    }
+
+    #[test]
+    fn revisioned_event() {
+        let json = r#"{"revision":42,"removed":"quay.io/foo/bar:1"}"#;
+        let evt: RevisionedEvent<ImageRef, ()> = serde_json::from_str(json).unwrap();
+        assert_eq!(evt.revision, 42);
+        assert!(
+            matches!(&evt.event, Event::Removed(image) if image == &image_ref("quay.io", "foo/bar", Some("1"), None))
+        );
+        assert_eq!(serde_json::to_string(&evt).unwrap(), json);
+    }
+
+    #[test]
+    fn serialize_as_string() {
+        let image = format!("quay.io/foo/bar@{DIGEST}");
+        let json = serde_json::to_string(&image.parse::<ImageRef>().unwrap()).unwrap();
+        assert_eq!(json, format!("\"{image}\""));
+    }
+
+    fn image(namespace: &str) -> Image {
+        Image {
+            pods: HashSet::from([PodRef {
+                cluster: None,
+                namespace: namespace.to_string(),
+                name: "pod".to_string(),
+                node: None,
+                workload: None,
+            }]),
+            ..Image::new(SbomState::Scheduled)
+        }
+    }
+
+    #[test]
+    fn workload_apply() {
+        let foo = image_ref("quay.io", "foo", Some("1"), None);
+        let bar = image_ref("quay.io", "bar", Some("1"), None);
+
+        let mut workload = Workload::default();
+        workload.apply(Event::Added(foo.clone(), image("default")));
+        workload.apply(Event::Added(bar.clone(), image("default")));
+        assert_eq!(workload.len(), 2);
+
+        workload.apply(Event::Modified(foo.clone(), image("other")));
+        assert_eq!(workload.get(&foo), Some(&image("other")));
+
+        workload.apply(Event::Removed(bar.clone()));
+        assert!(!workload.contains_key(&bar));
+        // removing an unknown image is a no-op
+        workload.apply(Event::Removed(bar.clone()));
+        assert_eq!(workload.len(), 1);
+
+        // a modification of an image we missed adds it
+        workload.apply(Event::Modified(bar.clone(), image("default")));
+        assert_eq!(workload.len(), 2);
+
+        // a restart replaces the whole state
+        workload.apply(Event::Restart(im::HashMap::from(vec![(
+            bar.clone(),
+            image("new"),
+        )])));
+        assert_eq!(workload, Workload(HashMap::from([(bar, image("new"))])));
+    }
 }
@@ -0,0 +1,158 @@
+use anyhow::{bail, Context};
+use bommer_api::codec::{Encoding, Frame};
+use bommer_api::data::{Image, ImageRef, Problem, RevisionedEvent, WorkloadStats};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL};
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use std::fmt::Formatter;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+/// A client for the API of a bommer server
+pub struct Client {
+    client: reqwest::Client,
+    url: Url,
+    token: Option<String>,
+}
+
+/// A page of the workload, keeping the order of the server
+pub struct Page {
+    /// Total number of matching images, before paging
+    pub total: Option<usize>,
+    pub items: Vec<(ImageRef, Image)>,
+}
+
+impl Client {
+    pub fn new(url: Url, token: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            token,
+        }
+    }
+
+    /// get the (filtered) workload
+    pub async fn workload(&self, query: &[(&str, String)]) -> anyhow::Result<Page> {
+        let response = self.get("api/v1/workload", query).await?;
+
+        let total = response
+            .headers()
+            .get("X-Total-Count")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let Ordered(items) = response.json().await?;
+
+        Ok(Page { total, items })
+    }
+
+    /// get the statistics of the (filtered) workload
+    pub async fn stats(&self, query: &[(&str, String)]) -> anyhow::Result<WorkloadStats> {
+        Ok(self.get("api/v1/stats", query).await?.json().await?)
+    }
+
+    /// get the SBOM document of an image
+    pub async fn sbom(&self, image: &str) -> anyhow::Result<Bytes> {
+        let response = self
+            .get("api/v1/sbom", &[("image", image.to_string())])
+            .await?;
+        Ok(response.bytes().await?)
+    }
+
+    /// stream the changes to the (filtered) workload
+    ///
+    /// Requests the events to be encoded as MessagePack, which is cheaper to decode than JSON.
+    pub async fn watch(
+        &self,
+        query: &[(&str, String)],
+    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<RevisionedEvent<ImageRef, Image>>>> {
+        let mut url = self.endpoint("api/v1/workload_stream")?;
+        let scheme = match url.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        url.set_scheme(scheme)
+            .map_err(|()| anyhow::anyhow!("Unable to use URL for websockets: {url}"))?;
+        url.query_pairs_mut().extend_pairs(query);
+
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            Encoding::MessagePack.protocol().parse()?,
+        );
+        if let Some(token) = &self.token {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, format!("Bearer {token}").parse()?);
+        }
+
+        let (stream, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .with_context(|| format!("Failed to connect to {url}"))?;
+
+        Ok(stream.filter_map(|msg| async move {
+            match msg {
+                Ok(Message::Text(text)) => Some(Frame::Text(text).decode().map_err(Into::into)),
+                Ok(Message::Binary(data)) => Some(Frame::Binary(data).decode().map_err(Into::into)),
+                Ok(_) => None,
+                Err(err) => Some(Err(err.into())),
+            }
+        }))
+    }
+
+    /// the URL of an API path, relative to the URL of the server, which may include a path
+    fn endpoint(&self, path: &str) -> Result<Url, url::ParseError> {
+        let mut url = self.url.clone();
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        url.join(path)
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> anyhow::Result<reqwest::Response> {
+        let mut request = self.client.get(self.endpoint(path)?).query(query);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            match serde_json::from_str::<Problem>(&message) {
+                Ok(problem) => bail!("Request failed: {problem}"),
+                Err(_) => bail!("Request failed: {status} {message}"),
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// a JSON object of images, as a list in the order of the document
+struct Ordered(Vec<(ImageRef, Image)>);
+
+impl<'de> Deserialize<'de> for Ordered {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OrderedVisitor;
+
+        impl<'de> Visitor<'de> for OrderedVisitor {
+            type Value = Ordered;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("an object of images")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut items = Vec::with_capacity(map.size_hint().unwrap_or_default());
+                while let Some(entry) = map.next_entry()? {
+                    items.push(entry);
+                }
+                Ok(Ordered(items))
+            }
+        }
+
+        deserializer.deserialize_map(OrderedVisitor)
+    }
+}
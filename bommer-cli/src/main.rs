@@ -0,0 +1,243 @@
+//! A command line client for the API of bommer.
+
+mod client;
+mod output;
+
+use bommer_api::data::Workload;
+use clap::{Args, Parser, Subcommand};
+use client::Client;
+use futures::StreamExt;
+use output::Output;
+use std::io::Write;
+use url::Url;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about = "Command line client for bommer", long_about = None)]
+struct Cli {
+    /// URL of the bommer server
+    #[arg(
+        long,
+        env = "BOMMER_URL",
+        default_value = "http://localhost:8080",
+        global = true
+    )]
+    url: Url,
+
+    /// Bearer token to authenticate with
+    #[arg(long, env = "BOMMER_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect the workload
+    #[command(subcommand)]
+    Workload(WorkloadCommand),
+    /// Inspect SBOMs
+    #[command(subcommand)]
+    Sbom(SbomCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum WorkloadCommand {
+    /// List the images of the workload
+    List {
+        #[command(flatten)]
+        filter: Filter,
+
+        /// Property to sort by
+        #[arg(long, value_enum, default_value_t = SortKey::Image)]
+        sort: SortKey,
+
+        /// Number of images to skip
+        #[arg(long)]
+        offset: Option<usize>,
+
+        /// Maximum number of images to list
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t)]
+        output: Output,
+    },
+    /// Show the number of images by the state of their SBOM, per namespace and registry
+    Stats {
+        #[command(flatten)]
+        filter: Filter,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t)]
+        output: Output,
+    },
+    /// Stream changes to the workload, until interrupted
+    Watch {
+        #[command(flatten)]
+        filter: Filter,
+
+        /// Resume after this revision
+        #[arg(long)]
+        since: Option<u64>,
+
+        /// Output format, JSON prints one event per line
+        #[arg(short, long, value_enum, default_value_t)]
+        output: Output,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SbomCommand {
+    /// Print the SBOM document of an image
+    Get {
+        /// The image reference, as reported by the workload
+        image: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct Filter {
+    /// Only images used in these namespaces
+    #[arg(short, long, value_delimiter = ',')]
+    namespace: Vec<String>,
+
+    /// Only images with this state of the SBOM lookup
+    #[arg(long, value_enum)]
+    sbom: Option<SbomFilter>,
+
+    /// Only images from this registry
+    #[arg(long)]
+    registry: Option<String>,
+
+    /// Only images whose reference contains this text
+    #[arg(short, long)]
+    query: Option<String>,
+}
+
+impl Filter {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![];
+        if !self.namespace.is_empty() {
+            query.push(("namespace", self.namespace.join(",")));
+        }
+        if let Some(sbom) = self.sbom {
+            query.push(("sbom", sbom.as_str().to_string()));
+        }
+        if let Some(registry) = &self.registry {
+            query.push(("registry", registry.clone()));
+        }
+        if let Some(q) = &self.query {
+            query.push(("q", q.clone()));
+        }
+        query
+    }
+}
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum SbomFilter {
+    Scheduled,
+    Err,
+    Missing,
+    Found,
+}
+
+impl SbomFilter {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Scheduled => "scheduled",
+            Self::Err => "err",
+            Self::Missing => "missing",
+            Self::Found => "found",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum SortKey {
+    Image,
+    Namespace,
+    SbomState,
+}
+
+impl SortKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Namespace => "namespace",
+            Self::SbomState => "sbomState",
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let client = Client::new(cli.url, cli.token);
+
+    match cli.command {
+        Command::Workload(WorkloadCommand::List {
+            filter,
+            sort,
+            offset,
+            limit,
+            output,
+        }) => {
+            let mut query = filter.query();
+            query.push(("sort", sort.as_str().to_string()));
+            if let Some(offset) = offset {
+                query.push(("offset", offset.to_string()));
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+
+            let page = client.workload(&query).await?;
+            match output {
+                Output::Table => {
+                    output::images(&page.items);
+                    if let Some(total) = page.total.filter(|total| *total > page.items.len()) {
+                        eprintln!("Showing {} of {total} images", page.items.len());
+                    }
+                }
+                Output::Json => output::json(&page.items)?,
+            }
+        }
+        Command::Workload(WorkloadCommand::Stats { filter, output }) => {
+            let stats = client.stats(&filter.query()).await?;
+            match output {
+                Output::Table => output::stats(&stats),
+                Output::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+            }
+        }
+        Command::Workload(WorkloadCommand::Watch {
+            filter,
+            since,
+            output,
+        }) => {
+            let mut query = filter.query();
+            if let Some(since) = since {
+                query.push(("since", since.to_string()));
+            }
+
+            let mut stream = Box::pin(client.watch(&query).await?);
+            // the state, as far as we know it, to describe removed images
+            let mut workload = Workload::default();
+            while let Some(evt) = stream.next().await {
+                let evt = evt?;
+                match output {
+                    Output::Table => output::event(&evt, &workload),
+                    Output::Json => println!("{}", serde_json::to_string(&evt)?),
+                }
+                workload.apply(evt.event);
+            }
+        }
+        Command::Sbom(SbomCommand::Get { image }) => {
+            let document = client.sbom(&image).await?;
+            std::io::stdout().write_all(&document)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use bommer_api::data::{
+    Event, Image, ImageRef, RevisionedEvent, SbomState, SbomStats, Workload, WorkloadStats,
+};
+use comfy_table::presets::NOTHING;
+use comfy_table::Table;
+use serde::Serializer;
+use std::collections::BTreeSet;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// A table, for humans
+    #[default]
+    Table,
+    /// JSON, for scripts
+    Json,
+}
+
+/// print the images as a table
+pub fn images(items: &[(ImageRef, Image)]) {
+    let mut table = Table::new();
+    table
+        .load_preset(NOTHING)
+        .set_header(["IMAGE", "NAMESPACES", "PODS", "SBOM"]);
+
+    for (image, state) in items {
+        table.add_row([
+            image.to_string(),
+            namespaces(state),
+            state.pods.len().to_string(),
+            sbom(&state.sbom),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+/// print the statistics as a table, the total first, followed by namespaces and registries
+pub fn stats(stats: &WorkloadStats) {
+    let mut table = Table::new();
+    table.load_preset(NOTHING).set_header([
+        "",
+        "IMAGES",
+        "FOUND",
+        "MISSING",
+        "FAILED",
+        "SCHEDULED",
+    ]);
+
+    let rows = std::iter::once(("total".to_string(), &stats.total))
+        .chain(
+            stats
+                .namespaces
+                .iter()
+                .map(|(namespace, stats)| (format!("namespace/{namespace}"), stats)),
+        )
+        .chain(
+            stats
+                .registries
+                .iter()
+                .map(|(registry, stats)| (format!("registry/{registry}"), stats)),
+        );
+    for (name, stats) in rows {
+        table.add_row(stats_row(name, stats));
+    }
+
+    println!("{table}");
+}
+
+fn stats_row(name: String, stats: &SbomStats) -> [String; 6] {
+    [
+        name,
+        stats.images.to_string(),
+        stats.found.to_string(),
+        stats.missing.to_string(),
+        stats.failed.to_string(),
+        stats.scheduled.to_string(),
+    ]
+}
+
+/// print the images as a JSON object, keeping their order
+pub fn json(items: &[(ImageRef, Image)]) -> serde_json::Result<()> {
+    let mut serializer = serde_json::Serializer::pretty(std::io::stdout().lock());
+    serializer.collect_map(items.iter().map(|(image, state)| (image, state)))?;
+    println!();
+    Ok(())
+}
+
+/// print an event as a single line, `workload` being the state before applying it
+pub fn event(evt: &RevisionedEvent<ImageRef, Image>, workload: &Workload) {
+    let revision = evt.revision;
+    match &evt.event {
+        Event::Added(image, state) => line(revision, "ADDED", image, state),
+        Event::Modified(image, state) => line(revision, "MODIFIED", image, state),
+        Event::Removed(image) => match workload.get(image) {
+            Some(state) => line(revision, "REMOVED", image, state),
+            None => println!("{revision:>8}  {:<8}  {image}", "REMOVED"),
+        },
+        Event::Restart(state) => {
+            let mut items = state.iter().collect::<Vec<_>>();
+            items.sort_unstable_by_key(|(image, _)| *image);
+            for (image, state) in items {
+                line(revision, "CURRENT", image, state);
+            }
+        }
+    }
+}
+
+fn line(revision: u64, kind: &str, image: &ImageRef, state: &Image) {
+    println!(
+        "{revision:>8}  {kind:<8}  {image}  {}  [{}]",
+        sbom(&state.sbom),
+        namespaces(state)
+    );
+}
+
+fn namespaces(state: &Image) -> String {
+    state
+        .pods
+        .iter()
+        .map(|pod| pod.namespace.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn sbom(state: &SbomState) -> String {
+    match state {
+        SbomState::Scheduled => "scheduled".into(),
+        SbomState::Err(err) => format!("error: {err}"),
+        SbomState::Missing => "missing".into(),
+        SbomState::Found(summary) => match &summary.quality {
+            Some(quality) => format!(
+                "found ({}, {} packages, grade {})",
+                summary.format, summary.packages, quality.grade
+            ),
+            None => format!("found ({}, {} packages)", summary.format, summary.packages),
+        },
+    }
+}
@@ -10,8 +10,13 @@ pub struct Backend {
 }
 
 impl Backend {
+    /// join a path to the URL of the backend, keeping the path the backend is served on
     pub fn join(&self, input: impl AsRef<str>) -> Result<Url, Error> {
-        Ok(self.url.join(input.as_ref())?)
+        let mut url = self.url.clone();
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Ok(url.join(input.as_ref().trim_start_matches('/'))?)
     }
 }
 
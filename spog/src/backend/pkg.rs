@@ -1,30 +1,13 @@
 use super::{Backend, Error};
-use bommer_api::data::{Image, ImageRef};
-use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
+use bommer_api::data::SbomDetails;
+
+pub use bommer_api::data::Workload;
 
 pub struct WorkloadService {
     backend: Backend,
     client: reqwest::Client,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
-pub struct Workload(pub HashMap<ImageRef, Image>);
-
-impl Deref for Workload {
-    type Target = HashMap<ImageRef, Image>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl DerefMut for Workload {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
 #[allow(unused)]
 impl WorkloadService {
     pub fn new(backend: Backend) -> Self {
@@ -34,10 +17,21 @@ impl WorkloadService {
         }
     }
 
-    pub async fn lookup(&self) -> Result<Workload, Error> {
+    /// get the full workload, or only that of a namespace
+    pub async fn lookup(&self, namespace: Option<&str>) -> Result<Workload, Error> {
+        let mut request = self.client.get(self.backend.join("api/v1/workload")?);
+        if let Some(namespace) = namespace {
+            request = request.query(&[("namespace", namespace)]);
+        }
+
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+
+    pub async fn sbom_details(&self, image: &str) -> Result<SbomDetails, Error> {
         Ok(self
             .client
-            .get(self.backend.join("/api/v1/workload")?)
+            .get(self.backend.join("api/v1/sbom/details")?)
+            .query(&[("image", image)])
             .send()
             .await?
             .error_for_status()?
@@ -0,0 +1,195 @@
+use crate::backend::Workload;
+use bommer_api::data::{Image, ImageRef, SbomState};
+use patternfly_yew::prelude::*;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
+use yew::prelude::*;
+
+const CRITICAL: &str = "Critical vulnerabilities";
+
+/// State of the SBOM lookup to filter by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumIter, EnumString)]
+pub enum SbomStateFilter {
+    Found,
+    Missing,
+    Error,
+    Scheduled,
+}
+
+impl SbomStateFilter {
+    fn matches(&self, state: &SbomState) -> bool {
+        matches!(
+            (self, state),
+            (Self::Found, SbomState::Found(_))
+                | (Self::Missing, SbomState::Missing)
+                | (Self::Error, SbomState::Err(_))
+                | (Self::Scheduled, SbomState::Scheduled)
+        )
+    }
+}
+
+/// Filters applied to the workload, client-side
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkloadFilter {
+    /// Only images used in this namespace
+    pub namespace: Option<String>,
+    /// Only images with this state of the SBOM lookup
+    pub sbom: Option<SbomStateFilter>,
+    /// Only images whose reference contains this text (case insensitive)
+    pub text: String,
+    /// Only images affected by critical vulnerabilities
+    pub critical: bool,
+}
+
+impl WorkloadFilter {
+    pub fn is_empty(&self) -> bool {
+        self.namespace.is_none() && self.sbom.is_none() && self.text.is_empty() && !self.critical
+    }
+
+    pub fn matches(&self, image: &ImageRef, state: &Image) -> bool {
+        if let Some(namespace) = &self.namespace {
+            if !state.pods.iter().any(|pod| &pod.namespace == namespace) {
+                return false;
+            }
+        }
+
+        if let Some(sbom) = &self.sbom {
+            if !sbom.matches(&state.sbom) {
+                return false;
+            }
+        }
+
+        if self.critical
+            && !state
+                .vulnerabilities
+                .as_ref()
+                .is_some_and(|vulnerabilities| vulnerabilities.critical > 0)
+        {
+            return false;
+        }
+
+        let text = self.text.trim();
+        text.is_empty()
+            || image
+                .to_string()
+                .to_lowercase()
+                .contains(&text.to_lowercase())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Properties)]
+pub struct WorkloadToolbarProperties {
+    pub workload: Rc<Workload>,
+    pub filter: WorkloadFilter,
+    pub onchange: Callback<WorkloadFilter>,
+}
+
+#[function_component(WorkloadToolbar)]
+pub fn workload_toolbar(props: &WorkloadToolbarProperties) -> Html {
+    let namespaces = use_memo(
+        |workload| {
+            workload
+                .values()
+                .flat_map(|image| image.pods.iter().map(|pod| pod.namespace.clone()))
+                .collect::<BTreeSet<_>>()
+        },
+        props.workload.clone(),
+    );
+
+    let onselect_namespace = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |namespace: String| {
+            onchange.emit(WorkloadFilter {
+                namespace: (!namespace.is_empty()).then_some(namespace),
+                ..filter.clone()
+            })
+        })
+    };
+    let onselect_sbom = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |sbom: String| {
+            onchange.emit(WorkloadFilter {
+                sbom: sbom.parse().ok(),
+                ..filter.clone()
+            })
+        })
+    };
+    let onselect_vulnerabilities = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |value: String| {
+            onchange.emit(WorkloadFilter {
+                critical: value == CRITICAL,
+                ..filter.clone()
+            })
+        })
+    };
+    let oninput_text = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |text: String| {
+            onchange.emit(WorkloadFilter {
+                text,
+                ..filter.clone()
+            })
+        })
+    };
+    let onclear = {
+        let onchange = props.onchange.clone();
+        Callback::from(move |_| onchange.emit(WorkloadFilter::default()))
+    };
+
+    html!(
+        <Toolbar>
+            <ToolbarGroup>
+                <ToolbarItem>
+                    <Select<String>
+                        placeholder={props.filter.namespace.clone().unwrap_or_else(|| "All namespaces".into())}
+                        variant={SelectVariant::Single(onselect_namespace)}
+                    >
+                        <SelectOption<String> value={String::new()} description="All namespaces" />
+                        { for namespaces.iter().map(|namespace| html_nested!(
+                            <SelectOption<String> value={namespace.clone()} />
+                        ))}
+                    </Select<String>>
+                </ToolbarItem>
+                <ToolbarItem>
+                    <Select<String>
+                        placeholder={props.filter.sbom.map(|sbom| sbom.to_string()).unwrap_or_else(|| "Any SBOM state".into())}
+                        variant={SelectVariant::Single(onselect_sbom)}
+                    >
+                        <SelectOption<String> value={String::new()} description="Any SBOM state" />
+                        { for SbomStateFilter::iter().map(|sbom| html_nested!(
+                            <SelectOption<String> value={sbom.to_string()} />
+                        ))}
+                    </Select<String>>
+                </ToolbarItem>
+                <ToolbarItem>
+                    <Select<String>
+                        placeholder={match props.filter.critical { true => CRITICAL, false => "Any vulnerabilities" }}
+                        variant={SelectVariant::Single(onselect_vulnerabilities)}
+                    >
+                        <SelectOption<String> value={String::new()} description="Any vulnerabilities" />
+                        <SelectOption<String> value={CRITICAL.to_string()} />
+                    </Select<String>>
+                </ToolbarItem>
+                <ToolbarItem>
+                    <TextInput
+                        icon={TextInputIcon::Search}
+                        placeholder="Filter by image"
+                        value={props.filter.text.clone()}
+                        oninput={oninput_text}
+                    />
+                </ToolbarItem>
+            </ToolbarGroup>
+            if !props.filter.is_empty() {
+                <ToolbarItem>
+                    <Button variant={ButtonVariant::Link} label="Clear filters" onclick={onclear} />
+                </ToolbarItem>
+            }
+        </Toolbar>
+    )
+}
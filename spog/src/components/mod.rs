@@ -1,6 +1,7 @@
 //! Re-usable component
 
 pub mod backend;
+pub mod filter;
 pub mod workload;
 
 use patternfly_yew::prelude::*;
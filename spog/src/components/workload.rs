@@ -1,15 +1,37 @@
-use bommer_api::data::{Image, ImageRef, SbomState};
+use super::filter::WorkloadFilter;
+use crate::pages::AppRoute;
+use crate::utils::encode_segment;
+use bommer_api::data::{Image, ImageRef, LookupErrorKind, SbomState};
 use itertools::Itertools;
 use patternfly_yew::prelude::*;
 use std::rc::Rc;
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 use yew::prelude::*;
+use yew_hooks::UseWebSocketReadyState;
+use yew_nested_router::prelude::*;
+
+/// choices for the number of images per page
+const LIMITS: &[usize] = &[10, 25, 50, 100];
+const DEFAULT_LIMIT: usize = 25;
+
+/// Property to sort the workload by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Display, EnumIter, EnumString)]
+pub enum SortBy {
+    #[default]
+    Image,
+    Pods,
+    #[strum(to_string = "SBOM state")]
+    SbomState,
+}
 
 #[derive(Clone, Debug, PartialEq, Properties)]
 pub struct WorkloadTableProperties {
     pub workload: Rc<crate::backend::Workload>,
+    #[prop_or_default]
+    pub filter: WorkloadFilter,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct WorkloadEntry {
     id: ImageRef,
     state: Image,
@@ -22,14 +44,95 @@ impl TableEntryRenderer for WorkloadEntry {
             1 => html!(self.state.pods.len()).into(),
             2 => match &self.state.sbom {
                 SbomState::Scheduled => html!("Retrieving…").into(),
-                SbomState::Missing => html!("Missing").into(),
-                SbomState::Err(err) => Cell::new(html!(
-                    <Tooltip text={err.to_string()}>
-                        { format!("Failed ({err})") }
-                    </Tooltip>
-                ))
-                .text_modifier(TextModifier::Truncate),
-                SbomState::Found(_) => html!("Found").into(),
+                SbomState::Missing => match &self.state.retry {
+                    Some(retry) => html!(
+                        <Tooltip text={format!("Next check: {}", retry.next.format("%H:%M:%S"))}>
+                            { "Missing" }
+                        </Tooltip>
+                    )
+                    .into(),
+                    None => html!("Missing").into(),
+                },
+                SbomState::Err(err) => {
+                    let text = match &self.state.retry {
+                        Some(retry) => format!(
+                            "{err} (attempt {}, next retry: {})",
+                            retry.attempts,
+                            retry.next.format("%H:%M:%S")
+                        ),
+                        None => err.to_string(),
+                    };
+                    let label = match (err.kind, err.status) {
+                        (LookupErrorKind::Transport, _) => "Unreachable".to_string(),
+                        (LookupErrorKind::Server, _) => "Server error".to_string(),
+                        (LookupErrorKind::UnexpectedStatus, Some(status)) => {
+                            format!("Rejected ({status})")
+                        }
+                        (LookupErrorKind::UnexpectedStatus, None) => "Rejected".to_string(),
+                        (LookupErrorKind::InvalidReference, _) => "Invalid reference".to_string(),
+                        (LookupErrorKind::InvalidSbom, _) => "Invalid SBOM".to_string(),
+                    };
+                    Cell::new(html!(
+                        <Tooltip {text}>
+                            { label }
+                        </Tooltip>
+                    ))
+                    .text_modifier(TextModifier::Truncate)
+                }
+                SbomState::Found(sbom) => {
+                    let mut text = match &sbom.version {
+                        Some(version) => format!("{} {version}: ", sbom.format),
+                        None => format!("{}: ", sbom.format),
+                    };
+                    text.push_str(&format!("{} packages", sbom.packages));
+                    if !sbom.licenses.is_empty() {
+                        text.push_str(&format!(", licenses: {}", sbom.licenses.join(", ")));
+                    }
+                    if !sbom.tools.is_empty() {
+                        text.push_str(&format!(", created by: {}", sbom.tools.join(", ")));
+                    }
+                    let target = AppRoute::Sbom {
+                        image: encode_segment(&self.id.to_string()),
+                    };
+                    html!(
+                        <Tooltip {text}>
+                            <Link<AppRoute> {target}>
+                                { format!("Found ({})", sbom.packages) }
+                            </Link<AppRoute>>
+                        </Tooltip>
+                    )
+                    .into()
+                }
+            },
+            3 => match &self.state.vulnerabilities {
+                Some(v) if v.total() == 0 => html!("None").into(),
+                Some(v) => {
+                    let text = format!(
+                        "{} critical, {} high, {} medium, {} low, {} unknown",
+                        v.critical, v.high, v.medium, v.low, v.unknown
+                    );
+                    let badges = [
+                        (v.critical, "critical", Color::Red),
+                        (v.high, "high", Color::Orange),
+                        (v.medium, "medium", Color::Gold),
+                        (v.low, "low", Color::Blue),
+                    ];
+                    html!(
+                        <Tooltip {text}>
+                            { for badges.into_iter().filter(|(count, ..)| *count > 0).map(|(count, severity, color)| html!(
+                                <>
+                                    <Label {color} compact=true label={format!("{count} {severity}")} />
+                                    { " " }
+                                </>
+                            ))}
+                            if v.total() == v.unknown {
+                                { format!("{} unknown", v.unknown) }
+                            }
+                        </Tooltip>
+                    )
+                    .into()
+                }
+                None => html!().into(),
             },
             _ => Default::default(),
         }
@@ -37,46 +140,210 @@ impl TableEntryRenderer for WorkloadEntry {
     }
 
     fn render_details(&self) -> Vec<Span> {
+        // group pods by their workload, pods without a workload are shown individually
+        let workloads = self
+            .state
+            .pods
+            .iter()
+            .filter_map(|pod| pod.workload.as_ref().map(|w| (&pod.cluster, w)))
+            .counts();
+        let pods = self.state.pods.iter().filter(|pod| pod.workload.is_none());
+
         vec![Span::max(html!(
             <ul>
-                { for self.state.pods.iter().sorted_unstable().map(| pod|{
-                    html!(<li> { &pod.namespace }  { " / " } { &pod.name} </li> )
+                { for workloads.into_iter().sorted_unstable().map(|((cluster, workload), count)|{
+                    html!(<li> { cluster_prefix(cluster) } { &workload.namespace } { " / " } { &workload.kind } { " " } { &workload.name } { format!(" ({count} pods)") } </li> )
+                })}
+                { for pods.sorted_unstable().map(| pod|{
+                    html!(<li> { cluster_prefix(&pod.cluster) } { &pod.namespace }  { " / " } { &pod.name} </li> )
                 })}
             </ul>
         ))]
     }
 }
 
+fn cluster_prefix(cluster: &Option<String>) -> String {
+    match cluster {
+        Some(cluster) => format!("{cluster} / "),
+        None => String::new(),
+    }
+}
+
 #[function_component(WorkloadTable)]
 pub fn workload_table(props: &WorkloadTableProperties) -> Html {
     let header = html_nested!(
         <TableHeader>
-            <TableColumn label="Image" width={ColumnWidth::Percent(80)} />
+            <TableColumn label="Image" width={ColumnWidth::Percent(65)} />
             <TableColumn label="Pods" width={ColumnWidth::Percent(5)}   />
             <TableColumn label="SBOM" width={ColumnWidth::Percent(10)}  />
+            <TableColumn label="Vulnerabilities" width={ColumnWidth::Percent(15)}  />
         </TableHeader>
     );
 
+    let sort = use_state_eq(SortBy::default);
+    let ascending = use_state_eq(|| true);
+    let offset = use_state_eq(|| 0usize);
+    let limit = use_state_eq(|| DEFAULT_LIMIT);
+
     let entries = use_memo(
-        |workload| {
-            let mut entries = SharedTableModel::with_capacity(workload.0.len());
-            for (k, v) in workload.0.iter().sorted_unstable_by_key(|(k, _)| *k) {
-                entries.push(WorkloadEntry {
+        |(workload, filter, sort, ascending)| {
+            let mut entries = workload
+                .0
+                .iter()
+                .filter(|(k, v)| filter.matches(k, v))
+                .collect::<Vec<_>>();
+
+            // ties are broken by the image reference, to keep a stable order
+            match sort {
+                SortBy::Image => entries.sort_unstable_by_key(|(k, _)| *k),
+                SortBy::Pods => entries.sort_unstable_by_key(|(k, v)| (v.pods.len(), *k)),
+                SortBy::SbomState => entries.sort_unstable_by_key(|(k, v)| (rank(&v.sbom), *k)),
+            }
+            if !ascending {
+                entries.reverse();
+            }
+
+            entries
+                .into_iter()
+                .map(|(k, v)| WorkloadEntry {
                     id: k.clone(),
                     state: v.clone(),
                 })
-            }
-            entries
+                .collect::<Vec<_>>()
         },
-        props.workload.clone(),
+        (
+            props.workload.clone(),
+            props.filter.clone(),
+            *sort,
+            *ascending,
+        ),
     );
 
+    let total = entries.len();
+    // the workload might have shrunk, or the filter changed, since selecting the page
+    let offset_value = match *offset < total {
+        true => *offset,
+        false => total.saturating_sub(1) / *limit * *limit,
+    };
+
+    let page = {
+        let mut page = SharedTableModel::with_capacity(*limit);
+        for entry in entries.iter().skip(offset_value).take(*limit) {
+            page.push(entry.clone());
+        }
+        page
+    };
+
+    let onselect_sort = {
+        let sort = sort.clone();
+        Callback::from(move |value: String| {
+            if let Ok(value) = value.parse() {
+                sort.set(value);
+            }
+        })
+    };
+    let ontoggle_order = {
+        let ascending = ascending.clone();
+        Callback::from(move |_| ascending.set(!*ascending))
+    };
+
+    let onnavigation = {
+        let offset = offset.clone();
+        let limit = *limit;
+        Callback::from(move |nav: Navigation| {
+            let last = total.saturating_sub(1) / limit * limit;
+            let value = match nav {
+                Navigation::First => 0,
+                Navigation::Previous => offset_value.saturating_sub(limit),
+                Navigation::Next => (offset_value + limit).min(last),
+                Navigation::Last => last,
+                Navigation::Page(page) => (page * limit).min(last),
+            };
+            offset.set(value);
+        })
+    };
+    let onlimit = {
+        let offset = offset.clone();
+        let limit = limit.clone();
+        Callback::from(move |value: usize| {
+            // keep the first entry of the current page visible
+            offset.set(offset_value / value * value);
+            limit.set(value);
+        })
+    };
+
     html!(
-        <Table<SharedTableModel<WorkloadEntry>>
-            {header}
-            grid={TableGridMode::Medium}
-            entries={(*entries).clone()}
-            mode={TableMode::CompactExpandable}
-        />
+        <>
+            <Toolbar>
+                <ToolbarGroup>
+                    <ToolbarItem>
+                        <Select<String>
+                            placeholder={format!("Sort by: {}", *sort)}
+                            variant={SelectVariant::Single(onselect_sort)}
+                        >
+                            { for SortBy::iter().map(|sort| html_nested!(
+                                <SelectOption<String> value={sort.to_string()} />
+                            ))}
+                        </Select<String>>
+                    </ToolbarItem>
+                    <ToolbarItem>
+                        <Button
+                            variant={ButtonVariant::Plain}
+                            icon={match *ascending { true => Icon::AngleUp, false => Icon::AngleDown }}
+                            onclick={ontoggle_order}
+                        />
+                    </ToolbarItem>
+                </ToolbarGroup>
+                <ToolbarItem r#type={ToolbarItemType::Pagination}>
+                    <Pagination
+                        total_entries={Some(total)}
+                        offset={offset_value}
+                        entries_per_page_choices={LIMITS.to_vec()}
+                        selected_choice={*limit}
+                        {onnavigation}
+                        {onlimit}
+                    />
+                </ToolbarItem>
+            </Toolbar>
+
+            <Table<SharedTableModel<WorkloadEntry>>
+                {header}
+                grid={TableGridMode::Medium}
+                entries={page}
+                mode={TableMode::CompactExpandable}
+            />
+        </>
     )
 }
+
+/// the order of SBOM states, following their lifecycle
+fn rank(state: &SbomState) -> u8 {
+    match state {
+        SbomState::Scheduled => 0,
+        SbomState::Err(_) => 1,
+        SbomState::Missing => 2,
+        SbomState::Found(_) => 3,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Properties)]
+pub struct ConnectionStatusProperties {
+    pub state: UseWebSocketReadyState,
+    /// Number of failed attempts to (re-)connect
+    pub attempts: u32,
+}
+
+/// The state of the connection streaming the workload
+#[function_component(ConnectionStatus)]
+pub fn connection_status(props: &ConnectionStatusProperties) -> Html {
+    match (&props.state, props.attempts) {
+        (UseWebSocketReadyState::Open, _) => html!(<Label color={Color::Green} label="Live" />),
+        (UseWebSocketReadyState::Connecting, 0) => {
+            html!(<Label color={Color::Grey} label="Connecting…" />)
+        }
+        (_, 0) => html!(<Label color={Color::Red} label="Disconnected" />),
+        (_, attempts) => html!(
+            <Label color={Color::Orange} label={format!("Reconnecting (attempt {attempts})…")} />
+        ),
+    }
+}
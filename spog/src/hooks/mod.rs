@@ -1,3 +1,7 @@
+mod workload;
+
+pub use workload::*;
+
 use crate::backend::Backend;
 use std::rc::Rc;
 use yew::prelude::*;
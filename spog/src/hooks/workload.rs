@@ -0,0 +1,173 @@
+use crate::backend::{self, IntoWs, WorkloadService};
+use crate::hooks::use_backend;
+use bommer_api::codec::{Encoding, Frame};
+use bommer_api::data::{Image, ImageRef, RevisionedEvent};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use yew::platform::{spawn_local, time::sleep};
+use yew::prelude::*;
+use yew_hooks::{use_websocket_with_options, UseWebSocketOptions, UseWebSocketReadyState};
+
+/// delay before the first attempt to reconnect, doubled with each failed attempt
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The current workload, along with the state of the stream providing it
+#[derive(Clone, Debug, PartialEq)]
+pub struct UseWorkload {
+    pub workload: Rc<backend::Workload>,
+    pub ready_state: UseWebSocketReadyState,
+    /// Number of failed attempts to (re-)connect
+    pub attempts: u32,
+}
+
+/// Stream the workload (of a namespace, if not empty) from the backend, reconnecting with an
+/// increasing delay when the connection drops.
+#[hook]
+pub fn use_workload(namespace: &str) -> UseWorkload {
+    let backend = use_backend();
+
+    let ws = use_websocket_with_options(
+        backend
+            .join(match namespace.is_empty() {
+                true => "api/v1/workload_stream?batch=true".to_string(),
+                false => format!("api/v1/workload_stream/{}?batch=true", namespace),
+            })
+            .unwrap()
+            .into_ws()
+            .to_string(),
+        UseWebSocketOptions {
+            // we reconnect ourselves, backing off
+            reconnect_limit: Some(0),
+            // MessagePack is a lot cheaper to decode for large snapshots, JSON if not supported
+            protocols: Some(
+                Encoding::ALL
+                    .iter()
+                    .map(|encoding| encoding.protocol().to_string())
+                    .collect(),
+            ),
+            ..Default::default()
+        },
+    );
+
+    let workload = use_state(|| Rc::new(backend::Workload::default()));
+    // number of failed attempts to (re-)connect
+    let attempts = use_state_eq(|| 0u32);
+    // if we received a message since the last (re-)connect
+    let received = use_mut_ref(|| false);
+    // the websocket starts out closed, before connecting for the first time
+    let initial = use_mut_ref(|| true);
+
+    {
+        let ws = ws.clone();
+        let attempts = attempts.clone();
+        let received = received.clone();
+        let workload = workload.clone();
+        let backend = backend.clone();
+        let namespace = namespace.to_string();
+        use_effect_with_deps(
+            move |state| {
+                let initial = std::mem::take(&mut *initial.borrow_mut());
+                match **state {
+                    UseWebSocketReadyState::Closed if initial => {}
+                    UseWebSocketReadyState::Closed => {
+                        let delay = backoff(*attempts);
+                        log::info!("Connection lost, reconnecting in {delay:?}");
+                        attempts.set(*attempts + 1);
+                        spawn_local(async move {
+                            sleep(delay).await;
+                            ws.open();
+                        });
+                    }
+                    UseWebSocketReadyState::Open => {
+                        *received.borrow_mut() = false;
+                        if *attempts > 0 {
+                            attempts.set(0);
+                            // we might have missed changes while being disconnected
+                            spawn_local(async move {
+                                let service = WorkloadService::new((*backend).clone());
+                                let namespace =
+                                    (!namespace.is_empty()).then_some(namespace.as_str());
+                                match service.lookup(namespace).await {
+                                    // the stream might already have caught up, which takes precedence
+                                    Ok(state) if !*received.borrow() => {
+                                        workload.set(Rc::new(state))
+                                    }
+                                    Ok(_) => {}
+                                    Err(err) => log::warn!("Failed to refresh workload: {err}"),
+                                }
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+
+                || ()
+            },
+            ws.ready_state.clone(),
+        );
+    }
+
+    {
+        let workload = workload.clone();
+        let received = received.clone();
+        use_effect_with_deps(
+            move |message| {
+                if let Some(message) = &**message {
+                    apply(&workload, &received, Frame::Text(message.clone()));
+                }
+
+                || ()
+            },
+            ws.message,
+        )
+    };
+
+    {
+        let workload = workload.clone();
+        let received = received.clone();
+        use_effect_with_deps(
+            move |message| {
+                if let Some(message) = &**message {
+                    apply(&workload, &received, Frame::Binary(message.clone()));
+                }
+
+                || ()
+            },
+            ws.message_bytes,
+        )
+    };
+
+    UseWorkload {
+        workload: (*workload).clone(),
+        ready_state: (*ws.ready_state).clone(),
+        attempts: *attempts,
+    }
+}
+
+/// apply a batch of events, encoded as negotiated when connecting
+fn apply(
+    workload: &UseStateHandle<Rc<backend::Workload>>,
+    received: &Rc<RefCell<bool>>,
+    frame: Frame,
+) {
+    *received.borrow_mut() = true;
+    // apply the whole batch before rendering, rather than each event on its own
+    match frame.decode::<Vec<RevisionedEvent<ImageRef, Image>>>() {
+        Ok(events) => {
+            let mut state = (**workload).clone();
+            state.apply_all(events.into_iter().map(|evt| evt.event));
+            workload.set(Rc::new(state));
+        }
+        Err(err) => log::warn!("Failed to decode workload events: {err}"),
+    }
+}
+
+/// the delay before the next attempt to reconnect
+fn backoff(attempts: u32) -> Duration {
+    INITIAL_DELAY
+        .checked_mul(2u32.saturating_pow(attempts))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY)
+}
@@ -0,0 +1,252 @@
+use crate::backend::Workload;
+use crate::components::workload::ConnectionStatus;
+use crate::hooks::{use_workload, UseWorkload};
+use bommer_api::data::{ImageRef, SbomState};
+use patternfly_yew::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use yew::prelude::*;
+
+/// Number of images by the state of their SBOM
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub images: usize,
+    pub found: usize,
+    pub missing: usize,
+    pub failed: usize,
+    pub scheduled: usize,
+}
+
+impl Coverage {
+    fn add(&mut self, state: &SbomState) {
+        self.images += 1;
+        match state {
+            SbomState::Found(_) => self.found += 1,
+            SbomState::Missing => self.missing += 1,
+            SbomState::Err(_) => self.failed += 1,
+            SbomState::Scheduled => self.scheduled += 1,
+        }
+    }
+
+    /// share of the images with an SBOM (in percent), not counting the ones still scheduled
+    fn percent(&self) -> Option<f64> {
+        match self.images - self.scheduled {
+            0 => None,
+            checked => Some(self.found as f64 * 100.0 / checked as f64),
+        }
+    }
+}
+
+/// Statistics of the workload
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Statistics {
+    total: Coverage,
+    namespaces: BTreeMap<String, Coverage>,
+    /// images for which the lookup failed, along with the error
+    failures: Vec<(ImageRef, String)>,
+}
+
+impl Statistics {
+    fn new(workload: &Workload) -> Self {
+        let mut result = Self::default();
+
+        for (image, state) in workload.iter() {
+            result.total.add(&state.sbom);
+
+            let namespaces = state
+                .pods
+                .iter()
+                .map(|pod| &pod.namespace)
+                .collect::<BTreeSet<_>>();
+            for namespace in namespaces {
+                result
+                    .namespaces
+                    .entry(namespace.clone())
+                    .or_default()
+                    .add(&state.sbom);
+            }
+
+            if let SbomState::Err(err) = &state.sbom {
+                result.failures.push((image.clone(), err.to_string()));
+            }
+        }
+
+        result.failures.sort_unstable();
+        result
+    }
+}
+
+#[function_component(Dashboard)]
+pub fn dashboard() -> Html {
+    let UseWorkload {
+        workload,
+        ready_state,
+        attempts,
+    } = use_workload("");
+
+    let statistics = use_memo(|workload| Statistics::new(workload), workload);
+    let total = &statistics.total;
+
+    let header = html_nested!(
+        <TableHeader>
+            <TableColumn label="Namespace" width={ColumnWidth::Percent(30)} />
+            <TableColumn label="Images" width={ColumnWidth::Percent(10)} />
+            <TableColumn label="Coverage" width={ColumnWidth::Percent(40)} />
+            <TableColumn label="Missing" width={ColumnWidth::Percent(10)} />
+            <TableColumn label="Failed" width={ColumnWidth::Percent(10)} />
+        </TableHeader>
+    );
+
+    let entries = use_memo(
+        |statistics| {
+            let mut entries = SharedTableModel::with_capacity(statistics.namespaces.len());
+            for (namespace, coverage) in &statistics.namespaces {
+                entries.push(NamespaceEntry {
+                    namespace: namespace.clone(),
+                    coverage: coverage.clone(),
+                });
+            }
+            entries
+        },
+        statistics.clone(),
+    );
+
+    html!(
+        <>
+            <PageSection
+                variant={PageSectionVariant::Light}
+                shadow={PageSectionShadow::Bottom}
+                fill=false
+            >
+                <Content>
+                    <Title level={Level::H1}>{"Dashboard"}</Title>
+                    <p><ConnectionStatus state={ready_state} {attempts} /></p>
+                </Content>
+            </PageSection>
+
+            <PageSection variant={PageSectionVariant::Default} fill=true>
+                <Grid gutter=true>
+                    <GridItem cols={[3]}>
+                        <Card title={html!("Images")}>
+                            <Title size={Size::XXXXLarge}>{ total.images }</Title>
+                            { format!("{} still being looked up", total.scheduled) }
+                        </Card>
+                    </GridItem>
+                    <GridItem cols={[3]}>
+                        <Card title={html!("SBOM coverage")}>
+                            <Title size={Size::XXXXLarge}>{ percent(total) }</Title>
+                            <CoverageBar coverage={total.clone()} />
+                        </Card>
+                    </GridItem>
+                    <GridItem cols={[3]}>
+                        <Card title={html!("Missing SBOMs")}>
+                            <Title size={Size::XXXXLarge}>{ total.missing }</Title>
+                        </Card>
+                    </GridItem>
+                    <GridItem cols={[3]}>
+                        <Card title={html!("Scan failures")}>
+                            <Title size={Size::XXXXLarge}>{ total.failed }</Title>
+                        </Card>
+                    </GridItem>
+
+                    <GridItem cols={[8]}>
+                        <Card title={html!("Namespaces")}>
+                            <Table<SharedTableModel<NamespaceEntry>>
+                                {header}
+                                grid={TableGridMode::Medium}
+                                entries={(*entries).clone()}
+                                mode={TableMode::Compact}
+                            />
+                        </Card>
+                    </GridItem>
+                    <GridItem cols={[4]}>
+                        <Card title={html!("Scan failures")}>
+                            if statistics.failures.is_empty() {
+                                { "None" }
+                            } else {
+                                <List>
+                                    { for statistics.failures.iter().map(|(image, err)| html!(
+                                        <Tooltip text={err.clone()}>
+                                            { image.to_string() }
+                                        </Tooltip>
+                                    ))}
+                                </List>
+                            }
+                        </Card>
+                    </GridItem>
+                </Grid>
+            </PageSection>
+        </>
+    )
+}
+
+fn percent(coverage: &Coverage) -> String {
+    match coverage.percent() {
+        Some(percent) => format!("{percent:.1}%"),
+        None => "n/a".to_string(),
+    }
+}
+
+#[derive(PartialEq)]
+pub struct NamespaceEntry {
+    namespace: String,
+    coverage: Coverage,
+}
+
+impl TableEntryRenderer for NamespaceEntry {
+    fn render_cell(&self, context: &CellContext) -> Cell {
+        match context.column {
+            0 => html!(&self.namespace).into(),
+            1 => html!(self.coverage.images).into(),
+            2 => html!(
+                <>
+                    { percent(&self.coverage) }
+                    <CoverageBar coverage={self.coverage.clone()} />
+                </>
+            )
+            .into(),
+            3 => html!(self.coverage.missing).into(),
+            4 => html!(self.coverage.failed).into(),
+            _ => Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Properties)]
+pub struct CoverageBarProperties {
+    pub coverage: Coverage,
+}
+
+/// A bar, showing the share of images by the state of their SBOM
+#[function_component(CoverageBar)]
+pub fn coverage_bar(props: &CoverageBarProperties) -> Html {
+    let coverage = &props.coverage;
+    if coverage.images == 0 {
+        return html!();
+    }
+
+    let segments = [
+        (coverage.found, "--pf-global--success-color--100", "Found"),
+        (
+            coverage.missing,
+            "--pf-global--warning-color--100",
+            "Missing",
+        ),
+        (coverage.failed, "--pf-global--danger-color--100", "Failed"),
+        (
+            coverage.scheduled,
+            "--pf-global--disabled-color--200",
+            "Scheduled",
+        ),
+    ];
+
+    html!(
+        <div class="spog-coverage-bar">
+            { for segments.into_iter().filter(|(count, ..)| *count > 0).map(|(count, color, label)| html!(
+                <div
+                    title={format!("{label}: {count}")}
+                    style={format!("flex-grow: {count}; background-color: var({color});")}
+                />
+            ))}
+        </div>
+    )
+}
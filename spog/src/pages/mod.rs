@@ -2,17 +2,26 @@
 
 use yew_nested_router::Target;
 
+mod dashboard;
 mod index;
+mod sbom;
 mod workload;
 
+pub use dashboard::*;
 pub use index::*;
+pub use sbom::*;
 pub use workload::*;
 
 #[derive(Clone, Debug, PartialEq, Eq, Target)]
 pub enum AppRoute {
     #[target(index)]
     Index,
+    Dashboard,
     ByNamespace {
         namespace: String,
     },
+    /// The SBOM of an image, the reference being percent-encoded
+    Sbom {
+        image: String,
+    },
 }
@@ -0,0 +1,144 @@
+use crate::backend::WorkloadService;
+use crate::components::remote_content;
+use crate::hooks::use_backend;
+use crate::utils::RenderOptional;
+use bommer_api::data::{SbomDetails, SbomPackage};
+use patternfly_yew::prelude::*;
+use yew::prelude::*;
+use yew_more_hooks::hooks::r#async::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, Properties)]
+pub struct SbomProperties {
+    /// The image reference
+    pub image: String,
+}
+
+#[function_component(Sbom)]
+pub fn sbom(props: &SbomProperties) -> Html {
+    let backend = use_backend();
+
+    let fetch = {
+        let backend = backend.clone();
+        use_async_with_cloned_deps(
+            |image| async move {
+                WorkloadService::new((*backend).clone())
+                    .sbom_details(&image)
+                    .await
+            },
+            props.image.clone(),
+        )
+    };
+
+    html!(
+        <>
+            <PageSection
+                variant={PageSectionVariant::Light}
+                shadow={PageSectionShadow::Bottom}
+                fill=false
+            >
+                <Content>
+                    <Title level={Level::H1}>{"SBOM"}</Title>
+                    <p>{ &props.image }</p>
+                </Content>
+            </PageSection>
+
+            <PageSection variant={PageSectionVariant::Default} fill=true>
+                { remote_content(&fetch, |details| html!(<SbomDetailsView details={details.clone()}/>)) }
+            </PageSection>
+        </>
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Properties)]
+pub struct SbomDetailsViewProperties {
+    pub details: SbomDetails,
+}
+
+#[function_component(SbomDetailsView)]
+fn sbom_details_view(props: &SbomDetailsViewProperties) -> Html {
+    let summary = &props.details.summary;
+
+    let header = html_nested!(
+        <TableHeader>
+            <TableColumn label="Name" width={ColumnWidth::Percent(30)} />
+            <TableColumn label="Version" width={ColumnWidth::Percent(15)} />
+            <TableColumn label="Licenses" width={ColumnWidth::Percent(20)} />
+            <TableColumn label="Package URL" width={ColumnWidth::Percent(35)} />
+        </TableHeader>
+    );
+
+    let entries = use_memo(
+        |packages| {
+            let mut packages = packages.clone();
+            // top-level packages first
+            packages.sort_by(|a, b| {
+                (!a.top_level, &a.name, &a.version).cmp(&(!b.top_level, &b.name, &b.version))
+            });
+
+            let mut entries = SharedTableModel::with_capacity(packages.len());
+            for package in packages {
+                entries.push(PackageEntry(package));
+            }
+            entries
+        },
+        props.details.packages.clone(),
+    );
+
+    html!(
+        <Grid gutter=true>
+            <GridItem cols={[12]}>
+                <Card title={html!("Document")}>
+                    <DescriptionList>
+                        <DescriptionGroup term="Format">
+                            { match &summary.version {
+                                Some(version) => format!("{} ({version})", summary.format),
+                                None => summary.format.to_string(),
+                            } }
+                        </DescriptionGroup>
+                        <DescriptionGroup term="Name">
+                            { summary.name.clone().or_none() }
+                        </DescriptionGroup>
+                        <DescriptionGroup term="Created">
+                            { summary.created.map(|created| created.to_rfc3339()).or_none() }
+                        </DescriptionGroup>
+                        <DescriptionGroup term="Created by">
+                            { (!summary.tools.is_empty()).then(|| summary.tools.join(", ")).or_none() }
+                        </DescriptionGroup>
+                        <DescriptionGroup term="Licenses">
+                            { (!summary.licenses.is_empty()).then(|| summary.licenses.join(", ")).or_none() }
+                        </DescriptionGroup>
+                        <DescriptionGroup term="Packages">
+                            { summary.packages }
+                        </DescriptionGroup>
+                    </DescriptionList>
+                </Card>
+            </GridItem>
+            <GridItem cols={[12]}>
+                <Table<SharedTableModel<PackageEntry>>
+                    {header}
+                    grid={TableGridMode::Medium}
+                    entries={(*entries).clone()}
+                    mode={TableMode::Compact}
+                />
+            </GridItem>
+        </Grid>
+    )
+}
+
+#[derive(PartialEq)]
+pub struct PackageEntry(SbomPackage);
+
+impl TableEntryRenderer for PackageEntry {
+    fn render_cell(&self, context: &CellContext) -> Cell {
+        match context.column {
+            0 => match self.0.top_level {
+                true => html!(<strong>{ &self.0.name }</strong>).into(),
+                false => html!(&self.0.name).into(),
+            },
+            1 => self.0.version.clone().or_none().into(),
+            2 => html!(self.0.licenses.join(", ")).into(),
+            3 => Cell::new(self.0.purl.clone().or_none()).text_modifier(TextModifier::Truncate),
+            _ => Default::default(),
+        }
+    }
+}
@@ -1,11 +1,10 @@
-use crate::backend::{self, IntoWs, WorkloadService};
-use crate::components::{remote_content, workload::WorkloadTable};
-use crate::hooks::use_backend;
-use bommer_api::data::{Event, Image, ImageRef};
+use crate::components::{
+    filter::{WorkloadFilter, WorkloadToolbar},
+    workload::{ConnectionStatus, WorkloadTable},
+};
+use crate::hooks::{use_workload, UseWorkload};
 use patternfly_yew::prelude::*;
-use std::rc::Rc;
 use yew::prelude::*;
-use yew_hooks::use_websocket;
 
 #[derive(Clone, Debug, PartialEq, Eq, Properties)]
 pub struct WorkloadProperties {
@@ -15,50 +14,14 @@ pub struct WorkloadProperties {
 
 #[function_component(Workload)]
 pub fn workload(props: &WorkloadProperties) -> Html {
-    let backend = use_backend();
+    let UseWorkload {
+        workload,
+        ready_state,
+        attempts,
+    } = use_workload(&props.namespace);
 
-    let ws = use_websocket(
-        backend
-            .join(match props.namespace.is_empty() {
-                true => "/api/v1/workload_stream".to_string(),
-                false => format!("/api/v1/workload_stream/{}", props.namespace),
-            })
-            .unwrap()
-            .into_ws()
-            .to_string(),
-    );
-
-    let workload = use_state(|| Rc::new(backend::Workload::default()));
-
-    {
-        let workload = workload.clone();
-        use_effect_with_deps(
-            move |message| {
-                if let Some(message) = &**message {
-                    if let Ok(evt) = serde_json::from_str::<Event<ImageRef, Image>>(&message) {
-                        match evt {
-                            Event::Added(image, state) | Event::Modified(image, state) => {
-                                let mut s = (**workload).clone();
-                                s.insert(image, state);
-                                workload.set(Rc::new(s));
-                            }
-                            Event::Removed(image) => {
-                                let mut s = (**workload).clone();
-                                s.remove(&image);
-                                workload.set(Rc::new(s));
-                            }
-                            Event::Restart(state) => {
-                                workload.set(Rc::new(backend::Workload(state)));
-                            }
-                        }
-                    }
-                }
-
-                || ()
-            },
-            ws.message,
-        )
-    };
+    let filter = use_state_eq(WorkloadFilter::default);
+    let onchange_filter = use_callback(|value, filter| filter.set(value), filter.clone());
 
     html!(
         <>
@@ -69,11 +32,13 @@ pub fn workload(props: &WorkloadProperties) -> Html {
             >
                 <Content>
                     <Title level={Level::H1}>{"Discovered Workload"}</Title>
+                    <p><ConnectionStatus state={ready_state} {attempts} /></p>
                 </Content>
             </PageSection>
 
             <PageSection variant={PageSectionVariant::Default} fill=true>
-                <WorkloadTable workload={(*workload).clone()} />
+                <WorkloadToolbar workload={workload.clone()} filter={(*filter).clone()} onchange={onchange_filter} />
+                <WorkloadTable {workload} filter={(*filter).clone()} />
             </PageSection>
 
         </>
@@ -1,3 +1,4 @@
+use url::form_urlencoded;
 use yew::prelude::*;
 
 pub trait RenderOptional: Sized {
@@ -26,3 +27,16 @@ where
         }
     }
 }
+
+/// encode a value (like an image reference) for use as a single segment of a route
+pub fn encode_segment(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// decode a segment of a route, created by [`encode_segment`]
+pub fn decode_segment(value: &str) -> String {
+    form_urlencoded::parse(format!("v={value}").as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,227 @@
+use super::{AgentUpdate, OwnerImages};
+use crate::bombastic::{TokenError, TokenProvider};
+use crate::http;
+use crate::store::{ImageOwner, Store};
+use bommer_api::data::ImageRef;
+use reqwest::StatusCode;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, info, warn};
+use url::{ParseError, Url};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Agent")]
+pub struct AgentConfig {
+    /// Run as an agent, pushing the workload to the aggregator at this URL instead of scanning it
+    #[arg(long, env = "AGGREGATOR_URL", requires = "agent_cluster")]
+    pub aggregator_url: Option<Url>,
+
+    /// The name of the cluster, as reported by the aggregator
+    #[arg(long, env = "AGENT_CLUSTER")]
+    pub agent_cluster: Option<String>,
+
+    /// Interval of pushing the changes of the workload to the aggregator
+    #[arg(long, env = "AGENT_PUSH_INTERVAL", default_value = "5s", value_parser = humantime::parse_duration)]
+    pub agent_push_interval: Duration,
+
+    /// Interval of pushing the full workload, instead of only the changes
+    #[arg(long, env = "AGENT_RESYNC_INTERVAL", default_value = "10m", value_parser = humantime::parse_duration)]
+    pub agent_resync_interval: Duration,
+
+    /// A static bearer token to authenticate with the aggregator
+    #[arg(long, env = "AGENT_TOKEN", conflicts_with = "agent_oidc_issuer_url")]
+    pub agent_token: Option<String>,
+
+    /// Issuer URL of the OIDC provider, used to request tokens for the aggregator using the client credentials flow
+    #[arg(
+        long,
+        env = "AGENT_OIDC_ISSUER_URL",
+        requires_all = ["agent_oidc_client_id", "agent_oidc_client_secret"]
+    )]
+    pub agent_oidc_issuer_url: Option<Url>,
+
+    /// The OIDC client ID of the agent
+    #[arg(long, env = "AGENT_OIDC_CLIENT_ID")]
+    pub agent_oidc_client_id: Option<String>,
+
+    /// The OIDC client secret of the agent
+    #[arg(long, env = "AGENT_OIDC_CLIENT_SECRET")]
+    pub agent_oidc_client_secret: Option<String>,
+}
+
+impl AgentConfig {
+    /// create the agent, if running as one
+    pub async fn agent(self, client: reqwest::Client) -> anyhow::Result<Option<Agent>> {
+        let (Some(url), Some(cluster)) = (self.aggregator_url.clone(), self.agent_cluster.clone())
+        else {
+            return Ok(None);
+        };
+
+        let tokens = TokenProvider::with_credentials(
+            self.agent_token.clone(),
+            self.agent_oidc_issuer_url.clone(),
+            self.agent_oidc_client_id.clone(),
+            self.agent_oidc_client_secret.clone(),
+            client.clone(),
+        )
+        .await?;
+
+        Ok(Some(Agent {
+            url,
+            cluster,
+            config: self,
+            tokens,
+            client,
+        }))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to build URL: {0}")]
+    Url(#[from] ParseError),
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Failed to acquire token: {0}")]
+    Token(#[from] TokenError),
+    #[error("The aggregator requires a full update")]
+    OutOfSequence,
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+}
+
+/// Pushes the workload of the cluster to the aggregator
+pub struct Agent {
+    url: Url,
+    cluster: String,
+    config: AgentConfig,
+    tokens: TokenProvider,
+    client: reqwest::Client,
+}
+
+impl Agent {
+    /// push the owners of the store periodically, once the watchers have synced
+    ///
+    /// The first update, as well as the first one after a failed push, carries the full workload.
+    /// Others only carry what changed since the previous one, if anything.
+    pub async fn run(self, store: Store<ImageRef, ImageOwner, ()>) -> anyhow::Result<()> {
+        let session = uuid::Uuid::new_v4().to_string();
+        let mut sequence = 0;
+        // the state the aggregator has, `None` if unknown
+        let mut pushed: Option<HashMap<ImageOwner, HashSet<ImageRef>>> = None;
+        let mut last_full = Instant::now();
+
+        let mut interval = tokio::time::interval(self.config.agent_push_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        info!(url = %self.url, cluster = self.cluster, "Pushing workload to aggregator");
+
+        loop {
+            interval.tick().await;
+
+            // until synced, we only have a partial view of the workload
+            if !store.sync_state().is_synced() {
+                continue;
+            }
+
+            let current = store.owners().await;
+            let (full, owners, removed) = match &pushed {
+                Some(pushed) if last_full.elapsed() < self.config.agent_resync_interval => {
+                    let (owners, removed) = changes(pushed, &current);
+                    if owners.is_empty() && removed.is_empty() {
+                        continue;
+                    }
+                    (false, owners, removed)
+                }
+                _ => (
+                    true,
+                    current
+                        .iter()
+                        .map(|(owner, images)| OwnerImages {
+                            owner: owner.clone(),
+                            images: images.clone(),
+                        })
+                        .collect(),
+                    vec![],
+                ),
+            };
+
+            sequence += 1;
+            let update = AgentUpdate {
+                cluster: self.cluster.clone(),
+                session: session.clone(),
+                sequence,
+                full,
+                owners,
+                removed,
+            };
+
+            debug!(
+                sequence,
+                full,
+                owners = update.owners.len(),
+                removed = update.removed.len(),
+                "Pushing update"
+            );
+
+            match self.push(&update).await {
+                Ok(()) => {
+                    if full {
+                        last_full = Instant::now();
+                    }
+                    pushed = Some(current);
+                }
+                Err(Error::OutOfSequence) => {
+                    info!("Aggregator lost track of the workload, pushing it in full");
+                    pushed = None;
+                }
+                Err(err) => {
+                    warn!("Failed to push workload to aggregator: {err}");
+                    pushed = None;
+                }
+            }
+        }
+    }
+
+    async fn push(&self, update: &AgentUpdate) -> Result<(), Error> {
+        let mut request = self
+            .client
+            .post(http::join(&self.url, "api/v1/agents/updates")?)
+            .json(update);
+
+        if let Some(token) = self.tokens.token().await? {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::CONFLICT => Err(Error::OutOfSequence),
+            status => Err(Error::UnexpectedStatus(status)),
+        }
+    }
+}
+
+/// the owners which were added or modified, and those which are gone
+fn changes(
+    pushed: &HashMap<ImageOwner, HashSet<ImageRef>>,
+    current: &HashMap<ImageOwner, HashSet<ImageRef>>,
+) -> (Vec<OwnerImages>, Vec<ImageOwner>) {
+    let owners = current
+        .iter()
+        .filter(|(owner, images)| pushed.get(owner) != Some(images))
+        .map(|(owner, images)| OwnerImages {
+            owner: owner.clone(),
+            images: images.clone(),
+        })
+        .collect();
+    let removed = pushed
+        .keys()
+        .filter(|owner| !current.contains_key(owner))
+        .cloned()
+        .collect();
+
+    (owners, removed)
+}
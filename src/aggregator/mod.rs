@@ -0,0 +1,300 @@
+//! Running bommer as agents in each cluster, pushing their workload to a central aggregator.
+//!
+//! Agents only watch their cluster, and push the images used by its pods (the owners of the
+//! store) to the aggregator. The aggregator merges the state of all clusters into its own store,
+//! and takes care of scanning and serving the API.
+
+mod agent;
+
+pub use agent::AgentConfig;
+
+use crate::http;
+use crate::store::{ImageOwner, Store};
+use bommer_api::data::ImageRef;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Aggregator")]
+pub struct AggregatorConfig {
+    /// Accept the workload of clusters pushed by agents, instead of watching a cluster
+    #[arg(long, env = "AGGREGATOR", conflicts_with = "aggregator_url")]
+    pub aggregator: bool,
+
+    /// Maximum size of an update pushed by an agent
+    #[arg(long, env = "MAX_AGENT_UPDATE_SIZE", default_value = "64MiB", value_parser = http::parse_size)]
+    pub max_agent_update_size: u64,
+
+    /// Agents allowed to push updates, along with the cluster, as `<identity>=<cluster>` (e.g. `token:east=east`)
+    #[arg(
+        long = "agent",
+        env = "AGENTS",
+        value_name = "IDENTITY=CLUSTER",
+        value_delimiter = ',',
+        value_parser = parse_agent
+    )]
+    pub agents: Vec<(String, String)>,
+}
+
+/// parse the binding of an agent identity to a cluster
+fn parse_agent(value: &str) -> Result<(String, String), String> {
+    match value.rsplit_once('=') {
+        Some((identity, cluster)) if !identity.is_empty() && !cluster.is_empty() => {
+            Ok((identity.to_string(), cluster.to_string()))
+        }
+        _ => Err(format!(
+            "Expected <identity>=<cluster>, e.g. token:east=east, got: {value}"
+        )),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing cluster name")]
+    MissingCluster,
+    #[error("Update {sequence} of session {session} doesn't follow the previous one")]
+    OutOfSequence { session: String, sequence: u64 },
+}
+
+/// An update of the workload of a cluster, pushed by an agent
+///
+/// An update either carries the full workload of the cluster, or the changes since the previous
+/// update of the same session. An agent starts a new session whenever it starts.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentUpdate {
+    /// The name of the cluster, which all owners get assigned to
+    pub cluster: String,
+    pub session: String,
+    /// The number of the update within its session
+    pub sequence: u64,
+    /// The update replaces the workload of the cluster, instead of modifying it
+    #[serde(default)]
+    pub full: bool,
+    /// Owners which were added or modified, along with their images
+    #[serde(default)]
+    pub owners: Vec<OwnerImages>,
+    /// Owners which are gone
+    #[serde(default)]
+    pub removed: Vec<ImageOwner>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerImages {
+    pub owner: ImageOwner,
+    pub images: HashSet<ImageRef>,
+}
+
+/// Merges the updates pushed by agents into the store
+#[derive(Clone)]
+pub struct Aggregator {
+    store: Store<ImageRef, ImageOwner, ()>,
+    max_update_size: u64,
+    /// The clusters each agent is allowed to push, by its identity
+    agents: Arc<HashMap<String, HashSet<String>>>,
+    /// The clusters of all agents, which need to push their full workload before being synced
+    clusters: Arc<HashSet<String>>,
+    /// The session and sequence of the last update, by cluster
+    sessions: Arc<Mutex<HashMap<String, (String, u64)>>>,
+}
+
+impl Aggregator {
+    pub fn new(config: &AggregatorConfig, store: Store<ImageRef, ImageOwner, ()>) -> Self {
+        Self {
+            store,
+            max_update_size: config.max_agent_update_size,
+            agents: Arc::new(config.agents.iter().cloned().fold(
+                HashMap::new(),
+                |mut agents, (identity, cluster)| {
+                    agents
+                        .entry(identity)
+                        .or_insert_with(HashSet::new)
+                        .insert(cluster);
+                    agents
+                },
+            )),
+            clusters: Arc::new(
+                config
+                    .agents
+                    .iter()
+                    .map(|(_, cluster)| cluster.clone())
+                    .collect(),
+            ),
+            sessions: Default::default(),
+        }
+    }
+
+    /// check if an agent, identified by its identity, may push the workload of a cluster
+    pub fn is_allowed(&self, identity: &str, cluster: &str) -> bool {
+        self.agents
+            .get(identity)
+            .is_some_and(|clusters| clusters.contains(cluster))
+    }
+
+    /// maximum size of an update
+    pub fn max_update_size(&self) -> u64 {
+        self.max_update_size
+    }
+
+    /// apply an update to the store
+    ///
+    /// Changes are only applied if they follow the previous update of the cluster. Otherwise, e.g.
+    /// after a restart of the aggregator, the agent needs to push its full workload.
+    pub async fn apply(&self, update: AgentUpdate) -> Result<(), Error> {
+        let AgentUpdate {
+            cluster,
+            session,
+            sequence,
+            full,
+            owners,
+            removed,
+        } = update;
+
+        if cluster.is_empty() {
+            return Err(Error::MissingCluster);
+        }
+
+        // updates of a cluster must be applied in order
+        let mut sessions = self.sessions.lock().await;
+
+        let follows = matches!(
+            sessions.get(&cluster),
+            Some((last_session, last)) if *last_session == session && last + 1 == sequence
+        );
+        if !full && !follows {
+            return Err(Error::OutOfSequence { session, sequence });
+        }
+
+        let owners = owners
+            .into_iter()
+            .map(|owner| (owner.owner.with_cluster(&cluster), owner.images))
+            .collect::<HashMap<_, _>>();
+
+        debug!(
+            %cluster,
+            %session,
+            sequence,
+            full,
+            owners = owners.len(),
+            removed = removed.len(),
+            "Applying agent update"
+        );
+
+        match full {
+            true => self.store.reset_cluster(&cluster, owners).await,
+            false => {
+                let removed = removed
+                    .into_iter()
+                    .map(|owner| owner.with_cluster(&cluster))
+                    .collect();
+                self.store.update_owners(owners, removed).await
+            }
+        }
+
+        metrics::increment_counter!(
+            "bommer_agent_updates_total",
+            "cluster" => cluster.clone(),
+            "full" => full.to_string()
+        );
+
+        let first = sessions.insert(cluster, (session, sequence)).is_none();
+
+        // the aggregator is synced once the agents of all clusters pushed their full workload,
+        // which every cluster having a session did
+        if first
+            && self
+                .clusters
+                .iter()
+                .all(|cluster| sessions.contains_key(cluster))
+        {
+            self.store.sync_state().mark_synced();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn update(cluster: &str, sequence: u64, full: bool) -> AgentUpdate {
+        AgentUpdate {
+            cluster: cluster.into(),
+            session: "session".into(),
+            sequence,
+            full,
+            owners: vec![],
+            removed: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn synced_by_full_update() {
+        let store = Store::new(1);
+        let aggregator = Aggregator::new(
+            &AggregatorConfig {
+                aggregator: true,
+                max_agent_update_size: 0,
+                agents: vec![
+                    ("token:east".into(), "east".into()),
+                    ("token:west".into(), "west".into()),
+                ],
+            },
+            store.clone(),
+        );
+
+        assert!(matches!(
+            aggregator.apply(update("east", 1, false)).await,
+            Err(Error::OutOfSequence { .. })
+        ));
+        assert!(!store.sync_state().is_synced());
+
+        // still waiting for the other cluster
+        aggregator.apply(update("east", 1, true)).await.unwrap();
+        aggregator.apply(update("east", 2, false)).await.unwrap();
+        assert!(!store.sync_state().is_synced());
+
+        aggregator.apply(update("west", 1, true)).await.unwrap();
+        assert!(store.sync_state().is_synced());
+
+        aggregator.apply(update("east", 3, false)).await.unwrap();
+        assert!(store.sync_state().is_synced());
+    }
+
+    #[test]
+    fn agents() {
+        let aggregator = Aggregator::new(
+            &AggregatorConfig {
+                aggregator: true,
+                max_agent_update_size: 0,
+                agents: ["token:east=east", "user:agent=west", "user:agent=north"]
+                    .into_iter()
+                    .map(|agent| parse_agent(agent).unwrap())
+                    .collect(),
+            },
+            Store::new(0),
+        );
+
+        assert!(aggregator.is_allowed("token:east", "east"));
+        assert!(!aggregator.is_allowed("token:east", "west"));
+        assert!(aggregator.is_allowed("user:agent", "west"));
+        assert!(aggregator.is_allowed("user:agent", "north"));
+        assert!(!aggregator.is_allowed("user:other", "west"));
+        assert!(!aggregator.is_allowed("anonymous", "east"));
+    }
+
+    #[test]
+    fn parse_agents() {
+        assert_eq!(
+            parse_agent("user:a=b=east"),
+            Ok(("user:a=b".to_string(), "east".to_string()))
+        );
+        assert!(parse_agent("token:east").is_err());
+        assert!(parse_agent("token:east=").is_err());
+        assert!(parse_agent("=east").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Backends keeping the state of the workload, so that it can be shared between replicas.
+//!
+//! By default, the state only lives in the memory of the instance watching the clusters. With a
+//! shared backend (Redis), that instance stores every change, and any number of stateless replicas
+//! follow it, serving the same view through the API.
+
+mod redis;
+
+use crate::store::SyncState;
+use crate::workload::WorkloadState;
+use bommer_api::data::{Event, Image, ImageRef};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// delay before retrying after the backend failed
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "State backend")]
+pub struct BackendConfig {
+    /// Where to keep the state of the workload
+    #[arg(long, env = "STATE_BACKEND", value_enum, default_value_t)]
+    pub state_backend: BackendKind,
+
+    /// URL of the Redis server, when using the Redis backend
+    #[arg(long, env = "REDIS_URL", default_value = "redis://localhost:6379")]
+    pub redis_url: String,
+
+    /// Prefix of the keys stored in Redis, allowing multiple instances to share one server
+    #[arg(long, env = "REDIS_PREFIX", default_value = "bommer")]
+    pub redis_prefix: String,
+
+    /// Run as a stateless replica, following the state of the backend instead of watching clusters
+    #[arg(long, env = "REPLICA")]
+    pub replica: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    /// Only keep the state in memory
+    #[default]
+    Memory,
+    /// Store the state in Redis, sharing it with replicas
+    Redis,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Redis error: {0}")]
+    Redis(#[from] ::redis::RedisError),
+    #[error("Failed to encode or decode state: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Lost the subscription to the changes")]
+    Disconnected,
+}
+
+pub type Changes = BoxStream<'static, Result<Event<ImageRef, Image>, Error>>;
+
+/// Storage of the state of the workload, behind the in-memory state serving the API
+///
+/// The instance watching the clusters stores each change of its state. Replicas follow those
+/// changes, applying them to their own state, which drives their subscribers.
+#[async_trait::async_trait]
+pub trait StateBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// store a change of the workload, a `Restart` replaces the full state
+    async fn store(&self, evt: &Event<ImageRef, Image>) -> Result<(), Error>;
+
+    /// follow the stored state, starting with a `Restart` of the full state
+    ///
+    /// As the backend might not know what the previous state of an image was, a change might be
+    /// reported as `Modified`, even if the image was just added. The stream ends when following
+    /// failed.
+    async fn follow(&self) -> Result<Changes, Error>;
+}
+
+/// Keeps the state in memory only, which is all there is to the local state
+pub struct Memory;
+
+#[async_trait::async_trait]
+impl StateBackend for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn store(&self, _evt: &Event<ImageRef, Image>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn follow(&self) -> Result<Changes, Error> {
+        Ok(futures::stream::pending().boxed())
+    }
+}
+
+impl BackendConfig {
+    fn backend(&self) -> anyhow::Result<Arc<dyn StateBackend>> {
+        Ok(match self.state_backend {
+            BackendKind::Memory => Arc::new(Memory),
+            BackendKind::Redis => {
+                info!("Keeping the state in Redis: {}", self.redis_url);
+                Arc::new(redis::Redis::new(&self.redis_url, &self.redis_prefix)?)
+            }
+        })
+    }
+
+    /// keep the state of the workload and the backend in sync
+    ///
+    /// Replicas follow the backend, all others store their changes in it. A replica is synced once
+    /// it loaded the full state of the backend. All others only store their state once synced, so
+    /// that an incomplete state doesn't replace the one of the backend.
+    pub async fn run(self, map: WorkloadState, sync: SyncState) -> anyhow::Result<()> {
+        let backend = self.backend()?;
+
+        // there's nothing besides the local state to keep in sync with
+        if self.state_backend == BackendKind::Memory {
+            return futures::future::pending().await;
+        }
+
+        if self.replica {
+            loop {
+                if let Err(err) = follow(backend.as_ref(), &map, &sync).await {
+                    warn!("Failed to follow the {} backend: {err}", backend.name());
+                }
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+
+        sync.synced().await;
+        info!("Storing the state in the {} backend", backend.name());
+
+        loop {
+            let mut sub = map.subscribe("backend", None).await;
+            while let Some(evt) = sub.recv().await {
+                if let Err(err) = backend.store(&evt).await {
+                    warn!(
+                        "Failed to store state in the {} backend: {err}",
+                        backend.name()
+                    );
+                    metrics::increment_counter!("bommer_backend_failures_total", "backend" => backend.name());
+                    break;
+                }
+            }
+            // start over with the full state, which replaces whatever got lost
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
+
+/// apply the changes of the backend to the local state, until following fails
+async fn follow(
+    backend: &dyn StateBackend,
+    map: &WorkloadState,
+    sync: &SyncState,
+) -> Result<(), Error> {
+    let mut changes = backend.follow().await?;
+    while let Some(evt) = changes.next().await {
+        match evt? {
+            Event::Restart(state) => {
+                map.set_state(state).await;
+                sync.mark_synced();
+            }
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                map.mutate_state(image, |_| Some(state)).await
+            }
+            Event::Removed(image) => map.mutate_state(image, |_| None).await,
+        }
+    }
+    Err(Error::Disconnected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::SbomState;
+
+    /// a backend replaying a fixed list of changes
+    struct Replay(Vec<Event<ImageRef, Image>>);
+
+    #[async_trait::async_trait]
+    impl StateBackend for Replay {
+        fn name(&self) -> &'static str {
+            "replay"
+        }
+
+        async fn store(&self, _evt: &Event<ImageRef, Image>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn follow(&self) -> Result<Changes, Error> {
+            Ok(futures::stream::iter(self.0.clone().into_iter().map(Ok)).boxed())
+        }
+    }
+
+    #[tokio::test]
+    async fn follow_changes() {
+        let map = WorkloadState::new(16);
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+        let third: ImageRef = "quay.io/example/third:1.0".parse().unwrap();
+
+        let backend = Replay(vec![
+            Event::Restart(im::HashMap::from_iter([
+                (first.clone(), Image::new(SbomState::Scheduled)),
+                (second.clone(), Image::new(SbomState::Scheduled)),
+            ])),
+            // the backend doesn't know if an image is new
+            Event::Modified(first.clone(), Image::new(SbomState::Missing)),
+            Event::Modified(third.clone(), Image::new(SbomState::Scheduled)),
+            Event::Removed(second),
+        ]);
+        let result = follow(&backend, &map, &SyncState::new(1)).await;
+        assert!(matches!(result, Err(Error::Disconnected)));
+
+        assert_eq!(
+            map.get_state().await,
+            im::HashMap::from_iter([
+                (first, Image::new(SbomState::Missing)),
+                (third, Image::new(SbomState::Scheduled)),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn replica_synced_by_restart() {
+        let map = WorkloadState::new(16);
+        let sync = SyncState::new(1);
+
+        let result = follow(&Replay(vec![]), &map, &sync).await;
+        assert!(matches!(result, Err(Error::Disconnected)));
+        assert!(!sync.is_synced());
+
+        let result = follow(
+            &Replay(vec![Event::Restart(Default::default())]),
+            &map,
+            &sync,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Disconnected)));
+        assert!(sync.is_synced());
+    }
+}
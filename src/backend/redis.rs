@@ -0,0 +1,260 @@
+use super::{Changes, Error, StateBackend};
+use bommer_api::data::{Event, Image, ImageRef};
+use futures::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client};
+use std::collections::HashSet;
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// number of keys read with a single command
+const CHUNK: usize = 256;
+
+/// the keyspace notifications replicas need: keyspace events, of string and generic commands
+const NOTIFICATIONS: &str = "K$g";
+
+/// Keeps the state in Redis, one key per image, holding its JSON encoded state
+///
+/// Replicas learn about changes through keyspace notifications, fetching the current value of
+/// the changed key.
+pub struct Redis {
+    client: Client,
+    keys: Keys,
+    connection: OnceCell<ConnectionManager>,
+}
+
+/// Maps images to keys, and back
+#[derive(Clone)]
+struct Keys {
+    prefix: String,
+}
+
+impl Keys {
+    fn key(&self, image: &ImageRef) -> String {
+        format!("{}{image}", self.prefix)
+    }
+
+    fn image(&self, key: &str) -> Option<ImageRef> {
+        key.strip_prefix(&self.prefix)?.parse().ok()
+    }
+}
+
+impl Redis {
+    pub fn new(url: &str, prefix: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: Client::open(url)?,
+            keys: Keys {
+                prefix: format!("{prefix}:image:"),
+            },
+            connection: OnceCell::new(),
+        })
+    }
+
+    /// the shared connection, reconnecting on its own
+    async fn connection(&self) -> Result<ConnectionManager, Error> {
+        Ok(self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?
+            .clone())
+    }
+
+    /// all keys of images
+    async fn list(&self, con: &mut ConnectionManager) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut iter = con
+            .scan_match::<_, String>(format!("{}*", self.keys.prefix))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// load the full state
+    async fn load(
+        &self,
+        con: &mut ConnectionManager,
+    ) -> Result<im::HashMap<ImageRef, Image>, Error> {
+        let keys = self.list(con).await?;
+        let mut state = im::HashMap::new();
+        for keys in keys.chunks(CHUNK) {
+            let values: Vec<Option<String>> = redis::cmd("MGET").arg(keys).query_async(con).await?;
+            for (key, value) in keys.iter().zip(values) {
+                // removed in the meantime
+                let Some(value) = value else { continue };
+                match self.keys.image(key) {
+                    Some(image) => {
+                        state.insert(image, serde_json::from_str(&value)?);
+                    }
+                    None => warn!(key, "Ignoring key, which isn't an image reference"),
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// replace the full state, in a single transaction
+    ///
+    /// Replicas following the changes never see a partial state, as they fetch the values only
+    /// after the transaction got applied.
+    async fn replace(
+        &self,
+        con: &mut ConnectionManager,
+        state: &im::HashMap<ImageRef, Image>,
+    ) -> Result<(), Error> {
+        let current = state
+            .keys()
+            .map(|image| self.keys.key(image))
+            .collect::<HashSet<_>>();
+        let gone = self
+            .list(con)
+            .await?
+            .into_iter()
+            .filter(|key| !current.contains(key))
+            .collect::<Vec<_>>();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if !gone.is_empty() {
+            pipe.del(gone).ignore();
+        }
+        for (image, state) in state {
+            pipe.set(self.keys.key(image), serde_json::to_string(state)?)
+                .ignore();
+        }
+        pipe.query_async::<_, ()>(con).await?;
+
+        Ok(())
+    }
+
+    /// make sure the keyspace notifications are enabled, adding the ones we need
+    async fn enable_notifications(&self, con: &mut ConnectionManager) -> Result<(), Error> {
+        let config: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("notify-keyspace-events")
+            .query_async(con)
+            .await?;
+        let current = config.get(1).map(String::as_str).unwrap_or_default();
+
+        let missing = NOTIFICATIONS
+            .chars()
+            .filter(|flag| !current.contains(*flag))
+            // 'A' is an alias for all classes of commands
+            .filter(|flag| *flag == 'K' || !current.contains('A'))
+            .collect::<String>();
+        if !missing.is_empty() {
+            info!("Enabling keyspace notifications: {missing}");
+            redis::cmd("CONFIG")
+                .arg("SET")
+                .arg("notify-keyspace-events")
+                .arg(format!("{current}{missing}"))
+                .query_async::<_, ()>(con)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl StateBackend for Redis {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn store(&self, evt: &Event<ImageRef, Image>) -> Result<(), Error> {
+        let mut con = self.connection().await?;
+        match evt {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                con.set::<_, _, ()>(self.keys.key(image), serde_json::to_string(state)?)
+                    .await?
+            }
+            Event::Removed(image) => con.del::<_, ()>(self.keys.key(image)).await?,
+            Event::Restart(state) => self.replace(&mut con, state).await?,
+        }
+        Ok(())
+    }
+
+    async fn follow(&self) -> Result<Changes, Error> {
+        let mut con = self.connection().await?;
+        // managed offerings might not allow changing the configuration, but might already have
+        // the notifications enabled
+        if let Err(err) = self.enable_notifications(&mut con).await {
+            warn!("Failed to enable keyspace notifications ({NOTIFICATIONS}), changes might not be seen: {err}");
+        }
+
+        let db = self.client.get_connection_info().redis.db;
+        let channel = format!("__keyspace@{db}__:");
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub
+            .psubscribe(format!("{channel}{}*", self.keys.prefix))
+            .await?;
+
+        // subscribe first, so that we don't miss changes happening while loading
+        let state = self.load(&mut con).await?;
+        info!(images = state.len(), "Loaded state from Redis");
+
+        let keys = self.keys.clone();
+        let changes = pubsub.into_on_message().filter_map(move |msg| {
+            let key = msg
+                .get_channel_name()
+                .strip_prefix(&channel)
+                .map(str::to_string);
+            let image = key.as_deref().and_then(|key| keys.image(key));
+            let mut con = con.clone();
+            async move {
+                let (image, key) = (image?, key?);
+                // whatever the operation was, the current value is what counts
+                let value = con.get::<_, Option<String>>(key).await;
+                Some(match value {
+                    Ok(Some(value)) => serde_json::from_str(&value)
+                        .map(|state| Event::Modified(image, state))
+                        .map_err(Error::from),
+                    Ok(None) => Ok(Event::Removed(image)),
+                    Err(err) => Err(err.into()),
+                })
+            }
+        });
+
+        Ok(futures::stream::once(async { Ok(Event::Restart(state)) })
+            .chain(changes)
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys() -> Keys {
+        Keys {
+            prefix: "bommer:image:".into(),
+        }
+    }
+
+    #[test]
+    fn key_round_trip() {
+        let keys = keys();
+        for image in [
+            "quay.io/example/image:1.0",
+            "docker.io/library/nginx@sha256:34e8724e0f47e31eb2ec3279ac398b657db5f60f167426ee73138e2e84af6486",
+            "registry.example.com:5000/example/image:1.0",
+        ] {
+            let image: ImageRef = image.parse().unwrap();
+            let key = keys.key(&image);
+            assert!(key.starts_with("bommer:image:"));
+            assert_eq!(keys.image(&key), Some(image));
+        }
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let keys = keys();
+        // a different prefix, e.g. another instance sharing the server
+        assert_eq!(keys.image("other:image:quay.io/example/image:1.0"), None);
+        assert_eq!(keys.image("bommer:other:quay.io/example/image:1.0"), None);
+        // not an image reference
+        assert_eq!(keys.image("bommer:image:"), None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::debug;
+use url::Url;
+
+/// refresh tokens this long before they expire
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Bombastic authentication")]
+pub struct BombasticAuthConfig {
+    /// A static bearer token to use
+    #[arg(
+        long = "bombastic-token",
+        env = "BOMBASTIC_TOKEN",
+        conflicts_with = "issuer_url"
+    )]
+    pub token: Option<String>,
+
+    /// Issuer URL of the OIDC provider, used to request tokens using the client credentials flow
+    #[arg(
+        long = "bombastic-oidc-issuer-url",
+        env = "BOMBASTIC_OIDC_ISSUER_URL",
+        requires_all = ["client_id", "client_secret"]
+    )]
+    pub issuer_url: Option<Url>,
+
+    /// The OIDC client ID
+    #[arg(long = "bombastic-oidc-client-id", env = "BOMBASTIC_OIDC_CLIENT_ID")]
+    pub client_id: Option<String>,
+
+    /// The OIDC client secret
+    #[arg(
+        long = "bombastic-oidc-client-secret",
+        env = "BOMBASTIC_OIDC_CLIENT_SECRET"
+    )]
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("Token request failed: {0}")]
+    Request(#[from] reqwest::Error),
+}
+
+/// Provides tokens for authenticating with bombastic, or other services using bearer tokens
+#[derive(Clone, Debug)]
+pub enum TokenProvider {
+    None,
+    Static(String),
+    ClientCredentials(Arc<ClientCredentials>),
+}
+
+impl TokenProvider {
+    pub async fn new(config: BombasticAuthConfig, client: reqwest::Client) -> anyhow::Result<Self> {
+        Self::with_credentials(
+            config.token,
+            config.issuer_url,
+            config.client_id,
+            config.client_secret,
+            client,
+        )
+        .await
+    }
+
+    /// create a provider using a static token, or else the OIDC client credentials if complete
+    pub async fn with_credentials(
+        token: Option<String>,
+        issuer_url: Option<Url>,
+        client_id: Option<String>,
+        client_secret: Option<String>,
+        client: reqwest::Client,
+    ) -> anyhow::Result<Self> {
+        Ok(match (token, issuer_url, client_id, client_secret) {
+            (Some(token), ..) => Self::Static(token),
+            (None, Some(issuer_url), Some(client_id), Some(client_secret)) => {
+                Self::ClientCredentials(Arc::new(
+                    ClientCredentials::discover(client, issuer_url, client_id, client_secret)
+                        .await?,
+                ))
+            }
+            _ => Self::None,
+        })
+    }
+
+    /// get a currently valid token, if authentication is enabled
+    pub async fn token(&self) -> Result<Option<String>, TokenError> {
+        match self {
+            Self::None => Ok(None),
+            Self::Static(token) => Ok(Some(token.clone())),
+            Self::ClientCredentials(provider) => Ok(Some(provider.token().await?)),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Discovery {
+    token_endpoint: Url,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Token {
+    access_token: String,
+    expires: Option<Instant>,
+}
+
+impl Token {
+    fn is_valid(&self) -> bool {
+        match self.expires {
+            Some(expires) => Instant::now() + EXPIRATION_MARGIN < expires,
+            None => true,
+        }
+    }
+}
+
+/// Requests tokens using the OIDC client credentials flow, refreshing them before they expire
+#[derive(Debug)]
+pub struct ClientCredentials {
+    client: reqwest::Client,
+    token_endpoint: Url,
+    client_id: String,
+    client_secret: String,
+    current: Mutex<Option<Token>>,
+}
+
+impl ClientCredentials {
+    async fn discover(
+        client: reqwest::Client,
+        issuer_url: Url,
+        client_id: String,
+        client_secret: String,
+    ) -> anyhow::Result<Self> {
+        let discovery: Discovery = client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                issuer_url.as_str().trim_end_matches('/')
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(Self {
+            client,
+            token_endpoint: discovery.token_endpoint,
+            client_id,
+            client_secret,
+            current: Default::default(),
+        })
+    }
+
+    async fn token(&self) -> Result<String, TokenError> {
+        let mut current = self.current.lock().await;
+
+        if let Some(token) = &*current {
+            if token.is_valid() {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        debug!("Requesting new access token");
+
+        let response: TokenResponse = self
+            .client
+            .post(self.token_endpoint.clone())
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let token = Token {
+            access_token: response.access_token,
+            expires: response
+                .expires_in
+                .map(|expires_in| Instant::now() + Duration::from_secs(expires_in)),
+        };
+        let access_token = token.access_token.clone();
+        *current = Some(token);
+
+        Ok(access_token)
+    }
+}
@@ -1,12 +1,21 @@
-use bommer_api::data::SBOM;
+use super::auth::{TokenError, TokenProvider};
+use crate::http::{self, BodyError};
+use crate::sbom;
+use crate::source::{PurlConfig, Sbom, SbomSource};
+use bommer_api::data::{ImageRef, LookupError, LookupErrorKind, SbomSummary};
+use bytes::Bytes;
 use packageurl::PackageUrl;
 use reqwest::{StatusCode, Url};
+use tracing::debug;
 use url::ParseError;
 
 #[derive(Clone, Debug)]
 pub struct BombasticSource {
     url: Url,
     client: reqwest::Client,
+    tokens: TokenProvider,
+    purls: PurlConfig,
+    max_size: u64,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -14,34 +23,138 @@ pub enum Error {
     #[error("Failed to build URL: {0}")]
     Url(#[from] ParseError),
     #[error("Request error: {0}")]
-    Request(#[from] reqwest::Error),
+    Transport(#[from] reqwest::Error),
+    #[error("Server error: {0}")]
+    Server(StatusCode),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("Failed to acquire token: {0}")]
+    Token(#[from] TokenError),
+    #[error("Failed to parse SBOM: {0}")]
+    Parse(#[from] sbom::ParseError),
+    #[error("SBOM exceeds the limit of {0} bytes")]
+    TooLarge(u64),
+}
+
+impl From<BodyError> for Error {
+    fn from(err: BodyError) -> Self {
+        match err {
+            BodyError::Transport(err) => Self::Transport(err),
+            BodyError::TooLarge(limit) => Self::TooLarge(limit),
+        }
+    }
+}
+
+impl Error {
+    /// the status code of the response, if the error was caused by one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Server(status) | Self::UnexpectedStatus(status) => Some(*status),
+            _ => None,
+        }
+    }
 }
 
 impl BombasticSource {
-    pub fn new(url: Url) -> Self {
+    pub fn new(
+        url: Url,
+        tokens: TokenProvider,
+        purls: PurlConfig,
+        max_size: u64,
+        client: reqwest::Client,
+    ) -> Self {
         Self {
             url,
-            client: reqwest::Client::new(),
+            client,
+            tokens,
+            purls,
+            max_size,
         }
     }
 
-    pub async fn lookup_sbom(&self, purl: PackageUrl<'_>) -> Result<Option<SBOM>, Error> {
-        let response = self
+    /// fetch the SBOM document
+    pub async fn fetch_sbom(&self, purl: PackageUrl<'_>) -> Result<Option<Bytes>, Error> {
+        let mut request = self
             .client
-            .get(self.url.join("/api/v1/sbom")?)
-            .query(&[("purl", purl.to_string())])
-            .send()
-            .await?;
+            .get(http::join(&self.url, "api/v1/sbom")?)
+            .query(&[("purl", purl.to_string())]);
+
+        if let Some(token) = self.tokens.token().await? {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
 
         match response.status() {
-            StatusCode::NOT_FOUND => return Ok(None),
-            _ => {}
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                Ok(Some(http::read_limited(response, self.max_size).await?))
+            }
+            status if status.is_server_error() => Err(Error::Server(status)),
+            status => Err(Error::UnexpectedStatus(status)),
+        }
+    }
+
+    /// fetch the SBOM document of the first package URL candidate bombastic knows
+    async fn find_sbom(
+        &self,
+        image: &ImageRef,
+    ) -> Result<Option<(PackageUrl<'static>, Bytes)>, LookupError> {
+        let candidates = self.purls.candidates(image)?;
+        let total = candidates.len();
+
+        for (n, purl) in candidates.into_iter().enumerate() {
+            if let Some(data) = self
+                .fetch_sbom(purl.clone())
+                .await
+                .map_err(to_lookup_error)?
+            {
+                debug!(%image, %purl, candidate = n + 1, total, "Found SBOM");
+                return Ok(Some((purl, data)));
+            }
         }
 
-        let response = response.error_for_status()?;
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl SbomSource for BombasticSource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        let (purl, data) = match self.find_sbom(image).await? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
 
-        Ok(Some(SBOM {
-            data: response.text().await?,
+        let summary = sbom::parse(&data).map_err(|err| to_lookup_error(err.into()))?;
+        Ok(Some(Sbom {
+            document: Some(data),
+            ..Sbom::from(SbomSummary {
+                purl: Some(purl.to_string()),
+                source: Some(self.url.to_string()),
+                ..summary
+            })
         }))
     }
+
+    async fn document(&self, image: &ImageRef) -> Result<Option<Bytes>, LookupError> {
+        Ok(self.find_sbom(image).await?.map(|(_, data)| data))
+    }
+}
+
+fn to_lookup_error(err: Error) -> LookupError {
+    let kind = match &err {
+        Error::Transport(_) | Error::Token(_) => LookupErrorKind::Transport,
+        Error::Server(_) => LookupErrorKind::Server,
+        Error::UnexpectedStatus(_) => LookupErrorKind::UnexpectedStatus,
+        Error::Url(_) => LookupErrorKind::InvalidReference,
+        Error::Parse(_) => LookupErrorKind::InvalidSbom,
+        Error::TooLarge(_) => LookupErrorKind::TooLarge,
+    };
+
+    LookupError {
+        kind,
+        status: err.status().map(|status| status.as_u16()),
+        message: err.to_string(),
+    }
 }
@@ -0,0 +1,215 @@
+use crate::aggregator::{AgentConfig, AggregatorConfig};
+use crate::backend::BackendConfig;
+use crate::bombastic::BombasticConfig;
+use crate::cloudevents::CloudEventsConfig;
+use crate::coverage::CoverageConfig;
+use crate::dependency_track::DependencyTrackConfig;
+use crate::documents::DocumentConfig;
+use crate::events::EventsConfig;
+use crate::guac::GuacConfig;
+use crate::http::HttpConfig;
+use crate::license::LicenseConfig;
+use crate::notify::NotifyConfig;
+use crate::publish::PublishConfig;
+use crate::pubsub::BufferConfig;
+use crate::registry::RegistryConfig;
+use crate::report::ReportConfig;
+use crate::scanner::ScannerConfig;
+use crate::search::SearchConfig;
+use crate::server::ServerConfig;
+use crate::shutdown::ShutdownConfig;
+use crate::snapshot::SnapshotConfig;
+use crate::source::SourceConfig;
+use crate::stats::StatsConfig;
+use crate::store::{ImageFilter, ImagePattern, LabelSelector, PodFilter};
+use crate::vexination::VexinationConfig;
+use std::time::Duration;
+
+/// Discover the workload of Kubernetes clusters, and correlate it with SBOMs
+#[derive(Clone, Debug, clap::Parser)]
+#[command(author, version, about)]
+pub struct Cli {
+    /// Log level / filter, using the `tracing` "env filter" syntax
+    #[arg(long, env = "RUST_LOG", default_value = "info")]
+    pub log_level: String,
+
+    /// Format of the log output
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    #[command(flatten)]
+    pub watcher: WatcherConfig,
+
+    #[command(flatten)]
+    pub source: SourceConfig,
+
+    #[command(flatten)]
+    pub bombastic: BombasticConfig,
+
+    #[command(flatten)]
+    pub guac: GuacConfig,
+
+    #[command(flatten)]
+    pub dependency_track: DependencyTrackConfig,
+
+    #[command(flatten)]
+    pub registry: RegistryConfig,
+
+    #[command(flatten)]
+    pub http: HttpConfig,
+
+    #[command(flatten)]
+    pub vexination: VexinationConfig,
+
+    #[command(flatten)]
+    pub scanner: ScannerConfig,
+
+    #[command(flatten)]
+    pub documents: DocumentConfig,
+
+    #[command(flatten)]
+    pub snapshot: SnapshotConfig,
+
+    #[command(flatten)]
+    pub backend: BackendConfig,
+
+    #[command(flatten)]
+    pub report: ReportConfig,
+
+    #[command(flatten)]
+    pub events: EventsConfig,
+
+    #[command(flatten)]
+    pub notify: NotifyConfig,
+
+    #[command(flatten)]
+    pub publish: PublishConfig,
+
+    #[command(flatten)]
+    pub cloudevents: CloudEventsConfig,
+
+    #[command(flatten)]
+    pub coverage: CoverageConfig,
+
+    #[command(flatten)]
+    pub stats: StatsConfig,
+
+    #[command(flatten)]
+    pub search: SearchConfig,
+
+    #[command(flatten)]
+    pub licenses: LicenseConfig,
+
+    #[command(flatten)]
+    pub agent: AgentConfig,
+
+    #[command(flatten)]
+    pub aggregator: AggregatorConfig,
+
+    #[command(flatten)]
+    pub server: ServerConfig,
+
+    #[command(flatten)]
+    pub buffers: BufferConfig,
+
+    #[command(flatten)]
+    pub shutdown: ShutdownConfig,
+}
+
+/// Format of the log output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// One JSON object per line, including all fields
+    Json,
+}
+
+/// Configuration of which pods to watch
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Watcher")]
+pub struct WatcherConfig {
+    /// Kubeconfig contexts of the clusters to watch, the default cluster if none are provided
+    #[arg(long = "context", env = "KUBE_CONTEXTS", value_delimiter = ',')]
+    pub contexts: Vec<String>,
+
+    /// Namespaces to watch, all if none are provided
+    #[arg(long = "namespace", env = "WATCH_NAMESPACES", value_delimiter = ',')]
+    pub namespaces: Vec<String>,
+
+    /// Namespaces to ignore
+    #[arg(
+        long = "ignore-namespace",
+        env = "IGNORE_NAMESPACES",
+        value_delimiter = ','
+    )]
+    pub ignore_namespaces: Vec<String>,
+
+    /// Label selector pods must match
+    #[arg(long, env = "WATCH_LABEL_SELECTOR")]
+    pub label_selector: Option<LabelSelector>,
+
+    /// Images to track, all if none are provided. Globs (e.g. `registry.example.com/*`), or regular expressions with a `regex:` prefix
+    #[arg(long = "include-image", env = "INCLUDE_IMAGES", value_delimiter = ',')]
+    pub include_images: Vec<ImagePattern>,
+
+    /// Images to ignore, e.g. `registry.k8s.io/pause`. Globs, or regular expressions with a `regex:` prefix
+    #[arg(long = "exclude-image", env = "EXCLUDE_IMAGES", value_delimiter = ',')]
+    pub exclude_images: Vec<ImagePattern>,
+
+    /// Also track the images of containers which didn't start yet, e.g. still pulling their image, or of pending pods
+    #[arg(long = "include-pending", env = "INCLUDE_PENDING")]
+    pub pending: bool,
+
+    /// Track the architecture of nodes, to resolve multi-arch images to the image actually running
+    #[arg(long = "node-arch", env = "TRACK_NODE_ARCH")]
+    pub node_arch: bool,
+
+    /// Time an image is kept after its last pod is gone, so that it survives rolling updates. Zero drops it right away
+    #[arg(long, env = "REMOVAL_GRACE", default_value = "0s", value_parser = humantime::parse_duration)]
+    pub removal_grace: Duration,
+
+    /// Owners of images to track: the containers of pods, the pod templates of jobs and cron jobs, and the images present on nodes
+    #[arg(
+        long = "track",
+        env = "TRACK_OWNERS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "pods"
+    )]
+    pub track: Vec<Track>,
+}
+
+/// An owner of images to track
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Track {
+    /// Containers of pods
+    Pods,
+    /// Jobs and cron jobs, collapsing their finished pods into the job
+    Jobs,
+    /// Images present on nodes
+    Nodes,
+}
+
+impl WatcherConfig {
+    pub fn filter(&self) -> PodFilter {
+        PodFilter {
+            include_namespaces: self.namespaces.iter().cloned().collect(),
+            exclude_namespaces: self.ignore_namespaces.iter().cloned().collect(),
+            label_selector: self.label_selector.clone(),
+        }
+    }
+
+    /// check if an owner of images is tracked
+    pub fn tracks(&self, track: Track) -> bool {
+        self.track.contains(&track)
+    }
+
+    pub fn image_filter(&self) -> ImageFilter {
+        ImageFilter {
+            include: self.include_images.clone(),
+            exclude: self.exclude_images.clone(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Wrapping published events and notifications into [CloudEvents](https://cloudevents.io)
+//! envelopes, using the structured JSON format.
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+/// the content type of events, in the structured format
+const CONTENT_TYPE: &str = "application/cloudevents+json";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "CloudEvents")]
+pub struct CloudEventsConfig {
+    /// Wrap published events and webhook notifications into CloudEvents (1.0) envelopes
+    #[arg(long, env = "CLOUDEVENTS")]
+    pub cloudevents: bool,
+
+    /// Identifier of the cluster, used as the source of CloudEvents
+    #[arg(long, env = "CLUSTER_ID", default_value = "bommer")]
+    pub cluster_id: String,
+}
+
+impl CloudEventsConfig {
+    pub fn envelope(&self) -> Envelope {
+        Envelope {
+            source: self.cloudevents.then(|| self.cluster_id.clone()),
+        }
+    }
+}
+
+/// Wraps events into CloudEvents envelopes, if enabled
+#[derive(Clone, Debug, Default)]
+pub struct Envelope {
+    /// the source of the events, `None` if disabled
+    source: Option<String>,
+}
+
+impl Envelope {
+    /// the content type of the wrapped events
+    pub fn content_type(&self) -> &'static str {
+        match self.source {
+            Some(_) => CONTENT_TYPE,
+            None => "application/json",
+        }
+    }
+
+    /// wrap an event, carrying the plain event as data
+    ///
+    /// The type is prefixed with `io.bommer.`, the subject is the image the event is about, if any.
+    pub fn wrap<T>(&self, ty: &str, subject: Option<&str>, time: DateTime<Utc>, data: T) -> Value
+    where
+        T: serde::Serialize,
+    {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return json!(data),
+        };
+
+        let mut event = json!({
+            "specversion": "1.0",
+            "id": uuid::Uuid::new_v4().to_string(),
+            "source": source,
+            "type": format!("io.bommer.{ty}"),
+            "time": time,
+            "datacontenttype": "application/json",
+            "data": data,
+        });
+        if let Some(subject) = subject {
+            event["subject"] = json!(subject);
+        }
+        event
+    }
+}
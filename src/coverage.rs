@@ -0,0 +1,313 @@
+//! Scheduled reports of the SBOM coverage, written to a directory or sent to an endpoint.
+
+use crate::store::SyncState;
+use crate::workload::WorkloadState;
+use anyhow::Context;
+use bommer_api::data::{Image, ImageRef, SbomStats};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{info, warn};
+use url::Url;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CoverageReportFormat {
+    Json,
+    Csv,
+}
+
+impl CoverageReportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv",
+        }
+    }
+
+    fn render(&self, report: &CoverageReport) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec_pretty(report)?,
+            Self::Csv => report.to_csv()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Coverage reports")]
+pub struct CoverageConfig {
+    /// Schedule of creating coverage reports, as a cron expression including seconds (e.g. `0 0 6 * * *`)
+    #[arg(long, env = "COVERAGE_REPORT_SCHEDULE", value_parser = Schedule::from_str)]
+    pub coverage_report_schedule: Option<Schedule>,
+
+    /// Format of the coverage reports
+    #[arg(long, env = "COVERAGE_REPORT_FORMAT", value_enum, default_value_t = CoverageReportFormat::Json)]
+    pub coverage_report_format: CoverageReportFormat,
+
+    /// Directory to write the coverage reports to
+    #[arg(long, env = "COVERAGE_REPORT_DIR")]
+    pub coverage_report_dir: Option<PathBuf>,
+
+    /// URL to POST the coverage reports to
+    #[arg(long, env = "COVERAGE_REPORT_URL")]
+    pub coverage_report_url: Option<Url>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageReport {
+    pub timestamp: DateTime<Utc>,
+    pub total: SbomStats,
+    /// The coverage of each namespace, counting the images used in it
+    pub namespaces: BTreeMap<String, SbomStats>,
+}
+
+impl CoverageReport {
+    pub fn new(timestamp: DateTime<Utc>, state: &im::HashMap<ImageRef, Image>) -> Self {
+        let mut total = SbomStats::default();
+        let mut namespaces = BTreeMap::<String, SbomStats>::new();
+
+        for image in state.values() {
+            total.add(&image.sbom);
+
+            let used_in = image
+                .pods
+                .iter()
+                .map(|pod| &pod.namespace)
+                .collect::<BTreeSet<_>>();
+            for namespace in used_in {
+                namespaces
+                    .entry(namespace.clone())
+                    .or_default()
+                    .add(&image.sbom);
+            }
+        }
+
+        Self {
+            timestamp,
+            total,
+            namespaces,
+        }
+    }
+
+    /// one row per namespace, the first one (with an empty namespace) being the total
+    fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record([
+            "namespace",
+            "images",
+            "found",
+            "missing",
+            "failed",
+            "scheduled",
+        ])?;
+
+        for (namespace, coverage) in std::iter::once(("", &self.total))
+            .chain(self.namespaces.iter().map(|(k, v)| (k.as_str(), v)))
+        {
+            writer.write_record([
+                namespace.to_string(),
+                coverage.images.to_string(),
+                coverage.found.to_string(),
+                coverage.missing.to_string(),
+                coverage.failed.to_string(),
+                coverage.scheduled.to_string(),
+            ])?;
+        }
+
+        Ok(writer.into_inner()?)
+    }
+}
+
+impl CoverageConfig {
+    /// create coverage reports according to the schedule, if enabled
+    pub async fn run(
+        self,
+        map: WorkloadState,
+        sync: SyncState,
+        client: reqwest::Client,
+    ) -> anyhow::Result<()> {
+        let schedule = match &self.coverage_report_schedule {
+            Some(schedule) => schedule,
+            None => return futures::future::pending().await,
+        };
+
+        if self.coverage_report_dir.is_none() && self.coverage_report_url.is_none() {
+            anyhow::bail!(
+                "Coverage reports require a directory (--coverage-report-dir) or URL (--coverage-report-url)"
+            );
+        }
+
+        while let Some(next) = schedule.upcoming(Utc).next() {
+            tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+
+            // until synced, we only have a partial view of the workload
+            if !sync.is_synced() {
+                warn!("Skipping coverage report, the pod watchers have not synced yet");
+                continue;
+            }
+
+            let report = CoverageReport::new(next, &map.get_state().await);
+            let data = self.coverage_report_format.render(&report)?;
+
+            if let Some(dir) = &self.coverage_report_dir {
+                let path = dir.join(format!(
+                    "coverage-{}.{}",
+                    next.format("%Y%m%dT%H%M%SZ"),
+                    self.coverage_report_format.extension()
+                ));
+                let data = data.clone();
+                match tokio::task::spawn_blocking(move || write(&path, &data)).await? {
+                    Ok(()) => info!("Wrote coverage report"),
+                    Err(err) => warn!("Failed to write coverage report: {err:#}"),
+                }
+            }
+
+            if let Some(url) = &self.coverage_report_url {
+                let result = client
+                    .post(url.clone())
+                    .header(CONTENT_TYPE, self.coverage_report_format.content_type())
+                    .body(data)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) => info!(%url, "Sent coverage report"),
+                    Err(err) => warn!(%url, "Failed to send coverage report: {err}"),
+                }
+            }
+        }
+
+        // the schedule has no more occurrences
+        futures::future::pending().await
+    }
+}
+
+/// write to a temporary file first, so that consumers never see a partial report
+fn write(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, data).with_context(|| format!("Failed to write {}", temp.display()))?;
+    std::fs::rename(&temp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::{LookupError, LookupErrorKind, PodRef, SbomState};
+
+    fn image(namespaces: &[&str], sbom: SbomState) -> Image {
+        Image {
+            pods: namespaces
+                .iter()
+                .enumerate()
+                .map(|(i, namespace)| PodRef {
+                    cluster: None,
+                    namespace: namespace.to_string(),
+                    name: format!("pod-{i}"),
+                    node: None,
+                    workload: None,
+                })
+                .collect(),
+            ..Image::new(sbom)
+        }
+    }
+
+    fn state() -> im::HashMap<ImageRef, Image> {
+        let found = SbomState::Found(
+            serde_json::from_value(serde_json::json!({"format": "spdx", "packages": 1})).unwrap(),
+        );
+        let failed = SbomState::Err(LookupError {
+            kind: LookupErrorKind::Server,
+            message: "failed".to_string(),
+            status: Some(500),
+        });
+
+        [
+            ("quay.io/example/a", image(&["default", "default"], found)),
+            (
+                "quay.io/example/b",
+                image(&["default", "other"], SbomState::Missing),
+            ),
+            ("quay.io/example/c", image(&["other"], failed)),
+            ("quay.io/example/d", image(&[], SbomState::Scheduled)),
+        ]
+        .into_iter()
+        .map(|(image, state)| (image.parse().unwrap(), state))
+        .collect()
+    }
+
+    fn stats(
+        images: usize,
+        found: usize,
+        missing: usize,
+        failed: usize,
+        scheduled: usize,
+    ) -> SbomStats {
+        SbomStats {
+            images,
+            found,
+            missing,
+            failed,
+            scheduled,
+        }
+    }
+
+    #[test]
+    fn report() {
+        let timestamp = "2023-05-01T06:00:00Z".parse().unwrap();
+        let report = CoverageReport::new(timestamp, &state());
+
+        assert_eq!(report.timestamp, timestamp);
+        assert_eq!(report.total, stats(4, 1, 1, 1, 1));
+        // images count once per namespace, no matter how many pods use them
+        assert_eq!(
+            report.namespaces,
+            BTreeMap::from([
+                ("default".to_string(), stats(2, 1, 1, 0, 0)),
+                ("other".to_string(), stats(2, 0, 1, 1, 0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn report_empty() {
+        let report = CoverageReport::new(Utc::now(), &Default::default());
+        assert_eq!(report.total, SbomStats::default());
+        assert!(report.namespaces.is_empty());
+    }
+
+    #[test]
+    fn render_csv() {
+        let report = CoverageReport::new(Utc::now(), &state());
+        let csv = CoverageReportFormat::Csv.render(&report).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "namespace,images,found,missing,failed,scheduled\n\
+             ,4,1,1,1,1\n\
+             default,2,1,1,0,0\n\
+             other,2,0,1,1,0\n"
+        );
+    }
+
+    #[test]
+    fn render_json() {
+        let timestamp = "2023-05-01T06:00:00Z".parse().unwrap();
+        let report = CoverageReport::new(timestamp, &state());
+        let json = CoverageReportFormat::Json.render(&report).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["timestamp"], "2023-05-01T06:00:00Z");
+        assert_eq!(json["total"]["images"], 4);
+        assert_eq!(json["namespaces"]["other"]["failed"], 1);
+    }
+}
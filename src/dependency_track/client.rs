@@ -0,0 +1,170 @@
+use super::DependencyTrackConfig;
+use crate::http;
+use crate::source::{PurlConfig, Sbom, SbomSource};
+use bommer_api::data::{
+    ImageRef, LookupError, LookupErrorKind, SbomFormat, SbomSummary, Vulnerabilities,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::{StatusCode, Url};
+use url::ParseError;
+
+/// Looks up SBOMs uploaded to Dependency-Track
+///
+/// Images are expected to be uploaded as a project, using the name of the image as project name,
+/// and its digest as project version.
+#[derive(Clone, Debug)]
+pub struct DependencyTrackSource {
+    url: Url,
+    api_key: Option<String>,
+    client: reqwest::Client,
+    purls: PurlConfig,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to build URL: {0}")]
+    Url(#[from] ParseError),
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Server error: {0}")]
+    Server(StatusCode),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Project {
+    name: String,
+    /// Time of the last BOM upload, in milliseconds since the epoch
+    #[serde(default)]
+    last_bom_import: Option<i64>,
+    /// Format of the last uploaded BOM, e.g. `CycloneDX 1.4`
+    #[serde(default)]
+    last_bom_import_format: Option<String>,
+    #[serde(default)]
+    metrics: Option<Metrics>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metrics {
+    #[serde(default)]
+    components: usize,
+    #[serde(default)]
+    critical: usize,
+    #[serde(default)]
+    high: usize,
+    #[serde(default)]
+    medium: usize,
+    #[serde(default)]
+    low: usize,
+    #[serde(default)]
+    unassigned: usize,
+}
+
+impl DependencyTrackSource {
+    pub fn new(config: DependencyTrackConfig, purls: PurlConfig, client: reqwest::Client) -> Self {
+        Self {
+            url: config.url,
+            api_key: config.api_key,
+            client,
+            purls,
+        }
+    }
+
+    async fn lookup_project(&self, name: &str, version: &str) -> Result<Option<Sbom>, Error> {
+        let mut request = self
+            .client
+            .get(http::join(&self.url, "api/v1/project/lookup")?)
+            .query(&[("name", name), ("version", version)]);
+
+        if let Some(api_key) = &self.api_key {
+            request = request.header("X-Api-Key", api_key);
+        }
+
+        let response = request.send().await?;
+
+        let project: Project = match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if status.is_success() => response.json().await?,
+            status if status.is_server_error() => return Err(Error::Server(status)),
+            status => return Err(Error::UnexpectedStatus(status)),
+        };
+
+        // a project without an uploaded BOM is of no use to us
+        let created = match project.last_bom_import {
+            Some(timestamp) => Utc.timestamp_millis_opt(timestamp).single(),
+            None => return Ok(None),
+        };
+
+        Ok(Some(to_sbom(project, created)))
+    }
+}
+
+fn to_sbom(project: Project, created: Option<DateTime<Utc>>) -> Sbom {
+    let (format, version) = match project
+        .last_bom_import_format
+        .as_deref()
+        .and_then(|format| format.split_once(' '))
+    {
+        Some(("CycloneDX", version)) => (SbomFormat::CycloneDx, Some(version.to_string())),
+        Some(("SPDX", version)) => (SbomFormat::Spdx, Some(version.to_string())),
+        _ => (SbomFormat::Unknown, None),
+    };
+
+    let metrics = project.metrics;
+
+    Sbom {
+        summary: SbomSummary {
+            format,
+            version,
+            name: Some(project.name),
+            packages: metrics.as_ref().map(|m| m.components).unwrap_or_default(),
+            licenses: vec![],
+            tools: vec![],
+            created,
+            purl: None,
+            source: None,
+            digest: None,
+            subjects: vec![],
+            verified: false,
+            license_ids: vec![],
+            quality: None,
+        },
+        vulnerabilities: metrics.map(|m| Vulnerabilities {
+            critical: m.critical,
+            high: m.high,
+            medium: m.medium,
+            low: m.low,
+            unknown: m.unassigned,
+        }),
+        document: None,
+    }
+}
+
+#[async_trait::async_trait]
+impl SbomSource for DependencyTrackSource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        let purl = self.purls.purl(image)?;
+        let version = purl.version().unwrap_or_default();
+
+        self.lookup_project(purl.name(), version)
+            .await
+            .map_err(|err| LookupError {
+                kind: match &err {
+                    Error::Transport(_) => LookupErrorKind::Transport,
+                    Error::Server(_) => LookupErrorKind::Server,
+                    Error::UnexpectedStatus(_) => LookupErrorKind::UnexpectedStatus,
+                    Error::Url(_) => LookupErrorKind::InvalidReference,
+                },
+                status: match &err {
+                    Error::Server(status) | Error::UnexpectedStatus(status) => {
+                        Some(status.as_u16())
+                    }
+                    _ => None,
+                },
+                message: err.to_string(),
+            })
+    }
+}
@@ -0,0 +1,23 @@
+mod client;
+
+pub use client::DependencyTrackSource;
+
+use url::Url;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Dependency-Track")]
+pub struct DependencyTrackConfig {
+    /// The base URL of the Dependency-Track API server
+    #[arg(
+        id = "dependency_track_url",
+        value_name = "URL",
+        long = "dependency-track-url",
+        env = "DEPENDENCY_TRACK_URL",
+        default_value = "http://localhost:8081"
+    )]
+    pub url: Url,
+
+    /// The API key to access Dependency-Track
+    #[arg(long = "dependency-track-api-key", env = "DEPENDENCY_TRACK_API_KEY")]
+    pub api_key: Option<String>,
+}
@@ -0,0 +1,37 @@
+use super::Error;
+use bytes::Bytes;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Stores documents as files in a directory
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// write the document to a temporary file first, so that readers never see a partial one
+    pub(super) async fn put(&self, key: &str, data: Bytes) -> Result<(), Error> {
+        let path = self.dir.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    pub(super) async fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        match tokio::fs::read(self.dir.join(key)).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
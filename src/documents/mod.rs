@@ -0,0 +1,165 @@
+//! Storing SBOM documents outside the workload state
+//!
+//! The workload state only keeps the summary of an SBOM, along with the digest of its document.
+//! With a document store configured, documents are written to the store when looked up, using
+//! their digest as key, and served from there instead of fetching them from the SBOM source again.
+
+mod files;
+mod s3;
+
+pub use files::FileStore;
+pub use s3::S3Store;
+
+use crate::source::SbomSource;
+use bommer_api::data::{ImageRef, LookupError, SbomSummary};
+use bytes::Bytes;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+use url::Url;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "SBOM documents")]
+pub struct DocumentConfig {
+    /// Directory to store SBOM documents in
+    #[arg(long, env = "DOCUMENTS_DIR", conflicts_with = "documents_s3_url")]
+    pub documents_dir: Option<PathBuf>,
+
+    /// URL of an S3 compatible bucket to store SBOM documents in, using path style (e.g. `https://s3.example.com/bucket`)
+    #[arg(long, env = "DOCUMENTS_S3_URL")]
+    pub documents_s3_url: Option<Url>,
+
+    /// Region of the bucket
+    #[arg(long, env = "DOCUMENTS_S3_REGION", default_value = "us-east-1")]
+    pub documents_s3_region: String,
+
+    /// Access key ID for the bucket
+    #[arg(
+        long,
+        env = "DOCUMENTS_S3_ACCESS_KEY_ID",
+        requires = "documents_s3_url"
+    )]
+    pub documents_s3_access_key_id: Option<String>,
+
+    /// Secret access key for the bucket
+    #[arg(
+        long,
+        env = "DOCUMENTS_S3_SECRET_ACCESS_KEY",
+        hide_env_values = true,
+        requires = "documents_s3_access_key_id"
+    )]
+    pub documents_s3_secret_access_key: Option<String>,
+}
+
+impl DocumentConfig {
+    /// create the document store, `None` if none is configured
+    pub fn store(&self, client: reqwest::Client) -> Option<DocumentStore> {
+        if let Some(dir) = &self.documents_dir {
+            return Some(DocumentStore::Files(FileStore::new(dir.clone())));
+        }
+
+        self.documents_s3_url.as_ref().map(|url| {
+            let credentials = self
+                .documents_s3_access_key_id
+                .clone()
+                .zip(self.documents_s3_secret_access_key.clone());
+            DocumentStore::S3(S3Store::new(
+                url.clone(),
+                self.documents_s3_region.clone(),
+                credentials,
+                client,
+            ))
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid document digest: {0}")]
+    InvalidDigest(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Failed to build URL: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(reqwest::StatusCode),
+}
+
+/// A store of SBOM documents, by the digest of the document
+#[derive(Clone, Debug)]
+pub enum DocumentStore {
+    Files(FileStore),
+    S3(S3Store),
+}
+
+impl DocumentStore {
+    /// store a document, replacing an existing one with the same digest
+    pub async fn put(&self, digest: &str, data: Bytes) -> Result<(), Error> {
+        let key = key(digest)?;
+        match self {
+            Self::Files(store) => store.put(&key, data).await,
+            Self::S3(store) => store.put(&key, data).await,
+        }
+    }
+
+    /// get a document, `None` if it isn't stored
+    pub async fn get(&self, digest: &str) -> Result<Option<Bytes>, Error> {
+        let key = key(digest)?;
+        match self {
+            Self::Files(store) => store.get(&key).await,
+            Self::S3(store) => store.get(&key).await,
+        }
+    }
+}
+
+/// Provides the SBOM documents of images, preferring the store over the SBOM source
+#[derive(Clone)]
+pub struct Documents {
+    store: Option<DocumentStore>,
+    source: Arc<dyn SbomSource>,
+}
+
+impl Documents {
+    pub fn new(store: Option<DocumentStore>, source: Arc<dyn SbomSource>) -> Self {
+        Self { store, source }
+    }
+
+    /// get the document of an image, using the digest of its SBOM to find it in the store
+    ///
+    /// Documents missing from the store (e.g. found before the store was configured) are fetched
+    /// from the SBOM source, as are documents the store fails to provide.
+    pub async fn get(
+        &self,
+        image: &ImageRef,
+        summary: Option<&SbomSummary>,
+    ) -> Result<Option<Bytes>, LookupError> {
+        let digest = summary.and_then(|summary| summary.digest.as_deref());
+        if let (Some(store), Some(digest)) = (&self.store, digest) {
+            match store.get(digest).await {
+                Ok(Some(data)) => return Ok(Some(data)),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!(%image, %digest, "Failed to read SBOM document from store: {err}")
+                }
+            }
+        }
+
+        self.source.document(image).await
+    }
+}
+
+/// the key of a document, e.g. `sha256/<hex>`
+///
+/// Digests are checked strictly, as they end up in paths.
+fn key(digest: &str) -> Result<String, Error> {
+    match digest.split_once(':') {
+        Some((algorithm @ "sha256", hex))
+            if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            Ok(format!("{algorithm}/{}", hex.to_ascii_lowercase()))
+        }
+        _ => Err(Error::InvalidDigest(digest.to_string())),
+    }
+}
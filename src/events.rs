@@ -0,0 +1,394 @@
+//! Emitting Kubernetes events for gaps in the SBOM coverage.
+//!
+//! Events are emitted on the workload controlling the pods using an image (or the pods
+//! themselves), so that they show up in `kubectl describe`, and in the event stream of the
+//! cluster.
+
+use crate::workload::WorkloadState;
+use bommer_api::data::{Event, Image, ImageRef, SbomState};
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::api::{ApiResource, DynamicObject, GroupVersionKind};
+use kube::runtime::events::{Event as KubeEvent, EventType, Recorder, Reporter};
+use kube::{Api, Client, Resource};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// maximum length of the note of an event
+const MAX_NOTE: usize = 1024;
+
+/// number of events waiting to be emitted, before dropping new ones
+const QUEUE: usize = 1024;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Events")]
+pub struct EventsConfig {
+    /// Emit Kubernetes events on workloads using images without an SBOM, or failing the lookup
+    #[arg(long, env = "EMIT_EVENTS")]
+    pub emit_events: bool,
+}
+
+impl EventsConfig {
+    /// emit events for changes of the workload, if enabled
+    pub async fn run(
+        self,
+        map: WorkloadState,
+        clusters: Vec<(Option<String>, Client)>,
+    ) -> anyhow::Result<()> {
+        if !self.emit_events {
+            return futures::future::pending().await;
+        }
+
+        let clients = clusters.into_iter().collect::<HashMap<_, _>>();
+        let reported = Reported::default();
+
+        // the API calls happen in a task of their own, not holding up the subscription
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let emitter = Emitter {
+            clients: clients.clone(),
+            reporter: Reporter {
+                controller: "bommer".into(),
+                instance: std::env::var("HOSTNAME").ok(),
+            },
+            reported: reported.clone(),
+        };
+        tokio::spawn(emitter.run(rx));
+
+        let mut planner = Planner {
+            clusters: clients.into_keys().collect(),
+            reported,
+            tx,
+        };
+
+        loop {
+            let mut sub = map.subscribe("events", None).await;
+            while let Some(evt) = sub.recv().await {
+                planner.handle(&evt);
+            }
+            // the subscription got dropped, we get a full state with the next one
+        }
+    }
+}
+
+/// The object to emit an event on
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Target {
+    cluster: Option<String>,
+    namespace: String,
+    kind: String,
+    name: String,
+}
+
+/// An event to emit
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Emission {
+    image: ImageRef,
+    target: Target,
+    reason: &'static str,
+    note: String,
+}
+
+/// The objects we emitted (or queued) events on, by image, until the image has an SBOM
+///
+/// Failing to emit an event drops the object again, so that the next change of the image
+/// retries it.
+#[derive(Clone, Default)]
+struct Reported(Arc<Mutex<HashMap<ImageRef, HashSet<Target>>>>);
+
+impl Reported {
+    fn lock(&self) -> MutexGuard<'_, HashMap<ImageRef, HashSet<Target>>> {
+        // the map is consistent at any time, even if a holder of the lock panicked
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Decides on the events to emit, following the changes of the workload
+struct Planner {
+    clusters: HashSet<Option<String>>,
+    reported: Reported,
+    tx: mpsc::Sender<Emission>,
+}
+
+impl Planner {
+    fn handle(&mut self, evt: &Event<ImageRef, Image>) {
+        match evt {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                self.check_image(image, state)
+            }
+            Event::Removed(image) => {
+                self.reported.lock().remove(image);
+            }
+            Event::Restart(state) => {
+                self.reported
+                    .lock()
+                    .retain(|image, _| state.contains_key(image));
+                for (image, state) in state {
+                    self.check_image(image, state);
+                }
+            }
+        }
+    }
+
+    fn check_image(&mut self, image: &ImageRef, state: &Image) {
+        let (reason, note) = match &state.sbom {
+            SbomState::Missing => ("SbomMissing", format!("No SBOM found for image {image}")),
+            SbomState::Err(err) if !err.is_retryable() => (
+                "SbomLookupFailed",
+                format!("Failed to look up SBOM for image {image}: {err}"),
+            ),
+            SbomState::Found(_) => {
+                // if the SBOM goes missing again, we report it again
+                self.reported.lock().remove(image);
+                return;
+            }
+            _ => return,
+        };
+
+        let targets = state
+            .pods
+            .iter()
+            .filter(|pod| self.clusters.contains(&pod.cluster))
+            .map(|pod| match &pod.workload {
+                Some(workload) if api_resource(&workload.kind).is_some() => Target {
+                    cluster: pod.cluster.clone(),
+                    namespace: workload.namespace.clone(),
+                    kind: workload.kind.clone(),
+                    name: workload.name.clone(),
+                },
+                _ => Target {
+                    cluster: pod.cluster.clone(),
+                    namespace: pod.namespace.clone(),
+                    kind: "Pod".into(),
+                    name: pod.name.clone(),
+                },
+            })
+            .collect::<HashSet<_>>();
+
+        let mut reported = self.reported.lock();
+        let reported = reported.entry(image.clone()).or_default();
+        for target in targets {
+            if reported.contains(&target) {
+                continue;
+            }
+
+            let emission = Emission {
+                image: image.clone(),
+                target: target.clone(),
+                reason,
+                note: truncate(&note, MAX_NOTE),
+            };
+            match self.tx.try_send(emission) {
+                Ok(()) => {
+                    reported.insert(target);
+                }
+                Err(_) => {
+                    // not reported, the next change of the image tries again
+                    warn!(%image, namespace = %target.namespace, "Dropping event, the API server can't keep up");
+                }
+            }
+        }
+    }
+}
+
+/// Emits the events, calling the API servers
+struct Emitter {
+    clients: HashMap<Option<String>, Client>,
+    reporter: Reporter,
+    reported: Reported,
+}
+
+impl Emitter {
+    async fn run(self, mut rx: mpsc::Receiver<Emission>) {
+        while let Some(emission) = rx.recv().await {
+            if !self.emit(&emission).await {
+                if let Some(reported) = self.reported.lock().get_mut(&emission.image) {
+                    reported.remove(&emission.target);
+                }
+            }
+        }
+    }
+
+    /// emit an event, `false` if it failed and should be re-tried
+    async fn emit(&self, emission: &Emission) -> bool {
+        let Emission {
+            image,
+            target,
+            reason,
+            note,
+        } = emission;
+
+        let Some(client) = self.clients.get(&target.cluster).cloned() else {
+            return true;
+        };
+
+        let reference = match reference(client.clone(), target).await {
+            Ok(Some(reference)) => reference,
+            Ok(None) => {
+                // gone in the meantime
+                return true;
+            }
+            Err(err) => {
+                warn!(%image, namespace = %target.namespace, "Failed to look up {} {}: {err}", target.kind, target.name);
+                return false;
+            }
+        };
+
+        let recorder = Recorder::new(client, self.reporter.clone(), reference);
+        let event = KubeEvent {
+            type_: EventType::Warning,
+            reason: reason.to_string(),
+            note: Some(note.clone()),
+            action: "LookupSbom".into(),
+            secondary: None,
+        };
+        match recorder.publish(event).await {
+            Ok(()) => {
+                debug!(%image, namespace = %target.namespace, reason, "Emitted event on {} {}", target.kind, target.name);
+                true
+            }
+            Err(err) => {
+                warn!(%image, namespace = %target.namespace, "Failed to emit event: {err}");
+                false
+            }
+        }
+    }
+}
+
+/// the API resource of the kinds we can emit events on
+fn api_resource(kind: &str) -> Option<ApiResource> {
+    let (group, version) = match kind {
+        "Pod" => ("", "v1"),
+        "Deployment" | "StatefulSet" | "DaemonSet" | "ReplicaSet" => ("apps", "v1"),
+        "Job" | "CronJob" => ("batch", "v1"),
+        _ => return None,
+    };
+    Some(ApiResource::from_gvk(&GroupVersionKind::gvk(
+        group, version, kind,
+    )))
+}
+
+/// look up the reference to the object, `None` if it doesn't exist (anymore)
+async fn reference(
+    client: Client,
+    target: &Target,
+) -> Result<Option<ObjectReference>, kube::Error> {
+    let resource = match api_resource(&target.kind) {
+        Some(resource) => resource,
+        None => return Ok(None),
+    };
+
+    let api: Api<DynamicObject> = Api::namespaced_with(client, &target.namespace, &resource);
+    Ok(api
+        .get_opt(&target.name)
+        .await?
+        .map(|object| object.object_ref(&resource)))
+}
+
+/// truncate a string to a maximum number of bytes, respecting character boundaries
+fn truncate(value: &str, max: usize) -> String {
+    if value.len() <= max {
+        return value.to_string();
+    }
+
+    let mut end = max;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::{PodRef, WorkloadRef};
+
+    fn image(sbom: SbomState) -> Image {
+        Image {
+            pods: [PodRef {
+                cluster: None,
+                namespace: "default".into(),
+                name: "app-1234".into(),
+                node: None,
+                workload: Some(WorkloadRef {
+                    kind: "Deployment".into(),
+                    namespace: "default".into(),
+                    name: "app".into(),
+                }),
+            }]
+            .into(),
+            ..Image::new(sbom)
+        }
+    }
+
+    fn planner(capacity: usize) -> (Planner, mpsc::Receiver<Emission>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let planner = Planner {
+            clusters: [None].into(),
+            reported: Default::default(),
+            tx,
+        };
+        (planner, rx)
+    }
+
+    #[test]
+    fn emitted_once() {
+        let (mut planner, mut rx) = planner(16);
+        let image_ref: ImageRef = "quay.io/example/app:1.0".parse().unwrap();
+
+        planner.handle(&Event::Added(image_ref.clone(), image(SbomState::Missing)));
+        planner.handle(&Event::Modified(
+            image_ref.clone(),
+            image(SbomState::Missing),
+        ));
+
+        let emission = rx.try_recv().unwrap();
+        assert_eq!(emission.reason, "SbomMissing");
+        assert_eq!(
+            emission.target,
+            Target {
+                cluster: None,
+                namespace: "default".into(),
+                kind: "Deployment".into(),
+                name: "app".into(),
+            }
+        );
+        assert!(rx.try_recv().is_err());
+
+        // dropped again by the emitter, e.g. as emitting failed
+        planner
+            .reported
+            .lock()
+            .get_mut(&image_ref)
+            .unwrap()
+            .remove(&emission.target);
+        planner.handle(&Event::Modified(image_ref, image(SbomState::Missing)));
+        assert_eq!(rx.try_recv().unwrap(), emission);
+    }
+
+    #[test]
+    fn retried_when_queue_full() {
+        let (mut planner, mut rx) = planner(1);
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+
+        planner.handle(&Event::Added(first.clone(), image(SbomState::Missing)));
+        // the queue is full, this one is dropped
+        planner.handle(&Event::Added(second.clone(), image(SbomState::Missing)));
+        assert_eq!(rx.try_recv().unwrap().image, first);
+        assert!(rx.try_recv().is_err());
+
+        planner.handle(&Event::Modified(second.clone(), image(SbomState::Missing)));
+        assert_eq!(rx.try_recv().unwrap().image, second);
+    }
+
+    #[test]
+    fn unknown_cluster() {
+        let (mut planner, mut rx) = planner(16);
+        planner.clusters = [Some("other".to_string())].into();
+
+        let image_ref: ImageRef = "quay.io/example/app:1.0".parse().unwrap();
+        planner.handle(&Event::Added(image_ref, image(SbomState::Missing)));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use super::{pod_name, ExportedImage};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+pub const CONTENT_TYPE: &str = "application/vnd.cyclonedx+json";
+
+/// render the images as a CycloneDX (JSON) document
+///
+/// Each image becomes a `container` component, with the packages of its SBOM as nested
+/// components. The namespaces and pods using an image are added as properties.
+pub fn render(images: &[ExportedImage]) -> Value {
+    let mut dependencies = BTreeMap::<String, BTreeSet<String>>::new();
+
+    let components = images
+        .iter()
+        .map(|image| component(image, &mut dependencies))
+        .collect::<Vec<_>>();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+        "version": 1,
+        "metadata": {
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+        },
+        "components": components,
+        "dependencies": dependencies
+            .into_iter()
+            .map(|(reference, depends_on)| json!({"ref": reference, "dependsOn": depends_on}))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn component(
+    image: &ExportedImage,
+    dependencies: &mut BTreeMap<String, BTreeSet<String>>,
+) -> Value {
+    let bom_ref = image.image.to_string();
+    // package IDs are only unique inside their SBOM, so they are prefixed with the image
+    let id = |id: &str| format!("{bom_ref}#{id}");
+
+    let packages = &image.packages;
+    let known = packages
+        .packages
+        .iter()
+        .map(|p| p.id.as_str())
+        .collect::<HashSet<_>>();
+
+    // a document may list the same component more than once, but references must be unique
+    let mut seen = HashSet::new();
+    let components = packages
+        .packages
+        .iter()
+        .filter(|p| seen.insert(p.id.as_str()))
+        .map(|p| {
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": id(&p.id),
+                "name": p.name,
+            });
+            if let Some(version) = &p.version {
+                component["version"] = json!(version);
+            }
+            if let Some(purl) = &p.purl {
+                component["purl"] = json!(purl);
+            }
+            if !p.licenses.is_empty() {
+                component["licenses"] = p
+                    .licenses
+                    .iter()
+                    .map(|l| json!({ "expression": l }))
+                    .collect();
+            }
+            component
+        })
+        .collect::<Vec<_>>();
+
+    dependencies.insert(
+        bom_ref.clone(),
+        packages
+            .roots
+            .iter()
+            .filter(|root| known.contains(root.as_str()))
+            .map(|root| id(root))
+            .collect(),
+    );
+    for (from, to) in &packages.dependencies {
+        if known.contains(from.as_str()) && known.contains(to.as_str()) {
+            dependencies.entry(id(from)).or_default().insert(id(to));
+        }
+    }
+
+    let properties = image
+        .namespaces()
+        .into_iter()
+        .map(|ns| json!({"name": "bommer:namespace", "value": ns}))
+        .chain(
+            image
+                .pods
+                .iter()
+                .map(|pod| json!({"name": "bommer:pod", "value": pod_name(pod)})),
+        )
+        .collect::<Vec<_>>();
+
+    let mut component = json!({
+        "type": "container",
+        "bom-ref": bom_ref,
+        "name": format!("{}/{}", image.image.registry, image.image.repository),
+        "properties": properties,
+        "components": components,
+    });
+    if let Some(version) = image.image.digest.as_ref().or(image.image.tag.as_ref()) {
+        component["version"] = json!(version);
+    }
+    if let Some(purl) = &image.purl {
+        component["purl"] = json!(purl);
+    }
+    component
+}
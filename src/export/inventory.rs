@@ -0,0 +1,41 @@
+use bommer_api::data::{Image, ImageRef, SbomState};
+use std::collections::BTreeSet;
+
+pub const CONTENT_TYPE: &str = "text/csv";
+
+/// render the images as CSV, one row per image
+///
+/// Unlike the SBOM formats, this only needs the state of the workload, and not the SBOMs
+/// themselves.
+pub fn render(items: &[(ImageRef, Image)]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(["image", "digest", "namespaces", "pods", "sbom"])?;
+
+    for (image, state) in items {
+        let namespaces = state
+            .pods
+            .iter()
+            .map(|pod| pod.namespace.as_str())
+            .collect::<BTreeSet<_>>();
+
+        writer.write_record([
+            image.to_string(),
+            image.digest.clone().unwrap_or_default(),
+            namespaces.into_iter().collect::<Vec<_>>().join(" "),
+            state.pods.len().to_string(),
+            sbom_state(&state.sbom).to_string(),
+        ])?;
+    }
+
+    writer.into_inner().map_err(|err| err.into_error().into())
+}
+
+/// the state of the SBOM, using the same names as the filter
+fn sbom_state(state: &SbomState) -> &'static str {
+    match state {
+        SbomState::Scheduled => "scheduled",
+        SbomState::Err(_) => "err",
+        SbomState::Missing => "missing",
+        SbomState::Found(_) => "found",
+    }
+}
@@ -0,0 +1,82 @@
+//! Export of the workload as a single, aggregated SBOM, or an inventory of its images.
+//!
+//! The SBOM documents of all images are fetched from the document store, or the SBOM source, as
+//! we only keep their summaries. Each image becomes a package of its own, containing the packages of its SBOM.
+
+pub mod cyclonedx;
+pub mod inventory;
+pub mod spdx;
+
+use crate::documents::Documents;
+use crate::sbom::{self, Packages};
+use bommer_api::data::{Image, ImageRef, PodRef, SbomState, SbomSummary};
+use futures::{stream, StreamExt};
+use std::collections::{BTreeSet, HashMap};
+use tracing::warn;
+
+/// number of SBOM documents fetched concurrently
+const CONCURRENCY: usize = 8;
+
+/// An image of the workload, along with the packages of its SBOM
+#[derive(Clone, Debug)]
+pub struct ExportedImage {
+    pub image: ImageRef,
+    pub purl: Option<String>,
+    pub pods: BTreeSet<PodRef>,
+    /// The packages of its SBOM, empty if there is none or it couldn't be fetched
+    pub packages: Packages,
+}
+
+impl ExportedImage {
+    /// the namespaces the image is used in
+    pub fn namespaces(&self) -> BTreeSet<&str> {
+        self.pods.iter().map(|pod| pod.namespace.as_str()).collect()
+    }
+}
+
+/// fetch the SBOMs of the images, returning the images ordered by their reference
+pub async fn collect(documents: &Documents, state: HashMap<ImageRef, Image>) -> Vec<ExportedImage> {
+    let mut images = state.into_iter().collect::<Vec<_>>();
+    images.sort_by_cached_key(|(image, _)| image.to_string());
+
+    stream::iter(images)
+        .map(|(image, state)| async move {
+            let packages = match state.sbom {
+                SbomState::Found(summary) => packages(documents, &image, summary).await,
+                _ => Packages::default(),
+            };
+            ExportedImage {
+                purl: state.purl,
+                pods: state.pods.into_iter().collect(),
+                image,
+                packages,
+            }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn packages(documents: &Documents, image: &ImageRef, summary: SbomSummary) -> Packages {
+    let data = match documents.get(image, Some(&summary)).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return Packages::default(),
+        Err(err) => {
+            warn!(%image, "Failed to fetch SBOM for export: {err}");
+            return Packages::default();
+        }
+    };
+
+    sbom::packages(&data).unwrap_or_else(|err| {
+        warn!(%image, "Failed to parse SBOM for export: {err}");
+        Packages::default()
+    })
+}
+
+/// a readable reference to a pod, including its cluster if known
+pub fn pod_name(pod: &PodRef) -> String {
+    match &pod.cluster {
+        Some(cluster) => format!("{cluster}/{}/{}", pod.namespace, pod.name),
+        None => format!("{}/{}", pod.namespace, pod.name),
+    }
+}
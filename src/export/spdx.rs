@@ -0,0 +1,168 @@
+use super::{pod_name, ExportedImage};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub const CONTENT_TYPE: &str = "application/spdx+json";
+
+const CLUSTER_ID: &str = "SPDXRef-Cluster";
+
+/// render the images as an SPDX (JSON) document
+///
+/// The document describes a synthetic `cluster` package, which contains a package for each
+/// image, which in turn contains the packages of its SBOM. The namespaces and pods using an
+/// image are added as annotations.
+pub fn render(images: &[ExportedImage]) -> Value {
+    let tool = format!(
+        "Tool: {}-{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    // SPDX requires UTC, without fractional seconds: `YYYY-MM-DDThh:mm:ssZ`
+    let created = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut packages = vec![json!({
+        "SPDXID": CLUSTER_ID,
+        "name": "cluster",
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+    })];
+    let mut relationships = vec![relationship("SPDXRef-DOCUMENT", "DESCRIBES", CLUSTER_ID)];
+
+    for (n, image) in images.iter().enumerate() {
+        let image_id = format!("SPDXRef-Image-{n}");
+        relationships.push(relationship(CLUSTER_ID, "CONTAINS", &image_id));
+
+        let annotation = |comment: String| {
+            json!({
+                "annotationType": "OTHER",
+                "annotator": tool,
+                "annotationDate": created,
+                "comment": comment,
+            })
+        };
+        let annotations = image
+            .namespaces()
+            .into_iter()
+            .map(|ns| annotation(format!("bommer:namespace={ns}")))
+            .chain(
+                image
+                    .pods
+                    .iter()
+                    .map(|pod| annotation(format!("bommer:pod={}", pod_name(pod)))),
+            )
+            .collect::<Vec<_>>();
+
+        let mut container = package(
+            &image_id,
+            &format!("{}/{}", image.image.registry, image.image.repository),
+            image.image.digest.as_ref().or(image.image.tag.as_ref()),
+            image.purl.as_ref(),
+            &[],
+        );
+        container["primaryPackagePurpose"] = json!("CONTAINER");
+        container["annotations"] = json!(annotations);
+        packages.push(container);
+
+        // package IDs of the SBOM may not be valid SPDX IDs, so they are numbered instead
+        let mut ids = HashMap::new();
+        for p in &image.packages.packages {
+            if ids.contains_key(p.id.as_str()) {
+                continue;
+            }
+            let id = format!("{image_id}-{}", ids.len());
+            packages.push(package(
+                &id,
+                &p.name,
+                p.version.as_ref(),
+                p.purl.as_ref(),
+                &p.licenses,
+            ));
+            ids.insert(p.id.as_str(), id);
+        }
+
+        for root in &image.packages.roots {
+            if let Some(root) = ids.get(root.as_str()) {
+                relationships.push(relationship(&image_id, "CONTAINS", root));
+            }
+        }
+        for (from, to) in &image.packages.dependencies {
+            if let (Some(from), Some(to)) = (ids.get(from.as_str()), ids.get(to.as_str())) {
+                relationships.push(relationship(from, "DEPENDS_ON", to));
+            }
+        }
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "workload",
+        "documentNamespace": format!("https://spdx.org/spdxdocs/bommer-workload-{}", uuid::Uuid::new_v4()),
+        "creationInfo": {
+            "created": created,
+            "creators": [tool],
+        },
+        "documentDescribes": [CLUSTER_ID],
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn package(
+    id: &str,
+    name: &str,
+    version: Option<&String>,
+    purl: Option<&String>,
+    licenses: &[String],
+) -> Value {
+    let mut package = json!({
+        "SPDXID": id,
+        "name": name,
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+        "licenseDeclared": match licenses {
+            [] => "NOASSERTION".to_string(),
+            [license] => license.clone(),
+            licenses => licenses
+                .iter()
+                .map(|l| format!("({l})"))
+                .collect::<Vec<_>>()
+                .join(" AND "),
+        },
+    });
+    if let Some(version) = version {
+        package["versionInfo"] = json!(version);
+    }
+    if let Some(purl) = purl {
+        package["externalRefs"] = json!([{
+            "referenceCategory": "PACKAGE-MANAGER",
+            "referenceType": "purl",
+            "referenceLocator": purl,
+        }]);
+    }
+    package
+}
+
+fn relationship(from: &str, relationship: &str, to: &str) -> Value {
+    json!({
+        "spdxElementId": from,
+        "relationshipType": relationship,
+        "relatedSpdxElement": to,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn created_format() {
+        let document = render(&[]);
+        let created = document["creationInfo"]["created"].as_str().unwrap();
+        assert_eq!(created.len(), "2023-01-01T00:00:00Z".len(), "{created}");
+        assert!(created.ends_with('Z'), "{created}");
+        assert!(DateTime::parse_from_rfc3339(created).is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::source::{PurlConfig, Sbom, SbomSource};
+use bommer_api::data::{ImageRef, LookupError, LookupErrorKind, SbomFormat, SbomSummary};
+use chrono::{DateTime, Utc};
+use packageurl::PackageUrl;
+use reqwest::{StatusCode, Url};
+use serde_json::json;
+
+const QUERY: &str = r#"
+query HasSBOM($spec: HasSBOMSpec!) {
+  HasSBOM(hasSBOMSpec: $spec) {
+    uri
+    knownSince
+    collector
+    includedSoftware {
+      __typename
+    }
+  }
+}
+"#;
+
+/// Looks up SBOMs ingested into GUAC
+///
+/// GUAC only keeps the metadata of an SBOM, along with the packages it contains, so the summary
+/// is built from that.
+#[derive(Clone, Debug)]
+pub struct GuacSource {
+    url: Url,
+    client: reqwest::Client,
+    purls: PurlConfig,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Server error: {0}")]
+    Server(StatusCode),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("Query failed: {0}")]
+    Query(String),
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Response {
+    #[serde(default)]
+    data: Option<Data>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Data {
+    #[serde(rename = "HasSBOM")]
+    has_sbom: Vec<HasSbom>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HasSbom {
+    uri: String,
+    #[serde(default)]
+    known_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    collector: Option<String>,
+    #[serde(default)]
+    included_software: Vec<serde::de::IgnoredAny>,
+}
+
+impl GuacSource {
+    pub fn new(url: Url, purls: PurlConfig, client: reqwest::Client) -> Self {
+        Self { url, client, purls }
+    }
+
+    /// query the SBOMs of a package, the most recent one wins
+    async fn query(&self, purl: &PackageUrl<'_>) -> Result<Option<SbomSummary>, Error> {
+        let variables = json!({
+            "spec": {
+                "subject": {
+                    "package": {
+                        "type": purl.ty(),
+                        "namespace": purl.namespace(),
+                        "name": purl.name(),
+                        "version": purl.version(),
+                    }
+                }
+            }
+        });
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .json(&json!({ "query": QUERY, "variables": variables }))
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {}
+            status if status.is_server_error() => return Err(Error::Server(status)),
+            status => return Err(Error::UnexpectedStatus(status)),
+        }
+
+        let response: Response = response.json().await?;
+
+        if !response.errors.is_empty() {
+            return Err(Error::Query(
+                response
+                    .errors
+                    .into_iter()
+                    .map(|err| err.message)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ));
+        }
+
+        Ok(response
+            .data
+            .into_iter()
+            .flat_map(|data| data.has_sbom)
+            .max_by_key(|sbom| sbom.known_since)
+            .map(|sbom| SbomSummary {
+                format: SbomFormat::Unknown,
+                version: None,
+                name: Some(sbom.uri),
+                packages: sbom.included_software.len(),
+                licenses: vec![],
+                tools: sbom.collector.into_iter().collect(),
+                created: sbom.known_since,
+                purl: Some(purl.to_string()),
+                source: None,
+                digest: None,
+                subjects: vec![],
+                verified: false,
+                license_ids: vec![],
+                quality: None,
+            }))
+    }
+}
+
+#[async_trait::async_trait]
+impl SbomSource for GuacSource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        let purl = self.purls.purl(image)?;
+
+        self.query(&purl)
+            .await
+            .map(|sbom| sbom.map(Sbom::from))
+            .map_err(|err| LookupError {
+                kind: match &err {
+                    Error::Transport(_) => LookupErrorKind::Transport,
+                    Error::Server(_) => LookupErrorKind::Server,
+                    Error::UnexpectedStatus(_) | Error::Query(_) => {
+                        LookupErrorKind::UnexpectedStatus
+                    }
+                },
+                status: match &err {
+                    Error::Server(status) | Error::UnexpectedStatus(status) => {
+                        Some(status.as_u16())
+                    }
+                    _ => None,
+                },
+                message: err.to_string(),
+            })
+    }
+}
@@ -0,0 +1,19 @@
+mod client;
+
+pub use client::GuacSource;
+
+use url::Url;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "GUAC")]
+pub struct GuacConfig {
+    /// The URL of the GUAC GraphQL endpoint
+    #[arg(
+        id = "guac_url",
+        value_name = "URL",
+        long = "guac-url",
+        env = "GUAC_URL",
+        default_value = "http://localhost:8080/query"
+    )]
+    pub url: Url,
+}
@@ -0,0 +1,201 @@
+use anyhow::Context;
+use bytes::{Bytes, BytesMut};
+use reqwest::{Certificate, NoProxy, Proxy};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Configuration of the HTTP client, shared by all outbound connections
+///
+/// Without an explicit proxy, the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables
+/// are used.
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "HTTP client")]
+pub struct HttpConfig {
+    /// Proxy to use for all outbound HTTP(S) connections, overriding the proxy environment variables
+    #[arg(long = "http-proxy", env = "HTTP_CLIENT_PROXY")]
+    pub proxy: Option<Url>,
+
+    /// Hosts to connect to without the proxy, in the same format as `NO_PROXY`, which it defaults to
+    #[arg(
+        long = "http-no-proxy",
+        env = "HTTP_CLIENT_NO_PROXY",
+        requires = "proxy"
+    )]
+    pub no_proxy: Option<String>,
+
+    /// Additional CA certificates to trust, in PEM format, e.g. of a TLS intercepting proxy
+    #[arg(
+        long = "http-ca-bundle",
+        env = "HTTP_CLIENT_CA_BUNDLES",
+        value_delimiter = ','
+    )]
+    pub ca_bundles: Vec<PathBuf>,
+}
+
+impl HttpConfig {
+    /// create the HTTP client
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(proxy) = &self.proxy {
+            let no_proxy = match &self.no_proxy {
+                Some(no_proxy) => NoProxy::from_string(no_proxy),
+                None => NoProxy::from_env(),
+            };
+            builder = builder.proxy(Proxy::all(proxy.clone())?.no_proxy(no_proxy));
+        }
+
+        for bundle in &self.ca_bundles {
+            for cert in load(bundle)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// load all certificates of a PEM bundle
+fn load(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    ))
+    .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "No certificates found in {}",
+        path.display()
+    );
+
+    Ok(certs
+        .iter()
+        .map(|cert| Certificate::from_der(cert))
+        .collect::<Result<_, _>>()?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Response exceeds the limit of {0} bytes")]
+    TooLarge(u64),
+}
+
+/// read the body of a response, failing as soon as it exceeds the limit
+///
+/// The body is read chunk by chunk, instead of buffering it in full first. A `Content-Length`
+/// exceeding the limit fails right away.
+pub async fn read_limited(mut response: reqwest::Response, limit: u64) -> Result<Bytes, BodyError> {
+    if matches!(response.content_length(), Some(len) if len > limit) {
+        return Err(BodyError::TooLarge(limit));
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(BodyError::TooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+/// join a path to the URL of a service, keeping the path the service is served on
+///
+/// Unlike [`Url::join`], this treats the last segment of the base as a directory even without a
+/// trailing slash, and the path as relative to it even with a leading one. So that joining
+/// `https://example.com/bombastic` and `api/v1/sbom` results in
+/// `https://example.com/bombastic/api/v1/sbom`, not `https://example.com/api/v1/sbom`.
+pub fn join(base: &Url, path: &str) -> Result<Url, url::ParseError> {
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join(path.trim_start_matches('/'))
+}
+
+/// parse a size in bytes, optionally using a binary unit (e.g. `512KiB`, `64MiB`, `1GiB`)
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, factor) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => match value[pos..].trim() {
+            "B" => (&value[..pos], 1),
+            "KiB" => (&value[..pos], 1 << 10),
+            "MiB" => (&value[..pos], 1 << 20),
+            "GiB" => (&value[..pos], 1 << 30),
+            unit => return Err(format!("Unknown unit: {unit}")),
+        },
+        None => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .map_err(|err| err.to_string())?
+        .checked_mul(factor)
+        .ok_or_else(|| "Size is too large".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn join_path() {
+        let join = |base: &str, path| {
+            super::join(&base.parse().unwrap(), path)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            join("https://example.com", "/api/v1/sbom"),
+            "https://example.com/api/v1/sbom"
+        );
+        assert_eq!(
+            join("https://example.com/", "api/v1/sbom"),
+            "https://example.com/api/v1/sbom"
+        );
+        assert_eq!(
+            join("https://example.com/bombastic", "/api/v1/sbom"),
+            "https://example.com/bombastic/api/v1/sbom"
+        );
+        assert_eq!(
+            join("https://example.com/bombastic/", "api/v1/sbom"),
+            "https://example.com/bombastic/api/v1/sbom"
+        );
+        // the query of the base doesn't carry over
+        assert_eq!(
+            join("https://example.com/bombastic?a=b", "api/v1/sbom"),
+            "https://example.com/bombastic/api/v1/sbom"
+        );
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_size("64MiB"), Ok(64 * 1024 * 1024));
+        assert_eq!(parse_size("1GiB"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size(" 64 MiB "), Ok(64 * 1024 * 1024));
+    }
+
+    #[test]
+    fn size_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MiB").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("1.5MiB").is_err());
+        assert_eq!(parse_size("1MB"), Err("Unknown unit: MB".to_string()));
+        assert_eq!(parse_size("1mib"), Err("Unknown unit: mib".to_string()));
+        assert_eq!(
+            parse_size(&format!("{}GiB", u64::MAX)),
+            Err("Size is too large".to_string())
+        );
+        assert!(parse_size("99999999999999999999").is_err());
+    }
+}
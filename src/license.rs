@@ -0,0 +1,22 @@
+//! Policies for the licenses of the packages in SBOMs.
+
+#[derive(Clone, Debug, Default, clap::Args)]
+#[command(next_help_heading = "Licenses")]
+pub struct LicenseConfig {
+    /// License identifiers which must not be used (e.g. `AGPL-3.0-only`), flagged when reporting licenses
+    #[arg(
+        long = "forbidden-license",
+        env = "FORBIDDEN_LICENSES",
+        value_delimiter = ','
+    )]
+    pub forbidden_licenses: Vec<String>,
+}
+
+impl LicenseConfig {
+    /// check if a license identifier is forbidden, ignoring its case
+    pub fn is_forbidden(&self, license: &str) -> bool {
+        self.forbidden_licenses
+            .iter()
+            .any(|forbidden| forbidden.eq_ignore_ascii_case(license))
+    }
+}
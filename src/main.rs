@@ -1,87 +1,600 @@
+mod aggregator;
+mod backend;
 mod bombastic;
+mod cli;
+mod cloudevents;
+mod coverage;
+mod dependency_track;
+mod documents;
+mod events;
+mod export;
+mod guac;
+mod http;
+mod license;
+mod notify;
+mod publish;
 mod pubsub;
+mod registry;
+mod report;
+mod sbom;
+mod scanner;
+mod search;
 mod server;
+mod shutdown;
+mod snapshot;
+mod source;
+mod stats;
 mod store;
+mod vexination;
 mod workload;
 
-use crate::bombastic::BombasticSource;
-use crate::server::ServerConfig;
-use crate::store::image_store;
-use futures::FutureExt;
-use k8s_openapi::api::core::v1::Pod;
-use kube::{runtime::watcher, Api, Client};
-use tracing::{info, warn};
+use crate::aggregator::Aggregator;
+use crate::backend::BackendKind;
+use crate::bombastic::{BombasticSource, TokenProvider};
+use crate::cli::{Cli, LogFormat, Track};
+use crate::dependency_track::DependencyTrackSource;
+use crate::documents::Documents;
+use crate::guac::GuacSource;
+use crate::registry::{DigestResolver, RegistrySource};
+use crate::scanner::{CircuitBreaker, SbomCache, ScanLog, ScannerContext};
+use crate::search::PackageIndex;
+use crate::server::ServerContext;
+use crate::source::{AggregateSource, FallbackSource, SbomSource, SourceKind};
+use crate::stats::CoverageHistory;
+use crate::store::{
+    image_store, pod_watcher, resyncing, Checkpoint, Checkpoints, Fingerprint, ImageFilter,
+    ImagePattern, JobSource, NodeImageSource, NodeResolver, PodEvent, PodFilter, PodSource, Resync,
+    WatchState, WorkloadResolver,
+};
+use crate::vexination::VexinationSource;
+use crate::workload::WorkloadState;
+use bommer_api::data::Event;
+use clap::Parser;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use k8s_openapi::api::batch::v1::{CronJob, Job};
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube::{config::KubeConfigOptions, runtime::watcher, Api, Client};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::pin::pin;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn, Level};
+use tracing_subscriber::EnvFilter;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+type PodStream = BoxStream<'static, Result<PodEvent, watcher::Error>>;
 
-    let client = Client::try_default().await?;
+/// What the pod sources watch, and how they report it, the same for all clusters
+struct PodSettings {
+    filter: PodFilter,
+    images: ImageFilter,
+    node_arch: bool,
+    pending: bool,
+    collapse_jobs: bool,
+    digests: Option<DigestResolver>,
+}
 
-    let api: Api<Pod> = Api::all(client);
+impl PodSettings {
+    /// the fingerprint of a watcher of the cluster, scoped to the namespace if any
+    fn fingerprint(
+        &self,
+        cluster: Option<String>,
+        namespace: Option<String>,
+        config: &watcher::Config,
+    ) -> Fingerprint {
+        Fingerprint {
+            cluster,
+            namespace,
+            label_selector: config.label_selector.clone(),
+            field_selector: config.field_selector.clone(),
+            include_images: patterns(&self.images.include),
+            exclude_images: patterns(&self.images.exclude),
+            node_arch: self.node_arch,
+            pending: self.pending,
+            resolve_digests: self.digests.is_some(),
+            collapse_jobs: self.collapse_jobs,
+        }
+    }
+}
+
+/// create the pod sources for a cluster
+///
+/// With an explicit list of namespaces, we run one watcher per namespace, which allows using
+/// namespaced roles. Otherwise, we watch the whole cluster.
+///
+/// Watchers with a persisted state resume watching from where they left off.
+fn pod_sources(
+    client: Client,
+    cluster: Option<String>,
+    settings: &PodSettings,
+    watches: &[WatchState],
+    resync: &Resync,
+) -> Vec<PodSource<PodStream>> {
+    let filter = &settings.filter;
 
-    let stream = watcher(
-        api,
-        watcher::Config {
+    // the namespace, filter, API, and watcher config of each source
+    let scopes = if filter.include_namespaces.is_empty() {
+        info!(
+            ?cluster,
+            "Watching all namespaces, excluding: {:?}", filter.exclude_namespaces
+        );
+        let config = watcher::Config {
+            field_selector: filter.field_selector(),
+            label_selector: filter.label_selector.as_ref().map(|s| s.to_string()),
             ..Default::default()
-        },
-    );
+        };
+        vec![(None, filter.clone(), Api::all(client.clone()), config)]
+    } else {
+        filter
+            .include_namespaces
+            .iter()
+            .filter(|namespace| filter.matches_namespace(namespace))
+            .map(|namespace| {
+                info!(?cluster, "Watching namespace: {namespace}");
+                let config = watcher::Config {
+                    label_selector: filter.label_selector.as_ref().map(|s| s.to_string()),
+                    ..Default::default()
+                };
+                let filter = PodFilter {
+                    label_selector: filter.label_selector.clone(),
+                    ..PodFilter::namespace(namespace)
+                };
+                let api = Api::namespaced(client.clone(), namespace);
+                (Some(namespace.clone()), filter, api, config)
+            })
+            .collect()
+    };
 
-    let url =
-        std::env::var("BOMBASTIC_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-    let source = BombasticSource::new(url.parse()?);
+    let nodes = settings
+        .node_arch
+        .then(|| NodeResolver::new(client.clone()));
 
-    let (store, runner) = image_store(stream);
+    scopes
+        .into_iter()
+        .map(|(namespace, filter, api, config)| {
+            let checkpoint = Checkpoint::new(
+                &settings.fingerprint(cluster.clone(), namespace, &config),
+                filter.clone(),
+            );
+            let resume = checkpoint.find(watches).cloned();
+            PodSource {
+                cluster: cluster.clone(),
+                filter,
+                images: settings.images.clone(),
+                resolver: WorkloadResolver::new(client.clone()),
+                nodes: nodes.clone(),
+                digests: settings.digests.clone(),
+                pending: settings.pending,
+                collapse_jobs: settings.collapse_jobs,
+                checkpoint,
+                stream: watch_pods(resync, api, config, resume.as_ref()),
+                resume,
+            }
+        })
+        .collect()
+}
 
-    if false {
-        let store = store.clone();
-        tokio::spawn(async move {
-            loop {
-                info!("Starting event stream");
-                let mut sub = store.subscribe(16).await;
-                while let Some(evt) = sub.recv().await {
-                    info!("Event: {evt:?}");
+/// the regular expressions of image patterns
+fn patterns(patterns: &[ImagePattern]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|pattern| pattern.as_str().to_string())
+        .collect()
+}
+
+/// watch pods, resuming from the persisted state only initially, not when resyncing
+fn watch_pods(
+    resync: &Resync,
+    api: Api<Pod>,
+    config: watcher::Config,
+    resume: Option<&WatchState>,
+) -> PodStream {
+    let mut resume = resume.map(|r| r.resource_version.clone());
+    resyncing(resync, move || {
+        pod_watcher(api.clone(), config.clone(), resume.take())
+    })
+    .boxed()
+}
+
+/// create the job sources for a cluster, following the same namespace strategy as for pods
+fn job_sources(
+    client: Client,
+    cluster: Option<String>,
+    filter: &PodFilter,
+    images: &ImageFilter,
+    resync: &Resync,
+) -> Vec<JobSource> {
+    if filter.include_namespaces.is_empty() {
+        let config = watcher::Config {
+            field_selector: filter.field_selector(),
+            ..Default::default()
+        };
+        vec![JobSource {
+            cluster,
+            filter: filter.clone(),
+            images: images.clone(),
+            cron_jobs: {
+                let api = Api::<CronJob>::all(client.clone());
+                let config = config.clone();
+                resyncing(resync, move || watcher(api.clone(), config.clone())).boxed()
+            },
+            jobs: {
+                let api = Api::<Job>::all(client);
+                resyncing(resync, move || watcher(api.clone(), config.clone())).boxed()
+            },
+        }]
+    } else {
+        filter
+            .include_namespaces
+            .iter()
+            .filter(|namespace| filter.matches_namespace(namespace))
+            .map(|namespace| {
+                info!(?cluster, "Watching jobs of namespace: {namespace}");
+                JobSource {
+                    cluster: cluster.clone(),
+                    filter: PodFilter {
+                        label_selector: filter.label_selector.clone(),
+                        ..PodFilter::namespace(namespace)
+                    },
+                    images: images.clone(),
+                    cron_jobs: {
+                        let api = Api::<CronJob>::namespaced(client.clone(), namespace);
+                        resyncing(resync, move || watcher(api.clone(), Default::default())).boxed()
+                    },
+                    jobs: {
+                        let api = Api::<Job>::namespaced(client.clone(), namespace);
+                        resyncing(resync, move || watcher(api.clone(), Default::default())).boxed()
+                    },
                 }
+            })
+            .collect()
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::try_new(&cli.log_level)?);
+    match cli.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).init(),
+    }
+
+    let metrics = PrometheusBuilder::new().install_recorder()?;
+
+    let http = cli.http.client()?;
+
+    let registry = cli.registry.client(http.clone())?;
+
+    // persisted state, allowing the watchers to resume
+
+    let persistence = cli.snapshot.open()?;
+    let snapshot = persistence.load();
+
+    // with a list of kubeconfig contexts, we watch each of the clusters. Otherwise, only the
+    // default one.
+
+    let mut clusters = Vec::new();
+
+    if cli.agent.aggregator_url.is_some() && cli.watcher.contexts.len() > 1 {
+        anyhow::bail!("An agent can only watch a single cluster");
+    }
+
+    if cli.backend.replica {
+        if cli.backend.state_backend == BackendKind::Memory {
+            anyhow::bail!("A replica requires a shared state backend");
+        }
+        if cli.agent.aggregator_url.is_some() || cli.aggregator.aggregator {
+            anyhow::bail!("A replica can't be an agent or aggregator");
+        }
+        // the clusters are watched, and the SBOMs looked up, by the instance storing the state
+        info!("Running as replica, following the state of the backend");
+    } else if cli.aggregator.aggregator {
+        // the clusters are watched by the agents
+        info!("Running as aggregator, receiving the workload of clusters from agents");
+    } else if cli.watcher.contexts.is_empty() {
+        clusters.push((None, Client::try_default().await?));
+    } else {
+        for context in &cli.watcher.contexts {
+            info!("Connecting to cluster: {context}");
+            let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
+                context: Some(context.clone()),
+                ..Default::default()
+            })
+            .await?;
+            clusters.push((Some(context.clone()), Client::try_from(config)?));
+        }
+    }
+
+    let node_arch = cli.watcher.node_arch;
+    let settings = PodSettings {
+        filter: cli.watcher.filter(),
+        images: cli.watcher.image_filter(),
+        node_arch,
+        pending: cli.watcher.pending,
+        collapse_jobs: cli.watcher.tracks(Track::Jobs),
+        digests: (cli.registry.resolve_digests || node_arch)
+            .then(|| DigestResolver::new(registry.clone(), cli.registry.resolve_digests)),
+    };
+
+    let mut sources = Vec::new();
+    let mut jobs = Vec::new();
+    let mut nodes = Vec::new();
+    let resync = Resync::default();
+
+    for (cluster, client) in &clusters {
+        if cli.watcher.tracks(Track::Pods) {
+            sources.extend(pod_sources(
+                client.clone(),
+                cluster.clone(),
+                &settings,
+                &snapshot.watches,
+                &resync,
+            ));
+        }
+        if cli.watcher.tracks(Track::Jobs) {
+            jobs.extend(job_sources(
+                client.clone(),
+                cluster.clone(),
+                &settings.filter,
+                &settings.images,
+                &resync,
+            ));
+        }
+        if cli.watcher.tracks(Track::Nodes) {
+            info!(?cluster, "Watching images of nodes");
+            nodes.push(NodeImageSource {
+                cluster: cluster.clone(),
+                images: settings.images.clone(),
+                arch: settings.node_arch,
+                stream: {
+                    let api = Api::<Node>::all(client.clone());
+                    resyncing(&resync, move || watcher(api.clone(), Default::default())).boxed()
+                },
+            });
+        }
+    }
+
+    let tokens = TokenProvider::new(cli.bombastic.auth, http.clone()).await?;
+    let vexination = cli
+        .vexination
+        .url
+        .map(|url| VexinationSource::new(url, tokens.clone(), http.clone()));
+    let source: Arc<dyn SbomSource> = match cli.source.kind {
+        SourceKind::Bombastic => {
+            let mut sources = cli
+                .bombastic
+                .urls
+                .into_iter()
+                .map(|url| {
+                    Arc::new(BombasticSource::new(
+                        url,
+                        tokens.clone(),
+                        cli.source.purl.clone(),
+                        cli.source.max_sbom_size,
+                        http.clone(),
+                    )) as Arc<dyn SbomSource>
+                })
+                .collect::<Vec<_>>();
+            match sources.len() {
+                1 => sources.remove(0),
+                _ => Arc::new(AggregateSource {
+                    sources,
+                    parallel: cli.bombastic.parallel,
+                }),
             }
-        });
+        }
+        SourceKind::Guac => Arc::new(GuacSource::new(
+            cli.guac.url,
+            cli.source.purl.clone(),
+            http.clone(),
+        )),
+        SourceKind::DependencyTrack => Arc::new(DependencyTrackSource::new(
+            cli.dependency_track,
+            cli.source.purl.clone(),
+            http.clone(),
+        )),
+    };
+
+    let source: Arc<dyn SbomSource> = match cli.registry.fallback {
+        true => Arc::new(FallbackSource {
+            primary: source,
+            fallback: Arc::new(RegistrySource::new(registry, cli.source.max_sbom_size)),
+        }),
+        false => source,
+    };
+
+    let checkpoints = sources
+        .iter()
+        .map(|source| source.checkpoint.clone())
+        .collect();
+    // replicas are synced by following the backend, the aggregator by the agents
+    let external = usize::from(cli.backend.replica || cli.aggregator.aggregator);
+    let (store, runner) = image_store(sources, jobs, nodes, external);
+    let checkpoints = Checkpoints::new(store.clone(), checkpoints);
+
+    if let Some(agent) = cli.agent.agent(http.clone()).await? {
+        // an agent only watches its cluster, the aggregator takes care of everything else
+        let shutdown = CancellationToken::new();
+        let until = |task| shutdown::until(&shutdown, task);
+        futures::future::try_join_all([
+            shutdown::on_signal(shutdown.clone()).boxed_local(),
+            server::run_agent(
+                cli.server,
+                store.sync_state().clone(),
+                metrics,
+                shutdown.clone(),
+            )
+            .boxed_local(),
+            until(runner.boxed_local()).boxed_local(),
+            until(agent.run(store).boxed_local()).boxed_local(),
+        ])
+        .await?;
+        return Ok(());
     }
 
+    let aggregator = cli
+        .aggregator
+        .aggregator
+        .then(|| Aggregator::new(&cli.aggregator, store.clone()));
+
     // SBOM scanner
 
-    let (map, runner2) = bombastic::store(store.clone(), source);
+    let shutdown = CancellationToken::new();
+
+    let breaker = CircuitBreaker::new(cli.scanner.breaker.clone());
+    let history = ScanLog::new(cli.scanner.history.clone());
+    let cache = SbomCache::new(cli.scanner.cache.clone());
+    let documents = cli.documents.store(http.clone());
+    let (map, runner2) = match cli.backend.replica {
+        // the state is filled by following the backend
+        true => (
+            WorkloadState::new(cli.buffers.consumer_buffer),
+            futures::future::ok(()).boxed_local(),
+        ),
+        false => {
+            let context = ScannerContext {
+                source: source.clone(),
+                breaker: breaker.clone(),
+                history: history.clone(),
+                cache: cache.clone(),
+                vexination,
+                purls: cli.source.purl.clone(),
+                documents: documents.clone(),
+            };
+            let (map, runner) = scanner::store(
+                store.clone(),
+                context,
+                cli.scanner,
+                cli.buffers,
+                snapshot.images.clone(),
+                cli.watcher.removal_grace,
+                shutdown.clone(),
+            );
+            (map, runner.boxed_local())
+        }
+    };
+    let runner3 = persistence.clone().run(map.clone(), checkpoints.clone());
+    let runner4 = cli
+        .report
+        .run(map.clone(), store.sync_state().clone(), clusters.clone());
+    let runner5 = cli.events.run(map.clone(), clusters);
+    let envelope = cli.cloudevents.envelope();
+    let runner6 = cli.notify.run(
+        map.clone(),
+        store.sync_state().clone(),
+        snapshot.images.clone(),
+        http.clone(),
+        envelope.clone(),
+        cli.buffers.notify_buffer,
+    );
+    let runner7 = cli
+        .coverage
+        .run(map.clone(), store.sync_state().clone(), http.clone());
+    let coverage = CoverageHistory::new(cli.stats);
+    let runner8 = coverage
+        .clone()
+        .run(map.clone(), store.sync_state().clone());
+    let documents = Documents::new(documents, source);
+    let index = match cli.search.package_index {
+        true => Some(PackageIndex::new()?),
+        false => None,
+    };
+    let runner9 = match index.clone() {
+        Some(index) => index.run(map.clone(), documents.clone()).boxed_local(),
+        None => futures::future::pending().boxed_local(),
+    };
+    let runner10 = cli.publish.run(map.clone(), envelope);
+    let runner11 = cli.backend.run(map.clone(), store.sync_state().clone());
 
-    {
+    // only subscribe when the changes get logged
+    if tracing::enabled!(Level::DEBUG) {
         let map = map.clone();
         tokio::spawn(async move {
             loop {
-                info!("Starting SBOM stream");
-                let mut sub = map.subscribe(16).await;
+                debug!("Starting SBOM stream");
+                let mut sub = map.subscribe("debug", None).await;
                 while let Some(evt) = sub.recv().await {
-                    info!("Event: {evt:?}");
+                    match &*evt {
+                        Event::Added(image, state) => {
+                            debug!(event = "added", %image, sbom = ?state.sbom, "Image added")
+                        }
+                        Event::Modified(image, state) => {
+                            debug!(event = "modified", %image, sbom = ?state.sbom, "Image modified")
+                        }
+                        Event::Removed(image) => debug!(event = "removed", %image, "Image removed"),
+                        Event::Restart(state) => {
+                            debug!(event = "restart", images = state.len(), "Images restarted")
+                        }
+                    }
                 }
-                warn!("Lost debug subscription");
+                debug!("Lost debug subscription");
             }
         });
     }
 
     // server
 
-    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "[::]:8080".to_string());
-
-    info!("Binding to {bind_addr}");
+    let context = ServerContext {
+        map: map.clone(),
+        store: store.clone(),
+        breaker,
+        history,
+        coverage,
+        documents,
+        index,
+        licenses: cli.licenses,
+        aggregator,
+        cache,
+        resync,
+        metrics,
+    };
+    let server = server::run(
+        cli.server,
+        context,
+        cli.buffers.subscriber_buffer,
+        http,
+        shutdown.clone(),
+    );
 
-    let config = ServerConfig { bind_addr };
+    // On shutdown, the watchers and other tasks are stopped right away. The scanner finishes its
+    // current scan, and the server closes its sessions. Only then the final state is persisted.
+    let until = |task| shutdown::until(&shutdown, task);
+    let runners = futures::future::try_join_all([
+        shutdown::on_signal(shutdown.clone()).boxed_local(),
+        server.boxed_local(),
+        until(runner.boxed_local()).boxed_local(),
+        runner2,
+        until(runner3.boxed_local()).boxed_local(),
+        until(runner4.boxed_local()).boxed_local(),
+        until(runner5.boxed_local()).boxed_local(),
+        until(runner6.boxed_local()).boxed_local(),
+        until(runner7.boxed_local()).boxed_local(),
+        until(runner8.boxed_local()).boxed_local(),
+        until(runner9.boxed_local()).boxed_local(),
+        until(runner10.boxed_local()).boxed_local(),
+        until(runner11.boxed_local()).boxed_local(),
+    ]);
 
-    let server = server::run(config, map);
+    let mut stopped = pin!(async {
+        runners.await?;
+        persistence.flush(&checkpoints).await
+    });
 
-    let (result, _, _) = futures::future::select_all([
-        server.boxed_local(),
-        runner.boxed_local(),
-        runner2.boxed_local(),
-    ])
-    .await;
+    tokio::select! {
+        // one of the tasks failed
+        result = &mut stopped => return result,
+        _ = shutdown.cancelled() => {}
+    }
 
-    result?;
+    match tokio::time::timeout(cli.shutdown.shutdown_timeout, stopped).await {
+        Ok(result) => result?,
+        Err(_) => warn!(
+            "Shutting down took longer than {}, exiting anyway",
+            humantime::format_duration(cli.shutdown.shutdown_timeout)
+        ),
+    }
 
     Ok(())
 }
@@ -0,0 +1,288 @@
+//! Formatting notifications for the different kinds of webhooks.
+
+use super::{Details, Notification};
+use crate::cloudevents::Envelope;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// number of images listed per namespace, in chat messages
+const MAX_IMAGES: usize = 10;
+
+/// number of blocks Slack accepts in a single message
+const MAX_SLACK_BLOCKS: usize = 50;
+
+const TITLE: &str = "SBOM coverage gaps";
+
+/// The format a webhook expects
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The notifications, as they are
+    Json,
+    /// A Slack message (incoming webhook)
+    Slack,
+    /// A Microsoft Teams message, carrying an adaptive card
+    Teams,
+}
+
+impl Format {
+    /// if notifications get collected into a single message
+    pub fn batched(&self) -> bool {
+        !matches!(self, Self::Json)
+    }
+
+    /// the content type of the bodies
+    pub fn content_type(&self, envelope: &Envelope) -> &'static str {
+        match self {
+            Self::Json => envelope.content_type(),
+            _ => "application/json",
+        }
+    }
+
+    /// render a batch of notifications into the bodies to send
+    pub fn render(&self, batch: &[Notification], envelope: &Envelope) -> Vec<Value> {
+        match self {
+            Self::Json => batch
+                .iter()
+                .map(|n| {
+                    envelope.wrap(
+                        &format!("notification.{}", n.details.kind().as_str()),
+                        n.details.image(),
+                        n.timestamp,
+                        n,
+                    )
+                })
+                .collect(),
+            Self::Slack => slack(&Summary::new(batch)),
+            Self::Teams => vec![teams(&Summary::new(batch))],
+        }
+    }
+}
+
+/// The gaps of a batch of notifications, by namespace
+struct Summary<'a> {
+    /// images (along with the reason) by namespace
+    namespaces: BTreeMap<&'a str, Vec<(&'a str, String)>>,
+    /// the most recent drop of the coverage
+    coverage: Option<String>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(batch: &'a [Notification]) -> Self {
+        let mut namespaces = BTreeMap::<_, Vec<_>>::new();
+        let mut coverage = None;
+
+        for notification in batch {
+            let (image, namespaces_of, reason) = match &notification.details {
+                Details::MissingSbom {
+                    image, namespaces, ..
+                } => (image, namespaces, "no SBOM".to_string()),
+                Details::ScanFailed {
+                    image,
+                    namespaces,
+                    error,
+                    ..
+                } => (image, namespaces, format!("lookup failed: {error}")),
+                Details::Coverage {
+                    coverage: value,
+                    threshold,
+                    images,
+                    found,
+                } => {
+                    coverage = Some(format!(
+                        "Only {found} of {images} images ({value:.1}%) have an SBOM, below the threshold of {threshold}%"
+                    ));
+                    continue;
+                }
+            };
+
+            for namespace in namespaces_of {
+                namespaces
+                    .entry(namespace.as_str())
+                    .or_default()
+                    .push((image.as_str(), reason.clone()));
+            }
+        }
+
+        Self {
+            namespaces,
+            coverage,
+        }
+    }
+
+    /// the gaps of each namespace, as a list using the bullet
+    fn lists<'b>(&'b self, bullet: &'b str) -> impl Iterator<Item = (&'a str, String)> + 'b {
+        self.namespaces.iter().map(move |(namespace, images)| {
+            let mut lines = images
+                .iter()
+                .take(MAX_IMAGES)
+                .map(|(image, reason)| format!("{bullet} `{image}` ({reason})"))
+                .collect::<Vec<_>>();
+            if images.len() > MAX_IMAGES {
+                lines.push(format!("{bullet} … and {} more", images.len() - MAX_IMAGES));
+            }
+            (*namespace, lines.join("\n"))
+        })
+    }
+
+    /// a single line summary
+    fn text(&self) -> String {
+        let images = self.namespaces.values().map(Vec::len).sum::<usize>();
+        match (&self.coverage, images) {
+            (Some(coverage), 0) => coverage.clone(),
+            (_, images) => format!(
+                "{TITLE}: {images} image(s) in {} namespace(s)",
+                self.namespaces.len()
+            ),
+        }
+    }
+}
+
+/// render the summary into Slack messages, split into several ones if it has too many blocks
+fn slack(summary: &Summary) -> Vec<Value> {
+    let mut sections = vec![];
+
+    if let Some(coverage) = &summary.coverage {
+        sections.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!(":warning: {coverage}") },
+        }));
+    }
+
+    // slack doesn't support markdown lists
+    for (namespace, list) in summary.lists("•") {
+        sections.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!("*{namespace}*\n{list}") },
+        }));
+    }
+
+    // each message starts with a header
+    sections
+        .chunks(MAX_SLACK_BLOCKS - 1)
+        .enumerate()
+        .map(|(index, sections)| {
+            let title = match index {
+                0 => TITLE.to_string(),
+                _ => format!("{TITLE} (continued)"),
+            };
+            let mut blocks = vec![json!({
+                "type": "header",
+                "text": { "type": "plain_text", "text": title },
+            })];
+            blocks.extend_from_slice(sections);
+            json!({
+                "text": summary.text(),
+                "blocks": blocks,
+            })
+        })
+        .collect()
+}
+
+fn teams(summary: &Summary) -> Value {
+    let mut body = vec![json!({
+        "type": "TextBlock",
+        "text": TITLE,
+        "size": "Large",
+        "weight": "Bolder",
+    })];
+
+    if let Some(coverage) = &summary.coverage {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": coverage,
+            "color": "Warning",
+            "wrap": true,
+        }));
+    }
+
+    for (namespace, list) in summary.lists("-") {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": namespace,
+            "weight": "Bolder",
+            "separator": true,
+        }));
+        body.push(json!({
+            "type": "TextBlock",
+            "text": list,
+            "wrap": true,
+        }));
+    }
+
+    json!({
+        "type": "message",
+        "summary": summary.text(),
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": body,
+            },
+        }],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    fn missing(image: &str, namespace: &str) -> Notification {
+        Notification {
+            timestamp: Utc::now(),
+            details: Details::MissingSbom {
+                image: image.into(),
+                namespaces: [namespace.to_string()].into(),
+                pods: Default::default(),
+            },
+        }
+    }
+
+    fn blocks(message: &Value) -> usize {
+        message["blocks"].as_array().unwrap().len()
+    }
+
+    #[test]
+    fn slack_single_message() {
+        let batch = [missing("a:1", "default"), missing("b:1", "other")];
+        let messages = Format::Slack.render(&batch, &Envelope::default());
+        assert_eq!(messages.len(), 1);
+        // the header, and one section per namespace
+        assert_eq!(blocks(&messages[0]), 3);
+        assert_eq!(
+            messages[0]["text"],
+            "SBOM coverage gaps: 2 image(s) in 2 namespace(s)"
+        );
+    }
+
+    #[test]
+    fn slack_split_by_blocks() {
+        let batch = (0..120)
+            .map(|n| missing(&format!("image:{n}"), &format!("namespace-{n:03}")))
+            .collect::<Vec<_>>();
+        let messages = Format::Slack.render(&batch, &Envelope::default());
+
+        assert_eq!(messages.len(), 3);
+        assert!(messages
+            .iter()
+            .all(|message| blocks(message) <= MAX_SLACK_BLOCKS));
+        // all namespaces are listed, each message has a header
+        assert_eq!(messages.iter().map(blocks).sum::<usize>(), 120 + 3);
+        assert_eq!(messages[0]["blocks"][0]["text"]["text"], TITLE);
+        assert_eq!(
+            messages[1]["blocks"][0]["text"]["text"],
+            "SBOM coverage gaps (continued)"
+        );
+    }
+
+    #[test]
+    fn json_not_batched() {
+        let batch = [missing("a:1", "default"), missing("b:1", "other")];
+        let messages = Format::Json.render(&batch, &Envelope::default());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["type"], "missingSbom");
+        assert_eq!(messages[0]["image"], "a:1");
+    }
+}
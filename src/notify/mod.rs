@@ -0,0 +1,436 @@
+//! Notifications about gaps in the SBOM coverage, sent to webhooks.
+
+mod format;
+mod webhook;
+
+use crate::cloudevents::Envelope;
+use crate::export::pod_name;
+use crate::store::SyncState;
+use crate::workload::WorkloadState;
+use bommer_api::data::{Event, Image, ImageRef, LookupError, SbomState};
+use chrono::{DateTime, Utc};
+use format::Format;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+use url::Url;
+use webhook::{Target, Webhook};
+
+/// interval of checking the coverage
+const COVERAGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The kind of a notification
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum NotificationKind {
+    /// An image without an SBOM appeared
+    MissingSbom,
+    /// Looking up the SBOM of an image failed permanently
+    ScanFailed,
+    /// The share of images with an SBOM dropped below the threshold
+    Coverage,
+}
+
+impl NotificationKind {
+    /// the name of the kind, as used for the `type` of notifications
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MissingSbom => "missingSbom",
+            Self::ScanFailed => "scanFailed",
+            Self::Coverage => "coverage",
+        }
+    }
+}
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Notifications")]
+pub struct NotifyConfig {
+    /// URLs of webhooks to send notifications to
+    #[arg(long = "webhook-url", env = "WEBHOOK_URLS", value_delimiter = ',')]
+    pub webhook_urls: Vec<Url>,
+
+    /// URLs of Slack incoming webhooks to send notifications to
+    #[arg(
+        long = "slack-webhook-url",
+        env = "SLACK_WEBHOOK_URLS",
+        value_delimiter = ','
+    )]
+    pub slack_webhook_urls: Vec<Url>,
+
+    /// URLs of Microsoft Teams webhooks to send notifications to
+    #[arg(
+        long = "teams-webhook-url",
+        env = "TEAMS_WEBHOOK_URLS",
+        value_delimiter = ','
+    )]
+    pub teams_webhook_urls: Vec<Url>,
+
+    /// Secret to sign notifications with (HMAC-SHA256), sent in the `X-Bommer-Signature` header
+    #[arg(long, env = "WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+
+    /// Kinds of notifications to send, all if none are provided
+    #[arg(
+        long = "webhook-event",
+        env = "WEBHOOK_EVENTS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub webhook_events: Vec<NotificationKind>,
+
+    /// Number of attempts to deliver a notification
+    #[arg(long, env = "WEBHOOK_ATTEMPTS", default_value_t = 5)]
+    pub webhook_attempts: u32,
+
+    /// Timeout of delivering a notification
+    #[arg(long, env = "WEBHOOK_TIMEOUT", default_value = "10s", value_parser = humantime::parse_duration)]
+    pub webhook_timeout: Duration,
+
+    /// Time to collect notifications for, before sending them as a single message to Slack or Teams
+    #[arg(long, env = "WEBHOOK_BATCH_WINDOW", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub webhook_batch_window: Duration,
+
+    /// Share of images with an SBOM (in percent), below which a notification is sent
+    #[arg(long, env = "COVERAGE_THRESHOLD", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub coverage_threshold: Option<u8>,
+}
+
+/// A notification, as sent to webhooks
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub details: Details,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Details {
+    MissingSbom {
+        image: String,
+        namespaces: BTreeSet<String>,
+        pods: BTreeSet<String>,
+    },
+    ScanFailed {
+        image: String,
+        namespaces: BTreeSet<String>,
+        pods: BTreeSet<String>,
+        error: LookupError,
+    },
+    Coverage {
+        /// Share of images with an SBOM, in percent
+        coverage: f64,
+        threshold: u8,
+        /// Number of scanned images
+        images: usize,
+        /// Number of images with an SBOM
+        found: usize,
+    },
+}
+
+impl Details {
+    fn kind(&self) -> NotificationKind {
+        match self {
+            Self::MissingSbom { .. } => NotificationKind::MissingSbom,
+            Self::ScanFailed { .. } => NotificationKind::ScanFailed,
+            Self::Coverage { .. } => NotificationKind::Coverage,
+        }
+    }
+
+    /// the image the notification is about, if any
+    fn image(&self) -> Option<&str> {
+        match self {
+            Self::MissingSbom { image, .. } | Self::ScanFailed { image, .. } => Some(image),
+            Self::Coverage { .. } => None,
+        }
+    }
+}
+
+impl NotifyConfig {
+    /// send notifications about changes of the workload, if any webhooks are configured
+    ///
+    /// The persisted state of the images is what was known, and notified about, before the
+    /// restart. Its gaps don't get reported again.
+    pub async fn run(
+        self,
+        map: WorkloadState,
+        sync: SyncState,
+        persisted: im::HashMap<ImageRef, Image>,
+        client: reqwest::Client,
+        envelope: Envelope,
+        buffer: usize,
+    ) -> anyhow::Result<()> {
+        let targets = Target::all(Format::Json, self.webhook_urls)
+            .chain(Target::all(Format::Slack, self.slack_webhook_urls))
+            .chain(Target::all(Format::Teams, self.teams_webhook_urls))
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return futures::future::pending().await;
+        }
+
+        let webhook = Webhook::new(
+            client,
+            self.webhook_secret,
+            envelope,
+            self.webhook_batch_window,
+            self.webhook_attempts,
+            self.webhook_timeout,
+        );
+        let senders = targets
+            .into_iter()
+            .map(|target| {
+                let (tx, rx) = mpsc::channel(buffer.max(1));
+                tokio::spawn(webhook.clone().run(target, rx));
+                tx
+            })
+            .collect();
+
+        let mut detector = Detector::new(
+            self.webhook_events,
+            self.coverage_threshold,
+            &persisted,
+            senders,
+        );
+
+        // until synced, we only have a partial view of the workload
+        sync.synced().await;
+
+        let mut interval = tokio::time::interval(COVERAGE_INTERVAL);
+        let mut sub = map.subscribe("notify", None).await;
+
+        loop {
+            tokio::select! {
+                evt = sub.recv() => match evt {
+                    Some(evt) => detector.handle(&evt),
+                    None => {
+                        // the subscription got dropped, we get a full state with the next one
+                        sub = map.subscribe("notify", None).await;
+                    }
+                },
+                _ = interval.tick() => {
+                    detector.check_coverage(&map.get_state().await);
+                }
+            }
+        }
+    }
+}
+
+/// Detects the changes to notify about
+struct Detector {
+    kinds: Vec<NotificationKind>,
+    threshold: Option<u8>,
+    /// Images we notified about, until they have an SBOM
+    ///
+    /// As the state of an image tells what it got notified about, this is derived from the
+    /// persisted state after a restart.
+    notified: HashMap<ImageRef, NotificationKind>,
+    /// If the coverage is below the threshold
+    below: bool,
+    /// The queues of the webhooks
+    senders: Vec<mpsc::Sender<Notification>>,
+}
+
+impl Detector {
+    fn new(
+        kinds: Vec<NotificationKind>,
+        threshold: Option<u8>,
+        persisted: &im::HashMap<ImageRef, Image>,
+        senders: Vec<mpsc::Sender<Notification>>,
+    ) -> Self {
+        let mut detector = Self {
+            kinds,
+            threshold,
+            notified: persisted
+                .iter()
+                .filter_map(|(image, state)| Some((image.clone(), Self::kind(state)?)))
+                .collect(),
+            below: false,
+            senders,
+        };
+        detector.below = detector.coverage(persisted).is_some_and(|(_, below)| below);
+        detector
+    }
+
+    fn handle(&mut self, evt: &Event<ImageRef, Image>) {
+        match evt {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                self.check_image(image.clone(), state)
+            }
+            Event::Removed(image) => {
+                self.notified.remove(image);
+            }
+            Event::Restart(state) => {
+                self.notified.retain(|image, _| state.contains_key(image));
+                for (image, state) in state {
+                    self.check_image(image.clone(), state);
+                }
+            }
+        }
+    }
+
+    /// the kind of notification the state of an image calls for, if any
+    fn kind(state: &Image) -> Option<NotificationKind> {
+        match &state.sbom {
+            SbomState::Missing => Some(NotificationKind::MissingSbom),
+            SbomState::Err(err) if !err.is_retryable() => Some(NotificationKind::ScanFailed),
+            _ => None,
+        }
+    }
+
+    fn check_image(&mut self, image: ImageRef, state: &Image) {
+        let kind = match (Self::kind(state), &state.sbom) {
+            (Some(kind), _) => kind,
+            (None, SbomState::Found(_)) => {
+                self.notified.remove(&image);
+                return;
+            }
+            (None, _) => return,
+        };
+
+        if self.notified.get(&image) == Some(&kind) {
+            return;
+        }
+        self.notified.insert(image.clone(), kind);
+
+        let namespaces = state.pods.iter().map(|pod| pod.namespace.clone()).collect();
+        let pods = state.pods.iter().map(pod_name).collect();
+        let image = image.to_string();
+
+        self.send(match &state.sbom {
+            SbomState::Err(err) => Details::ScanFailed {
+                image,
+                namespaces,
+                pods,
+                error: err.clone(),
+            },
+            _ => Details::MissingSbom {
+                image,
+                namespaces,
+                pods,
+            },
+        });
+    }
+
+    /// the coverage details of a state, and if it is below the threshold
+    fn coverage(&self, state: &im::HashMap<ImageRef, Image>) -> Option<(Details, bool)> {
+        let threshold = self.threshold?;
+
+        // images which are still scheduled don't count yet
+        let (images, found) =
+            state
+                .values()
+                .fold((0, 0), |(images, found), image| match image.sbom {
+                    SbomState::Scheduled => (images, found),
+                    SbomState::Found(_) => (images + 1, found + 1),
+                    _ => (images + 1, found),
+                });
+        if images == 0 {
+            return None;
+        }
+
+        let coverage = found as f64 * 100.0 / images as f64;
+        Some((
+            Details::Coverage {
+                coverage,
+                threshold,
+                images,
+                found,
+            },
+            coverage < threshold as f64,
+        ))
+    }
+
+    fn check_coverage(&mut self, state: &im::HashMap<ImageRef, Image>) {
+        let Some((details, below)) = self.coverage(state) else {
+            return;
+        };
+
+        // only notify when dropping below the threshold, not while staying there
+        if below && !self.below {
+            self.send(details);
+        }
+        self.below = below;
+    }
+
+    fn send(&self, details: Details) {
+        if !self.kinds.is_empty() && !self.kinds.contains(&details.kind()) {
+            return;
+        }
+
+        let notification = Notification {
+            timestamp: Utc::now(),
+            details,
+        };
+        for tx in &self.senders {
+            if tx.try_send(notification.clone()).is_err() {
+                warn!("Dropping notification, a webhook can't keep up");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::SbomSummary;
+
+    /// the images of the notifications which got sent
+    fn sent(rx: &mut mpsc::Receiver<Notification>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|notification| match notification.details {
+                Details::MissingSbom { image, .. } | Details::ScanFailed { image, .. } => image,
+                Details::Coverage { .. } => "coverage".into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn persisted_gaps_not_notified_again() {
+        let known: ImageRef = "quay.io/example/known:1.0".parse().unwrap();
+        let new: ImageRef = "quay.io/example/new:1.0".parse().unwrap();
+        let persisted = im::HashMap::from_iter([(known.clone(), Image::new(SbomState::Missing))]);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut detector = Detector::new(vec![], Some(50), &persisted, vec![tx]);
+
+        // the state after the restart, still missing the same SBOM, and below the threshold
+        detector.handle(&Event::Restart(persisted.clone()));
+        detector.check_coverage(&persisted);
+        assert!(sent(&mut rx).is_empty());
+
+        detector.handle(&Event::Added(new.clone(), Image::new(SbomState::Missing)));
+        assert_eq!(sent(&mut rx), vec![new.to_string()]);
+    }
+
+    #[test]
+    fn notified_again_after_found() {
+        let known: ImageRef = "quay.io/example/known:1.0".parse().unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut detector = Detector::new(vec![], None, &Default::default(), vec![tx]);
+
+        detector.handle(&Event::Added(known.clone(), Image::new(SbomState::Missing)));
+        detector.handle(&Event::Modified(
+            known.clone(),
+            Image::new(SbomState::Missing),
+        ));
+        assert_eq!(sent(&mut rx), vec![known.to_string()]);
+
+        let found: SbomSummary =
+            serde_json::from_value(serde_json::json!({"format": "spdx", "packages": 1})).unwrap();
+        detector.handle(&Event::Modified(
+            known.clone(),
+            Image::new(SbomState::Found(found)),
+        ));
+        detector.handle(&Event::Modified(
+            known.clone(),
+            Image::new(SbomState::Missing),
+        ));
+        assert_eq!(sent(&mut rx), vec![known.to_string()]);
+    }
+}
@@ -0,0 +1,154 @@
+use super::format::Format;
+use super::Notification;
+use crate::cloudevents::Envelope;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use url::Url;
+
+const SIGNATURE: &str = "X-Bommer-Signature";
+
+/// delay before the first retry, doubled with each attempt
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// A webhook, along with the format it expects
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub url: Url,
+    pub format: Format,
+}
+
+impl Target {
+    pub fn all(format: Format, urls: Vec<Url>) -> impl Iterator<Item = Self> {
+        urls.into_iter().map(move |url| Self { url, format })
+    }
+}
+
+/// Delivers notifications to webhooks
+#[derive(Clone)]
+pub struct Webhook {
+    client: reqwest::Client,
+    secret: Option<String>,
+    envelope: Envelope,
+    batch_window: Duration,
+    attempts: u32,
+    timeout: Duration,
+}
+
+impl Webhook {
+    pub fn new(
+        client: reqwest::Client,
+        secret: Option<String>,
+        envelope: Envelope,
+        batch_window: Duration,
+        attempts: u32,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            client,
+            secret,
+            envelope,
+            batch_window,
+            attempts: attempts.max(1),
+            timeout,
+        }
+    }
+
+    /// deliver notifications to a target, one batch after the other
+    ///
+    /// Each target gets its own queue, so that a slow or failing target doesn't hold up the
+    /// others, and batches are collected for each target on its own.
+    pub async fn run(self, target: Target, mut rx: mpsc::Receiver<Notification>) {
+        // only chat messages get batched, plain notifications are sent right away
+        let batch_window = match target.format.batched() {
+            true => self.batch_window,
+            false => Duration::ZERO,
+        };
+        let content_type = target.format.content_type(&self.envelope);
+
+        while let Some(notification) = rx.recv().await {
+            let mut batch = vec![notification];
+
+            let deadline = Instant::now() + batch_window;
+            while let Ok(Some(notification)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+                batch.push(notification);
+            }
+
+            for body in target.format.render(&batch, &self.envelope) {
+                match serde_json::to_vec(&body) {
+                    Ok(body) => self.deliver(&target.url, content_type, &body).await,
+                    Err(err) => warn!("Failed to encode notification: {err}"),
+                }
+            }
+        }
+    }
+
+    /// deliver a notification, re-trying temporary failures
+    async fn deliver(&self, url: &Url, content_type: &str, body: &[u8]) {
+        let mut delay = INITIAL_DELAY;
+
+        for attempt in 1..=self.attempts {
+            match self.send(url, content_type, body).await {
+                Ok(()) => {
+                    debug!(%url, "Delivered notification");
+                    return;
+                }
+                Err(Error::Permanent(err)) => {
+                    warn!(%url, "Failed to deliver notification: {err}");
+                    return;
+                }
+                Err(Error::Temporary(err)) if attempt < self.attempts => {
+                    debug!(%url, attempt, "Failed to deliver notification, re-trying: {err}");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(Error::Temporary(err)) => {
+                    warn!(%url, "Failed to deliver notification, giving up: {err}");
+                }
+            }
+        }
+    }
+
+    async fn send(&self, url: &Url, content_type: &str, body: &[u8]) -> Result<(), Error> {
+        let mut request = self
+            .client
+            .post(url.clone())
+            .timeout(self.timeout)
+            .header(CONTENT_TYPE, content_type)
+            .body(body.to_vec());
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE, format!("sha256={}", sign(secret, body)));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|err| Error::Temporary(err.to_string()))?;
+
+        let status = response.status();
+        match status {
+            _ if status.is_success() => Ok(()),
+            _ if status.is_server_error() || status.as_u16() == 429 => {
+                Err(Error::Temporary(status.to_string()))
+            }
+            _ => Err(Error::Permanent(status.to_string())),
+        }
+    }
+}
+
+enum Error {
+    Temporary(String),
+    Permanent(String),
+}
+
+/// sign the body, returning the hex encoded HMAC-SHA256
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,85 @@
+use super::{Broker, Delivery, Error};
+use bytes::Bytes;
+use futures::FutureExt;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use std::time::Duration;
+
+/// time to wait for the queue of the producer to drain, when it is full
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Publishes events to a Kafka topic, keyed by image
+///
+/// Messages are queued right away, and delivered in the background, reporting the outcome of the
+/// delivery later on. With the idempotent producer, the messages of an image are kept in order,
+/// even when retried.
+pub struct Kafka {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl Kafka {
+    pub fn new(
+        brokers: &str,
+        topic: String,
+        properties: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", brokers)
+            .set("client.id", "bommer")
+            .set("enable.idempotence", "true");
+        for (key, value) in properties {
+            config.set(key, value);
+        }
+
+        Ok(Self {
+            producer: config.create()?,
+            topic,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Broker for Kafka {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(
+        &self,
+        key: &str,
+        content_type: &str,
+        payload: Bytes,
+    ) -> Result<Delivery, Error> {
+        loop {
+            let headers = OwnedHeaders::new().insert(Header {
+                key: "content-type",
+                value: Some(content_type),
+            });
+            let record = FutureRecord::to(&self.topic)
+                .key(key)
+                .headers(headers)
+                .payload(&payload[..]);
+            match self.producer.send_result(record) {
+                Ok(delivery) => {
+                    return Ok(async move {
+                        match delivery.await {
+                            Ok(Ok(_)) => Ok(()),
+                            Ok(Err((err, _))) => Err(err.into()),
+                            // the producer got dropped before delivering the message
+                            Err(_) => Err(KafkaError::Canceled.into()),
+                        }
+                    }
+                    .boxed());
+                }
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                    tokio::time::sleep(QUEUE_FULL_BACKOFF).await;
+                }
+                Err((err, _)) => return Err(err.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,589 @@
+//! Publishing the events of the workload to message brokers (NATS or Kafka), so that other
+//! systems can follow the changes of the workload and its SBOMs.
+
+mod kafka;
+mod nats;
+
+use crate::cloudevents::Envelope;
+use crate::workload::WorkloadState;
+use bommer_api::data::{Event, Image, ImageRef};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// interval of retrying the changes brokers failed to accept
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Event publishing")]
+pub struct PublishConfig {
+    /// Address of the NATS server to publish events to, e.g. `nats://localhost:4222`
+    #[arg(long, env = "NATS_URL")]
+    pub nats_url: Option<String>,
+
+    /// The NATS subject to publish events to
+    #[arg(long, env = "NATS_SUBJECT", default_value = "bommer.events")]
+    pub nats_subject: String,
+
+    /// Token to authenticate with the NATS server
+    #[arg(long, env = "NATS_TOKEN")]
+    pub nats_token: Option<String>,
+
+    /// Kafka brokers to publish events to, as a comma separated list of `host:port`
+    #[arg(long, env = "KAFKA_BROKERS")]
+    pub kafka_brokers: Option<String>,
+
+    /// The Kafka topic to publish events to
+    #[arg(long, env = "KAFKA_TOPIC", default_value = "bommer-events")]
+    pub kafka_topic: String,
+
+    /// Additional properties of the Kafka producer, e.g. `security.protocol=SASL_SSL`
+    #[arg(long = "kafka-property", env = "KAFKA_PROPERTIES", value_delimiter = ',', value_parser = parse_property)]
+    pub kafka_properties: Vec<(String, String)>,
+}
+
+fn parse_property(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+        None => Err(format!("Expected `key=value`, got: {value}")),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("NATS error: {0}")]
+    Nats(#[from] async_nats::PublishError),
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+}
+
+/// An event of the workload, as published to brokers
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message<'a> {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change<'a>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Change<'a> {
+    Added {
+        image: &'a ImageRef,
+        state: &'a Image,
+    },
+    Modified {
+        image: &'a ImageRef,
+        state: &'a Image,
+    },
+    Removed {
+        image: &'a ImageRef,
+    },
+}
+
+impl Change<'_> {
+    fn image(&self) -> &ImageRef {
+        match self {
+            Self::Added { image, .. } | Self::Modified { image, .. } | Self::Removed { image } => {
+                image
+            }
+        }
+    }
+
+    /// the type of the change, as used for the `type` of events
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added { .. } => "added",
+            Self::Modified { .. } => "modified",
+            Self::Removed { .. } => "removed",
+        }
+    }
+}
+
+/// The outcome of delivering a message, which a broker accepted
+type Delivery = BoxFuture<'static, Result<(), Error>>;
+
+/// A message broker, events are published to
+#[async_trait::async_trait]
+trait Broker: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// publish a message, keyed by the image it is about
+    ///
+    /// Brokers may accept a message before delivering it, the returned future reports if the
+    /// delivery succeeded.
+    async fn publish(
+        &self,
+        key: &str,
+        content_type: &str,
+        payload: Bytes,
+    ) -> Result<Delivery, Error>;
+}
+
+impl PublishConfig {
+    /// publish the events of the workload, if any broker is configured
+    pub async fn run(self, map: WorkloadState, envelope: Envelope) -> anyhow::Result<()> {
+        let mut brokers: Vec<Box<dyn Broker>> = Vec::new();
+        if let Some(url) = &self.nats_url {
+            info!("Publishing events to NATS: {url}");
+            brokers.push(Box::new(
+                nats::Nats::connect(url, self.nats_subject.clone(), self.nats_token.clone())
+                    .await?,
+            ));
+        }
+        if let Some(brokers_list) = &self.kafka_brokers {
+            info!("Publishing events to Kafka: {brokers_list}");
+            brokers.push(Box::new(kafka::Kafka::new(
+                brokers_list,
+                self.kafka_topic.clone(),
+                &self.kafka_properties,
+            )?));
+        }
+
+        if brokers.is_empty() {
+            return futures::future::pending().await;
+        }
+
+        let (mut publisher, mut undelivered) = Publisher::new(brokers, envelope);
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+        retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let mut sub = map.subscribe("publish", None).await;
+            loop {
+                tokio::select! {
+                    evt = sub.recv() => match evt {
+                        Some(evt) => publisher.handle(Arc::unwrap_or_clone(evt)).await,
+                        None => break,
+                    },
+                    Some((index, image)) = undelivered.recv() => publisher.undelivered(index, image),
+                    _ = retry.tick(), if publisher.has_pending() => publisher.retry().await,
+                }
+            }
+            // the subscription got dropped, we get a full state with the next one
+        }
+    }
+}
+
+/// A broker, along with what it got published
+struct Target {
+    broker: Box<dyn Broker>,
+    /// The last state of each image, successfully published to the broker
+    published: HashMap<ImageRef, Image>,
+    /// The state of images which failed to get published, `None` for a removal
+    pending: HashMap<ImageRef, Option<Image>>,
+    /// Images of which the broker failed to deliver a change, after accepting it
+    ///
+    /// Consumers might have missed any of their changes, so their state gets published again,
+    /// even if it didn't change.
+    undelivered: HashSet<ImageRef>,
+}
+
+/// Turns the events of the workload into changes of images
+///
+/// After a restart of the subscription, only the changes compared to what was published before
+/// are published, so that consumers see a consistent stream of changes for each image. What got
+/// published is tracked for each broker, and only once the broker accepted it. Changes a broker
+/// failed to accept, or failed to deliver later on, are retried with the latest state of the image.
+struct Publisher {
+    targets: Vec<Target>,
+    envelope: Envelope,
+    /// reports the images (along with the index of their target) which failed to get delivered
+    undelivered: mpsc::UnboundedSender<(usize, ImageRef)>,
+}
+
+impl Publisher {
+    /// create a publisher, along with the receiver of the failed deliveries, which need to be
+    /// handed back to [`Publisher::undelivered`]
+    fn new(
+        brokers: Vec<Box<dyn Broker>>,
+        envelope: Envelope,
+    ) -> (Self, mpsc::UnboundedReceiver<(usize, ImageRef)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let publisher = Self {
+            targets: brokers
+                .into_iter()
+                .map(|broker| Target {
+                    broker,
+                    published: Default::default(),
+                    pending: Default::default(),
+                    undelivered: Default::default(),
+                })
+                .collect(),
+            envelope,
+            undelivered: tx,
+        };
+        (publisher, rx)
+    }
+
+    async fn handle(&mut self, evt: Event<ImageRef, Image>) {
+        match evt {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                self.update(image, Some(state)).await;
+            }
+            Event::Removed(image) => self.update(image, None).await,
+            Event::Restart(state) => {
+                let gone = self
+                    .targets
+                    .iter()
+                    .flat_map(|target| target.published.keys().chain(target.pending.keys()))
+                    .filter(|image| !state.contains_key(image))
+                    .cloned()
+                    .collect::<HashSet<_>>();
+                for image in gone {
+                    self.update(image, None).await;
+                }
+                for (image, state) in state {
+                    self.update(image, Some(state)).await;
+                }
+            }
+        }
+    }
+
+    /// if any changes wait for being retried
+    fn has_pending(&self) -> bool {
+        self.targets.iter().any(|target| !target.pending.is_empty())
+    }
+
+    /// retry publishing the changes which failed before
+    async fn retry(&mut self) {
+        for index in 0..self.targets.len() {
+            let pending = std::mem::take(&mut self.targets[index].pending);
+            for (image, state) in pending {
+                self.publish(index, image, state).await;
+            }
+        }
+    }
+
+    /// schedule publishing the latest state of an image again, as the broker failed to deliver it
+    fn undelivered(&mut self, index: usize, image: ImageRef) {
+        let target = &mut self.targets[index];
+        // a pending state is newer than anything which got delivered
+        if !target.pending.contains_key(&image) {
+            let state = target.published.get(&image).cloned();
+            target.pending.insert(image.clone(), state);
+        }
+        target.undelivered.insert(image);
+    }
+
+    /// publish the state of an image to all brokers, `None` if it got removed
+    async fn update(&mut self, image: ImageRef, state: Option<Image>) {
+        for index in 0..self.targets.len() {
+            // a newer state replaces a pending one
+            self.targets[index].pending.remove(&image);
+            self.publish(index, image.clone(), state.clone()).await;
+        }
+    }
+
+    /// publish the state of an image to a broker, keeping it for a retry if that fails
+    async fn publish(&mut self, index: usize, image: ImageRef, state: Option<Image>) {
+        let target = &self.targets[index];
+        let resend = target.undelivered.contains(&image);
+        let change = match (target.published.get(&image), &state) {
+            (Some(current), Some(state)) if current == state && !resend => return,
+            (Some(_), Some(state)) => Change::Modified {
+                image: &image,
+                state,
+            },
+            (None, Some(state)) => Change::Added {
+                image: &image,
+                state,
+            },
+            (Some(_), None) => Change::Removed { image: &image },
+            (None, None) if resend => Change::Removed { image: &image },
+            (None, None) => return,
+        };
+
+        let Some(payload) = self.encode(&change) else {
+            // encoding won't work any better next time
+            return;
+        };
+
+        let key = image.to_string();
+        let target = &mut self.targets[index];
+        match target
+            .broker
+            .publish(&key, self.envelope.content_type(), payload)
+            .await
+        {
+            Ok(delivery) => {
+                target.undelivered.remove(&image);
+                match state {
+                    Some(state) => {
+                        target.published.insert(image.clone(), state);
+                    }
+                    None => {
+                        target.published.remove(&image);
+                    }
+                }
+
+                let name = target.broker.name();
+                let undelivered = self.undelivered.clone();
+                let mut delivered = async move {
+                    if let Err(err) = delivery.await {
+                        warn!(image = key, "Failed to deliver event to {name}: {err}");
+                        metrics::increment_counter!("bommer_publish_failures_total", "broker" => name);
+                        let _ = undelivered.send((index, image));
+                    }
+                }
+                .boxed();
+                // some brokers know right away, no need for a task then
+                if (&mut delivered).now_or_never().is_none() {
+                    tokio::spawn(delivered);
+                }
+            }
+            Err(err) => {
+                warn!(
+                    image = key,
+                    "Failed to publish event to {}: {err}",
+                    target.broker.name()
+                );
+                metrics::increment_counter!("bommer_publish_failures_total", "broker" => target.broker.name());
+                target.pending.insert(image, state);
+            }
+        }
+    }
+
+    /// encode a change as the payload of a message
+    fn encode(&self, change: &Change<'_>) -> Option<Bytes> {
+        let key = change.image().to_string();
+        let ty = format!("image.{}", change.as_str());
+        let message = Message {
+            timestamp: Utc::now(),
+            change: change.clone(),
+        };
+        let message = self
+            .envelope
+            .wrap(&ty, Some(&key), message.timestamp, &message);
+        match serde_json::to_vec(&message) {
+            Ok(payload) => Some(Bytes::from(payload)),
+            Err(err) => {
+                warn!(image = key, "Failed to encode event: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::SbomState;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    /// records the published messages, failing to accept or to deliver them while told to
+    #[derive(Clone, Default)]
+    struct Recorder {
+        failing: Arc<AtomicBool>,
+        undeliverable: Arc<AtomicBool>,
+        published: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Recorder {
+        /// the published messages, as key and type of change
+        fn take(&self) -> Vec<(String, String)> {
+            std::mem::take(&mut self.published.lock().unwrap())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Broker for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn publish(
+            &self,
+            key: &str,
+            _content_type: &str,
+            payload: Bytes,
+        ) -> Result<Delivery, Error> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(Error::Kafka(rdkafka::error::KafkaError::Canceled));
+            }
+            let message: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+            self.published.lock().unwrap().push((
+                key.to_string(),
+                message["type"].as_str().unwrap().to_string(),
+            ));
+
+            let undeliverable = self.undeliverable.load(Ordering::SeqCst);
+            Ok(async move {
+                // delivering takes a while
+                tokio::task::yield_now().await;
+                match undeliverable {
+                    true => Err(Error::Kafka(rdkafka::error::KafkaError::Canceled)),
+                    false => Ok(()),
+                }
+            }
+            .boxed())
+        }
+    }
+
+    fn published(key: &str, ty: &str) -> (String, String) {
+        (key.to_string(), ty.to_string())
+    }
+
+    #[tokio::test]
+    async fn failed_changes_retried() {
+        let (healthy, failing) = (Recorder::default(), Recorder::default());
+        failing.failing.store(true, Ordering::SeqCst);
+        let (mut publisher, _) = Publisher::new(
+            vec![Box::new(healthy.clone()), Box::new(failing.clone())],
+            Envelope::default(),
+        );
+
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+        publisher
+            .handle(Event::Added(
+                first.clone(),
+                Image::new(SbomState::Scheduled),
+            ))
+            .await;
+        publisher
+            .handle(Event::Added(
+                second.clone(),
+                Image::new(SbomState::Scheduled),
+            ))
+            .await;
+        publisher
+            .handle(Event::Modified(
+                first.clone(),
+                Image::new(SbomState::Missing),
+            ))
+            .await;
+        publisher.handle(Event::Removed(second.clone())).await;
+
+        assert_eq!(
+            healthy.take(),
+            vec![
+                published("quay.io/example/first:1.0", "added"),
+                published("quay.io/example/second:1.0", "added"),
+                published("quay.io/example/first:1.0", "modified"),
+                published("quay.io/example/second:1.0", "removed"),
+            ]
+        );
+        assert!(failing.take().is_empty());
+        assert!(publisher.has_pending());
+
+        // the broker only gets what it never accepted, in its latest state
+        failing.failing.store(false, Ordering::SeqCst);
+        publisher.retry().await;
+        assert!(!publisher.has_pending());
+        assert!(healthy.take().is_empty());
+        assert_eq!(
+            failing.take(),
+            vec![published("quay.io/example/first:1.0", "added")]
+        );
+        assert_eq!(
+            publisher.targets[1].published.get(&first),
+            Some(&Image::new(SbomState::Missing))
+        );
+    }
+
+    #[tokio::test]
+    async fn restart_publishes_differences() {
+        let broker = Recorder::default();
+        let (mut publisher, _) =
+            Publisher::new(vec![Box::new(broker.clone())], Envelope::default());
+
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+        publisher
+            .handle(Event::Added(first.clone(), Image::new(SbomState::Missing)))
+            .await;
+        broker.take();
+
+        publisher
+            .handle(Event::Restart(im::HashMap::from_iter([(
+                second,
+                Image::new(SbomState::Missing),
+            )])))
+            .await;
+        assert_eq!(
+            broker.take(),
+            vec![
+                published("quay.io/example/first:1.0", "removed"),
+                published("quay.io/example/second:1.0", "added"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn undelivered_changes_retried() {
+        let broker = Recorder::default();
+        let (mut publisher, mut undelivered) =
+            Publisher::new(vec![Box::new(broker.clone())], Envelope::default());
+
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+        publisher
+            .handle(Event::Added(second.clone(), Image::new(SbomState::Missing)))
+            .await;
+        assert!(undelivered.try_recv().is_err());
+
+        // the broker accepts the changes, but fails to deliver them
+        broker.undeliverable.store(true, Ordering::SeqCst);
+        publisher
+            .handle(Event::Added(
+                first.clone(),
+                Image::new(SbomState::Scheduled),
+            ))
+            .await;
+        publisher.handle(Event::Removed(second.clone())).await;
+        assert_eq!(
+            broker.take(),
+            vec![
+                published("quay.io/example/second:1.0", "added"),
+                published("quay.io/example/first:1.0", "added"),
+                published("quay.io/example/second:1.0", "removed"),
+            ]
+        );
+        assert!(!publisher.has_pending());
+
+        for _ in 0..2 {
+            let (index, image) = undelivered.recv().await.unwrap();
+            publisher.undelivered(index, image);
+        }
+        assert!(publisher.has_pending());
+
+        // the latest state gets published again, even though it didn't change
+        broker.undeliverable.store(false, Ordering::SeqCst);
+        publisher.retry().await;
+        assert!(!publisher.has_pending());
+        let mut retried = broker.take();
+        retried.sort();
+        assert_eq!(
+            retried,
+            vec![
+                published("quay.io/example/first:1.0", "modified"),
+                published("quay.io/example/second:1.0", "removed"),
+            ]
+        );
+
+        // once delivered, unchanged states aren't published again
+        publisher
+            .handle(Event::Modified(
+                first.clone(),
+                Image::new(SbomState::Scheduled),
+            ))
+            .await;
+        assert!(broker.take().is_empty());
+        tokio::task::yield_now().await;
+        assert!(undelivered.try_recv().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use super::{Broker, Delivery, Error};
+use async_nats::{Client, ConnectOptions, HeaderMap};
+use bytes::Bytes;
+use futures::future::{self, FutureExt};
+
+/// Publishes events to a NATS subject
+pub struct Nats {
+    client: Client,
+    subject: String,
+}
+
+impl Nats {
+    pub async fn connect(
+        url: &str,
+        subject: String,
+        token: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let mut options = ConnectOptions::new()
+            .name("bommer")
+            // keep trying in the background, instead of failing to start
+            .retry_on_initial_connect();
+        if let Some(token) = token {
+            options = options.token(token);
+        }
+
+        Ok(Self {
+            client: options.connect(url).await?,
+            subject,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Broker for Nats {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(
+        &self,
+        _key: &str,
+        content_type: &str,
+        payload: Bytes,
+    ) -> Result<Delivery, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", content_type);
+        self.client
+            .publish_with_headers(self.subject.clone(), headers, payload)
+            .await?;
+        // core NATS doesn't acknowledge messages, there's nothing more to wait for
+        Ok(future::ok(()).boxed())
+    }
+}
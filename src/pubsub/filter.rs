@@ -0,0 +1,101 @@
+use bommer_api::data::Event;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::sync::Arc;
+
+type FilterFn<K, V> = dyn Fn(&K, V) -> Option<V> + Send + Sync;
+
+/// A filter applied to the entries of a subscription
+///
+/// Entries can be dropped (returning `None`), or reduced (e.g. to only some of their pods).
+pub struct Filter<K, V>(Arc<FilterFn<K, V>>);
+
+impl<K, V> Filter<K, V> {
+    pub fn new(f: impl Fn(&K, V) -> Option<V> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl<K, V> Clone for Filter<K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, V> Debug for Filter<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filter").finish_non_exhaustive()
+    }
+}
+
+/// The part of the state a filtered subscriber sees
+///
+/// We need to track which keys the subscriber knows about, as a change may move an entry in or
+/// out of the filter, which then turns into an added or removed entry for the subscriber.
+#[derive(Debug)]
+pub struct View<K, V> {
+    filter: Filter<K, V>,
+    known: HashSet<K>,
+}
+
+impl<K, V> View<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    pub fn new(filter: Filter<K, V>) -> Self {
+        Self {
+            filter,
+            known: Default::default(),
+        }
+    }
+
+    /// translate an event into what the subscriber needs to see, if anything
+    pub fn translate(&mut self, evt: &Event<K, V>) -> Option<Event<K, V>> {
+        match evt {
+            Event::Added(key, value) | Event::Modified(key, value) => {
+                match (self.filter.0)(key, value.clone()) {
+                    Some(value) => match self.known.insert(key.clone()) {
+                        true => Some(Event::Added(key.clone(), value)),
+                        false => Some(Event::Modified(key.clone(), value)),
+                    },
+                    None => self.known.remove(key).then(|| Event::Removed(key.clone())),
+                }
+            }
+            Event::Removed(key) => self.known.remove(key).then(|| Event::Removed(key.clone())),
+            Event::Restart(state) => Some(Event::Restart(self.restart(state))),
+        }
+    }
+
+    /// translate an event when resuming a subscription
+    ///
+    /// We don't know what the subscriber knew at that point, so we report everything not matching
+    /// the filter as removed. Once all events are replayed, the view must be reset to the current
+    /// state using [`Self::restart`].
+    pub fn replay(&self, evt: &Event<K, V>) -> Option<Event<K, V>> {
+        match evt {
+            Event::Added(key, value) | Event::Modified(key, value) => {
+                Some(match (self.filter.0)(key, value.clone()) {
+                    Some(value) => Event::Modified(key.clone(), value),
+                    None => Event::Removed(key.clone()),
+                })
+            }
+            Event::Removed(key) => Some(Event::Removed(key.clone())),
+            // the history never contains a restart, as it replaces all previous events
+            Event::Restart(_) => None,
+        }
+    }
+
+    /// filter a full state, which replaces what the subscriber knows
+    pub fn restart(&mut self, state: &im::HashMap<K, V>) -> im::HashMap<K, V> {
+        let state = state
+            .iter()
+            .filter_map(|(key, value)| {
+                (self.filter.0)(key, value.clone()).map(|value| (key.clone(), value))
+            })
+            .collect::<im::HashMap<_, _>>();
+        self.known = state.keys().cloned().collect();
+        state
+    }
+}
@@ -0,0 +1,610 @@
+mod filter;
+mod queue;
+
+pub use filter::Filter;
+pub use queue::SlowSubscriber;
+
+use bommer_api::data::{Event, RecentEvents, RecordedEvent};
+use chrono::{DateTime, Utc};
+use filter::View;
+use futures::{stream, StreamExt};
+use queue::{Push, Queue};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tokio::time::MissedTickBehavior;
+use tracing::debug;
+
+/// buffer of subscriptions which don't request a specific one
+const DEFAULT_BUFFER: usize = 16;
+
+/// number of events we keep, for subscribers to resume from
+const HISTORY: usize = 1024;
+
+/// an event, along with the revision of the state it leads to
+///
+/// Events are shared between all subscribers which see the same event, rather than cloning
+/// them (including full snapshots) for each one.
+type Item<K, V> = (u64, Arc<Event<K, V>>);
+
+/// an event of the history, along with the time it happened
+#[derive(Debug)]
+struct Recorded<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    revision: u64,
+    timestamp: DateTime<Utc>,
+    event: Arc<Event<K, V>>,
+}
+
+/// Capacities of the queues between components, delivering events to subscribers
+#[derive(Clone, Copy, Debug, clap::Args)]
+#[command(next_help_heading = "Buffers")]
+pub struct BufferConfig {
+    /// Events buffered for processing the changes of the watchers into the workload
+    #[arg(long, env = "STORE_BUFFER", default_value_t = 32)]
+    pub store_buffer: usize,
+
+    /// Events buffered for the scanner, before images get queued for scanning
+    #[arg(long, env = "SCANNER_BUFFER", default_value_t = 128)]
+    pub scanner_buffer: usize,
+
+    /// Events buffered for internal consumers of the workload (persistence, reports, events, notifications)
+    #[arg(long, env = "CONSUMER_BUFFER", default_value_t = DEFAULT_BUFFER)]
+    pub consumer_buffer: usize,
+
+    /// Events buffered for API subscribers (websocket, GraphQL, and gRPC streams)
+    #[arg(long, env = "SUBSCRIBER_BUFFER", default_value_t = 32)]
+    pub subscriber_buffer: usize,
+
+    /// Notifications buffered for delivery to webhooks, before dropping new ones
+    #[arg(long, env = "NOTIFY_BUFFER", default_value_t = 1024)]
+    pub notify_buffer: usize,
+}
+
+/// Options of a subscription
+pub struct SubscribeOptions<K, V> {
+    /// How to handle the subscriber not keeping up
+    pub policy: SlowSubscriber,
+    /// Only deliver the matching entries
+    pub filter: Option<Filter<K, V>>,
+    /// Resume after this revision, rather than starting with the full state
+    pub since: Option<u64>,
+}
+
+impl<K, V> Default for SubscribeOptions<K, V> {
+    fn default() -> Self {
+        Self {
+            policy: Default::default(),
+            filter: None,
+            since: None,
+        }
+    }
+}
+
+pub struct Subscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + Send + Sync + 'static,
+{
+    queue: Arc<Queue<Item<K, V>>>,
+    unsubscribe: Option<Box<dyn FnOnce() + Send + Sync + 'static>>,
+}
+
+impl<K, V> Subscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + Send + Sync + 'static,
+{
+    fn new(
+        queue: Arc<Queue<Item<K, V>>>,
+        unsubscribe: impl FnOnce() + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            queue,
+            unsubscribe: Some(Box::new(unsubscribe)),
+        }
+    }
+
+    /// receive the next event, `None` if the subscription ended
+    ///
+    /// The event might be shared with other subscribers. Those needing to own it can use
+    /// [`Arc::unwrap_or_clone`], only cloning it if it actually is shared.
+    pub async fn recv(&mut self) -> Option<Arc<Event<K, V>>> {
+        self.queue.recv().await.map(|(_, evt)| evt)
+    }
+
+    /// receive the next event, along with the revision of the state it leads to
+    pub async fn recv_revision(&mut self) -> Option<(u64, Arc<Event<K, V>>)> {
+        self.queue.recv().await
+    }
+
+    /// coalesce events of the same key, delivering at most one event per key and window
+    ///
+    /// A `Restart` discards all pending events and is delivered right away. Events only pile up
+    /// at the end of a window, so the buffer can be the same as for the original subscription.
+    pub fn coalesce(mut self, window: Duration, buffer: usize) -> Self {
+        let queue = Arc::new(Queue::new(buffer));
+
+        let tx = queue.clone();
+        tokio::spawn(async move {
+            let mut pending = HashMap::<K, (u64, Event<K, V>)>::new();
+            let mut interval = tokio::time::interval(window);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    evt = self.recv_revision() => match evt {
+                        Some((revision, evt)) if matches!(*evt, Event::Restart(_)) => {
+                            pending.clear();
+                            if tx.push((revision, evt), SlowSubscriber::Wait).await != Push::Queued {
+                                break;
+                            }
+                        }
+                        Some((revision, evt)) => merge(&mut pending, revision, Arc::unwrap_or_clone(evt)),
+                        None => break,
+                    },
+                    _ = interval.tick() => {
+                        if tx.is_closed() {
+                            break;
+                        }
+                        let mut events = pending.drain().map(|(_, item)| item).collect::<Vec<_>>();
+                        events.sort_unstable_by_key(|(revision, _)| *revision);
+                        for (revision, evt) in events {
+                            if tx.push((revision, Arc::new(evt)), SlowSubscriber::Wait).await != Push::Queued {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+
+            tx.close();
+        });
+
+        // the task owns the original subscription, and drops it once the receiver is gone
+        Subscription::new(queue, || {})
+    }
+}
+
+/// merge an event into the pending event of its key
+fn merge<K, V>(pending: &mut HashMap<K, (u64, Event<K, V>)>, revision: u64, evt: Event<K, V>)
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    let key = match &evt {
+        Event::Added(key, _) | Event::Modified(key, _) | Event::Removed(key) => key.clone(),
+        Event::Restart(_) => return,
+    };
+
+    let evt = match (pending.remove(&key).map(|(_, evt)| evt), evt) {
+        // the subscriber never saw it
+        (Some(Event::Added(..)), Event::Removed(_)) => return,
+        (Some(Event::Added(..)), Event::Modified(key, value)) => Event::Added(key, value),
+        // the subscriber still knows the old one
+        (Some(Event::Removed(_)), Event::Added(key, value)) => Event::Modified(key, value),
+        (_, evt) => evt,
+    };
+
+    pending.insert(key, (revision, evt));
+}
+
+impl<K, V> Drop for Subscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe();
+        }
+    }
+}
+
+/// The sending side of a subscription
+#[derive(Debug)]
+struct Listener<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug,
+{
+    queue: Arc<Queue<Item<K, V>>>,
+    policy: SlowSubscriber,
+    /// the kind of subscriber, for reporting metrics
+    name: &'static str,
+    /// the filtered view of the subscriber, if it has a filter
+    view: Option<View<K, V>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct State<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    inner: Arc<RwLock<Inner<K, V>>>,
+}
+
+#[derive(Debug)]
+struct Inner<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    /// last known state
+    ///
+    /// A persistent map, so that taking a snapshot of it only copies it on later changes.
+    state: im::HashMap<K, V>,
+    /// listeners
+    listeners: HashMap<uuid::Uuid, Listener<K, V>>,
+    /// revision of the state, increased with every event
+    revision: u64,
+    /// the most recent events, for subscribers to resume from
+    history: VecDeque<Recorded<K, V>>,
+    /// the revision the history starts from
+    history_start: u64,
+    /// buffer of subscriptions which don't request a specific one
+    default_buffer: usize,
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    async fn broadcast(&mut self, evt: Event<K, V>) {
+        self.revision += 1;
+        let revision = self.revision;
+        let evt = Arc::new(evt);
+
+        match *evt {
+            Event::Restart(_) => {
+                // no point in keeping events the full state replaces
+                self.history.clear();
+                self.history_start = revision;
+            }
+            _ => {
+                if self.history.len() >= HISTORY {
+                    if let Some(recorded) = self.history.pop_front() {
+                        self.history_start = recorded.revision;
+                    }
+                }
+                self.history.push_back(Recorded {
+                    revision,
+                    timestamp: Utc::now(),
+                    event: evt.clone(),
+                });
+            }
+        }
+
+        // filter first, as that needs to update the views of the listeners
+        let events = self
+            .listeners
+            .iter_mut()
+            .filter_map(|(id, l)| {
+                let evt = match &mut l.view {
+                    Some(view) => Arc::new(view.translate(&evt)?),
+                    None => evt.clone(),
+                };
+                Some((*id, l.queue.clone(), l.policy, l.name, (revision, evt)))
+            })
+            .collect::<Vec<_>>();
+
+        let listeners = stream::iter(events);
+        let listeners = listeners.map(|(id, queue, policy, name, evt)| {
+            async move {
+                match queue.push(evt, policy).await {
+                    Push::Queued => {
+                        metrics::histogram!("bommer_subscriber_queue_depth", queue.len() as f64, "subscriber" => name);
+                        None
+                    }
+                    Push::Dropped => {
+                        metrics::increment_counter!("bommer_subscriber_dropped_events_total", "policy" => policy.as_str());
+                        None
+                    }
+                    Push::Disconnected => {
+                        metrics::increment_counter!("bommer_subscriber_disconnects_total", "policy" => policy.as_str());
+                        debug!(?id, "Disconnecting slow listener");
+                        Some(id)
+                    }
+                    Push::Closed => Some(id),
+                }
+            }
+        });
+        let failed: Vec<uuid::Uuid> = listeners
+            .buffer_unordered(10)
+            .filter_map(|s| async move { s })
+            .collect()
+            .await;
+
+        // remove failed subscribers
+
+        for id in failed {
+            debug!(?id, "Removing failed listener");
+            self.listeners.remove(&id);
+        }
+    }
+}
+
+impl<K, V> State<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + PartialEq + Send + Sync + 'static,
+{
+    /// subscribe to the changes, starting with the full state
+    ///
+    /// The name identifies the kind of subscriber in metrics. Without a buffer, the default
+    /// buffer of the state is used.
+    pub async fn subscribe(
+        &self,
+        name: &'static str,
+        buffer: impl Into<Option<usize>>,
+    ) -> Subscription<K, V> {
+        self.subscribe_with(name, buffer, Default::default()).await
+    }
+
+    /// subscribe, using additional options
+    ///
+    /// With a filter, the subscriber only sees the matching entries, starting with a filtered
+    /// `Restart`. When resuming, and the events since the requested revision are still known,
+    /// only those get delivered, instead of a `Restart`.
+    pub async fn subscribe_with(
+        &self,
+        name: &'static str,
+        buffer: impl Into<Option<usize>>,
+        options: SubscribeOptions<K, V>,
+    ) -> Subscription<K, V> {
+        let SubscribeOptions {
+            policy,
+            filter,
+            since,
+        } = options;
+
+        let mut lock = self.inner.write().await;
+
+        // take the chance to drop listeners which went away without unsubscribing
+        lock.listeners
+            .retain(|_, listener| !listener.queue.is_closed());
+
+        let mut view = filter.map(View::new);
+
+        let since = since.filter(|since| *since >= lock.history_start && *since <= lock.revision);
+        let initial = match since {
+            Some(since) => {
+                let mut events = Vec::new();
+                for recorded in lock
+                    .history
+                    .iter()
+                    .filter(|recorded| recorded.revision > since)
+                {
+                    let evt = match &mut view {
+                        Some(view) => match view.replay(&recorded.event) {
+                            Some(evt) => Arc::new(evt),
+                            None => continue,
+                        },
+                        None => recorded.event.clone(),
+                    };
+                    events.push((recorded.revision, evt));
+                }
+                // the subscriber now is in sync with the current state
+                if let Some(view) = &mut view {
+                    view.restart(&lock.state);
+                }
+                events
+            }
+            None => {
+                let state = match &mut view {
+                    Some(view) => view.restart(&lock.state),
+                    None => lock.state.clone(),
+                };
+                vec![(lock.revision, Arc::new(Event::Restart(state)))]
+            }
+        };
+
+        // the initial events must never get dropped
+        let buffer = buffer.into().unwrap_or(lock.default_buffer);
+        let queue = Arc::new(Queue::new(buffer.max(initial.len())));
+        queue.pin(initial);
+
+        let id = loop {
+            let id = uuid::Uuid::new_v4();
+            if let Entry::Vacant(entry) = lock.listeners.entry(id) {
+                entry.insert(Listener {
+                    queue: queue.clone(),
+                    policy,
+                    name,
+                    view,
+                });
+                break id;
+            }
+        };
+
+        let inner = self.inner.clone();
+
+        Subscription::new(queue, move || {
+            tokio::spawn(async move {
+                inner.write().await.listeners.remove(&id);
+            });
+        })
+    }
+
+    /// the most recent events, for clients polling instead of subscribing
+    ///
+    /// Without a revision, these are the latest events. Otherwise, the events following that
+    /// revision, as long as it is still part of the history. A restart replaces the history, as
+    /// it replaces the full state.
+    pub async fn recent(&self, since: Option<u64>, limit: usize) -> RecentEvents<K, V> {
+        let lock = self.inner.read().await;
+
+        let record = |recorded: &Recorded<K, V>| RecordedEvent {
+            revision: recorded.revision,
+            timestamp: recorded.timestamp,
+            event: (*recorded.event).clone(),
+        };
+
+        let (expired, events) = match since {
+            Some(since) if since < lock.history_start || since > lock.revision => (true, vec![]),
+            Some(since) => (
+                false,
+                lock.history
+                    .iter()
+                    .filter(|recorded| recorded.revision > since)
+                    .take(limit)
+                    .map(record)
+                    .collect(),
+            ),
+            None => (
+                false,
+                lock.history
+                    .iter()
+                    .skip(lock.history.len().saturating_sub(limit))
+                    .map(record)
+                    .collect(),
+            ),
+        };
+
+        RecentEvents {
+            revision: lock.revision,
+            expired,
+            events,
+        }
+    }
+
+    /// a snapshot of the current state, cheap to take as it shares the entries with the state
+    pub async fn get_state(&self) -> im::HashMap<K, V> {
+        self.inner.read().await.state.clone()
+    }
+
+    /// the current revision of the state, which changes with every event
+    pub async fn revision(&self) -> u64 {
+        self.inner.read().await.revision
+    }
+
+    /// the current state, along with its revision
+    pub async fn get_revisioned_state(&self) -> (u64, im::HashMap<K, V>) {
+        let lock = self.inner.read().await;
+        (lock.revision, lock.state.clone())
+    }
+
+    pub async fn set_state(&self, state: im::HashMap<K, V>) {
+        let mut lock = self.inner.write().await;
+        lock.state = state.clone();
+        Inner::broadcast(&mut lock, Event::Restart(state)).await;
+    }
+
+    pub async fn mutate_state<F>(&self, key: K, f: F)
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        let mut lock = self.inner.write().await;
+
+        let evt = match lock.state.entry(key.clone()) {
+            im::hashmap::Entry::Vacant(entry) => {
+                if let Some(state) = f(None) {
+                    entry.insert(state.clone());
+                    Some(Event::Added(key, state))
+                } else {
+                    None
+                }
+            }
+            im::hashmap::Entry::Occupied(mut entry) => match f(Some(entry.get().clone())) {
+                Some(state) => {
+                    if entry.get() != &state {
+                        *entry.get_mut() = state.clone();
+                        Some(Event::Modified(key, state))
+                    } else {
+                        None
+                    }
+                }
+                None => {
+                    entry.remove();
+                    Some(Event::Removed(key))
+                }
+            },
+        };
+
+        if let Some(evt) = evt {
+            Inner::broadcast(&mut lock, evt).await;
+        }
+    }
+
+    pub async fn iter_mut<F>(&self, f: F)
+    where
+        F: Fn(&K, &V) -> Output<V>,
+    {
+        let mut lock = self.inner.write().await;
+
+        let mut ops = Vec::new();
+
+        for (k, v) in lock.state.iter() {
+            match f(k, v) {
+                Output::Keep => {}
+                Output::Modify(state) => {
+                    if v != &state {
+                        ops.push((k.clone(), state));
+                    }
+                }
+            }
+        }
+
+        for (k, state) in ops.into_iter() {
+            lock.state.insert(k.clone(), state.clone());
+            Inner::broadcast(&mut lock, Event::Modified(k, state)).await;
+        }
+    }
+}
+
+pub enum Output<T> {
+    Keep,
+    Modify(T),
+}
+
+impl<K, V> Default for State<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    fn default() -> Self {
+        Self::with_buffer(DEFAULT_BUFFER)
+    }
+}
+
+impl<K, V> State<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    /// create an empty state, using a default buffer for subscriptions which don't request one
+    pub fn with_buffer(default_buffer: usize) -> Self {
+        // start from the current time, so that revisions of a previous instance are older than
+        // our history, and can't be resumed from.
+        //
+        // This assumes the wall clock doesn't go backwards between two instances (e.g. when moving
+        // to a node with a skewed clock), and that an instance creates less than one revision per
+        // microsecond on average, so that its revisions never get ahead of the clock. Otherwise, a
+        // client might resume from a revision of the previous instance, missing changes.
+        let revision = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                state: Default::default(),
+                listeners: Default::default(),
+                revision,
+                history: Default::default(),
+                history_start: revision,
+                default_buffer,
+            })),
+        }
+    }
+}
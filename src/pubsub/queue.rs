@@ -0,0 +1,255 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+/// time we wait for a slow subscriber to catch up, using [`SlowSubscriber::Wait`]
+const WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How to handle a subscriber which can't keep up with the events
+///
+/// Events are pushed while holding the lock of the state, so waiting holds up all changes. That's
+/// fine for internal subscribers, which keep up, but not for external clients.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SlowSubscriber {
+    /// Wait for the subscriber to catch up, disconnecting it after a short timeout
+    #[default]
+    Wait,
+    /// Drop the oldest queued event, disconnecting the subscriber if only its initial events are queued
+    DropOldest,
+    /// Drop the event which doesn't fit into the queue anymore
+    DropNewest,
+    /// Disconnect the subscriber right away
+    Disconnect,
+}
+
+impl SlowSubscriber {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Wait => "wait",
+            Self::DropOldest => "drop-oldest",
+            Self::DropNewest => "drop-newest",
+            Self::Disconnect => "disconnect",
+        }
+    }
+}
+
+/// A bounded queue of events for a single subscriber
+///
+/// Unlike a channel, the sending side can drop queued items, which we need to implement the
+/// [`SlowSubscriber`] policies. Pinned items (the initial events of a subscriber) never get dropped,
+/// as the subscriber can't make sense of any later event without them.
+#[derive(Debug)]
+pub struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    capacity: usize,
+    /// notifies the receiver about new items, or the queue being closed
+    readable: Notify,
+    /// notifies the sender about free capacity
+    writable: Notify,
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    items: VecDeque<T>,
+    /// number of pinned items, at the front of the queue
+    pinned: usize,
+    closed: bool,
+}
+
+/// The outcome of pushing an item to a queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Push {
+    /// The item was queued
+    Queued,
+    /// The queue was full, and an item got dropped
+    Dropped,
+    /// The queue was closed already
+    Closed,
+    /// The subscriber was too slow, and the queue got closed
+    Disconnected,
+}
+
+impl<T> Queue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(capacity),
+                pinned: 0,
+                closed: false,
+            }),
+            capacity: capacity.max(1),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.readable.notify_one();
+        self.writable.notify_one();
+    }
+
+    /// queue items which must not be dropped, ahead of all other items
+    ///
+    /// The items are queued even if that exceeds the capacity. This must be called before pushing
+    /// any other item.
+    pub fn pin(&self, items: impl IntoIterator<Item = T>) {
+        let mut state = self.state.lock();
+        debug_assert_eq!(state.items.len(), state.pinned);
+        for item in items {
+            state.items.push_back(item);
+            state.pinned += 1;
+        }
+        drop(state);
+        self.readable.notify_one();
+    }
+
+    /// push an item, applying the policy in case the queue is full
+    pub async fn push(&self, item: T, policy: SlowSubscriber) -> Push {
+        let mut item = Some(item);
+
+        loop {
+            {
+                let mut state = self.state.lock();
+                if state.closed {
+                    return Push::Closed;
+                }
+
+                if state.items.len() < self.capacity {
+                    state.items.extend(item.take());
+                    drop(state);
+                    self.readable.notify_one();
+                    return Push::Queued;
+                }
+
+                match policy {
+                    SlowSubscriber::Wait => {}
+                    SlowSubscriber::DropOldest if state.pinned < state.items.len() => {
+                        let oldest = state.pinned;
+                        state.items.remove(oldest);
+                        state.items.extend(item.take());
+                        drop(state);
+                        self.readable.notify_one();
+                        return Push::Dropped;
+                    }
+                    // nothing we could drop
+                    SlowSubscriber::DropOldest => {
+                        drop(state);
+                        self.close();
+                        return Push::Disconnected;
+                    }
+                    SlowSubscriber::DropNewest => return Push::Dropped,
+                    SlowSubscriber::Disconnect => {
+                        drop(state);
+                        self.close();
+                        return Push::Disconnected;
+                    }
+                }
+            }
+
+            if timeout(WAIT_TIMEOUT, self.writable.notified())
+                .await
+                .is_err()
+            {
+                self.close();
+                return Push::Disconnected;
+            }
+        }
+    }
+
+    /// number of queued items
+    pub fn len(&self) -> usize {
+        self.state.lock().items.len()
+    }
+
+    /// receive the next item, `None` once the queue is closed and drained
+    pub async fn recv(&self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.state.lock();
+                if let Some(item) = state.items.pop_front() {
+                    state.pinned = state.pinned.saturating_sub(1);
+                    drop(state);
+                    self.writable.notify_one();
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.readable.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// the queued items, without receiving them
+    fn items(queue: &Queue<u32>) -> Vec<u32> {
+        queue.state.lock().items.iter().copied().collect()
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_pinned() {
+        let queue = Queue::new(2);
+        queue.pin([0]);
+
+        assert_eq!(
+            queue.push(1, SlowSubscriber::DropOldest).await,
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(2, SlowSubscriber::DropOldest).await,
+            Push::Dropped
+        );
+        assert_eq!(items(&queue), vec![0, 2]);
+
+        // once received, it's just a regular item
+        assert_eq!(queue.recv().await, Some(0));
+        assert_eq!(
+            queue.push(3, SlowSubscriber::DropOldest).await,
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(4, SlowSubscriber::DropOldest).await,
+            Push::Dropped
+        );
+        assert_eq!(items(&queue), vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_disconnects_when_pinned() {
+        let queue = Queue::new(2);
+        queue.pin([0, 1]);
+
+        assert_eq!(
+            queue.push(2, SlowSubscriber::DropOldest).await,
+            Push::Disconnected
+        );
+        // the subscriber still gets what was queued
+        assert_eq!(queue.recv().await, Some(0));
+        assert_eq!(queue.recv().await, Some(1));
+        assert_eq!(queue.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_pinned() {
+        let queue = Queue::new(1);
+        queue.pin([0]);
+
+        assert_eq!(
+            queue.push(1, SlowSubscriber::DropNewest).await,
+            Push::Dropped
+        );
+        assert_eq!(items(&queue), vec![0]);
+    }
+}
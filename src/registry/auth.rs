@@ -0,0 +1,195 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Credentials for a registry
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Credentials by registry, as stored in a docker `config.json` file
+#[derive(Clone, Debug, Default)]
+pub struct CredentialStore {
+    credentials: HashMap<String, Credentials>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DockerAuth {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl CredentialStore {
+    /// load a docker config file, like the content of a `kubernetes.io/dockerconfigjson` secret
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let config: DockerConfig = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        let credentials = config
+            .auths
+            .into_iter()
+            .filter_map(|(registry, auth)| {
+                let credentials = match auth {
+                    DockerAuth {
+                        username: Some(username),
+                        password: Some(password),
+                        ..
+                    } => Credentials { username, password },
+                    DockerAuth {
+                        auth: Some(auth), ..
+                    } => {
+                        let auth = String::from_utf8(STANDARD.decode(auth).ok()?).ok()?;
+                        let (username, password) = auth.split_once(':')?;
+                        Credentials {
+                            username: username.to_string(),
+                            password: password.to_string(),
+                        }
+                    }
+                    _ => return None,
+                };
+                Some((normalize(&registry), credentials))
+            })
+            .collect();
+
+        Ok(Self { credentials })
+    }
+
+    pub fn get(&self, registry: &str) -> Option<&Credentials> {
+        self.credentials.get(registry)
+    }
+}
+
+/// normalize the registry keys of a docker config file, which may be URLs
+fn normalize(registry: &str) -> String {
+    let registry = registry
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let registry = registry.split('/').next().unwrap_or(registry);
+
+    match registry {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io".to_string(),
+        registry => registry.to_string(),
+    }
+}
+
+/// A `WWW-Authenticate` challenge of a registry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Challenge {
+    Basic,
+    Bearer {
+        realm: String,
+        service: Option<String>,
+        scope: Option<String>,
+    },
+}
+
+impl Challenge {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (scheme, params) = value.split_once(' ').unwrap_or((value, ""));
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Some(Self::Basic);
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+
+        let mut params = parse_params(params);
+
+        Some(Self::Bearer {
+            realm: params.remove("realm")?,
+            service: params.remove("service"),
+            scope: params.remove("scope"),
+        })
+    }
+}
+
+/// parse the `key="value"` pairs of a challenge, values may contain commas
+fn parse_params(mut params: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+
+    while let Some((key, rest)) = params.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+        let (value, rest) = match rest.strip_prefix('"') {
+            Some(rest) => rest.split_once('"').unwrap_or((rest, "")),
+            None => rest.split_once(',').unwrap_or((rest, "")),
+        };
+        result.insert(key, value.to_string());
+        params = rest;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn challenge_basic() {
+        assert_eq!(Challenge::parse("Basic"), Some(Challenge::Basic));
+        assert_eq!(
+            Challenge::parse(r#"basic realm="registry""#),
+            Some(Challenge::Basic)
+        );
+    }
+
+    #[test]
+    fn challenge_bearer() {
+        assert_eq!(
+            Challenge::parse(
+                r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#
+            ),
+            Some(Challenge::Bearer {
+                realm: "https://auth.docker.io/token".to_string(),
+                service: Some("registry.docker.io".to_string()),
+                scope: Some("repository:library/nginx:pull".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn challenge_bearer_params() {
+        // commas inside values, unquoted values, spaces and upper case keys
+        assert_eq!(
+            Challenge::parse(
+                r#"bearer Realm=https://quay.io/v2/auth, scope="repository:a/b:pull,push""#
+            ),
+            Some(Challenge::Bearer {
+                realm: "https://quay.io/v2/auth".to_string(),
+                service: None,
+                scope: Some("repository:a/b:pull,push".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn challenge_invalid() {
+        // a realm is required
+        assert_eq!(Challenge::parse(r#"Bearer service="registry""#), None);
+        assert_eq!(Challenge::parse("Bearer"), None);
+        assert_eq!(Challenge::parse(r#"Digest realm="registry""#), None);
+        assert_eq!(Challenge::parse(""), None);
+    }
+
+    #[test]
+    fn normalize_registry() {
+        assert_eq!(normalize("quay.io"), "quay.io");
+        assert_eq!(normalize("localhost:5000"), "localhost:5000");
+        assert_eq!(normalize("https://index.docker.io/v1/"), "docker.io");
+        assert_eq!(normalize("registry-1.docker.io"), "docker.io");
+        assert_eq!(normalize("http://ghcr.io/v2/"), "ghcr.io");
+    }
+}
@@ -0,0 +1,357 @@
+use super::auth::{Challenge, CredentialStore};
+use super::reference::{Reference, Repository};
+use crate::http::{self, BodyError};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use reqwest::header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Method, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::debug;
+
+const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// media types of manifests (and indexes) we accept when resolving tags
+const MEDIA_TYPES_MANIFEST: &[&str] = &[
+    MEDIA_TYPE_INDEX,
+    MEDIA_TYPE_MANIFEST,
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+const DOCKER_CONTENT_DIGEST: &str = "Docker-Content-Digest";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Server error: {0}")]
+    Server(StatusCode),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("Failed to authenticate: {0}")]
+    Authentication(String),
+    #[error("Blob exceeds the limit of {0} bytes")]
+    TooLarge(u64),
+}
+
+impl From<BodyError> for Error {
+    fn from(err: BodyError) -> Self {
+        match err {
+            BodyError::Transport(err) => Self::Transport(err),
+            BodyError::TooLarge(limit) => Self::TooLarge(limit),
+        }
+    }
+}
+
+impl Error {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Server(status) | Self::UnexpectedStatus(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// An OCI content descriptor
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    #[serde(default)]
+    pub artifact_type: Option<String>,
+    #[serde(default)]
+    pub platform: Option<Platform>,
+}
+
+/// The platform of an image, as listed in an image index
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Platform {
+    pub architecture: String,
+    pub os: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Index {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    #[serde(default)]
+    pub layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// A minimal client for the OCI distribution API, only pulling content
+#[derive(Clone, Debug)]
+pub struct RegistryClient {
+    client: reqwest::Client,
+    credentials: CredentialStore,
+    insecure: HashSet<String>,
+    /// Authorization header values, by registry and repository
+    authorizations: Arc<Mutex<HashMap<(String, String), String>>>,
+}
+
+impl RegistryClient {
+    pub fn new(
+        client: reqwest::Client,
+        credentials: CredentialStore,
+        insecure: HashSet<String>,
+    ) -> Self {
+        Self {
+            client,
+            credentials,
+            insecure,
+            authorizations: Default::default(),
+        }
+    }
+
+    /// get the referrers of an image
+    ///
+    /// If the registry doesn't support the referrers API, we fall back to the tag schema.
+    pub async fn referrers(&self, reference: &Reference) -> Result<Vec<Descriptor>, Error> {
+        let path = format!("referrers/{}", reference.digest);
+        let response = self.get(reference, &path, &[MEDIA_TYPE_INDEX]).await?;
+
+        let response = match response.status() {
+            StatusCode::NOT_FOUND => {
+                let tag = reference.digest.replacen(':', "-", 1);
+                let response = self
+                    .get(reference, &format!("manifests/{tag}"), &[MEDIA_TYPE_INDEX])
+                    .await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Ok(vec![]);
+                }
+                response
+            }
+            _ => response,
+        };
+
+        let index: Index = check(response)?.json().await?;
+        Ok(index.manifests)
+    }
+
+    /// get an image manifest by digest
+    pub async fn manifest(&self, reference: &Reference, digest: &str) -> Result<Manifest, Error> {
+        let response = self
+            .get(
+                reference,
+                &format!("manifests/{digest}"),
+                &[MEDIA_TYPE_MANIFEST],
+            )
+            .await?;
+        Ok(check(response)?.json().await?)
+    }
+
+    /// get a blob by digest, failing if it exceeds the limit
+    pub async fn blob(
+        &self,
+        reference: &Reference,
+        digest: &str,
+        limit: u64,
+    ) -> Result<Bytes, Error> {
+        let response = self.get(reference, &format!("blobs/{digest}"), &[]).await?;
+        Ok(http::read_limited(check(response)?, limit).await?)
+    }
+
+    /// resolve the digest of a tag, `None` if the tag doesn't exist
+    pub async fn resolve_digest(
+        &self,
+        repository: &Repository,
+        tag: &str,
+    ) -> Result<Option<String>, Error> {
+        let response = self
+            .request(
+                Method::HEAD,
+                repository,
+                &format!("manifests/{tag}"),
+                MEDIA_TYPES_MANIFEST,
+            )
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(check(response)?
+            .headers()
+            .get(DOCKER_CONTENT_DIGEST)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string))
+    }
+
+    /// resolve the digest of the platform specific image, for an image index
+    ///
+    /// Returns `None` if the digest doesn't refer to an index, or the index has no image for the
+    /// platform.
+    pub async fn platform_digest(
+        &self,
+        reference: &Reference,
+        arch: &str,
+    ) -> Result<Option<String>, Error> {
+        let response = self
+            .get(
+                reference,
+                &format!("manifests/{}", reference.digest),
+                MEDIA_TYPES_MANIFEST,
+            )
+            .await?;
+
+        let index: Index = check(response)?.json().await?;
+        Ok(index
+            .manifests
+            .into_iter()
+            .find(|manifest| {
+                manifest
+                    .platform
+                    .as_ref()
+                    .is_some_and(|platform| platform.os == "linux" && platform.architecture == arch)
+            })
+            .map(|manifest| manifest.digest))
+    }
+
+    async fn get(
+        &self,
+        reference: &Reference,
+        path: &str,
+        accept: &[&str],
+    ) -> Result<reqwest::Response, Error> {
+        self.request(Method::GET, &reference.repository, path, accept)
+            .await
+    }
+
+    /// request a resource of a repository, authenticating when being challenged
+    async fn request(
+        &self,
+        method: Method,
+        repository: &Repository,
+        path: &str,
+        accept: &[&str],
+    ) -> Result<reqwest::Response, Error> {
+        let scheme = match self.insecure.contains(&repository.registry) {
+            true => "http",
+            false => "https",
+        };
+        let url = format!(
+            "{scheme}://{}/v2/{}/{path}",
+            repository.host(),
+            repository.name
+        );
+        let key = (repository.registry.clone(), repository.name.clone());
+
+        let request = || {
+            let mut request = self.client.request(method.clone(), &url);
+            if !accept.is_empty() {
+                request = request.header(ACCEPT, accept.join(", "));
+            }
+            request
+        };
+
+        let authorization = self.authorizations.lock().get(&key).cloned();
+        let response = match &authorization {
+            Some(authorization) => request().header(AUTHORIZATION, authorization),
+            None => request(),
+        }
+        .send()
+        .await?;
+
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        // (re-)authenticate and try once more
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Challenge::parse)
+            .ok_or_else(|| Error::Authentication("Missing or invalid challenge".into()))?;
+
+        let authorization = self.authenticate(repository, challenge).await?;
+        self.authorizations
+            .lock()
+            .insert(key, authorization.clone());
+
+        Ok(request()
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await?)
+    }
+
+    /// respond to a challenge, returning the value of the authorization header
+    async fn authenticate(
+        &self,
+        repository: &Repository,
+        challenge: Challenge,
+    ) -> Result<String, Error> {
+        let credentials = self.credentials.get(&repository.registry);
+
+        match challenge {
+            Challenge::Basic => {
+                let credentials = credentials.ok_or_else(|| {
+                    Error::Authentication(format!("No credentials for {}", repository.registry))
+                })?;
+                Ok(format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", credentials.username, credentials.password))
+                ))
+            }
+            Challenge::Bearer {
+                realm,
+                service,
+                scope,
+            } => {
+                debug!("Requesting registry token from {realm}");
+
+                let scope = scope.unwrap_or_else(|| format!("repository:{}:pull", repository.name));
+                let mut request = self.client.get(&realm).query(&[("scope", scope)]);
+                if let Some(service) = service {
+                    request = request.query(&[("service", service)]);
+                }
+                if let Some(credentials) = credentials {
+                    request =
+                        request.basic_auth(&credentials.username, Some(&credentials.password));
+                }
+
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(Error::Authentication(format!(
+                        "Token request failed: {}",
+                        response.status()
+                    )));
+                }
+
+                let response: TokenResponse = response.json().await?;
+                let token = response
+                    .token
+                    .or(response.access_token)
+                    .ok_or_else(|| Error::Authentication("Missing token".into()))?;
+
+                Ok(format!("Bearer {token}"))
+            }
+        }
+    }
+}
+
+fn check(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    match response.status() {
+        status if status.is_success() => Ok(response),
+        status if status.is_server_error() => Err(Error::Server(status)),
+        status => Err(Error::UnexpectedStatus(status)),
+    }
+}
@@ -0,0 +1,209 @@
+mod auth;
+mod client;
+mod reference;
+mod resolver;
+
+pub use auth::CredentialStore;
+pub use client::RegistryClient;
+pub use reference::Reference;
+pub use resolver::DigestResolver;
+
+use crate::sbom;
+use crate::source::{Sbom, SbomSource};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bommer_api::data::{ImageRef, LookupError, LookupErrorKind, SbomSummary};
+use bytes::Bytes;
+use std::path::PathBuf;
+use tracing::debug;
+
+const SPDX_JSON: &str = "application/spdx+json";
+const CYCLONEDX_JSON: &str = "application/vnd.cyclonedx+json";
+const IN_TOTO_JSON: &str = "application/vnd.in-toto+json";
+const DSSE_ENVELOPE: &str = "application/vnd.dsse.envelope.v1+json";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Registry")]
+pub struct RegistryConfig {
+    /// Look up SBOMs attached to an image in its registry, if the SBOM source has none
+    #[arg(long = "registry-fallback", env = "REGISTRY_FALLBACK")]
+    pub fallback: bool,
+
+    /// Resolve the digest of images by tag, for runtimes which don't report usable image IDs
+    #[arg(long = "resolve-digests", env = "RESOLVE_DIGESTS")]
+    pub resolve_digests: bool,
+
+    /// Docker config file, providing the credentials of private registries
+    #[arg(long = "registry-auth-file", env = "REGISTRY_AUTH_FILE")]
+    pub auth_file: Option<PathBuf>,
+
+    /// Registries to access using plain HTTP
+    #[arg(
+        long = "registry-insecure",
+        env = "REGISTRY_INSECURE",
+        value_delimiter = ','
+    )]
+    pub insecure: Vec<String>,
+}
+
+impl RegistryConfig {
+    /// create a registry client, using the configured credentials
+    pub fn client(&self, client: reqwest::Client) -> anyhow::Result<RegistryClient> {
+        let credentials = match &self.auth_file {
+            Some(path) => CredentialStore::load(path)?,
+            None => Default::default(),
+        };
+
+        Ok(RegistryClient::new(
+            client,
+            credentials,
+            self.insecure.iter().cloned().collect(),
+        ))
+    }
+}
+
+/// Looks up SBOMs attached to images in their registry
+///
+/// SBOMs are discovered using the OCI referrers API, either attached directly, or wrapped in
+/// an in-toto attestation (like `cosign attest` does).
+#[derive(Clone, Debug)]
+pub struct RegistrySource {
+    client: RegistryClient,
+    /// Maximum size of an SBOM layer
+    max_size: u64,
+}
+
+impl RegistrySource {
+    pub fn new(client: RegistryClient, max_size: u64) -> Self {
+        Self { client, max_size }
+    }
+
+    /// find the first SBOM attached to an image, along with its summary
+    async fn lookup_referrers(
+        &self,
+        reference: &Reference,
+    ) -> Result<Option<(Bytes, SbomSummary)>, LookupError> {
+        let mut referrers = self
+            .client
+            .referrers(reference)
+            .await
+            .map_err(to_lookup_error)?
+            .into_iter()
+            .filter(|referrer| is_candidate(referrer.artifact_type.as_deref()))
+            .collect::<Vec<_>>();
+
+        // prefer plain SBOMs over attestations
+        referrers.sort_by_key(|referrer| !is_sbom(referrer.artifact_type.as_deref()));
+
+        for referrer in referrers {
+            let manifest = self
+                .client
+                .manifest(reference, &referrer.digest)
+                .await
+                .map_err(to_lookup_error)?;
+
+            for layer in manifest.layers {
+                let data = self
+                    .client
+                    .blob(reference, &layer.digest, self.max_size)
+                    .await
+                    .map_err(to_lookup_error)?;
+
+                let data = match layer.media_type.as_str() {
+                    SPDX_JSON | CYCLONEDX_JSON => data,
+                    IN_TOTO_JSON | DSSE_ENVELOPE => match unwrap_attestation(&data) {
+                        Some(data) => data.into(),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+
+                match sbom::parse(&data) {
+                    Ok(summary) => return Ok(Some((data, summary))),
+                    Err(err) => debug!("Ignoring layer {}: {err}", layer.digest),
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl SbomSource for RegistrySource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        let result = self.lookup_referrers(&reference(image)?).await?;
+        Ok(result.map(|(data, summary)| Sbom {
+            document: Some(data),
+            ..summary.into()
+        }))
+    }
+
+    async fn document(&self, image: &ImageRef) -> Result<Option<Bytes>, LookupError> {
+        let result = self.lookup_referrers(&reference(image)?).await?;
+        Ok(result.map(|(data, _)| data))
+    }
+}
+
+fn reference(image: &ImageRef) -> Result<Reference, LookupError> {
+    Reference::parse(image).ok_or_else(|| LookupError {
+        kind: LookupErrorKind::InvalidReference,
+        message: format!("Unable to parse image reference: {image}"),
+        status: None,
+    })
+}
+
+/// check if an artifact type might carry an SBOM
+fn is_candidate(artifact_type: Option<&str>) -> bool {
+    match artifact_type {
+        Some(IN_TOTO_JSON | DSSE_ENVELOPE) => true,
+        artifact_type => is_sbom(artifact_type),
+    }
+}
+
+fn is_sbom(artifact_type: Option<&str>) -> bool {
+    matches!(artifact_type, Some(SPDX_JSON | CYCLONEDX_JSON))
+}
+
+#[derive(serde::Deserialize)]
+struct Envelope {
+    payload: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Statement {
+    predicate_type: String,
+    predicate: serde_json::Value,
+}
+
+/// extract an SBOM predicate from an in-toto statement, which might be wrapped in a DSSE envelope
+fn unwrap_attestation(data: &[u8]) -> Option<Vec<u8>> {
+    let statement = match serde_json::from_slice::<Envelope>(data) {
+        Ok(envelope) => STANDARD.decode(envelope.payload).ok()?,
+        Err(_) => data.to_vec(),
+    };
+
+    let statement: Statement = serde_json::from_slice(&statement).ok()?;
+    match statement.predicate_type.as_str() {
+        "https://spdx.dev/Document" | "https://cyclonedx.org/bom" => {
+            serde_json::to_vec(&statement.predicate).ok()
+        }
+        _ => None,
+    }
+}
+
+fn to_lookup_error(err: client::Error) -> LookupError {
+    LookupError {
+        kind: match &err {
+            client::Error::Transport(_) => LookupErrorKind::Transport,
+            client::Error::Server(_) => LookupErrorKind::Server,
+            client::Error::UnexpectedStatus(_) | client::Error::Authentication(_) => {
+                LookupErrorKind::UnexpectedStatus
+            }
+            client::Error::TooLarge(_) => LookupErrorKind::TooLarge,
+        },
+        status: err.status().map(|status| status.as_u16()),
+        message: err.to_string(),
+    }
+}
@@ -0,0 +1,128 @@
+use bommer_api::data::ImageRef;
+
+const DOCKER_HUB: &str = "docker.io";
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// A repository in a registry
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Repository {
+    /// The registry host, including an optional port
+    pub registry: String,
+    /// The name of the repository inside the registry
+    pub name: String,
+}
+
+impl Repository {
+    /// the host to contact for the registry
+    pub fn host(&self) -> &str {
+        match self.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_REGISTRY,
+            registry => registry,
+        }
+    }
+}
+
+impl From<&ImageRef> for Repository {
+    fn from(image: &ImageRef) -> Self {
+        Self {
+            registry: image.registry.clone(),
+            name: image.repository.clone(),
+        }
+    }
+}
+
+/// A reference to an image by digest, as required to talk to its registry
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Reference {
+    pub repository: Repository,
+    /// The digest of the image, e.g. `sha256:…`
+    pub digest: String,
+}
+
+impl Reference {
+    /// create a reference from an image reference, `None` if it doesn't have a digest
+    pub fn parse(image: &ImageRef) -> Option<Self> {
+        Some(Self {
+            repository: Repository::from(image),
+            digest: image.digest.clone()?,
+        })
+    }
+}
+
+/// A reference to an image by tag
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TagReference {
+    pub repository: Repository,
+    pub tag: String,
+}
+
+impl TagReference {
+    /// create a reference from an image reference, `None` if it has a digest
+    pub fn parse(image: &ImageRef) -> Option<Self> {
+        if image.digest.is_some() {
+            return None;
+        }
+
+        Some(Self {
+            repository: Repository::from(image),
+            tag: image.tag.clone().unwrap_or_else(|| "latest".to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn image(s: &str) -> ImageRef {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn reference() {
+        let reference = Reference::parse(&image(&format!("quay.io/example/app:1.0@{DIGEST}")));
+        assert_eq!(
+            reference,
+            Some(Reference {
+                repository: Repository {
+                    registry: "quay.io".to_string(),
+                    name: "example/app".to_string(),
+                },
+                digest: DIGEST.to_string(),
+            })
+        );
+
+        // a digest is required
+        assert_eq!(Reference::parse(&image("quay.io/example/app:1.0")), None);
+    }
+
+    #[test]
+    fn tag_reference() {
+        let reference = TagReference::parse(&image("localhost:5000/app:1.0")).unwrap();
+        assert_eq!(reference.repository.registry, "localhost:5000");
+        assert_eq!(reference.repository.name, "app");
+        assert_eq!(reference.tag, "1.0");
+
+        // defaults to the latest tag
+        let reference = TagReference::parse(&image("quay.io/example/app")).unwrap();
+        assert_eq!(reference.tag, "latest");
+
+        // a digest is better used as is
+        assert_eq!(
+            TagReference::parse(&image(&format!("quay.io/example/app@{DIGEST}"))),
+            None
+        );
+    }
+
+    #[test]
+    fn host() {
+        let reference = Reference::parse(&image(&format!("nginx@{DIGEST}"))).unwrap();
+        assert_eq!(reference.repository.registry, DOCKER_HUB);
+        assert_eq!(reference.repository.host(), DOCKER_HUB_REGISTRY);
+
+        let reference = Reference::parse(&image(&format!("quay.io/example/app@{DIGEST}"))).unwrap();
+        assert_eq!(reference.repository.host(), "quay.io");
+    }
+}
@@ -0,0 +1,229 @@
+use super::reference::{Reference, TagReference};
+use super::RegistryClient;
+use bommer_api::data::ImageRef;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// time we remember the digest of a tag, as tags may move
+const TTL: Duration = Duration::from_secs(5 * 60);
+
+/// maximum number of digests we remember, for each kind of lookup
+const CAPACITY: usize = 10_000;
+
+/// a resolved digest, `None` if the tag doesn't exist, along with the time it was resolved
+type Entry = (Instant, Option<String>);
+
+/// an image index along with the architecture to look up
+type PlatformKey = (Reference, String);
+
+/// Resolved digests, expiring after a TTL (if any), and limited in number
+#[derive(Debug)]
+struct Cache<K> {
+    entries: HashMap<K, Entry>,
+    ttl: Option<Duration>,
+    capacity: usize,
+}
+
+impl<K: Clone + Eq + Hash> Cache<K> {
+    fn new(ttl: Option<Duration>, capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            ttl,
+            capacity,
+        }
+    }
+
+    fn valid(&self, (inserted, _): &Entry) -> bool {
+        self.ttl.is_none_or(|ttl| inserted.elapsed() < ttl)
+    }
+
+    /// get a resolved digest, `None` if there is no (valid) entry
+    fn get(&mut self, key: &K) -> Option<Option<String>> {
+        match self.entries.get(key) {
+            Some(entry) if self.valid(entry) => Some(entry.1.clone()),
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: K, digest: Option<String>) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            // drop expired entries first, and the oldest one if that wasn't enough
+            let ttl = self.ttl;
+            self.entries
+                .retain(|_, (inserted, _)| ttl.is_none_or(|ttl| inserted.elapsed() < ttl));
+
+            if self.entries.len() >= self.capacity {
+                if let Some(oldest) = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, (inserted, _))| *inserted)
+                    .map(|(k, _)| k.clone())
+                {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+
+        self.entries.insert(key, (Instant::now(), digest));
+    }
+}
+
+/// Resolves image references into references by (platform specific) digest, by asking the registry
+///
+/// Resolving tags is a workaround for environments (like kind) which don't report a usable
+/// image ID. As tags may have moved since the image was pulled, the result is only a best guess.
+///
+/// Images which carry an architecture, and refer to a multi-arch image index, get resolved to
+/// the digest of the image for that architecture. As digests are immutable, those results never
+/// expire. Both are limited in number though, dropping the oldest ones first.
+#[derive(Clone, Debug)]
+pub struct DigestResolver {
+    client: RegistryClient,
+    tags: bool,
+    cache: Arc<Mutex<Cache<TagReference>>>,
+    platforms: Arc<Mutex<Cache<PlatformKey>>>,
+}
+
+impl DigestResolver {
+    /// create a new resolver, `tags` enables resolving images by tag
+    pub fn new(client: RegistryClient, tags: bool) -> Self {
+        Self {
+            client,
+            tags,
+            cache: Arc::new(Mutex::new(Cache::new(Some(TTL), CAPACITY))),
+            platforms: Arc::new(Mutex::new(Cache::new(None, CAPACITY))),
+        }
+    }
+
+    /// resolve an image reference, returns the original reference if it can't be resolved
+    pub async fn resolve(&self, image: ImageRef) -> ImageRef {
+        let image = match self.tags {
+            true => self.resolve_tag(image).await,
+            false => image,
+        };
+        self.resolve_platform(image).await
+    }
+
+    async fn resolve_tag(&self, image: ImageRef) -> ImageRef {
+        let reference = match TagReference::parse(&image) {
+            Some(reference) => reference,
+            None => return image,
+        };
+
+        let cached = self.cache.lock().get(&reference);
+
+        let digest = match cached {
+            Some(digest) => digest,
+            None => {
+                let digest = match self
+                    .client
+                    .resolve_digest(&reference.repository, &reference.tag)
+                    .await
+                {
+                    Ok(digest) => digest,
+                    Err(err) => {
+                        // don't cache, try again next time
+                        warn!(%image, "Failed to resolve digest: {err}");
+                        return image;
+                    }
+                };
+                self.cache.lock().insert(reference.clone(), digest.clone());
+                digest
+            }
+        };
+
+        match digest {
+            Some(digest) => {
+                let resolved = image.with_digest(digest);
+                debug!(%image, %resolved, "Resolved digest");
+                resolved
+            }
+            None => image,
+        }
+    }
+
+    async fn resolve_platform(&self, image: ImageRef) -> ImageRef {
+        let (reference, arch) = match (Reference::parse(&image), &image.arch) {
+            (Some(reference), Some(arch)) => (reference, arch.clone()),
+            _ => return image,
+        };
+        let key = (reference, arch);
+
+        let cached = self.platforms.lock().get(&key);
+        let digest = match cached {
+            Some(digest) => digest,
+            None => {
+                let digest = match self.client.platform_digest(&key.0, &key.1).await {
+                    Ok(digest) => digest,
+                    Err(err) => {
+                        // don't cache, try again next time
+                        warn!(%image, "Failed to resolve platform digest: {err}");
+                        return image;
+                    }
+                };
+                self.platforms.lock().insert(key, digest.clone());
+                digest
+            }
+        };
+
+        match digest {
+            Some(digest) => {
+                let resolved = image.with_digest(digest);
+                debug!(%image, %resolved, "Resolved digest");
+                resolved
+            }
+            None => image,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn capacity() {
+        let mut cache = Cache::new(None, 2);
+        cache.insert(1, Some("a".to_string()));
+        cache.insert(2, None);
+        assert_eq!(cache.get(&1), Some(Some("a".to_string())));
+        assert_eq!(cache.get(&2), Some(None));
+
+        // replacing an entry doesn't need room
+        cache.insert(1, Some("b".to_string()));
+        assert_eq!(cache.entries.len(), 2);
+
+        // the oldest entry makes room
+        cache.insert(3, Some("c".to_string()));
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(Some("b".to_string())));
+        assert_eq!(cache.get(&3), Some(Some("c".to_string())));
+    }
+
+    #[test]
+    fn expiry() {
+        let mut cache = Cache::new(Some(Duration::ZERO), 2);
+        cache.insert(1, Some("a".to_string()));
+        assert_eq!(cache.get(&1), None);
+        assert!(cache.entries.is_empty());
+
+        // expired entries make room first
+        let mut cache = Cache::new(Some(Duration::from_secs(1)), 2);
+        cache.insert(1, None);
+        cache.entries.get_mut(&1).unwrap().0 -= Duration::from_secs(2);
+        cache.insert(2, None);
+        cache.insert(3, None);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(None));
+        assert_eq!(cache.get(&3), Some(None));
+    }
+}
@@ -0,0 +1,96 @@
+use bommer_api::data::Vulnerabilities;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::NamespaceResourceScope;
+use kube::Resource;
+use std::borrow::Cow;
+
+pub const GROUP: &str = "bommer.xkcd-2347.github.io";
+pub const VERSION: &str = "v1alpha1";
+pub const KIND: &str = "ImageSbomReport";
+
+/// A report of the images used in a namespace, and the state of their SBOMs
+///
+/// The definition is in `deploy/crds/imagesbomreports.yaml`. As the report is generated, there
+/// is no spec, but only the report itself.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ImageSbomReport {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub report: Report,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub summary: Summary,
+    /// The images used in the namespace, ordered by their reference
+    #[serde(default)]
+    pub images: Vec<ImageReport>,
+}
+
+/// Number of images by the state of their SBOM, and the vulnerabilities of all images
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub images: usize,
+    pub found: usize,
+    pub missing: usize,
+    pub failed: usize,
+    pub scheduled: usize,
+    pub vulnerabilities: Vulnerabilities,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageReport {
+    pub image: String,
+    /// Number of pods in the namespace using the image
+    pub pods: usize,
+    pub sbom: SbomStatus,
+    /// Number of packages of the SBOM, if found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packages: Option<usize>,
+    /// The error looking up the SBOM, if failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vulnerabilities: Option<Vulnerabilities>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SbomStatus {
+    Scheduled,
+    Failed,
+    Missing,
+    Found,
+}
+
+impl Resource for ImageSbomReport {
+    type DynamicType = ();
+    type Scope = NamespaceResourceScope;
+
+    fn kind(_: &()) -> Cow<'_, str> {
+        KIND.into()
+    }
+
+    fn group(_: &()) -> Cow<'_, str> {
+        GROUP.into()
+    }
+
+    fn version(_: &()) -> Cow<'_, str> {
+        VERSION.into()
+    }
+
+    fn plural(_: &()) -> Cow<'_, str> {
+        "imagesbomreports".into()
+    }
+
+    fn meta(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn meta_mut(&mut self) -> &mut ObjectMeta {
+        &mut self.metadata
+    }
+}
@@ -0,0 +1,262 @@
+//! Publishing the state of the workload as custom resources, one report per namespace.
+//!
+//! This allows users to check the SBOM coverage of their namespace using `kubectl`, without
+//! access to the API of bommer.
+
+mod crd;
+
+use crate::store::SyncState;
+use crate::workload::WorkloadState;
+use bommer_api::data::{Image, ImageRef, SbomState, Vulnerabilities};
+use crd::{ImageReport, ImageSbomReport, Report, SbomStatus, Summary};
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams};
+use kube::{Api, Client, ResourceExt};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const MANAGED_BY: &str = "app.kubernetes.io/managed-by";
+const FIELD_MANAGER: &str = "bommer";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Reports")]
+pub struct ReportConfig {
+    /// Publish an `ImageSbomReport` resource for each namespace, requires the CRD to be installed
+    #[arg(long, env = "PUBLISH_REPORTS")]
+    pub publish_reports: bool,
+
+    /// Name of the report resources
+    #[arg(long, env = "REPORT_NAME", default_value = "workload")]
+    pub report_name: String,
+
+    /// Interval of updating the reports, if the workload changed
+    #[arg(long, env = "REPORT_INTERVAL", default_value = "1m", value_parser = humantime::parse_duration)]
+    pub report_interval: Duration,
+}
+
+impl ReportConfig {
+    /// periodically publish the reports to each cluster, if enabled
+    pub async fn run(
+        self,
+        map: WorkloadState,
+        sync: SyncState,
+        clusters: Vec<(Option<String>, Client)>,
+    ) -> anyhow::Result<()> {
+        if !self.publish_reports {
+            return futures::future::pending().await;
+        }
+
+        let mut publishers = Vec::with_capacity(clusters.len());
+        for (cluster, client) in clusters {
+            let mut publisher = Publisher {
+                name: self.report_name.clone(),
+                cluster,
+                client,
+                published: Default::default(),
+            };
+            publisher.discover().await;
+            publishers.push(publisher);
+        }
+
+        let mut interval = tokio::time::interval(self.report_interval);
+        let mut sub = map.subscribe("report", None).await;
+        let mut dirty = true;
+
+        loop {
+            tokio::select! {
+                evt = sub.recv() => match evt {
+                    Some(_) => dirty = true,
+                    None => {
+                        // the subscription got dropped, we might have missed something
+                        sub = map.subscribe("report", None).await;
+                    }
+                },
+                _ = interval.tick() => {
+                    // until synced, we don't know all namespaces, and would remove their reports
+                    if !dirty || !sync.is_synced() {
+                        continue;
+                    }
+                    dirty = false;
+
+                    let state = map.get_state().await;
+                    for publisher in &mut publishers {
+                        if !publisher.publish(&state).await {
+                            // try again next time
+                            dirty = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Publishes the reports of a single cluster
+struct Publisher {
+    name: String,
+    cluster: Option<String>,
+    client: Client,
+    /// The reports we published, by namespace
+    published: HashMap<String, Option<Report>>,
+}
+
+impl Publisher {
+    /// find reports published by a previous instance, so that outdated ones get removed
+    async fn discover(&mut self) {
+        let api: Api<ImageSbomReport> = Api::all(self.client.clone());
+        let params = ListParams::default().labels(&format!("{MANAGED_BY}={FIELD_MANAGER}"));
+        match api.list_metadata(&params).await {
+            Ok(reports) => {
+                for report in reports {
+                    if let Some(namespace) = report.namespace() {
+                        self.published.insert(namespace, None);
+                    }
+                }
+            }
+            // we might only have permission for some namespaces
+            Err(err) => debug!(cluster = ?self.cluster, "Failed to list existing reports: {err}"),
+        }
+    }
+
+    /// publish the reports which changed, returns `false` if any of them failed
+    async fn publish(&mut self, state: &im::HashMap<ImageRef, Image>) -> bool {
+        let reports = reports(state, &self.cluster);
+        let mut ok = true;
+
+        let outdated = self
+            .published
+            .keys()
+            .filter(|namespace| !reports.contains_key(*namespace))
+            .cloned()
+            .collect::<Vec<_>>();
+        for namespace in outdated {
+            let api: Api<ImageSbomReport> = Api::namespaced(self.client.clone(), &namespace);
+            match api.delete(&self.name, &DeleteParams::default()).await {
+                Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                    debug!(cluster = ?self.cluster, %namespace, "Removed report");
+                    self.published.remove(&namespace);
+                }
+                Err(err) => {
+                    warn!(cluster = ?self.cluster, %namespace, "Failed to remove report: {err}");
+                    ok = false;
+                }
+            }
+        }
+
+        for (namespace, report) in reports {
+            if self.published.get(&namespace) == Some(&Some(report.clone())) {
+                continue;
+            }
+
+            let api: Api<ImageSbomReport> = Api::namespaced(self.client.clone(), &namespace);
+            let resource = serde_json::json!({
+                "apiVersion": format!("{}/{}", crd::GROUP, crd::VERSION),
+                "kind": crd::KIND,
+                "metadata": {
+                    "name": self.name,
+                    "labels": { MANAGED_BY: FIELD_MANAGER },
+                },
+                "report": report,
+            });
+            let params = PatchParams::apply(FIELD_MANAGER).force();
+            match api
+                .patch(&self.name, &params, &Patch::Apply(&resource))
+                .await
+            {
+                Ok(_) => {
+                    debug!(cluster = ?self.cluster, %namespace, "Published report");
+                    self.published.insert(namespace, Some(report));
+                }
+                Err(err) => {
+                    warn!(cluster = ?self.cluster, %namespace, "Failed to publish report: {err}");
+                    ok = false;
+                }
+            }
+        }
+
+        if ok {
+            info!(
+                cluster = ?self.cluster,
+                "Published reports for {} namespaces",
+                self.published.len()
+            );
+        }
+
+        ok
+    }
+}
+
+/// create the reports of all namespaces of a cluster
+fn reports(
+    state: &im::HashMap<ImageRef, Image>,
+    cluster: &Option<String>,
+) -> BTreeMap<String, Report> {
+    let mut images = BTreeMap::<String, BTreeMap<String, (usize, &Image)>>::new();
+    for (image, state) in state {
+        for pod in state.pods.iter().filter(|pod| &pod.cluster == cluster) {
+            images
+                .entry(pod.namespace.clone())
+                .or_default()
+                .entry(image.to_string())
+                .or_insert((0, state))
+                .0 += 1;
+        }
+    }
+
+    images
+        .into_iter()
+        .map(|(namespace, images)| {
+            let mut summary = Summary::default();
+            let images = images
+                .into_iter()
+                .map(|(image, (pods, state))| {
+                    let report = image_report(image, pods, state);
+                    summary.add(&report);
+                    report
+                })
+                .collect();
+            (namespace, Report { summary, images })
+        })
+        .collect()
+}
+
+fn image_report(image: String, pods: usize, state: &Image) -> ImageReport {
+    let (sbom, packages, error) = match &state.sbom {
+        SbomState::Scheduled => (SbomStatus::Scheduled, None, None),
+        SbomState::Err(err) => (SbomStatus::Failed, None, Some(err.message.clone())),
+        SbomState::Missing => (SbomStatus::Missing, None, None),
+        SbomState::Found(summary) => (SbomStatus::Found, Some(summary.packages), None),
+    };
+
+    ImageReport {
+        image,
+        pods,
+        sbom,
+        packages,
+        error,
+        vulnerabilities: state.vulnerabilities.clone(),
+    }
+}
+
+impl Summary {
+    fn add(&mut self, image: &ImageReport) {
+        self.images += 1;
+        match image.sbom {
+            SbomStatus::Scheduled => self.scheduled += 1,
+            SbomStatus::Failed => self.failed += 1,
+            SbomStatus::Missing => self.missing += 1,
+            SbomStatus::Found => self.found += 1,
+        }
+        if let Some(vulnerabilities) = &image.vulnerabilities {
+            add(&mut self.vulnerabilities, vulnerabilities);
+        }
+    }
+}
+
+fn add(total: &mut Vulnerabilities, vulnerabilities: &Vulnerabilities) {
+    total.critical += vulnerabilities.critical;
+    total.high += vulnerabilities.high;
+    total.medium += vulnerabilities.medium;
+    total.low += vulnerabilities.low;
+    total.unknown += vulnerabilities.unknown;
+}
@@ -0,0 +1,401 @@
+use super::quality::Measurements;
+use super::{subject_digest, Packages};
+use bommer_api::data::{SbomFormat, SbomSummary};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+
+/// The subset of a CycloneDX (JSON) document we are interested in
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    spec_version: String,
+    #[serde(default)]
+    metadata: Option<Metadata>,
+    #[serde(default)]
+    components: Vec<Component>,
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    #[serde(default)]
+    timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    tools: Option<Tools>,
+    #[serde(default)]
+    component: Option<Component>,
+    #[serde(default)]
+    authors: Vec<serde::de::IgnoredAny>,
+    #[serde(default)]
+    supplier: Option<serde::de::IgnoredAny>,
+    #[serde(default)]
+    manufacture: Option<serde::de::IgnoredAny>,
+}
+
+/// Tools are a plain list before 1.5, and split into components and services afterwards
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum Tools {
+    Legacy(Vec<Tool>),
+    Split {
+        #[serde(default)]
+        components: Vec<Tool>,
+        #[serde(default)]
+        services: Vec<Tool>,
+    },
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Tool {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Component {
+    #[serde(default, rename = "bom-ref")]
+    bom_ref: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    purl: Option<String>,
+    #[serde(default)]
+    cpe: Option<String>,
+    #[serde(default)]
+    supplier: Option<serde::de::IgnoredAny>,
+    #[serde(default)]
+    publisher: Option<String>,
+    #[serde(default)]
+    licenses: Vec<LicenseChoice>,
+    #[serde(default)]
+    hashes: Vec<Hash>,
+    #[serde(default)]
+    components: Vec<Component>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Hash {
+    alg: String,
+    content: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum LicenseChoice {
+    License { license: License },
+    Expression { expression: String },
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Dependency {
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(default)]
+    depends_on: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct License {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// parse a CycloneDX JSON document into its summary
+pub fn parse(data: &[u8]) -> Result<SbomSummary, serde_json::Error> {
+    let doc: Document = serde_json::from_slice(data)?;
+
+    let quality = measure(&doc).quality();
+
+    let (created, tools, component) = match doc.metadata {
+        Some(metadata) => (metadata.timestamp, metadata.tools, metadata.component),
+        None => (None, None, None),
+    };
+
+    let tools = match tools {
+        Some(Tools::Legacy(tools)) => tools,
+        Some(Tools::Split {
+            components,
+            services,
+        }) => components.into_iter().chain(services).collect(),
+        None => vec![],
+    };
+
+    let licenses = component.iter().flat_map(licenses).collect::<BTreeSet<_>>();
+    let subjects = component.iter().flat_map(subjects).collect::<BTreeSet<_>>();
+    let license_ids = component
+        .iter()
+        .chain(all(&doc.components))
+        .flat_map(license_ids)
+        .collect::<BTreeSet<_>>();
+
+    Ok(SbomSummary {
+        format: SbomFormat::CycloneDx,
+        version: Some(doc.spec_version),
+        name: component.and_then(|c| c.name),
+        packages: count(&doc.components),
+        licenses: licenses.into_iter().collect(),
+        tools: tools
+            .into_iter()
+            .filter_map(|tool| match (tool.name, tool.version) {
+                (Some(name), Some(version)) => Some(format!("{name}-{version}")),
+                (name, _) => name,
+            })
+            .collect(),
+        created,
+        purl: None,
+        source: None,
+        digest: None,
+        subjects: subjects.into_iter().collect(),
+        verified: false,
+        license_ids: license_ids.into_iter().collect(),
+        quality: Some(Box::new(quality)),
+    })
+}
+
+/// measure what the document provides, for scoring its quality
+fn measure(doc: &Document) -> Measurements {
+    let components = all(&doc.components);
+    let count = |f: fn(&Component) -> bool| components.iter().filter(|c| f(c)).count();
+
+    let metadata = doc.metadata.as_ref();
+    Measurements {
+        packages: components.len(),
+        suppliers: count(|c| c.supplier.is_some() || c.publisher.is_some()),
+        versions: count(|c| c.version.is_some()),
+        identifiers: count(|c| c.purl.is_some() || c.cpe.is_some()),
+        dependencies: doc.dependencies.iter().any(|d| !d.depends_on.is_empty())
+            || components.iter().any(|c| !c.components.is_empty()),
+        author: metadata.is_some_and(|m| {
+            !m.authors.is_empty()
+                || m.supplier.is_some()
+                || m.manufacture.is_some()
+                || m.tools.is_some()
+        }),
+        timestamp: metadata.is_some_and(|m| m.timestamp.is_some()),
+    }
+}
+
+/// extract the components of a CycloneDX JSON document, including nested ones
+pub fn packages(data: &[u8]) -> Result<Packages, serde_json::Error> {
+    let doc: Document = serde_json::from_slice(data)?;
+
+    let mut result = Packages::default();
+    let mut next = 0;
+
+    let main = doc
+        .metadata
+        .and_then(|metadata| metadata.component)
+        .map(|component| collect(&component, None, &mut next, &mut result));
+    let components = doc
+        .components
+        .iter()
+        .map(|component| collect(component, None, &mut next, &mut result))
+        .collect::<Vec<_>>();
+
+    match main {
+        Some(main) => {
+            // without dependency information, the main component contains all others
+            if doc.dependencies.is_empty() {
+                result.dependencies.extend(
+                    components
+                        .into_iter()
+                        .map(|component| (main.clone(), component)),
+                );
+            }
+            result.roots.push(main);
+        }
+        None => result.roots = components,
+    }
+
+    result
+        .dependencies
+        .extend(doc.dependencies.into_iter().flat_map(|d| {
+            let reference = d.reference;
+            d.depends_on
+                .into_iter()
+                .map(move |depends_on| (reference.clone(), depends_on))
+        }));
+
+    Ok(result)
+}
+
+/// collect a component and its nested components, returning its ID
+///
+/// Components without a `bom-ref` get a generated one.
+fn collect(
+    component: &Component,
+    parent: Option<&str>,
+    next: &mut usize,
+    result: &mut Packages,
+) -> String {
+    let id = match &component.bom_ref {
+        Some(bom_ref) => bom_ref.clone(),
+        None => {
+            *next += 1;
+            format!("bommer:{next}")
+        }
+    };
+
+    result.packages.push(super::Package {
+        id: id.clone(),
+        name: component.name.clone().unwrap_or_default(),
+        version: component.version.clone(),
+        purl: component.purl.clone(),
+        licenses: licenses(component).collect(),
+    });
+    if let Some(parent) = parent {
+        result.dependencies.push((parent.to_string(), id.clone()));
+    }
+
+    for c in &component.components {
+        collect(c, Some(&id), next, result);
+    }
+
+    id
+}
+
+fn licenses(component: &Component) -> impl Iterator<Item = String> + '_ {
+    component.licenses.iter().filter_map(|l| match l {
+        LicenseChoice::License { license } => license.id.clone().or(license.name.clone()),
+        LicenseChoice::Expression { expression } => Some(expression.clone()),
+    })
+}
+
+/// the license identifiers of a component, taking expressions apart
+fn license_ids(component: &Component) -> impl Iterator<Item = String> + '_ {
+    component.licenses.iter().flat_map(|l| match l {
+        LicenseChoice::License { license } => license
+            .id
+            .clone()
+            .or(license.name.clone())
+            .into_iter()
+            .collect(),
+        LicenseChoice::Expression { expression } => super::expression_ids(expression)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// the digests of a component, from its hashes, version, and package URL
+fn subjects(component: &Component) -> impl Iterator<Item = String> + '_ {
+    component
+        .hashes
+        .iter()
+        .filter(|h| h.alg == "SHA-256")
+        .map(|h| format!("sha256:{}", h.content))
+        .chain(component.version.clone())
+        .chain(component.purl.clone())
+        .filter_map(|value| subject_digest(&value))
+}
+
+/// count components, including nested ones
+fn count(components: &[Component]) -> usize {
+    components.iter().map(|c| 1 + count(&c.components)).sum()
+}
+
+/// all components, including nested ones
+fn all(components: &[Component]) -> Vec<&Component> {
+    components
+        .iter()
+        .flat_map(|c| std::iter::once(c).chain(all(&c.components)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn summary() {
+        let doc = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "metadata": {
+                "timestamp": "2023-05-01T12:00:00Z",
+                "tools": [{"name": "syft", "version": "0.80.0"}, {"name": "custom"}],
+                "component": {
+                    "name": "quay.io/example/app",
+                    "version": DIGEST,
+                    "licenses": [{"license": {"id": "Apache-2.0"}}],
+                },
+            },
+            "components": [
+                {
+                    "name": "lib",
+                    "licenses": [{"expression": "MIT OR GPL-2.0+"}],
+                    "hashes": [{"alg": "SHA-256", "content": "ff".repeat(32)}],
+                    "components": [
+                        {"name": "nested", "licenses": [{"license": {"name": "Custom"}}]},
+                    ],
+                },
+            ],
+        });
+
+        let summary = parse(&serde_json::to_vec(&doc).unwrap()).unwrap();
+        assert_eq!(summary.format, SbomFormat::CycloneDx);
+        assert_eq!(summary.version.as_deref(), Some("1.4"));
+        assert_eq!(summary.name.as_deref(), Some("quay.io/example/app"));
+        // nested components count too
+        assert_eq!(summary.packages, 2);
+        // only the main component
+        assert_eq!(summary.licenses, vec!["Apache-2.0"]);
+        assert_eq!(
+            summary.license_ids,
+            vec!["Apache-2.0", "Custom", "GPL-2.0", "MIT"]
+        );
+        assert_eq!(summary.tools, vec!["syft-0.80.0", "custom"]);
+        assert_eq!(
+            summary.created,
+            Some("2023-05-01T12:00:00Z".parse().unwrap())
+        );
+        // the hash of a component which isn't the main one doesn't count
+        assert_eq!(summary.subjects, vec![DIGEST]);
+        assert!(summary.quality.is_some());
+    }
+
+    #[test]
+    fn split_tools() {
+        let doc = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "metadata": {
+                "tools": {
+                    "components": [{"name": "syft", "version": "0.90.0"}],
+                    "services": [{"name": "scanner"}],
+                },
+                "component": {
+                    "name": "app",
+                    "purl": format!("pkg:oci/app@{DIGEST}"),
+                },
+            },
+        });
+
+        let summary = parse(&serde_json::to_vec(&doc).unwrap()).unwrap();
+        assert_eq!(summary.tools, vec!["syft-0.90.0", "scanner"]);
+        assert_eq!(summary.subjects, vec![DIGEST]);
+        assert_eq!(summary.packages, 0);
+        assert_eq!(summary.created, None);
+    }
+
+    #[test]
+    fn without_metadata() {
+        let summary = parse(br#"{"specVersion": "1.4", "components": [{"name": "lib"}]}"#).unwrap();
+        assert_eq!(summary.name, None);
+        assert_eq!(summary.packages, 1);
+        assert!(summary.licenses.is_empty());
+        assert!(summary.tools.is_empty());
+        assert!(summary.subjects.is_empty());
+    }
+}
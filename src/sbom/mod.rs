@@ -0,0 +1,233 @@
+mod cyclonedx;
+mod quality;
+mod spdx;
+
+use bommer_api::data::{ImageRef, SbomFormat, SbomSummary};
+use packageurl::PackageUrl;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("Invalid document: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unknown SBOM format")]
+    UnknownFormat,
+}
+
+/// Just enough of a document to detect its format
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe {
+    #[serde(default)]
+    spdx_version: Option<serde::de::IgnoredAny>,
+    #[serde(default)]
+    bom_format: Option<String>,
+}
+
+/// A package (component) of an SBOM, independent of its format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Package {
+    /// The ID of the package, unique inside its document
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub purl: Option<String>,
+    /// License expressions of the package
+    pub licenses: Vec<String>,
+}
+
+/// The packages of an SBOM, along with their dependencies
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Packages {
+    pub packages: Vec<Package>,
+    /// IDs of the packages the document describes
+    pub roots: Vec<String>,
+    /// Dependencies between packages, as pairs of IDs
+    pub dependencies: Vec<(String, String)>,
+}
+
+/// detect the format of an SBOM (JSON) document, and parse it into its summary
+///
+/// The summary carries the digest of the document, but isn't verified against an image yet.
+pub fn parse(data: &[u8]) -> Result<SbomSummary, ParseError> {
+    let summary = match detect(data)? {
+        SbomFormat::Spdx => spdx::parse(data)?,
+        _ => cyclonedx::parse(data)?,
+    };
+
+    Ok(SbomSummary {
+        digest: Some(format!("sha256:{}", hex::encode(Sha256::digest(data)))),
+        ..summary
+    })
+}
+
+/// check if the SBOM describes the image, by comparing the digests of the described components
+///
+/// Without a digest of the image, or of the described components, there's nothing to verify.
+pub fn verify(summary: &SbomSummary, image: &ImageRef) -> bool {
+    match &image.digest {
+        Some(digest) => summary
+            .subjects
+            .iter()
+            .any(|subject| subject.eq_ignore_ascii_case(digest)),
+        None => false,
+    }
+}
+
+/// the digest of a component, as stated by one of its properties
+///
+/// Image components carry their digest in different ways: as hash, as version, or as version of
+/// their package URL. Only SHA-256 digests are considered, as that's what images use.
+fn subject_digest(value: &str) -> Option<String> {
+    let value = match value.starts_with("pkg:") {
+        true => PackageUrl::from_str(value).ok()?.version()?.to_string(),
+        false => value.to_string(),
+    };
+
+    let hex = value.strip_prefix("sha256:")?;
+    match hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Some(format!("sha256:{}", hex.to_ascii_lowercase())),
+        false => None,
+    }
+}
+
+/// the license identifiers of an SPDX license expression, e.g. `MIT OR Apache-2.0`
+///
+/// Exceptions (`WITH …`) are dropped, as well as the "or later" suffix (`+`).
+fn expression_ids(expression: &str) -> impl Iterator<Item = &str> {
+    let mut exception = false;
+    expression
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+        .filter(move |token| {
+            let skip = exception;
+            exception = token.eq_ignore_ascii_case("WITH");
+            !skip
+                && !exception
+                && !token.eq_ignore_ascii_case("AND")
+                && !token.eq_ignore_ascii_case("OR")
+        })
+        .map(|token| token.trim_end_matches('+'))
+}
+
+/// detect the format of an SBOM (JSON) document, and extract its packages
+pub fn packages(data: &[u8]) -> Result<Packages, ParseError> {
+    match detect(data)? {
+        SbomFormat::Spdx => Ok(spdx::packages(data)?),
+        _ => Ok(cyclonedx::packages(data)?),
+    }
+}
+
+/// detect the format of an SBOM document
+pub fn detect(data: &[u8]) -> Result<SbomFormat, ParseError> {
+    let probe: Probe = serde_json::from_slice(data)?;
+
+    match probe {
+        Probe {
+            spdx_version: Some(_),
+            ..
+        } => Ok(SbomFormat::Spdx),
+        Probe {
+            bom_format: Some(format),
+            ..
+        } if format == "CycloneDX" => Ok(SbomFormat::CycloneDx),
+        _ => Err(ParseError::UnknownFormat),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(
+            detect(br#"{"spdxVersion": "SPDX-2.3"}"#).unwrap(),
+            SbomFormat::Spdx
+        );
+        assert_eq!(
+            detect(br#"{"bomFormat": "CycloneDX", "specVersion": "1.4"}"#).unwrap(),
+            SbomFormat::CycloneDx
+        );
+        assert!(matches!(
+            detect(br#"{"bomFormat": "Other"}"#),
+            Err(ParseError::UnknownFormat)
+        ));
+        assert!(matches!(detect(br#"{}"#), Err(ParseError::UnknownFormat)));
+        assert!(matches!(detect(b"not json"), Err(ParseError::Json(_))));
+    }
+
+    #[test]
+    fn parse_digest() {
+        let data = br#"{"bomFormat": "CycloneDX", "specVersion": "1.4"}"#;
+        let summary = parse(data).unwrap();
+        assert_eq!(summary.format, SbomFormat::CycloneDx);
+        assert_eq!(
+            summary.digest,
+            Some(format!("sha256:{}", hex::encode(Sha256::digest(data))))
+        );
+        assert!(!summary.verified);
+    }
+
+    #[test]
+    fn subject_digests() {
+        assert_eq!(subject_digest(DIGEST).as_deref(), Some(DIGEST));
+        assert_eq!(
+            subject_digest(&DIGEST.replace("abcdef", "ABCDEF")).as_deref(),
+            Some(DIGEST)
+        );
+        assert_eq!(
+            subject_digest(&format!(
+                "pkg:oci/app@{DIGEST}?repository_url=quay.io/example"
+            ))
+            .as_deref(),
+            Some(DIGEST)
+        );
+
+        // not a digest, or not a SHA-256 one
+        assert_eq!(subject_digest("1.0.0"), None);
+        assert_eq!(subject_digest("pkg:cargo/serde@1.0.0"), None);
+        assert_eq!(subject_digest("pkg:oci/app"), None);
+        assert_eq!(subject_digest("sha256:0123"), None);
+        assert_eq!(subject_digest(&format!("{DIGEST}xy")), None);
+        assert_eq!(subject_digest(&format!("sha512:{}", "ff".repeat(64))), None);
+    }
+
+    #[test]
+    fn verify_subjects() {
+        let summary = SbomSummary {
+            subjects: vec![DIGEST.to_string()],
+            ..parse(br#"{"bomFormat": "CycloneDX", "specVersion": "1.4"}"#).unwrap()
+        };
+
+        let image = |s: &str| s.parse::<ImageRef>().unwrap();
+        assert!(verify(
+            &summary,
+            &image(&format!("quay.io/example/app@{DIGEST}"))
+        ));
+        assert!(verify(
+            &summary,
+            &image(&format!(
+                "quay.io/example/app@{}",
+                DIGEST.to_ascii_uppercase()
+            ))
+        ));
+        assert!(!verify(
+            &summary,
+            &image(&format!("quay.io/example/app@sha256:{}", "ff".repeat(32)))
+        ));
+        // nothing to verify against
+        assert!(!verify(&summary, &image("quay.io/example/app:1.0")));
+        let summary = SbomSummary {
+            subjects: vec![],
+            ..summary
+        };
+        assert!(!verify(
+            &summary,
+            &image(&format!("quay.io/example/app@{DIGEST}"))
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+use bommer_api::data::{QualityCheck, QualityCriterion, QualityGrade, SbomQuality};
+
+/// What a document provides, for scoring its quality
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Measurements {
+    /// Number of packages
+    pub packages: usize,
+    /// Number of packages naming their supplier
+    pub suppliers: usize,
+    /// Number of packages having a version
+    pub versions: usize,
+    /// Number of packages having a package URL or CPE
+    pub identifiers: usize,
+    pub dependencies: bool,
+    pub author: bool,
+    pub timestamp: bool,
+}
+
+impl Measurements {
+    /// score the quality, all criteria being weighted equally
+    pub fn quality(&self) -> SbomQuality {
+        let share = |count: usize| match self.packages {
+            0 => 0,
+            packages => (count.min(packages) * 100 / packages) as u8,
+        };
+        let present = |present: bool| match present {
+            true => 100,
+            false => 0,
+        };
+
+        let checks = vec![
+            (QualityCriterion::Supplier, share(self.suppliers)),
+            (QualityCriterion::Version, share(self.versions)),
+            (QualityCriterion::Identifier, share(self.identifiers)),
+            (QualityCriterion::Dependencies, present(self.dependencies)),
+            (QualityCriterion::Author, present(self.author)),
+            (QualityCriterion::Timestamp, present(self.timestamp)),
+        ];
+
+        let score = (checks
+            .iter()
+            .map(|(_, score)| *score as usize)
+            .sum::<usize>()
+            / checks.len()) as u8;
+
+        SbomQuality {
+            score,
+            grade: QualityGrade::from_score(score),
+            checks: checks
+                .into_iter()
+                .map(|(criterion, score)| QualityCheck { criterion, score })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use super::quality::Measurements;
+use super::{subject_digest, Packages};
+use bommer_api::data::{SbomFormat, SbomSummary};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+
+/// The subset of an SPDX (JSON) document we are interested in
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    spdx_version: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    creation_info: Option<CreationInfo>,
+    #[serde(default)]
+    document_describes: Vec<String>,
+    #[serde(default)]
+    packages: Vec<Package>,
+    #[serde(default)]
+    relationships: Vec<Relationship>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreationInfo {
+    #[serde(default)]
+    created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    creators: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Package {
+    #[serde(rename = "SPDXID")]
+    spdx_id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    version_info: Option<String>,
+    #[serde(default)]
+    supplier: Option<String>,
+    #[serde(default)]
+    external_refs: Vec<ExternalRef>,
+    #[serde(default)]
+    license_declared: Option<String>,
+    #[serde(default)]
+    license_concluded: Option<String>,
+    #[serde(default)]
+    checksums: Vec<Checksum>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Checksum {
+    algorithm: String,
+    checksum_value: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExternalRef {
+    reference_type: String,
+    reference_locator: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Relationship {
+    spdx_element_id: String,
+    relationship_type: String,
+    related_spdx_element: String,
+}
+
+/// parse an SPDX JSON document into its summary
+pub fn parse(data: &[u8]) -> Result<SbomSummary, serde_json::Error> {
+    let doc: Document = serde_json::from_slice(data)?;
+
+    let described = described(&doc);
+
+    let licenses = doc
+        .packages
+        .iter()
+        .filter(|p| described.contains(p.spdx_id.as_str()))
+        .filter_map(|p| asserted(&p.license_declared).or_else(|| asserted(&p.license_concluded)))
+        .map(ToString::to_string)
+        .collect::<BTreeSet<_>>();
+
+    let subjects = doc
+        .packages
+        .iter()
+        .filter(|p| described.contains(p.spdx_id.as_str()))
+        .flat_map(subjects)
+        .collect::<BTreeSet<_>>();
+
+    let license_ids = doc
+        .packages
+        .iter()
+        .filter_map(|p| asserted(&p.license_declared).or_else(|| asserted(&p.license_concluded)))
+        .flat_map(super::expression_ids)
+        .map(ToString::to_string)
+        .collect::<BTreeSet<_>>();
+
+    let quality = measure(&doc).quality();
+
+    let (created, tools) = match doc.creation_info {
+        Some(info) => (
+            info.created,
+            info.creators
+                .iter()
+                .filter_map(|c| c.strip_prefix("Tool:"))
+                .map(|c| c.trim().to_string())
+                .collect(),
+        ),
+        None => (None, vec![]),
+    };
+
+    Ok(SbomSummary {
+        format: SbomFormat::Spdx,
+        version: Some(doc.spdx_version),
+        name: doc.name,
+        packages: doc.packages.len(),
+        licenses: licenses.into_iter().collect(),
+        tools,
+        created,
+        purl: None,
+        source: None,
+        digest: None,
+        subjects: subjects.into_iter().collect(),
+        verified: false,
+        license_ids: license_ids.into_iter().collect(),
+        quality: Some(Box::new(quality)),
+    })
+}
+
+/// measure what the document provides, for scoring its quality
+fn measure(doc: &Document) -> Measurements {
+    let count = |f: fn(&Package) -> bool| doc.packages.iter().filter(|p| f(p)).count();
+
+    Measurements {
+        packages: doc.packages.len(),
+        suppliers: count(|p| asserted(&p.supplier).is_some()),
+        versions: count(|p| p.version_info.is_some()),
+        identifiers: count(|p| {
+            p.external_refs.iter().any(|r| {
+                matches!(
+                    r.reference_type.as_str(),
+                    "purl" | "cpe22Type" | "cpe23Type"
+                )
+            })
+        }),
+        dependencies: doc
+            .relationships
+            .iter()
+            .any(|r| r.spdx_element_id != "SPDXRef-DOCUMENT"),
+        author: doc
+            .creation_info
+            .as_ref()
+            .is_some_and(|info| !info.creators.is_empty()),
+        timestamp: doc
+            .creation_info
+            .as_ref()
+            .is_some_and(|info| info.created.is_some()),
+    }
+}
+
+/// extract the packages of an SPDX JSON document
+pub fn packages(data: &[u8]) -> Result<Packages, serde_json::Error> {
+    let doc: Document = serde_json::from_slice(data)?;
+
+    let roots = described(&doc)
+        .into_iter()
+        .map(ToString::to_string)
+        .collect();
+
+    let dependencies = doc
+        .relationships
+        .iter()
+        .filter(|r| r.spdx_element_id != "SPDXRef-DOCUMENT")
+        .filter_map(|r| match r.relationship_type.as_str() {
+            "DEPENDS_ON" | "CONTAINS" => {
+                Some((r.spdx_element_id.clone(), r.related_spdx_element.clone()))
+            }
+            "DEPENDENCY_OF" | "CONTAINED_BY" => {
+                Some((r.related_spdx_element.clone(), r.spdx_element_id.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let packages = doc
+        .packages
+        .into_iter()
+        .map(|p| super::Package {
+            licenses: asserted(&p.license_declared)
+                .or_else(|| asserted(&p.license_concluded))
+                .map(ToString::to_string)
+                .into_iter()
+                .collect(),
+            purl: p
+                .external_refs
+                .into_iter()
+                .find(|r| r.reference_type == "purl")
+                .map(|r| r.reference_locator),
+            id: p.spdx_id,
+            name: p.name.unwrap_or_default(),
+            version: p.version_info,
+        })
+        .collect();
+
+    Ok(Packages {
+        packages,
+        roots,
+        dependencies,
+    })
+}
+
+/// the packages described by the document, either directly or through a relationship
+fn described(doc: &Document) -> BTreeSet<&str> {
+    doc.document_describes
+        .iter()
+        .map(String::as_str)
+        .chain(
+            doc.relationships
+                .iter()
+                .filter(|r| {
+                    r.spdx_element_id == "SPDXRef-DOCUMENT" && r.relationship_type == "DESCRIBES"
+                })
+                .map(|r| r.related_spdx_element.as_str()),
+        )
+        .collect()
+}
+
+/// the digests of a package, from its checksums, version, and package URLs
+fn subjects(package: &Package) -> impl Iterator<Item = String> + '_ {
+    let checksums = package
+        .checksums
+        .iter()
+        .filter(|c| c.algorithm.eq_ignore_ascii_case("SHA256"))
+        .map(|c| format!("sha256:{}", c.checksum_value));
+    let purls = package
+        .external_refs
+        .iter()
+        .filter(|r| r.reference_type == "purl")
+        .map(|r| r.reference_locator.clone());
+
+    checksums
+        .chain(package.version_info.clone())
+        .chain(purls)
+        .filter_map(|value| subject_digest(&value))
+}
+
+/// a value (e.g. a license expression), unless it's one of the SPDX placeholders
+fn asserted(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .filter(|l| !matches!(*l, "NOASSERTION" | "NONE" | ""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn summary() {
+        let doc = json!({
+            "spdxVersion": "SPDX-2.3",
+            "name": "quay.io/example/app",
+            "creationInfo": {
+                "created": "2023-05-01T12:00:00Z",
+                "creators": ["Organization: Example", "Tool: syft-0.80.0"],
+            },
+            "documentDescribes": ["SPDXRef-app"],
+            "packages": [
+                {
+                    "SPDXID": "SPDXRef-app",
+                    "name": "app",
+                    "versionInfo": DIGEST,
+                    "licenseDeclared": "NOASSERTION",
+                    "licenseConcluded": "Apache-2.0 WITH LLVM-exception",
+                },
+                {
+                    "SPDXID": "SPDXRef-lib",
+                    "name": "lib",
+                    "versionInfo": "1.0.0",
+                    "licenseDeclared": "(MIT OR GPL-2.0+)",
+                    "checksums": [{"algorithm": "SHA256", "checksumValue": "ff".repeat(32)}],
+                },
+            ],
+            "relationships": [{
+                "spdxElementId": "SPDXRef-app",
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": "SPDXRef-lib",
+            }],
+        });
+
+        let summary = parse(&serde_json::to_vec(&doc).unwrap()).unwrap();
+        assert_eq!(summary.format, SbomFormat::Spdx);
+        assert_eq!(summary.version.as_deref(), Some("SPDX-2.3"));
+        assert_eq!(summary.name.as_deref(), Some("quay.io/example/app"));
+        assert_eq!(summary.packages, 2);
+        // only the described package, falling back to the concluded license
+        assert_eq!(summary.licenses, vec!["Apache-2.0 WITH LLVM-exception"]);
+        assert_eq!(summary.license_ids, vec!["Apache-2.0", "GPL-2.0", "MIT"]);
+        assert_eq!(summary.tools, vec!["syft-0.80.0"]);
+        assert_eq!(
+            summary.created,
+            Some("2023-05-01T12:00:00Z".parse().unwrap())
+        );
+        // the checksum of a package which isn't described doesn't count
+        assert_eq!(summary.subjects, vec![DIGEST]);
+        assert!(summary.quality.is_some());
+    }
+
+    #[test]
+    fn described_by_relationship() {
+        let doc = json!({
+            "spdxVersion": "SPDX-2.2",
+            "packages": [{
+                "SPDXID": "SPDXRef-app",
+                "licenseDeclared": "MIT",
+                "externalRefs": [{
+                    "referenceType": "purl",
+                    "referenceLocator": format!("pkg:oci/app@{DIGEST}"),
+                }],
+            }],
+            "relationships": [{
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": "SPDXRef-app",
+            }],
+        });
+
+        let summary = parse(&serde_json::to_vec(&doc).unwrap()).unwrap();
+        assert_eq!(summary.name, None);
+        assert_eq!(summary.licenses, vec!["MIT"]);
+        assert_eq!(summary.subjects, vec![DIGEST]);
+        assert!(summary.tools.is_empty());
+        assert_eq!(summary.created, None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(br#"{"packages": []}"#).is_err());
+        assert!(parse(b"not json").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Circuit breaker")]
+pub struct BreakerConfig {
+    /// Number of consecutive failed lookups after which scanning is paused, zero disables the circuit breaker
+    #[arg(long, env = "BREAKER_THRESHOLD", default_value_t = 5)]
+    pub breaker_threshold: u32,
+
+    /// Interval of probing the SBOM source while scanning is paused
+    #[arg(long, env = "BREAKER_PROBE_INTERVAL", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub breaker_probe_interval: Duration,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// Lookups pass, counting the consecutive failures
+    Closed { failures: u32 },
+    /// Lookups are paused until the next probe is due
+    Open { probe: Instant },
+    /// A single lookup is let through, probing the source
+    HalfOpen,
+}
+
+/// Pauses scanning while the SBOM source is unavailable
+///
+/// After a number of consecutive lookups failed with a temporary error, the breaker opens and
+/// lookups get paused, instead of recording an error for every image. Once the probe interval
+/// elapsed, a single lookup is let through. If that succeeds, scanning resumes. Otherwise, the
+/// breaker stays open until the next probe.
+///
+/// With multiple scanner workers, only one of them gets to probe the source. The others wait for
+/// the outcome of the probe.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Arc<Mutex<State>>,
+    /// notifies waiting lookups about the outcome of a probe
+    probed: Arc<Notify>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        metrics::gauge!("bommer_sbom_source_breaker_open", 0.0);
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+            probed: Default::default(),
+        }
+    }
+
+    /// check if lookups are currently paused, or being probed
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.lock(), State::Closed { .. })
+    }
+
+    /// wait until a lookup may be performed
+    ///
+    /// While the source is being probed, no other lookup may be performed. The lookup acquiring
+    /// the probe must record its outcome.
+    pub async fn acquire(&self) {
+        loop {
+            // receives notifications from now on, even before being polled
+            let probed = self.probed.notified();
+            let probe = {
+                let mut state = self.state.lock();
+                match *state {
+                    State::Closed { .. } => return,
+                    State::Open { probe } if probe <= Instant::now() => {
+                        info!("Probing SBOM source");
+                        *state = State::HalfOpen;
+                        return;
+                    }
+                    State::Open { probe } => Some(probe),
+                    State::HalfOpen => None,
+                }
+            };
+            match probe {
+                Some(probe) => tokio::time::sleep_until(probe).await,
+                None => probed.await,
+            }
+        }
+    }
+
+    /// record the outcome of a lookup, `available` being false if the source failed temporarily
+    pub fn record(&self, available: bool) {
+        if self.config.breaker_threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock();
+        let probe = Instant::now() + self.config.breaker_probe_interval;
+
+        *state = match (*state, available) {
+            (State::Closed { .. }, true) => State::Closed { failures: 0 },
+            (_, true) => {
+                info!("SBOM source is available again, resuming scanning");
+                metrics::gauge!("bommer_sbom_source_breaker_open", 0.0);
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, false) => {
+                let failures = failures + 1;
+                if failures < self.config.breaker_threshold {
+                    State::Closed { failures }
+                } else {
+                    warn!("SBOM source failed {failures} times in a row, pausing scanning");
+                    metrics::gauge!("bommer_sbom_source_breaker_open", 1.0);
+                    metrics::increment_counter!("bommer_sbom_source_breaker_trips_total");
+                    State::Open { probe }
+                }
+            }
+            (State::HalfOpen | State::Open { .. }, false) => {
+                info!(
+                    "SBOM source is still unavailable, probing again in {}",
+                    humantime::format_duration(self.config.breaker_probe_interval)
+                );
+                State::Open { probe }
+            }
+        };
+        drop(state);
+
+        self.probed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::poll;
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    fn breaker(threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            breaker_threshold: threshold,
+            breaker_probe_interval: INTERVAL,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trips_after_consecutive_failures() {
+        let breaker = breaker(3);
+
+        // a success resets the count
+        breaker.record(false);
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        breaker.record(false);
+        assert!(!breaker.is_open());
+        assert!(poll!(Box::pin(breaker.acquire())).is_ready());
+
+        breaker.record(false);
+        assert!(breaker.is_open());
+        assert_eq!(
+            *breaker.state.lock(),
+            State::Open {
+                probe: Instant::now() + INTERVAL
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn probes_once_due() {
+        let breaker = breaker(1);
+        breaker.record(false);
+
+        let start = Instant::now();
+        let probe = breaker.acquire();
+        tokio::pin!(probe);
+        assert!(poll!(&mut probe).is_pending());
+
+        tokio::time::sleep(INTERVAL - Duration::from_secs(1)).await;
+        assert!(poll!(&mut probe).is_pending());
+
+        // the lookup passes as the probe
+        probe.await;
+        assert_eq!(start.elapsed(), INTERVAL);
+        assert_eq!(*breaker.state.lock(), State::HalfOpen);
+        assert!(breaker.is_open());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_probe_reopens() {
+        let breaker = breaker(1);
+        breaker.record(false);
+        breaker.acquire().await;
+
+        // the next probe is due an interval after the failed one
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        breaker.record(false);
+        let failed = Instant::now();
+        assert_eq!(
+            *breaker.state.lock(),
+            State::Open {
+                probe: failed + INTERVAL
+            }
+        );
+
+        breaker.acquire().await;
+        assert_eq!(failed.elapsed(), INTERVAL);
+        assert_eq!(*breaker.state.lock(), State::HalfOpen);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recovers() {
+        let breaker = breaker(2);
+        breaker.record(false);
+        breaker.record(false);
+        breaker.acquire().await;
+
+        breaker.record(true);
+        assert!(!breaker.is_open());
+        assert_eq!(*breaker.state.lock(), State::Closed { failures: 0 });
+
+        // lookups pass right away, and it takes the full threshold to trip again
+        let now = Instant::now();
+        breaker.acquire().await;
+        assert_eq!(now.elapsed(), Duration::ZERO);
+        breaker.record(false);
+        assert!(!breaker.is_open());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disabled() {
+        let breaker = breaker(0);
+        for _ in 0..10 {
+            breaker.record(false);
+        }
+        assert!(!breaker.is_open());
+        assert!(poll!(Box::pin(breaker.acquire())).is_ready());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_probe() {
+        let breaker = breaker(1);
+        breaker.acquire().await;
+        breaker.record(false);
+        assert!(breaker.is_open());
+
+        // the first lookup gets to probe, once the probe is due
+        breaker.acquire().await;
+
+        // all others wait for its outcome, no matter how long it takes
+        let second = breaker.acquire();
+        let third = breaker.acquire();
+        tokio::pin!(second, third);
+        assert!(poll!(&mut second).is_pending());
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(poll!(&mut second).is_pending());
+        assert!(poll!(&mut third).is_pending());
+
+        breaker.record(true);
+        assert!(poll!(&mut second).is_ready());
+        assert!(poll!(&mut third).is_ready());
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,107 @@
+use crate::source::Sbom;
+use bommer_api::data::ImageRef;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Cache")]
+pub struct CacheConfig {
+    /// Time lookup results are being cached
+    #[arg(long, env = "CACHE_TTL", default_value = "1h", value_parser = humantime::parse_duration)]
+    pub cache_ttl: Duration,
+
+    /// Maximum number of cached lookup results, zero disables the cache
+    #[arg(long, env = "CACHE_MAX_ENTRIES", default_value_t = 10_000)]
+    pub cache_max_entries: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: Option<Sbom>,
+    inserted: Instant,
+}
+
+/// Caches lookup results by image digest
+///
+/// When images disappear and re-appear (e.g. during a rolling update), we don't need to look them
+/// up again, as the SBOM of a digest doesn't change.
+#[derive(Clone, Debug)]
+pub struct SbomCache {
+    config: CacheConfig,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+/// the digest of an image, which is what we cache by
+fn digest(image: &ImageRef) -> Option<&str> {
+    image.digest.as_deref()
+}
+
+impl SbomCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// get a cached result, `None` if there is no (valid) cached result
+    pub fn get(&self, image: &ImageRef) -> Option<Option<Sbom>> {
+        let digest = digest(image)?;
+        let mut entries = self.entries.lock();
+
+        match entries.get(digest) {
+            Some(entry) if entry.inserted.elapsed() < self.config.cache_ttl => {
+                Some(entry.result.clone())
+            }
+            Some(_) => {
+                entries.remove(digest);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// drop all cached results, e.g. after migrating the SBOM source
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    pub fn insert(&self, image: &ImageRef, result: Option<Sbom>) {
+        if self.config.cache_max_entries == 0 {
+            return;
+        }
+
+        let digest = match digest(image) {
+            Some(digest) => digest,
+            None => return,
+        };
+
+        let mut entries = self.entries.lock();
+
+        if entries.len() >= self.config.cache_max_entries && !entries.contains_key(digest) {
+            // drop expired entries first, and the oldest one if that wasn't enough
+            let ttl = self.config.cache_ttl;
+            entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+
+            if entries.len() >= self.config.cache_max_entries {
+                if let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted)
+                    .map(|(k, _)| k.clone())
+                {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(
+            digest.to_string(),
+            Entry {
+                result,
+                inserted: Instant::now(),
+            },
+        );
+    }
+}
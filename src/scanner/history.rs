@@ -0,0 +1,86 @@
+use bommer_api::data::{ImageRef, LookupError, ScanAttempt, ScanHistory, ScanOutcome};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Scan history")]
+pub struct ScanLogConfig {
+    /// Number of SBOM lookups kept per image, zero disables the history
+    #[arg(long, env = "SCAN_HISTORY", default_value_t = 10)]
+    pub scan_history: usize,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    last_found: Option<DateTime<Utc>>,
+    attempts: VecDeque<ScanAttempt>,
+}
+
+/// Records the SBOM lookups of images
+///
+/// Only the most recent attempts are kept, and only for images which are part of the workload.
+#[derive(Clone, Debug)]
+pub struct ScanLog {
+    config: ScanLogConfig,
+    entries: Arc<Mutex<HashMap<ImageRef, Entry>>>,
+}
+
+impl ScanLog {
+    pub fn new(config: ScanLogConfig) -> Self {
+        Self {
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// record the result of a lookup
+    pub fn record(&self, image: &ImageRef, error: Option<&LookupError>, found: bool, cached: bool) {
+        if self.config.scan_history == 0 {
+            return;
+        }
+
+        let timestamp = Utc::now();
+        let outcome = match (error, found) {
+            (Some(_), _) => ScanOutcome::Err,
+            (None, true) => ScanOutcome::Found,
+            (None, false) => ScanOutcome::Missing,
+        };
+
+        let mut entries = self.entries.lock();
+        let entry = entries.entry(image.clone()).or_default();
+
+        if outcome == ScanOutcome::Found {
+            entry.last_found = Some(timestamp);
+        }
+
+        entry.attempts.push_front(ScanAttempt {
+            timestamp,
+            outcome,
+            error: error.cloned(),
+            cached,
+        });
+        entry.attempts.truncate(self.config.scan_history);
+    }
+
+    /// the history of an image, `None` if it wasn't looked up yet
+    pub fn get(&self, image: &ImageRef) -> Option<ScanHistory> {
+        self.entries.lock().get(image).map(|entry| ScanHistory {
+            last_found: entry.last_found,
+            attempts: entry.attempts.iter().cloned().collect(),
+        })
+    }
+
+    /// forget the history of an image, which is no longer part of the workload
+    pub fn remove(&self, image: &ImageRef) {
+        self.entries.lock().remove(image);
+    }
+
+    /// only keep the history of the provided images
+    pub fn retain(&self, images: &HashSet<&ImageRef>) {
+        self.entries
+            .lock()
+            .retain(|image, _| images.contains(image));
+    }
+}
@@ -0,0 +1,701 @@
+mod breaker;
+mod cache;
+mod history;
+mod queue;
+mod retry;
+
+pub use breaker::{BreakerConfig, CircuitBreaker};
+pub use cache::{CacheConfig, SbomCache};
+pub use history::{ScanLog, ScanLogConfig};
+pub use queue::{Priority, ScanQueue};
+pub use retry::RetryConfig;
+
+use crate::documents::DocumentStore;
+use crate::pubsub::{BufferConfig, Output};
+use crate::sbom;
+use crate::source::{PurlConfig, Sbom, SbomSource};
+use crate::store::{ImageOwner, Store};
+use crate::vexination::VexinationSource;
+use crate::workload::WorkloadState;
+use bommer_api::data::{
+    ContainerUsage, Event, Image, ImageRef, JobRef, NodeRef, PodRef, SbomState, SbomSummary,
+    Vulnerabilities,
+};
+use chrono::Utc;
+use futures::FutureExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Scanner")]
+pub struct ScannerConfig {
+    /// Number of images looked up concurrently
+    #[arg(long, env = "SCAN_WORKERS", default_value_t = 1)]
+    pub scan_workers: usize,
+
+    #[command(flatten)]
+    pub retry: RetryConfig,
+
+    #[command(flatten)]
+    pub cache: CacheConfig,
+
+    #[command(flatten)]
+    pub breaker: BreakerConfig,
+
+    #[command(flatten)]
+    pub history: ScanLogConfig,
+}
+
+/// What the scanner looks up SBOMs with, and records the results to
+pub struct ScannerContext {
+    pub source: Arc<dyn SbomSource>,
+    pub breaker: CircuitBreaker,
+    pub history: ScanLog,
+    pub cache: SbomCache,
+    pub vexination: Option<VexinationSource>,
+    pub purls: PurlConfig,
+    pub documents: Option<DocumentStore>,
+}
+
+pub fn store(
+    store: Store<ImageRef, ImageOwner, ()>,
+    context: ScannerContext,
+    config: ScannerConfig,
+    buffers: BufferConfig,
+    snapshot: im::HashMap<ImageRef, Image>,
+    removal_grace: Duration,
+    shutdown: CancellationToken,
+) -> (WorkloadState, impl Future<Output = anyhow::Result<()>>) {
+    let map = WorkloadState::new(buffers.consumer_buffer);
+
+    (map.clone(), async move {
+        // serve the persisted state until the watchers are synced
+        map.set_state(snapshot.clone()).await;
+
+        let (result, _, _) = futures::future::select_all([
+            runner(
+                store,
+                map.clone(),
+                snapshot,
+                context.purls.clone(),
+                buffers.store_buffer,
+                removal_grace,
+            )
+            .boxed_local(),
+            scanner(
+                map.clone(),
+                context,
+                config,
+                buffers.scanner_buffer,
+                shutdown,
+            )
+            .boxed_local(),
+            rescanner(map).boxed_local(),
+        ])
+        .await;
+
+        result
+    })
+}
+
+struct Scanner {
+    map: WorkloadState,
+    source: Arc<dyn SbomSource>,
+    retry: RetryConfig,
+    cache: SbomCache,
+    breaker: CircuitBreaker,
+    history: ScanLog,
+    vexination: Option<VexinationSource>,
+    purls: PurlConfig,
+    documents: Option<DocumentStore>,
+    queue: ScanQueue,
+}
+
+impl Scanner {
+    /// queue the scheduled images of the workload, by their priority
+    ///
+    /// Returns when shutting down.
+    async fn feed(&self, buffer: usize, shutdown: &CancellationToken) -> anyhow::Result<()> {
+        loop {
+            info!("Starting subscription ... ");
+            let mut sub = self.map.subscribe("scanner", buffer).await;
+            loop {
+                let evt = tokio::select! {
+                    evt = sub.recv() => match evt {
+                        Some(evt) => evt,
+                        None => break,
+                    },
+                    _ = shutdown.cancelled() => return Ok(()),
+                };
+
+                match Arc::unwrap_or_clone(evt) {
+                    Event::Added(image, state) | Event::Modified(image, state) => {
+                        match state.sbom {
+                            SbomState::Scheduled => self.queue.push(image, state),
+                            _ => self.queue.cancel(&image),
+                        }
+                    }
+                    Event::Restart(state) => {
+                        let images = state.keys().collect();
+                        self.history.retain(&images);
+                        self.queue.reset(&images);
+                        for (image, state) in state {
+                            if let SbomState::Scheduled = state.sbom {
+                                self.queue.push(image, state);
+                            }
+                        }
+                    }
+                    Event::Removed(image) => {
+                        self.history.remove(&image);
+                        self.queue.remove(&image);
+                    }
+                }
+            }
+
+            // lost subscription, delay and re-try
+            warn!("Lost subscription");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// scan queued images, until shutting down
+    ///
+    /// When shutting down, the scan currently in progress gets finished before returning.
+    async fn work(&self, shutdown: &CancellationToken) {
+        loop {
+            let (image, state) = tokio::select! {
+                next = self.queue.pop() => next,
+                _ = shutdown.cancelled() => return,
+            };
+            self.scan(&image, &state).await;
+        }
+    }
+
+    /// look up the vulnerabilities of an image, if vexination is configured
+    async fn vulnerabilities(&self, image: &ImageRef) -> Option<Vulnerabilities> {
+        let vexination = self.vexination.as_ref()?;
+        let purl = self.purls.purl(image).ok()?;
+        match vexination.lookup_vulnerabilities(&purl).await {
+            Ok(vulnerabilities) => Some(vulnerabilities),
+            Err(err) => {
+                warn!(%image, "Failed to look up vulnerabilities: {err}");
+                None
+            }
+        }
+    }
+
+    /// move the document of an SBOM to the document store, so that it isn't kept in memory
+    async fn spill(&self, image: &ImageRef, mut sbom: Sbom) -> Sbom {
+        let document = sbom.document.take();
+        if let (Some(store), Some(document), Some(digest)) =
+            (&self.documents, document, &sbom.summary.digest)
+        {
+            if let Err(err) = store.put(digest, document).await {
+                warn!(%image, %digest, "Failed to store SBOM document: {err}");
+            }
+        }
+        sbom
+    }
+
+    /// scan an image, using a cached result unless this is a re-scan
+    async fn scan(&self, image: &ImageRef, state: &Image) {
+        let cached = match state.retry {
+            None => self.cache.get(image),
+            Some(_) => None,
+        };
+
+        let from_cache = cached.is_some();
+        let result = match cached {
+            Some(result) => Ok(result),
+            None => {
+                self.breaker.acquire().await;
+                let result = self.source.lookup(image).await;
+                self.breaker
+                    .record(!matches!(&result, Err(err) if err.is_retryable()));
+                let result = match result {
+                    Ok(Some(sbom)) => Ok(Some(self.spill(image, sbom).await)),
+                    result => result,
+                };
+                if let Ok(result) = &result {
+                    self.cache.insert(image, result.clone());
+                }
+                result
+            }
+        };
+
+        // the next scan of images without an SBOM is less urgent than one of a failed lookup
+        self.queue.record(
+            image,
+            match &result {
+                Ok(Some(_)) => None,
+                Err(err) if err.is_retryable() => Some(Priority::Retry),
+                _ => Some(Priority::Refresh),
+            },
+        );
+
+        match &result {
+            Ok(Some(sbom))
+                if !sbom.summary.subjects.is_empty() && !sbom::verify(&sbom.summary, image) =>
+            {
+                info!(%image, subjects = ?sbom.summary.subjects, "Found SBOM describing a different digest")
+            }
+            Ok(Some(_)) => debug!(%image, "Found SBOM"),
+            Ok(None) => debug!(%image, "No SBOM found"),
+            Err(err) => debug!(%image, kind = ?err.kind, "Failed to look up SBOM: {err}"),
+        }
+
+        self.history.record(
+            image,
+            result.as_ref().err(),
+            matches!(result, Ok(Some(_))),
+            from_cache,
+        );
+
+        // vulnerabilities change over time, so we don't cache them. Unless they are provided by the
+        // source, along with the SBOM.
+        let vulnerabilities = match &result {
+            Ok(Some(sbom)) => match self.vulnerabilities(image).await {
+                Some(vulnerabilities) => Some(vulnerabilities),
+                None => sbom.vulnerabilities.clone(),
+            },
+            _ => None,
+        };
+
+        self.map
+            .mutate_state(image.clone(), |current| {
+                current.map(|mut current| {
+                    match result {
+                        Ok(Some(result)) => {
+                            current.sbom = SbomState::Found(SbomSummary {
+                                verified: sbom::verify(&result.summary, image),
+                                ..result.summary
+                            });
+                            current.retry = None;
+                            current.vulnerabilities = vulnerabilities;
+                        }
+                        Ok(None) => {
+                            current.sbom = SbomState::Missing;
+                            current.vulnerabilities = None;
+                            current.retry = Some(self.retry.missing(current.retry.as_ref()));
+                        }
+                        Err(err) => {
+                            // temporary errors get re-tried soon, others only periodically
+                            current.retry = Some(match err.is_retryable() {
+                                true => self.retry.failed(current.retry.as_ref()),
+                                false => self.retry.missing(current.retry.as_ref()),
+                            });
+                            current.sbom = SbomState::Err(err);
+                            current.vulnerabilities = None;
+                        }
+                    }
+                    current
+                })
+            })
+            .await;
+    }
+}
+
+/// scan incoming changes, prioritizing new images over re-scans
+///
+/// When shutting down, the scans currently in progress get finished before returning.
+async fn scanner(
+    map: WorkloadState,
+    context: ScannerContext,
+    config: ScannerConfig,
+    buffer: usize,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let ScannerContext {
+        source,
+        breaker,
+        history,
+        cache,
+        vexination,
+        purls,
+        documents,
+    } = context;
+
+    let scanner = Scanner {
+        map: map.clone(),
+        source,
+        retry: config.retry,
+        cache,
+        breaker,
+        history,
+        vexination,
+        purls,
+        documents,
+        queue: ScanQueue::default(),
+    };
+
+    let workers = (0..config.scan_workers.max(1)).map(|_| scanner.work(&shutdown));
+    let (result, _) = futures::future::join(
+        scanner.feed(buffer, &shutdown),
+        futures::future::join_all(workers),
+    )
+    .await;
+
+    info!("Scanner stopped");
+    result
+}
+
+/// periodically re-scan changes
+///
+/// Images which failed, or had no SBOM, are re-scheduled once their retry is due.
+async fn rescanner(map: WorkloadState) -> anyhow::Result<()> {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let now = Utc::now();
+
+        map.iter_mut(|_k, state| match &state.sbom {
+            SbomState::Err(_) | SbomState::Missing
+                if state.retry.as_ref().is_none_or(|r| r.next <= now) =>
+            {
+                let mut state = state.clone();
+                state.sbom = SbomState::Scheduled;
+                Output::Modify(state)
+            }
+            _ => Output::Keep,
+        })
+        .await;
+    }
+}
+
+/// How an image is used, derived from its owners in the store
+struct Usage {
+    pods: HashSet<PodRef>,
+    /// none of the containers runs the image
+    declared: bool,
+    containers: Vec<ContainerUsage>,
+    jobs: HashSet<JobRef>,
+    nodes: HashSet<NodeRef>,
+}
+
+impl Usage {
+    fn new(owners: HashSet<ImageOwner>) -> Self {
+        let mut containers = BTreeMap::<_, (HashSet<_>, bool)>::new();
+        let mut pods = HashSet::with_capacity(owners.len());
+        let mut jobs = HashSet::new();
+        let mut nodes = HashSet::new();
+
+        for owner in owners {
+            match owner {
+                ImageOwner::Container(owner) => {
+                    let (pods_of, running) = containers
+                        .entry((owner.container, owner.kind, owner.pod.namespace.clone()))
+                        .or_default();
+                    *running |= owner.running;
+                    pods_of.insert(owner.pod.clone());
+                    pods.insert(owner.pod);
+                }
+                ImageOwner::Job(job) => {
+                    jobs.insert(job);
+                }
+                ImageOwner::Node(node) => {
+                    nodes.insert(node);
+                }
+            }
+        }
+
+        let containers = containers
+            .into_iter()
+            .map(
+                |((name, kind, namespace), (pods, running))| ContainerUsage {
+                    name,
+                    kind,
+                    namespace,
+                    pods: pods.len(),
+                    running,
+                },
+            )
+            .collect::<Vec<_>>();
+
+        Self {
+            declared: Image::declared_by(&containers),
+            pods,
+            containers,
+            jobs,
+            nodes,
+        }
+    }
+
+    /// apply the usage to the state of an image
+    fn apply(self, image: &mut Image) {
+        image.pods = self.pods;
+        image.declared = self.declared;
+        image.containers = self.containers;
+        image.jobs = self.jobs;
+        image.nodes = self.nodes;
+    }
+}
+
+/// The state of images we already know, e.g. from the persisted snapshot
+#[derive(Default)]
+struct Known {
+    images: im::HashMap<ImageRef, Image>,
+    /// images with a looked up SBOM, by digest
+    digests: HashMap<String, ImageRef>,
+}
+
+impl Known {
+    fn new(images: im::HashMap<ImageRef, Image>) -> Self {
+        let digests = images
+            .iter()
+            .filter(|(_, state)| !matches!(state.sbom, SbomState::Scheduled))
+            .filter_map(|(image, _)| Some((image.digest.clone()?, image.clone())))
+            .collect();
+        Self { images, digests }
+    }
+
+    /// merge with the current state, which takes precedence
+    fn with(&self, current: im::HashMap<ImageRef, Image>) -> Self {
+        let mut images = self.images.clone();
+        images.extend(current);
+        Self::new(images)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// the state of the image, or of an image with the same digest (e.g. using a different tag)
+    fn get(&self, image: &ImageRef) -> Option<&Image> {
+        self.images.get(image).or_else(|| {
+            let digest = image.digest.as_ref()?;
+            self.images.get(self.digests.get(digest)?)
+        })
+    }
+}
+
+/// the initial state of an image, taking over what we know about it
+///
+/// If the package URL of the image changed (e.g. by configuring a different strategy), what we
+/// know about it is outdated.
+fn initial(
+    known: &Known,
+    image: &ImageRef,
+    owners: HashSet<ImageOwner>,
+    purls: &PurlConfig,
+) -> Image {
+    let Usage {
+        pods,
+        declared,
+        containers,
+        jobs,
+        nodes,
+    } = Usage::new(owners);
+    let purl = purls.purl(image).ok().map(|purl| purl.to_string());
+    match known.get(image) {
+        Some(preserved) if preserved.purl.is_none() || preserved.purl == purl => Image {
+            pods,
+            declared,
+            containers,
+            jobs,
+            nodes,
+            purl,
+            ..preserved.clone()
+        },
+        _ => Image {
+            pods,
+            declared,
+            containers,
+            jobs,
+            nodes,
+            purl,
+            ..Image::new(SbomState::Scheduled)
+        },
+    }
+}
+
+/// feed the images of the store into the map
+///
+/// Until the store is synced, images take over the state of the persisted snapshot, and the images
+/// of the snapshot which the store didn't report (yet) are kept. Once synced, those which are still
+/// missing are gone. On a restart of the store (e.g. a re-list of the pods), images keep their
+/// current state, so that they don't get scanned again.
+///
+/// Images without any pods are kept for the removal grace period, with their last state. If they
+/// come back in time (e.g. during a rolling update), they are neither removed nor scanned again.
+async fn runner(
+    store: Store<ImageRef, ImageOwner, ()>,
+    map: WorkloadState,
+    preserved: im::HashMap<ImageRef, Image>,
+    purls: PurlConfig,
+    buffer: usize,
+    removal_grace: Duration,
+) -> anyhow::Result<()> {
+    // images of the snapshot, which the store didn't report so far
+    let mut carried = preserved.keys().cloned().collect::<HashSet<_>>();
+    let mut preserved = Known::new(preserved);
+
+    // images without pods, and when to drop them
+    let mut removals = HashMap::<ImageRef, Instant>::new();
+
+    loop {
+        let mut sub = store.subscribe("workload", buffer).await;
+        loop {
+            let next = removals.values().min().copied();
+            let evt = tokio::select! {
+                evt = sub.recv() => match evt {
+                    Some(evt) => evt,
+                    None => break,
+                },
+                _ = store.sync_state().synced(), if !preserved.is_empty() => {
+                    // we might not have seen all events up to the sync yet, those images remain
+                    let current = store.get_state().await;
+                    for image in carried.drain().filter(|image| !current.contains_key(image)) {
+                        debug!(%image, "Image of the snapshot is gone");
+                        map.mutate_state(image, |_| None).await;
+                    }
+                    preserved = Known::default();
+                    continue;
+                }
+                _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let now = Instant::now();
+                    let mut expired = vec![];
+                    removals.retain(|image, due| match *due <= now {
+                        true => {
+                            expired.push(image.clone());
+                            false
+                        }
+                        false => true,
+                    });
+                    for image in expired {
+                        debug!(%image, "Removal grace period expired");
+                        map.mutate_state(image, |_| None).await;
+                    }
+                    continue;
+                }
+            };
+
+            match Arc::unwrap_or_clone(evt) {
+                Event::Added(image, state) | Event::Modified(image, state) => {
+                    removals.remove(&image);
+                    carried.remove(&image);
+                    map.mutate_state(image.clone(), |current| match current {
+                        Some(mut current) => {
+                            Usage::new(state.owners).apply(&mut current);
+                            Some(current)
+                        }
+                        None => Some(initial(&preserved, &image, state.owners, &purls)),
+                    })
+                    .await;
+                }
+                Event::Removed(image) if removal_grace.is_zero() => {
+                    map.mutate_state(image, |_| None).await;
+                }
+                Event::Removed(image) => {
+                    removals.insert(image, Instant::now() + removal_grace);
+                }
+                Event::Restart(state) => {
+                    // keep the images which are still in their grace period
+                    let current = map.get_state().await;
+                    removals.retain(|image, _| {
+                        !state.contains_key(image) && current.contains_key(image)
+                    });
+                    // until synced, the store might not have reported all images of the snapshot
+                    carried.retain(|image| {
+                        !state.contains_key(image)
+                            && !removals.contains_key(image)
+                            && current.contains_key(image)
+                    });
+                    let kept = removals
+                        .keys()
+                        .chain(&carried)
+                        .filter_map(|image| Some((image.clone(), current.get(image)?.clone())))
+                        .collect::<Vec<_>>();
+
+                    let known = preserved.with(current);
+                    map.set_state(
+                        state
+                            .into_iter()
+                            .map(|(k, v)| {
+                                let image = initial(&known, &k, v.owners, &purls);
+                                (k, image)
+                            })
+                            .chain(kept)
+                            .collect(),
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::NodeRef;
+
+    fn purls() -> PurlConfig {
+        PurlConfig {
+            purl_type: Default::default(),
+            purl_repository_url: false,
+            purl_tag: false,
+            purl_fallback: false,
+        }
+    }
+
+    fn looked_up() -> Image {
+        Image::new(SbomState::Missing)
+    }
+
+    /// the snapshot is served until the store is synced, only dropping the images which are gone
+    #[tokio::test(start_paused = true)]
+    async fn snapshot_until_synced() {
+        let kept: ImageRef = "quay.io/example/kept:1.0".parse().unwrap();
+        let gone: ImageRef = "quay.io/example/gone:1.0".parse().unwrap();
+        let snapshot =
+            im::HashMap::from_iter([(kept.clone(), looked_up()), (gone.clone(), looked_up())]);
+
+        let store = Store::<ImageRef, ImageOwner, ()>::new(1);
+        let map = WorkloadState::new(16);
+        map.set_state(snapshot.clone()).await;
+
+        let runner = runner(
+            store.clone(),
+            map.clone(),
+            snapshot,
+            purls(),
+            16,
+            Duration::ZERO,
+        );
+
+        let checks = async {
+            // the store starts out empty, which must not replace the snapshot
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let state = map.get_state().await;
+            assert!(state.contains_key(&kept));
+            assert!(state.contains_key(&gone));
+
+            let node = ImageOwner::Node(NodeRef {
+                cluster: None,
+                name: "node".into(),
+            });
+            store
+                .reset_scoped(|_| true, [(node, [kept.clone()].into())].into(), |_| ())
+                .await;
+            store.sync_state().mark_synced();
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let state = map.get_state().await;
+            assert_eq!(
+                state.get(&kept).map(|image| &image.sbom),
+                Some(&SbomState::Missing)
+            );
+            assert!(!state.contains_key(&gone));
+        };
+
+        tokio::select! {
+            result = runner => panic!("runner stopped: {result:?}"),
+            _ = checks => {}
+        }
+    }
+}
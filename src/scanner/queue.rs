@@ -0,0 +1,290 @@
+use bommer_api::data::{Image, ImageRef};
+use parking_lot::Mutex;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tokio::sync::Notify;
+
+/// The priority of a scan, higher ones are scanned first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Re-scanning an image which had no SBOM
+    Refresh,
+    /// Re-trying a lookup which failed
+    Retry,
+    /// Scanning an image for the first time
+    New,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Refresh => "refresh",
+            Self::Retry => "retry",
+            Self::New => "new",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    priority: Priority,
+    seq: u64,
+    state: Image,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<ImageRef, Entry>,
+    /// queued images, by priority first, and the order they were queued in second
+    order: BTreeSet<(Reverse<Priority>, u64, ImageRef)>,
+    next: u64,
+    /// number of queued images, by priority
+    counts: [usize; 3],
+    /// the priority of the next scan of images which are waiting for a retry
+    retries: HashMap<ImageRef, Priority>,
+}
+
+impl Inner {
+    fn insert(&mut self, image: ImageRef, entry: Entry) {
+        self.counts[entry.priority as usize] += 1;
+        self.order
+            .insert((Reverse(entry.priority), entry.seq, image.clone()));
+        self.entries.insert(image, entry);
+    }
+
+    fn remove(&mut self, image: &ImageRef) -> Option<Entry> {
+        let entry = self.entries.remove(image)?;
+        self.counts[entry.priority as usize] -= 1;
+        self.order
+            .remove(&(Reverse(entry.priority), entry.seq, image.clone()));
+        Some(entry)
+    }
+
+    fn update_metrics(&self) {
+        for priority in [Priority::Refresh, Priority::Retry, Priority::New] {
+            let len = self.counts[priority as usize];
+            metrics::gauge!("bommer_scan_queue_length", len as f64, "priority" => priority.as_str());
+        }
+    }
+}
+
+/// Images waiting to be scanned, handed out to the scanner workers by priority
+///
+/// An image is only queued once. Queuing it again updates its state, and raises its priority if
+/// necessary, but keeps its position among images of the same priority.
+#[derive(Debug, Default)]
+pub struct ScanQueue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+impl ScanQueue {
+    /// the priority of scanning an image, based on the outcome of its previous scan
+    pub fn priority(&self, image: &ImageRef, state: &Image) -> Priority {
+        match state.retry {
+            None => Priority::New,
+            // without knowing why, e.g. after a restart, we assume it failed
+            Some(_) => self
+                .inner
+                .lock()
+                .retries
+                .get(image)
+                .copied()
+                .unwrap_or(Priority::Retry),
+        }
+    }
+
+    /// record the priority the next scan of an image gets, once it's due
+    pub fn record(&self, image: &ImageRef, priority: Option<Priority>) {
+        let mut inner = self.inner.lock();
+        match priority {
+            Some(priority) => inner.retries.insert(image.clone(), priority),
+            None => inner.retries.remove(image),
+        };
+    }
+
+    /// queue an image, or update the queued state of it
+    pub fn push(&self, image: ImageRef, state: Image) {
+        let priority = self.priority(&image, &state);
+
+        let mut inner = self.inner.lock();
+        let entry = match inner.remove(&image) {
+            Some(entry) => Entry {
+                priority: priority.max(entry.priority),
+                seq: entry.seq,
+                state,
+            },
+            None => {
+                inner.next += 1;
+                Entry {
+                    priority,
+                    seq: inner.next,
+                    state,
+                }
+            }
+        };
+        inner.insert(image, entry);
+        inner.update_metrics();
+        drop(inner);
+
+        self.notify.notify_one();
+    }
+
+    /// remove an image from the queue, as it no longer needs to be scanned
+    pub fn cancel(&self, image: &ImageRef) {
+        let mut inner = self.inner.lock();
+        if inner.remove(image).is_some() {
+            inner.update_metrics();
+        }
+    }
+
+    /// remove an image from the queue, as well as what we know about its previous scans
+    pub fn remove(&self, image: &ImageRef) {
+        self.cancel(image);
+        self.inner.lock().retries.remove(image);
+    }
+
+    /// drop all queued images, and forget about images which are no longer present
+    pub fn reset(&self, images: &HashSet<&ImageRef>) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        inner.order.clear();
+        inner.counts = Default::default();
+        inner.retries.retain(|image, _| images.contains(image));
+        inner.update_metrics();
+    }
+
+    /// take the image with the highest priority, waiting for one if the queue is empty
+    pub async fn pop(&self) -> (ImageRef, Image) {
+        loop {
+            if let Some(next) = self.try_pop() {
+                return next;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<(ImageRef, Image)> {
+        let mut inner = self.inner.lock();
+        let (_, _, image) = inner.order.first()?.clone();
+        let entry = inner.remove(&image)?;
+        inner.update_metrics();
+
+        // wake up another worker, in case more images are queued
+        if !inner.order.is_empty() {
+            self.notify.notify_one();
+        }
+
+        Some((image, entry.state))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::{RetryState, SbomState};
+    use chrono::Utc;
+    use futures::poll;
+
+    fn image(name: &str) -> ImageRef {
+        format!("quay.io/example/{name}:1.0").parse().unwrap()
+    }
+
+    /// the state of an image, which was scanned before if it has a retry
+    fn state(retry: bool) -> Image {
+        Image {
+            retry: retry.then(|| RetryState {
+                attempts: 1,
+                next: Utc::now(),
+            }),
+            ..Image::new(SbomState::Scheduled)
+        }
+    }
+
+    /// pop all queued images, in order
+    fn drain(queue: &ScanQueue) -> Vec<ImageRef> {
+        std::iter::from_fn(|| queue.try_pop().map(|(image, _)| image)).collect()
+    }
+
+    #[test]
+    fn by_priority() {
+        let queue = ScanQueue::default();
+        queue.record(&image("refresh"), Some(Priority::Refresh));
+        queue.record(&image("retry"), Some(Priority::Retry));
+
+        queue.push(image("refresh"), state(true));
+        queue.push(image("retry"), state(true));
+        // scanned before, but we don't know the outcome (e.g. after a restart)
+        queue.push(image("unknown"), state(true));
+        queue.push(image("new"), state(false));
+
+        assert_eq!(
+            drain(&queue),
+            vec![
+                image("new"),
+                image("retry"),
+                image("unknown"),
+                image("refresh")
+            ]
+        );
+    }
+
+    #[test]
+    fn queued_once() {
+        let queue = ScanQueue::default();
+        queue.record(&image("a"), Some(Priority::Refresh));
+        queue.record(&image("b"), Some(Priority::Refresh));
+        queue.record(&image("c"), Some(Priority::Refresh));
+        queue.push(image("a"), state(true));
+        queue.push(image("b"), state(true));
+        queue.push(image("c"), state(true));
+
+        // keeps its position among images of the same priority
+        queue.push(image("a"), state(true));
+        // gets a higher priority
+        queue.push(image("c"), state(false));
+        // never gets a lower priority
+        queue.record(&image("c"), Some(Priority::Refresh));
+        queue.push(image("c"), state(true));
+
+        assert_eq!(drain(&queue), vec![image("c"), image("a"), image("b")]);
+    }
+
+    #[test]
+    fn cancel_and_reset() {
+        let queue = ScanQueue::default();
+        queue.push(image("a"), state(false));
+        queue.push(image("b"), state(false));
+        queue.push(image("c"), state(false));
+
+        queue.cancel(&image("b"));
+        assert_eq!(drain(&queue), vec![image("a"), image("c")]);
+
+        queue.record(&image("a"), Some(Priority::Refresh));
+        queue.record(&image("b"), Some(Priority::Refresh));
+        queue.push(image("a"), state(false));
+        queue.reset(&[&image("a")].into());
+        assert_eq!(drain(&queue), Vec::<ImageRef>::new());
+
+        // what we knew about images which are gone is forgotten
+        assert_eq!(queue.priority(&image("a"), &state(true)), Priority::Refresh);
+        assert_eq!(queue.priority(&image("b"), &state(true)), Priority::Retry);
+
+        queue.remove(&image("a"));
+        assert_eq!(queue.priority(&image("a"), &state(true)), Priority::Retry);
+    }
+
+    #[tokio::test]
+    async fn pop_waits() {
+        let queue = ScanQueue::default();
+        let pop = queue.pop();
+        tokio::pin!(pop);
+        assert!(poll!(&mut pop).is_pending());
+
+        queue.push(image("a"), state(false));
+        match poll!(&mut pop) {
+            std::task::Poll::Ready((popped, _)) => assert_eq!(popped, image("a")),
+            std::task::Poll::Pending => panic!("image not handed out"),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use bommer_api::data::RetryState;
+use chrono::Utc;
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Retry")]
+pub struct RetryConfig {
+    /// Delay before the first retry of a failed lookup
+    #[arg(long, env = "RETRY_INITIAL_DELAY", default_value = "5s", value_parser = humantime::parse_duration)]
+    pub retry_initial_delay: Duration,
+
+    /// Maximum delay between two retries of a failed lookup
+    #[arg(long, env = "RETRY_MAX_DELAY", default_value = "10m", value_parser = humantime::parse_duration)]
+    pub retry_max_delay: Duration,
+
+    /// Interval before re-scanning an image which has no SBOM
+    #[arg(long, env = "RESCAN_INTERVAL", default_value = "5m", value_parser = humantime::parse_duration)]
+    pub rescan_interval: Duration,
+
+    /// Maximum interval between two re-scans of an image which has no SBOM
+    #[arg(long, env = "RESCAN_MAX_INTERVAL", default_value = "6h", value_parser = humantime::parse_duration)]
+    pub rescan_max_interval: Duration,
+}
+
+impl RetryConfig {
+    /// create the retry state after another failed attempt
+    pub fn failed(&self, current: Option<&RetryState>) -> RetryState {
+        next(current, self.retry_initial_delay, self.retry_max_delay)
+    }
+
+    /// create the retry state after another attempt which didn't find an SBOM
+    pub fn missing(&self, current: Option<&RetryState>) -> RetryState {
+        next(current, self.rescan_interval, self.rescan_max_interval)
+    }
+}
+
+/// the delay after a number of attempts, exponentially increasing and with some jitter
+fn delay(attempts: u32, initial: Duration, max: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    let jitter = rand::thread_rng().gen_range(0.8..1.2);
+
+    initial.saturating_mul(factor).mul_f64(jitter).min(max)
+}
+
+fn next(current: Option<&RetryState>, initial: Duration, max: Duration) -> RetryState {
+    let attempts = current.map(|r| r.attempts).unwrap_or_default() + 1;
+    let delay =
+        chrono::Duration::from_std(delay(attempts, initial, max)).unwrap_or(chrono::Duration::MAX);
+
+    RetryState {
+        attempts,
+        next: Utc::now() + delay,
+    }
+}
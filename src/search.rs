@@ -0,0 +1,356 @@
+//! A full-text index of the packages contained in the SBOMs of the workload, answering which
+//! images contain a package.
+//!
+//! The index is kept up to date in the background, indexing the packages of an image when its
+//! SBOM is found, and evicting them once the image is gone.
+
+use crate::documents::Documents;
+use crate::sbom;
+use crate::workload::WorkloadState;
+use bommer_api::data::{Event, Image, ImageRef, SbomPackage, SbomState};
+use packageurl::PackageUrl;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use tantivy::collector::TopDocs;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, QueryParserError, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, Value, FAST, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, TantivyError, Term};
+use tracing::{debug, info, warn};
+
+/// memory used by the writer for buffering documents, before flushing them into a segment
+const WRITER_MEMORY: usize = 50_000_000;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Package search")]
+pub struct SearchConfig {
+    /// Index the packages of the SBOMs found, for searching images by the packages they contain
+    #[arg(long, env = "PACKAGE_INDEX")]
+    pub package_index: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid query: {0}")]
+    Query(#[from] QueryParserError),
+    #[error("Index error: {0}")]
+    Index(#[from] TantivyError),
+}
+
+/// The fields of a package in the index, one document per package of an image
+#[derive(Clone, Copy, Debug)]
+struct Fields {
+    image: Field,
+    /// The package a package URL refers to, independent of its version
+    package: Field,
+    purl: Field,
+    name: Field,
+    version: Field,
+    license: Field,
+    top_level: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Self) {
+        let mut schema = Schema::builder();
+        let fields = Self {
+            image: schema.add_text_field("image", STRING | STORED),
+            package: schema.add_text_field("package", STRING),
+            purl: schema.add_text_field("purl", STRING | STORED),
+            name: schema.add_text_field("name", TEXT | STORED),
+            version: schema.add_text_field("version", STRING | STORED),
+            license: schema.add_text_field("license", TEXT | STORED),
+            top_level: schema.add_bool_field("top_level", INDEXED | FAST | STORED),
+        };
+        (schema.build(), fields)
+    }
+
+    fn document(&self, image: &ImageRef, package: &SbomPackage) -> TantivyDocument {
+        let mut doc = TantivyDocument::default();
+        doc.add_text(self.image, image.to_string());
+        doc.add_text(self.name, &package.name);
+        if let Some(version) = &package.version {
+            doc.add_text(self.version, version);
+        }
+        if let Some(purl) = &package.purl {
+            doc.add_text(self.purl, purl);
+            if let Ok(parsed) = PackageUrl::from_str(purl) {
+                doc.add_text(self.package, self::package(&parsed));
+            }
+        }
+        for license in &package.licenses {
+            doc.add_text(self.license, license);
+        }
+        doc.add_bool(self.top_level, package.top_level);
+        doc
+    }
+
+    /// the image and package a document was created from
+    fn parse(&self, doc: &TantivyDocument) -> Option<(ImageRef, SbomPackage)> {
+        let text = |field| {
+            doc.get_first(field)
+                .and_then(|value| value.as_str())
+                .map(ToString::to_string)
+        };
+
+        let image = text(self.image)?.parse().ok()?;
+        Some((
+            image,
+            SbomPackage {
+                name: text(self.name)?,
+                version: text(self.version),
+                purl: text(self.purl),
+                licenses: doc
+                    .get_all(self.license)
+                    .filter_map(|value| value.as_str())
+                    .map(ToString::to_string)
+                    .collect(),
+                top_level: doc
+                    .get_first(self.top_level)
+                    .and_then(|value| value.as_bool())
+                    .unwrap_or_default(),
+            },
+        ))
+    }
+}
+
+struct Inner {
+    index: Index,
+    fields: Fields,
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+    /// The digest of the SBOM document of each indexed image
+    indexed: Mutex<HashMap<ImageRef, Option<String>>>,
+}
+
+/// A full-text index of the packages of the SBOMs found
+///
+/// The name and licenses of packages are searchable as text. Packages can also be looked up by
+/// their package URL, ignoring version, qualifiers, and subpath. So looking up a package finds all
+/// versions of it, unless a version is provided as well.
+#[derive(Clone)]
+pub struct PackageIndex {
+    inner: Arc<Inner>,
+}
+
+impl PackageIndex {
+    /// create a new, empty, index in memory
+    pub fn new() -> Result<Self, Error> {
+        let (schema, fields) = Fields::schema();
+        let index = Index::create_in_ram(schema);
+        let writer = index.writer_with_num_threads(1, WRITER_MEMORY)?;
+        // we reload after each commit, so that searches see the changes right away
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                index,
+                fields,
+                writer: Mutex::new(writer),
+                reader,
+                indexed: Default::default(),
+            }),
+        })
+    }
+
+    /// the images containing a package, along with the versions of it
+    pub fn lookup(
+        &self,
+        purl: &PackageUrl,
+        limit: usize,
+    ) -> Result<BTreeMap<ImageRef, Vec<SbomPackage>>, Error> {
+        let fields = &self.inner.fields;
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_text(fields.package, &package(purl)),
+                IndexRecordOption::Basic,
+            )),
+        )];
+        if let Some(version) = purl.version() {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(fields.version, version),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        self.search(&BooleanQuery::new(clauses), limit)
+    }
+
+    /// the images containing packages matching a query, e.g. `log4j` or `license:GPL`
+    ///
+    /// Without naming a field, the name and licenses of packages are searched.
+    pub fn query(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<BTreeMap<ImageRef, Vec<SbomPackage>>, Error> {
+        let fields = &self.inner.fields;
+        let parser = QueryParser::for_index(&self.inner.index, vec![fields.name, fields.license]);
+        let query = parser.parse_query(query)?;
+        self.search(&query, limit)
+    }
+
+    fn search(
+        &self,
+        query: &dyn Query,
+        limit: usize,
+    ) -> Result<BTreeMap<ImageRef, Vec<SbomPackage>>, Error> {
+        let searcher = self.inner.reader.searcher();
+        let mut result = BTreeMap::<ImageRef, Vec<SbomPackage>>::new();
+
+        for (_, address) in searcher.search(query, &TopDocs::with_limit(limit.max(1)))? {
+            let doc = searcher.doc::<TantivyDocument>(address)?;
+            if let Some((image, package)) = self.inner.fields.parse(&doc) {
+                result.entry(image).or_default().push(package);
+            }
+        }
+
+        for packages in result.values_mut() {
+            packages.sort_unstable_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        }
+
+        Ok(result)
+    }
+
+    /// keep the index in sync with the SBOMs of the workload
+    pub async fn run(self, map: WorkloadState, documents: Documents) -> anyhow::Result<()> {
+        loop {
+            let mut sub = map.subscribe("search", None).await;
+            while let Some(evt) = sub.recv().await {
+                let changed = match &*evt {
+                    Event::Added(image, state) | Event::Modified(image, state) => {
+                        self.update(&documents, image.clone(), state).await
+                    }
+                    Event::Removed(image) => self.evict(image),
+                    Event::Restart(state) => {
+                        let mut changed = false;
+                        let gone = self
+                            .inner
+                            .indexed
+                            .lock()
+                            .keys()
+                            .filter(|image| !state.contains_key(image))
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        for image in gone {
+                            changed |= self.evict(&image);
+                        }
+                        for (image, state) in state {
+                            changed |= self.update(&documents, image.clone(), state).await;
+                        }
+                        info!(
+                            "Indexed the packages of {} images",
+                            self.inner.indexed.lock().len()
+                        );
+                        changed
+                    }
+                };
+
+                // commit once per event, so that a restart doesn't create a segment per image
+                if changed {
+                    if let Err(err) = self.commit().await {
+                        warn!("Failed to commit package index: {err}");
+                    }
+                }
+            }
+            // the subscription got dropped, we get a full state with the next one
+        }
+    }
+
+    /// index the packages of an image, if its SBOM changed, returning if the index changed
+    async fn update(&self, documents: &Documents, image: ImageRef, state: &Image) -> bool {
+        let summary = match &state.sbom {
+            SbomState::Found(summary) => summary,
+            _ => return self.evict(&image),
+        };
+
+        if self.inner.indexed.lock().get(&image) == Some(&summary.digest) {
+            return false;
+        }
+
+        // sources without documents still get recorded, so that we don't ask again
+        let packages = match documents.get(&image, Some(summary)).await {
+            Ok(Some(data)) => match sbom::packages(&data) {
+                Ok(packages) => packages,
+                Err(err) => {
+                    warn!(%image, "Failed to index packages of SBOM: {err}");
+                    return false;
+                }
+            },
+            Ok(None) => Default::default(),
+            Err(err) => {
+                warn!(%image, "Failed to fetch SBOM for indexing: {err}");
+                return false;
+            }
+        };
+
+        let roots = packages.roots.iter().collect::<HashSet<_>>();
+        let fields = &self.inner.fields;
+        let writer = self.inner.writer.lock();
+        writer.delete_term(Term::from_field_text(fields.image, &image.to_string()));
+        for package in &packages.packages {
+            let package = SbomPackage {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                purl: package.purl.clone(),
+                licenses: package.licenses.clone(),
+                top_level: roots.contains(&package.id),
+            };
+            if let Err(err) = writer.add_document(fields.document(&image, &package)) {
+                warn!(%image, "Failed to index package: {err}");
+            }
+        }
+        drop(writer);
+
+        debug!(%image, packages = packages.packages.len(), "Indexed packages");
+        self.inner
+            .indexed
+            .lock()
+            .insert(image, summary.digest.clone());
+        true
+    }
+
+    /// drop the packages of an image from the index, returning if it was indexed
+    fn evict(&self, image: &ImageRef) -> bool {
+        if self.inner.indexed.lock().remove(image).is_none() {
+            return false;
+        }
+
+        let term = Term::from_field_text(self.inner.fields.image, &image.to_string());
+        self.inner.writer.lock().delete_term(term);
+        true
+    }
+
+    /// make the pending changes visible to searches
+    async fn commit(&self) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            inner.writer.lock().commit()?;
+            inner.reader.reload()
+        })
+        .await
+        .map_err(|err| TantivyError::InternalError(err.to_string()))??;
+        Ok(())
+    }
+}
+
+/// the package a package URL refers to, independent of its version
+fn package(purl: &PackageUrl) -> String {
+    let mut package = format!("pkg:{}/", purl.ty().to_ascii_lowercase());
+    if let Some(namespace) = purl.namespace() {
+        package.push_str(namespace);
+        package.push('/');
+    }
+    package.push_str(purl.name());
+    package
+}
@@ -0,0 +1,111 @@
+use super::limit;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderName;
+use actix_web::http::StatusCode;
+use std::future::Future;
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, Instrument, Span};
+
+/// Tracks a request, from receiving it until the response is ready
+///
+/// Each request gets a span, carrying its method, path, and client, so that everything logged
+/// while handling it can be correlated. The identity of the caller gets recorded once it's
+/// authenticated. The client is the peer, or the one reported by the trusted proxy in the client
+/// header, the same one the rate limiter sees.
+pub struct Access {
+    span: Span,
+    started: Instant,
+    enabled: bool,
+    /// probes and scrapes, which are only logged at debug level
+    internal: bool,
+}
+
+impl Access {
+    pub fn start(req: &ServiceRequest, enabled: bool, client_header: Option<&HeaderName>) -> Self {
+        let path = req.path();
+        let client = limit::client(req, client_header)
+            .map_or_else(|| "unknown".to_string(), |client| client.to_string());
+        let span = info_span!(
+            "request",
+            method = %req.method(),
+            path,
+            client = client.as_str(),
+            identity = Empty,
+            status = Empty,
+            latency_ms = Empty,
+        );
+
+        Self {
+            internal: path.starts_with("/health/") || path == "/metrics",
+            span,
+            started: Instant::now(),
+            enabled,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// record the outcome of the request, logging it if enabled
+    pub fn finish<B>(self, result: &Result<ServiceResponse<B>, actix_web::Error>) {
+        let status = match result {
+            Ok(response) => response.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        let latency = self.started.elapsed();
+
+        self.span.record("status", status.as_u16());
+        self.span
+            .record("latency_ms", latency.as_secs_f64() * 1000.0);
+
+        if !self.enabled {
+            return;
+        }
+
+        let _entered = self.span.enter();
+        match (self.internal, status) {
+            // upgrading to a websocket, the session gets logged on its own
+            (_, StatusCode::SWITCHING_PROTOCOLS) => info!("Websocket upgraded"),
+            (true, _) => debug!("Request handled"),
+            (false, _) => info!("Request handled"),
+        }
+    }
+}
+
+/// record the identity of the caller on the span of the request
+pub fn record_identity(identity: &impl std::fmt::Display) {
+    Span::current().record("identity", tracing::field::display(identity));
+}
+
+/// run a websocket session, logging when it starts and ends if enabled
+///
+/// The session gets its own span, as it outlives the request which upgraded it.
+pub fn session<F: Future<Output = ()>>(
+    path: &str,
+    identity: &impl std::fmt::Display,
+    enabled: bool,
+    session: F,
+) -> impl Future<Output = ()> {
+    let span = info_span!(
+        "websocket",
+        path,
+        %identity,
+        close = Empty,
+        duration_ms = Empty,
+    );
+
+    async move {
+        let started = Instant::now();
+        if enabled {
+            info!("Websocket session started");
+        }
+        session.await;
+        Span::current().record("duration_ms", started.elapsed().as_millis() as u64);
+        if enabled {
+            info!("Websocket session ended");
+        }
+    }
+    .instrument(span)
+}
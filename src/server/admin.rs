@@ -0,0 +1,111 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use super::tokens::Scope;
+use crate::pubsub::Output;
+use crate::scanner::SbomCache;
+use crate::store::Resync;
+use crate::workload::WorkloadState;
+use actix_web::{delete, post, web, HttpResponse};
+use bommer_api::data::SbomState;
+use tracing::info;
+
+/// The components the admin API acts on
+pub struct Admin {
+    pub subjects: Vec<String>,
+    pub cache: SbomCache,
+    pub resync: Resync,
+}
+
+impl Admin {
+    /// ensure the caller is one of the configured admins, or uses an admin token, returning who it is
+    fn authorize(&self, identity: Identity) -> Result<String, ApiError> {
+        match identity {
+            Identity::User { subject } if self.subjects.contains(&subject) => Ok(subject),
+            Identity::Token {
+                name,
+                scope: Scope::Admin,
+            } => Ok(name),
+            // already authorized for the path, using RBAC
+            Identity::Kubernetes(user) => Ok(user.username),
+            _ => Err(ApiError::Forbidden("Admin access required")),
+        }
+    }
+}
+
+/// Reset the state of the workload
+///
+/// Drops the cached lookup results and the SBOM state of all images, which then get looked up
+/// again, and lets all watchers list the cluster again.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 202, description = "The state is being reset"),
+        (status = 403, description = "The caller is not an admin"),
+    )
+)]
+#[post("/api/v1/admin/reset")]
+pub async fn reset(
+    identity: Identity,
+    admin: web::Data<Admin>,
+    map: web::Data<WorkloadState>,
+) -> Result<HttpResponse, ApiError> {
+    let subject = admin.authorize(identity)?;
+    info!(%subject, "Resetting the state");
+
+    admin.cache.clear();
+    map.iter_mut(|_, state| {
+        let mut state = state.clone();
+        state.sbom = SbomState::Scheduled;
+        state.retry = None;
+        state.vulnerabilities = None;
+        Output::Modify(state)
+    })
+    .await;
+    admin.resync.request();
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Drop the cached lookup results
+///
+/// Images found in the cache are only looked up again once they get scanned the next time.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 204, description = "The cache was cleared"),
+        (status = 403, description = "The caller is not an admin"),
+    )
+)]
+#[delete("/api/v1/admin/cache")]
+pub async fn clear_cache(
+    identity: Identity,
+    admin: web::Data<Admin>,
+) -> Result<HttpResponse, ApiError> {
+    let subject = admin.authorize(identity)?;
+    info!(%subject, "Clearing the SBOM cache");
+
+    admin.cache.clear();
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Let all watchers list the cluster again
+///
+/// Fixes a workload which drifted from the cluster, e.g. due to missed events. Images keep their
+/// SBOM state.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 202, description = "The watchers are listing again"),
+        (status = 403, description = "The caller is not an admin"),
+    )
+)]
+#[post("/api/v1/admin/resync")]
+pub async fn resync(identity: Identity, admin: web::Data<Admin>) -> Result<HttpResponse, ApiError> {
+    let subject = admin.authorize(identity)?;
+    info!(%subject, "Resyncing the watchers");
+
+    admin.resync.request();
+
+    Ok(HttpResponse::Accepted().finish())
+}
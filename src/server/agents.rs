@@ -0,0 +1,74 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use super::tokens::Scope;
+use crate::aggregator::{AgentUpdate, Aggregator, Error};
+use actix_web::{post, web, HttpResponse};
+use futures::StreamExt;
+use tracing::warn;
+
+/// Push an update of the workload of a cluster, as an agent
+///
+/// Requires running as aggregator, and an authenticated caller, which isn't limited to reading and
+/// is configured as agent of the cluster. An update carrying only the changes must follow the
+/// previous update of the same session, otherwise the agent needs to push its full workload.
+#[utoipa::path(
+    tag = "agents",
+    responses(
+        (status = 204, description = "The update was applied"),
+        (status = 400, description = "Invalid update"),
+        (status = 403, description = "The caller isn't an agent of the cluster"),
+        (status = 404, description = "Not running as aggregator"),
+        (status = 409, description = "The update doesn't follow the previous one, a full update is required"),
+        (status = 413, description = "The update exceeds the size limit"),
+    )
+)]
+#[post("/api/v1/agents/updates")]
+pub async fn push_update(
+    identity: Identity,
+    aggregator: web::Data<Option<Aggregator>>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let aggregator = aggregator
+        .as_ref()
+        .as_ref()
+        .ok_or(ApiError::Disabled("Not running as aggregator"))?;
+
+    let read_only = matches!(
+        identity,
+        Identity::Token {
+            scope: Scope::Read,
+            ..
+        }
+    );
+    if identity == Identity::Anonymous || read_only {
+        return Err(ApiError::Forbidden(
+            "Agents must not be anonymous or read-only",
+        ));
+    }
+
+    let limit = aggregator.max_update_size();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| ApiError::InvalidRequest(err.to_string()))?;
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(ApiError::PayloadTooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let update: AgentUpdate = serde_json::from_slice(&body)
+        .map_err(|err| ApiError::InvalidRequest(format!("Invalid update: {err}")))?;
+
+    if !aggregator.is_allowed(&identity.to_string(), &update.cluster) {
+        warn!(%identity, cluster = update.cluster, "Rejecting update of an agent which isn't allowed to push the cluster");
+        return Err(ApiError::Forbidden(
+            "Not allowed to push the workload of the cluster",
+        ));
+    }
+
+    match aggregator.apply(update).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(err @ Error::OutOfSequence { .. }) => Err(ApiError::OutOfSequence(err.to_string())),
+        Err(err) => Err(ApiError::InvalidRequest(err.to_string())),
+    }
+}
@@ -0,0 +1,462 @@
+use super::access::record_identity;
+use super::error::{problem, ApiError};
+use super::review::KubernetesAuth;
+use super::tokens::{ApiTokens, Scope};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::{debug, info};
+use url::Url;
+
+/// minimum time between two attempts to refresh the keys
+const MIN_REFRESH: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Authentication")]
+pub struct AuthConfig {
+    /// Issuer URL of the OIDC provider, used to validate bearer tokens
+    #[arg(long, env = "OIDC_ISSUER_URL")]
+    pub oidc_issuer_url: Option<Url>,
+
+    /// Audience the bearer tokens must be issued for
+    #[arg(long, env = "OIDC_AUDIENCE", requires = "oidc_issuer_url")]
+    pub oidc_audience: Option<String>,
+
+    /// Allow anonymous read access to the API, intended for development setups
+    #[arg(long, env = "ALLOW_ANONYMOUS")]
+    pub allow_anonymous: bool,
+
+    /// Subjects of the bearer tokens allowed to use the admin API
+    #[arg(
+        long = "admin-subject",
+        env = "ADMIN_SUBJECTS",
+        value_name = "SUBJECT",
+        value_delimiter = ',',
+        requires = "oidc_issuer_url"
+    )]
+    pub admin_subjects: Vec<String>,
+
+    /// File of static API tokens with their scopes (e.g. mounted from a Secret), in JSON format
+    #[arg(long, env = "API_TOKENS")]
+    pub api_tokens: Option<PathBuf>,
+
+    /// Authenticate callers by their Kubernetes tokens, and authorize them using RBAC
+    #[arg(long, env = "KUBERNETES_AUTH")]
+    pub kubernetes_auth: bool,
+
+    /// Audiences the Kubernetes tokens must be issued for
+    #[arg(
+        long = "kubernetes-auth-audience",
+        env = "KUBERNETES_AUTH_AUDIENCES",
+        value_name = "AUDIENCE",
+        value_delimiter = ',',
+        requires = "kubernetes_auth"
+    )]
+    pub kubernetes_auth_audiences: Vec<String>,
+}
+
+/// A user of the cluster, as reported by a `TokenReview`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KubernetesUser {
+    pub username: String,
+    pub uid: Option<String>,
+    pub groups: Vec<String>,
+    pub extra: BTreeMap<String, Vec<String>>,
+}
+
+/// The identity of an API caller
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Identity {
+    Anonymous,
+    User { subject: String },
+    Token { name: String, scope: Scope },
+    Kubernetes(KubernetesUser),
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Anonymous => f.write_str("anonymous"),
+            Self::User { subject } => write!(f, "user:{subject}"),
+            Self::Token { name, .. } => write!(f, "token:{name}"),
+            Self::Kubernetes(user) => write!(f, "kubernetes:{}", user.username),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Missing bearer token")]
+    MissingToken,
+    #[error("Invalid token: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    #[error("Unknown API token")]
+    UnknownToken,
+    #[error("Token rejected by the cluster")]
+    Rejected,
+    #[error("Access denied")]
+    Forbidden,
+    #[error("Failed to review access: {0}")]
+    Review(#[from] kube::Error),
+    #[error("Unknown signing key")]
+    UnknownKey,
+    #[error("Failed to retrieve signing keys: {0}")]
+    Keys(#[from] reqwest::Error),
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Keys(_) | Self::Review(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let (code, title) = match self {
+            Self::MissingToken => ("missing-token", "Authentication required"),
+            Self::InvalidToken(_) | Self::UnknownToken | Self::UnknownKey | Self::Rejected => {
+                ("invalid-token", "Invalid token")
+            }
+            Self::Forbidden => ("forbidden", "Access denied"),
+            Self::Keys(_) | Self::Review(_) => ("auth-unavailable", "Authentication unavailable"),
+        };
+        let mut response = problem(self.status_code(), code, title, Some(self.to_string()));
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: Url,
+}
+
+#[derive(serde::Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+struct Oidc {
+    client: reqwest::Client,
+    issuer: String,
+    audience: Option<String>,
+    jwks_uri: Url,
+    keys: RwLock<(JwkSet, Instant)>,
+}
+
+impl Oidc {
+    async fn discover(
+        client: reqwest::Client,
+        issuer: Url,
+        audience: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let discovery: Discovery = client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                issuer.as_str().trim_end_matches('/')
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        info!("Discovered OIDC issuer: {}", discovery.issuer);
+
+        let keys = fetch_keys(&client, &discovery.jwks_uri).await?;
+
+        Ok(Self {
+            client,
+            issuer: discovery.issuer,
+            audience,
+            jwks_uri: discovery.jwks_uri,
+            keys: RwLock::new((keys, Instant::now())),
+        })
+    }
+
+    /// find the key, refreshing the key set if the key is unknown
+    async fn key(&self, kid: &str) -> Result<Jwk, AuthError> {
+        if let Some(jwk) = self.keys.read().await.0.find(kid) {
+            return Ok(jwk.clone());
+        }
+
+        let mut keys = self.keys.write().await;
+        if keys.1.elapsed() > MIN_REFRESH {
+            debug!("Unknown key ({kid}), refreshing key set");
+            *keys = (
+                fetch_keys(&self.client, &self.jwks_uri).await?,
+                Instant::now(),
+            );
+        }
+
+        keys.0.find(kid).cloned().ok_or(AuthError::UnknownKey)
+    }
+
+    async fn validate(&self, token: &str) -> Result<Identity, AuthError> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let jwk = self
+            .key(header.kid.as_deref().ok_or(AuthError::UnknownKey)?)
+            .await?;
+
+        // the token must not pick an algorithm the key isn't meant for
+        let algorithms = algorithms(&jwk);
+        if !algorithms.contains(&header.alg) {
+            return Err(AuthError::InvalidToken(ErrorKind::InvalidAlgorithm.into()));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        validation.set_issuer(&[&self.issuer]);
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+
+        let token =
+            jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_jwk(&jwk)?, &validation)?;
+
+        Ok(Identity::User {
+            subject: token.claims.sub,
+        })
+    }
+}
+
+/// the algorithms a key may be used with, the one it states or the ones of its type
+///
+/// Symmetric keys are never accepted, as the key set of a provider is public.
+fn algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    let algorithms = match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => vec![],
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => vec![],
+    };
+
+    match jwk.common.algorithm {
+        Some(algorithm) => algorithms.into_iter().filter(|a| *a == algorithm).collect(),
+        None => algorithms,
+    }
+}
+
+async fn fetch_keys(client: &reqwest::Client, uri: &Url) -> Result<JwkSet, reqwest::Error> {
+    client
+        .get(uri.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+/// Authenticates API callers
+#[derive(Clone)]
+pub struct Authenticator {
+    allow_anonymous: bool,
+    oidc: Option<Arc<Oidc>>,
+    tokens: Option<Arc<ApiTokens>>,
+    kubernetes: Option<Arc<KubernetesAuth>>,
+}
+
+impl Authenticator {
+    pub async fn new(config: AuthConfig, client: reqwest::Client) -> anyhow::Result<Self> {
+        let oidc = match config.oidc_issuer_url {
+            Some(issuer) => Some(Arc::new(
+                Oidc::discover(client, issuer, config.oidc_audience).await?,
+            )),
+            None if config.allow_anonymous
+                || config.api_tokens.is_some()
+                || config.kubernetes_auth =>
+            {
+                None
+            }
+            None => anyhow::bail!(
+                "No OIDC issuer, API tokens, or Kubernetes authentication configured, and anonymous access is disabled, enable at least one"
+            ),
+        };
+        let tokens = match config.api_tokens {
+            Some(path) => Some(Arc::new(ApiTokens::load(path)?)),
+            None => None,
+        };
+        let kubernetes = match config.kubernetes_auth {
+            true => Some(Arc::new(KubernetesAuth::new(
+                kube::Client::try_default().await?,
+                config.kubernetes_auth_audiences,
+            ))),
+            false => None,
+        };
+
+        Ok(Self {
+            allow_anonymous: config.allow_anonymous,
+            oidc,
+            tokens,
+            kubernetes,
+        })
+    }
+
+    /// authenticate a caller, static API tokens take precedence over OIDC tokens
+    pub async fn authenticate(&self, token: Option<&str>) -> Result<Identity, AuthError> {
+        if let (Some(token), Some(tokens)) = (token, &self.tokens) {
+            if let Some(token) = tokens.get(token) {
+                return Ok(Identity::Token {
+                    name: token.name,
+                    scope: token.scope,
+                });
+            }
+        }
+
+        match (token, &self.oidc, &self.kubernetes) {
+            // the token might have been issued by the cluster instead
+            (Some(token), Some(oidc), Some(kubernetes)) => match oidc.validate(token).await {
+                Ok(identity) => Ok(identity),
+                Err(_) => kubernetes.authenticate(token).await,
+            },
+            (Some(token), Some(oidc), None) => oidc.validate(token).await,
+            (Some(token), None, Some(kubernetes)) => kubernetes.authenticate(token).await,
+            (Some(_), None, None) if self.tokens.is_some() => Err(AuthError::UnknownToken),
+            _ if self.allow_anonymous => Ok(Identity::Anonymous),
+            _ => Err(AuthError::MissingToken),
+        }
+    }
+
+    /// check if the caller may access a path of the API, using a verb (e.g. `get`)
+    ///
+    /// Only callers authenticated by the cluster are checked, using RBAC. Others are authorized by
+    /// the endpoints themselves.
+    pub async fn authorize(
+        &self,
+        identity: &Identity,
+        path: &str,
+        verb: &str,
+    ) -> Result<(), AuthError> {
+        match (identity, &self.kubernetes) {
+            (Identity::Kubernetes(user), Some(kubernetes)) => {
+                kubernetes.authorize(user, path, verb).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// extract the bearer token from the request
+///
+/// As browsers can't set headers when opening a websocket, the token of a websocket handshake can
+/// also be provided using the `access_token` query parameter.
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .or_else(|| {
+            if !is_websocket(req) {
+                return None;
+            }
+            web::Query::<TokenQuery>::from_query(req.query_string())
+                .ok()?
+                .into_inner()
+                .access_token
+        })
+}
+
+/// check if the request is a websocket handshake
+fn is_websocket(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+impl FromRequest for Identity {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let authenticator = req.app_data::<web::Data<Authenticator>>().cloned();
+        let token = bearer_token(req);
+        let path = req.path().to_string();
+        let verb = req.method().as_str().to_ascii_lowercase();
+
+        Box::pin(async move {
+            let authenticator =
+                authenticator.ok_or_else(|| ApiError::Internal("Missing authenticator".into()))?;
+            let identity = authenticator.authenticate(token.as_deref()).await?;
+            record_identity(&identity);
+            authenticator.authorize(&identity, &path, &verb).await?;
+            Ok(identity)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn jwk(value: serde_json::Value) -> Jwk {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn algorithms_by_type() {
+        let rsa = jwk(json!({"kty": "RSA", "kid": "1", "n": "AQAB", "e": "AQAB"}));
+        assert!(algorithms(&rsa).contains(&Algorithm::RS256));
+        assert!(algorithms(&rsa).contains(&Algorithm::PS512));
+        assert!(!algorithms(&rsa).contains(&Algorithm::HS256));
+        assert!(!algorithms(&rsa).contains(&Algorithm::ES256));
+
+        let ec = jwk(json!({"kty": "EC", "kid": "2", "crv": "P-384", "x": "AQAB", "y": "AQAB"}));
+        assert_eq!(algorithms(&ec), vec![Algorithm::ES384]);
+
+        let okp = jwk(json!({"kty": "OKP", "kid": "3", "crv": "Ed25519", "x": "AQAB"}));
+        assert_eq!(algorithms(&okp), vec![Algorithm::EdDSA]);
+    }
+
+    #[test]
+    fn algorithms_stated() {
+        let rsa = jwk(json!({"kty": "RSA", "alg": "PS256", "n": "AQAB", "e": "AQAB"}));
+        assert_eq!(algorithms(&rsa), vec![Algorithm::PS256]);
+
+        // stating an algorithm of another type of key doesn't allow it
+        let rsa = jwk(json!({"kty": "RSA", "alg": "HS256", "n": "AQAB", "e": "AQAB"}));
+        assert!(algorithms(&rsa).is_empty());
+    }
+
+    #[test]
+    fn algorithms_symmetric() {
+        let oct = jwk(json!({"kty": "oct", "alg": "HS256", "k": "c2VjcmV0"}));
+        assert!(algorithms(&oct).is_empty());
+    }
+}
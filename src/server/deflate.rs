@@ -0,0 +1,356 @@
+//! The `permessage-deflate` websocket extension ([RFC 7692](https://www.rfc-editor.org/rfc/rfc7692))
+//!
+//! Only messages sent to the client get compressed, which works as the workload stream doesn't
+//! accept any data from the client. The frames produced by `actix-ws` are rewritten on their way
+//! out, compressing the payload of data frames and flagging them using the RSV1 bit.
+
+use actix_web::body::{BodyStream, MessageBody};
+use actix_web::http::header::{HeaderValue, SEC_WEBSOCKET_EXTENSIONS};
+use actix_web::{HttpRequest, HttpResponse};
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use futures::TryStreamExt;
+use std::io::Write;
+
+const EXTENSION: &str = "permessage-deflate";
+
+/// the end of a block of a sync flush, which gets removed from each message
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+
+/// The negotiated parameters of the extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Params {
+    /// Compress each message on its own, as requested by the client
+    no_context_takeover: bool,
+}
+
+/// pick the first offer of the client we can accept, if any
+///
+/// We can't reduce the window size of the compressor, so offers limiting it are declined.
+fn negotiate(req: &HttpRequest) -> Option<Params> {
+    let mut offers = req
+        .headers()
+        .get_all(SEC_WEBSOCKET_EXTENSIONS)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+
+    offers.find_map(|offer| {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next()? != EXTENSION {
+            return None;
+        }
+
+        let mut result = Params {
+            no_context_takeover: false,
+        };
+        for param in params {
+            match param.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                None if param == "server_no_context_takeover" => result.no_context_takeover = true,
+                None if param == "client_no_context_takeover" => {}
+                None if param == "client_max_window_bits" => {}
+                Some(("client_max_window_bits", _)) => {}
+                Some(("server_max_window_bits", bits)) if bits.trim_matches('"') == "15" => {}
+                _ => return None,
+            }
+        }
+
+        Some(result)
+    })
+}
+
+/// enable the extension for a websocket handshake response, if the client offered it
+pub fn apply(req: &HttpRequest, res: HttpResponse) -> HttpResponse {
+    let params = match negotiate(req) {
+        Some(params) => params,
+        None => return res,
+    };
+
+    let mut res = res;
+    let value = match params.no_context_takeover {
+        true => "permessage-deflate; server_no_context_takeover",
+        false => EXTENSION,
+    };
+    res.headers_mut()
+        .insert(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static(value));
+
+    let mut frames = Frames::new(params);
+    res.map_body(move |_, body| {
+        let stream = futures::stream::unfold(body, |mut body| async move {
+            let chunk = futures::future::poll_fn(|cx| body.as_pin_mut().poll_next(cx)).await?;
+            Some((chunk, body))
+        });
+        let stream = stream
+            .map_ok(move |chunk: Bytes| frames.push(&chunk))
+            .try_filter(|chunk| futures::future::ready(!chunk.is_empty()));
+        BodyStream::new(stream).boxed()
+    })
+}
+
+/// Rewrites a stream of server frames, compressing data frames
+struct Frames {
+    params: Params,
+    encoder: DeflateEncoder<Vec<u8>>,
+    /// a frame which isn't complete yet
+    pending: BytesMut,
+}
+
+impl Frames {
+    fn new(params: Params) -> Self {
+        Self {
+            params,
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// process a chunk of the stream, returning the complete frames it contained
+    fn push(&mut self, chunk: &[u8]) -> Bytes {
+        self.pending.extend_from_slice(chunk);
+
+        let mut result = BytesMut::new();
+        while let Some((header, len)) = parse_header(&self.pending) {
+            if self.pending.len() < header + len {
+                break;
+            }
+            let frame = self.pending.split_to(header + len);
+            let (first, payload) = (frame[0], &frame[header..]);
+
+            // control frames must not be compressed, and fragmented messages stay uncompressed
+            let opcode = first & 0x0f;
+            if first & FIN == 0 || !matches!(opcode, OP_TEXT | OP_BINARY) {
+                result.extend_from_slice(&frame);
+                continue;
+            }
+
+            match self.compress(payload) {
+                Ok(payload) => write_frame(&mut result, FIN | RSV1 | opcode, &payload),
+                // writing into memory, which only fails if we mess up the state
+                Err(_) => result.extend_from_slice(&frame),
+            }
+        }
+
+        result.freeze()
+    }
+
+    fn compress(&mut self, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.encoder.write_all(payload)?;
+        self.encoder.flush()?;
+        let mut compressed = std::mem::take(self.encoder.get_mut());
+        if compressed.ends_with(&TAIL) {
+            compressed.truncate(compressed.len() - TAIL.len());
+        }
+
+        if self.params.no_context_takeover {
+            self.encoder.reset(Vec::new())?;
+        }
+
+        Ok(compressed)
+    }
+}
+
+/// parse the header of an (unmasked) server frame, returning its length and the payload length
+fn parse_header(data: &[u8]) -> Option<(usize, usize)> {
+    let len = *data.get(1)? & 0x7f;
+    match len {
+        126 => Some((
+            4,
+            u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize,
+        )),
+        127 => Some((
+            10,
+            u64::from_be_bytes(data.get(2..10)?.try_into().ok()?) as usize,
+        )),
+        len => Some((2, len as usize)),
+    }
+}
+
+fn write_frame(buf: &mut BytesMut, first: u8, payload: &[u8]) {
+    buf.reserve(payload.len() + 10);
+    buf.put_u8(first);
+    match payload.len() {
+        len if len < 126 => buf.put_u8(len as u8),
+        len if len <= u16::MAX as usize => {
+            buf.put_u8(126);
+            buf.put_u16(len as u16);
+        }
+        len => {
+            buf.put_u8(127);
+            buf.put_u64(len as u64);
+        }
+    }
+    buf.put_slice(payload);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use flate2::write::DeflateDecoder;
+
+    const OP_CONTINUATION: u8 = 0x0;
+    const OP_CLOSE: u8 = 0x8;
+    const OP_PING: u8 = 0x9;
+
+    fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        write_frame(&mut buf, first, payload);
+        buf.to_vec()
+    }
+
+    /// split a stream of frames into the first byte and payload of each frame
+    fn split(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut result = vec![];
+        while !data.is_empty() {
+            let (header, len) = parse_header(data).expect("incomplete header");
+            result.push((data[0], data[header..header + len].to_vec()));
+            data = &data[header + len..];
+        }
+        result
+    }
+
+    /// Decompresses messages, like a client would
+    struct Client(DeflateDecoder<Vec<u8>>);
+
+    impl Client {
+        fn new() -> Self {
+            Self(DeflateDecoder::new(Vec::new()))
+        }
+
+        fn inflate(&mut self, payload: &[u8]) -> Vec<u8> {
+            self.0.write_all(payload).unwrap();
+            self.0.write_all(&TAIL).unwrap();
+            self.0.flush().unwrap();
+            std::mem::take(self.0.get_mut())
+        }
+    }
+
+    /// some data which doesn't compress well
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn params(no_context_takeover: bool) -> Params {
+        Params {
+            no_context_takeover,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut frames = Frames::new(params(false));
+        let mut client = Client::new();
+
+        for message in [&b"{\"hello\":\"world\"}"[..], &[b'x'; 1000], &noise(70_000)] {
+            let output = frames.push(&frame(FIN | OP_TEXT, message));
+            let output = split(&output);
+            assert_eq!(output.len(), 1);
+            assert_eq!(output[0].0, FIN | RSV1 | OP_TEXT);
+            assert_eq!(client.inflate(&output[0].1), message);
+        }
+    }
+
+    #[test]
+    fn split_chunks() {
+        let mut frames = Frames::new(params(false));
+        let message = noise(300);
+        let input = [
+            frame(FIN | OP_BINARY, &message),
+            frame(FIN | OP_BINARY, &message),
+        ]
+        .concat();
+
+        // frames only get emitted once they are complete
+        let mut output = vec![];
+        for chunk in input.chunks(7) {
+            output.extend_from_slice(&frames.push(chunk));
+        }
+
+        let mut client = Client::new();
+        let output = split(&output);
+        assert_eq!(output.len(), 2);
+        for (first, payload) in output {
+            assert_eq!(first, FIN | RSV1 | OP_BINARY);
+            assert_eq!(client.inflate(&payload), message);
+        }
+    }
+
+    #[test]
+    fn uncompressed_frames() {
+        let mut frames = Frames::new(params(false));
+        let input = [
+            frame(FIN | OP_PING, b"ping"),
+            frame(OP_TEXT, b"frag"),
+            frame(FIN | OP_CONTINUATION, b"mented"),
+            frame(FIN | OP_CLOSE, &[0x03, 0xe8]),
+        ]
+        .concat();
+
+        // control frames, and fragmented messages, are passed on as they are
+        assert_eq!(frames.push(&input).as_ref(), input.as_slice());
+    }
+
+    #[test]
+    fn no_context_takeover() {
+        let message = b"the same message, again and again";
+
+        let mut shared = Frames::new(params(false));
+        let first = split(&shared.push(&frame(FIN | OP_TEXT, message)));
+        let second = split(&shared.push(&frame(FIN | OP_TEXT, message)));
+        // the second message refers to the first one
+        assert!(second[0].1.len() < first[0].1.len());
+
+        // each message can be decompressed on its own
+        let mut frames = Frames::new(params(true));
+        for _ in 0..2 {
+            let output = split(&frames.push(&frame(FIN | OP_TEXT, message)));
+            assert_eq!(Client::new().inflate(&output[0].1), message);
+        }
+    }
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |offer: &str| {
+            negotiate(
+                &TestRequest::default()
+                    .insert_header((SEC_WEBSOCKET_EXTENSIONS, offer))
+                    .to_http_request(),
+            )
+        };
+
+        assert_eq!(negotiate("permessage-deflate"), Some(params(false)));
+        assert_eq!(
+            negotiate("permessage-deflate; client_max_window_bits"),
+            Some(params(false))
+        );
+        assert_eq!(
+            negotiate("permessage-deflate; server_no_context_takeover"),
+            Some(params(true))
+        );
+        // we can't limit the window, but can fall back to the next offer
+        assert_eq!(
+            negotiate("permessage-deflate; server_max_window_bits=10"),
+            None
+        );
+        assert_eq!(
+            negotiate(
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate; server_max_window_bits=15"
+            ),
+            Some(params(false))
+        );
+        assert_eq!(negotiate("x-webkit-deflate-frame"), None);
+    }
+}
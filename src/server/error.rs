@@ -0,0 +1,120 @@
+use actix_web::http::header::{self, HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use bommer_api::data::{ImageRefError, Problem};
+
+/// seconds after which clients should try again, while the initial sync is pending
+const SYNC_RETRY_AFTER: u64 = 5;
+
+/// An error of the API, rendered as problem details (RFC 7807), along with a stable code
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("{0}")]
+    InvalidImage(#[from] ImageRefError),
+    #[error("{0}")]
+    Handshake(String),
+    #[error("{0}")]
+    Forbidden(&'static str),
+    #[error("No such resource")]
+    NotFound,
+    #[error("Image is not part of the workload")]
+    ImageNotFound,
+    #[error("No SBOM document available")]
+    DocumentNotFound,
+    #[error("{0}")]
+    Disabled(&'static str),
+    #[error("{0}")]
+    OutOfSequence(String),
+    #[error("The request exceeds the limit of {0} bytes")]
+    PayloadTooLarge(u64),
+    #[error("{0}")]
+    InvalidSbom(String),
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    #[error("Too many subscribers")]
+    TooManySubscribers,
+    #[error("The workload is incomplete until the initial sync completed")]
+    NotSynced,
+    #[error("{0}")]
+    Internal(String),
+    #[error("{0}")]
+    Upstream(String),
+}
+
+impl ApiError {
+    /// the stable code of the error, along with a title
+    fn code(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::InvalidRequest(_) => ("invalid-request", "Invalid request"),
+            Self::InvalidImage(_) => ("invalid-image", "Invalid image reference"),
+            Self::Handshake(_) => ("websocket-handshake", "Websocket handshake failed"),
+            Self::Forbidden(_) => ("forbidden", "Access denied"),
+            Self::NotFound => ("not-found", "Not found"),
+            Self::ImageNotFound => ("image-not-found", "Image not found"),
+            Self::DocumentNotFound => ("document-not-found", "SBOM document not found"),
+            Self::Disabled(_) => ("not-enabled", "Feature not enabled"),
+            Self::OutOfSequence(_) => ("out-of-sequence", "Update out of sequence"),
+            Self::PayloadTooLarge(_) => ("payload-too-large", "Payload too large"),
+            Self::InvalidSbom(_) => ("invalid-sbom", "Invalid SBOM"),
+            Self::RateLimited => ("rate-limited", "Too many requests"),
+            Self::TooManySubscribers => ("too-many-subscribers", "Too many subscribers"),
+            Self::NotSynced => ("not-synced", "Initial sync pending"),
+            Self::Internal(_) => ("internal-error", "Internal error"),
+            Self::Upstream(_) => ("upstream-error", "Upstream service failed"),
+        }
+    }
+
+    /// handles errors of extracting the request (e.g. its query), to be set on the extractors
+    pub fn extractor<E: std::fmt::Display>(err: E, _req: &HttpRequest) -> actix_web::Error {
+        Self::InvalidRequest(err.to_string()).into()
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest(_) | Self::InvalidImage(_) | Self::Handshake(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound | Self::ImageNotFound | Self::DocumentNotFound | Self::Disabled(_) => {
+                StatusCode::NOT_FOUND
+            }
+            Self::OutOfSequence(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::InvalidSbom(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::RateLimited | Self::TooManySubscribers => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotSynced => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let (code, title) = self.code();
+        let mut response = problem(self.status_code(), code, title, Some(self.to_string()));
+        if let Self::NotSynced = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(SYNC_RETRY_AFTER));
+        }
+        response
+    }
+}
+
+/// render problem details
+pub fn problem(
+    status: StatusCode,
+    code: &str,
+    title: &str,
+    detail: Option<String>,
+) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header((
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(Problem::CONTENT_TYPE),
+        ))
+        .json(Problem::new(status.as_u16(), code, title, detail))
+}
@@ -0,0 +1,111 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use super::query::{WorkloadFilter, WorkloadQuery};
+use crate::documents::Documents;
+use crate::export::{self, ExportedImage};
+use crate::workload::WorkloadState;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse, Responder};
+use bommer_api::data::ImageRef;
+use std::collections::HashMap;
+
+/// Export the workload as a single CycloneDX document
+///
+/// The SBOMs of all images are merged into one document. Each image is a `container` component,
+/// containing the components of its SBOM, and listing the namespaces and pods using it as
+/// properties (`bommer:namespace`, `bommer:pod`). The same filters as for getting the workload
+/// can be applied.
+#[utoipa::path(
+    tag = "export",
+    params(WorkloadFilter),
+    responses(
+        (status = 200, description = "The aggregated SBOM", content_type = "application/vnd.cyclonedx+json"),
+    )
+)]
+#[get("/api/v1/export/cyclonedx")]
+pub async fn cyclonedx(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    documents: web::Data<Documents>,
+    filter: web::Query<WorkloadFilter>,
+) -> impl Responder {
+    let images = collect(&map, &documents, &filter).await;
+
+    HttpResponse::Ok()
+        .content_type(export::cyclonedx::CONTENT_TYPE)
+        .json(export::cyclonedx::render(&images))
+}
+
+/// Export the workload as a single SPDX document
+///
+/// The SBOMs of all images are merged into one document. It describes a synthetic `cluster`
+/// package, which contains a package for each image, which in turn contains the packages of its
+/// SBOM. The namespaces and pods using an image are added as annotations. The same filters as
+/// for getting the workload can be applied.
+#[utoipa::path(
+    tag = "export",
+    params(WorkloadFilter),
+    responses(
+        (status = 200, description = "The aggregated SBOM", content_type = "application/spdx+json"),
+    )
+)]
+#[get("/api/v1/export/spdx")]
+pub async fn spdx(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    documents: web::Data<Documents>,
+    filter: web::Query<WorkloadFilter>,
+) -> impl Responder {
+    let images = collect(&map, &documents, &filter).await;
+
+    HttpResponse::Ok()
+        .content_type(export::spdx::CONTENT_TYPE)
+        .json(export::spdx::render(&images))
+}
+
+/// Export the workload as CSV, one row per image
+///
+/// Each row lists the image, its digest, the (space separated) namespaces it is used in, the
+/// number of pods using it, and the state of its SBOM. The same filters and sorting as for
+/// getting the workload can be applied.
+#[utoipa::path(
+    tag = "export",
+    params(WorkloadFilter, WorkloadQuery),
+    responses(
+        (status = 200, description = "The inventory of images", content_type = "text/csv"),
+    )
+)]
+#[get("/api/v1/workload.csv")]
+pub async fn csv(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    filter: web::Query<WorkloadFilter>,
+    query: web::Query<WorkloadQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let page = query.apply(&filter, map.get_state().await);
+    let data = export::inventory::render(&page.items)
+        .map_err(|err| ApiError::Internal(err.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(export::inventory::CONTENT_TYPE)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("workload.csv".into())],
+        })
+        .body(data))
+}
+
+async fn collect(
+    map: &WorkloadState,
+    documents: &Documents,
+    filter: &WorkloadFilter,
+) -> Vec<ExportedImage> {
+    let state = map
+        .get_state()
+        .await
+        .into_iter()
+        .filter_map(|(image, state)| Some((image.clone(), filter.apply(&image, state)?)))
+        .collect::<HashMap<ImageRef, _>>();
+
+    export::collect(documents, state).await
+}
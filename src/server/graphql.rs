@@ -0,0 +1,828 @@
+//! GraphQL API, including subscriptions to changes of the workload
+
+use super::access;
+use super::auth::Identity;
+use super::error::ApiError;
+use super::limit::Permit;
+use super::query::{SbomFilter, WorkloadFilter, WorkloadQuery};
+use crate::pubsub::{SlowSubscriber, SubscribeOptions};
+use crate::workload::WorkloadState;
+use actix_web::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message};
+use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{Context, EmptyMutation, Enum, InputObject, Object, Schema, Subscription};
+use bommer_api::data::{self, Event, ImageRef};
+use chrono::{DateTime, Utc};
+use futures::{future, stream, Stream, StreamExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::task::spawn_local;
+use tokio_util::sync::CancellationToken;
+
+pub type WorkloadSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// maximum nesting of a query, leaving room for the introspection queries of GraphQL clients
+const MAX_DEPTH: usize = 16;
+
+/// maximum number of fields a query may select, counting each alias separately
+const MAX_COMPLEXITY: usize = 1000;
+
+/// Events buffered for each subscription
+struct SubscriberBuffer(usize);
+
+/// create the schema, serving the workload
+pub fn schema(
+    map: WorkloadState,
+    slow_subscriber: SlowSubscriber,
+    buffer: usize,
+) -> WorkloadSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(map)
+        .data(slow_subscriber)
+        .data(SubscriberBuffer(buffer))
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Execute a GraphQL query
+#[utoipa::path(
+    tag = "workload",
+    request_body(content = Object, description = "The GraphQL request", content_type = "application/json"),
+    responses(
+        (status = 200, description = "The GraphQL response"),
+    )
+)]
+#[post("/api/v1/graphql")]
+pub async fn graphql(
+    _identity: Identity,
+    schema: web::Data<WorkloadSchema>,
+    request: web::Json<async_graphql::Request>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(schema.execute(request.into_inner()).await)
+}
+
+/// Execute GraphQL subscriptions, using a websocket
+///
+/// Both the `graphql-transport-ws` and the legacy `graphql-ws` protocol are supported.
+#[utoipa::path(
+    tag = "workload",
+    responses(
+        (status = 101, description = "Switching to the websocket protocol"),
+    )
+)]
+#[get("/api/v1/graphql/ws")]
+pub async fn graphql_ws(
+    identity: Identity,
+    req: HttpRequest,
+    stream: web::Payload,
+    schema: web::Data<WorkloadSchema>,
+    settings: web::Data<super::ws::Settings>,
+) -> Result<HttpResponse, ApiError> {
+    let protocol = req
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|p| p.trim().parse::<WebSocketProtocols>().ok())
+        })
+        .ok_or_else(|| ApiError::Handshake("Unsupported websocket protocol".into()))?;
+
+    let permit = settings
+        .subscribers
+        .acquire()
+        .ok_or(ApiError::TooManySubscribers)?;
+    let (mut res, session, msg_stream) =
+        actix_ws::handle(&req, stream).map_err(|err| ApiError::Handshake(err.to_string()))?;
+    res.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(protocol.sec_websocket_protocol()),
+    );
+
+    spawn_local(access::session(
+        req.path(),
+        &identity,
+        settings.access_log,
+        run_ws(
+            schema.as_ref().clone(),
+            protocol,
+            session,
+            msg_stream,
+            settings.shutdown.clone(),
+            permit,
+        ),
+    ));
+
+    Ok(res)
+}
+
+async fn run_ws(
+    schema: WorkloadSchema,
+    protocol: WebSocketProtocols,
+    mut session: actix_ws::Session,
+    msg_stream: actix_ws::MessageStream,
+    shutdown: CancellationToken,
+    // released when the session ends
+    _permit: Permit,
+) {
+    let pong = session.clone();
+    let input = msg_stream
+        .take_while(|msg| {
+            future::ready(matches!(msg, Ok(msg) if !matches!(msg, Message::Close(_))))
+        })
+        .filter_map(move |msg| {
+            let mut pong = pong.clone();
+            async move {
+                match msg {
+                    Ok(Message::Text(text)) => Some(text.into_bytes()),
+                    Ok(Message::Binary(data)) => Some(data),
+                    Ok(Message::Ping(data)) => {
+                        let _ = pong.pong(&data).await;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+        });
+
+    let mut output = std::pin::pin!(WebSocket::new(schema, input, protocol));
+    loop {
+        let msg = tokio::select! {
+            msg = output.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = shutdown.cancelled() => {
+                let _ = session
+                    .close(Some((CloseCode::Away, "Server shutting down").into()))
+                    .await;
+                return;
+            }
+        };
+
+        match msg {
+            WsMessage::Text(text) => {
+                if session.text(text).await.is_err() {
+                    return;
+                }
+            }
+            WsMessage::Close(code, reason) => {
+                let _ = session
+                    .close(Some(CloseReason {
+                        code: CloseCode::from(code),
+                        description: Some(reason),
+                    }))
+                    .await;
+                return;
+            }
+        }
+    }
+
+    let _ = session.close(None).await;
+}
+
+/// Filters of the workload, matching the query parameters of the REST API
+#[derive(Clone, Debug, Default, InputObject)]
+pub struct WorkloadFilterInput {
+    /// Only images used in these namespaces, along with only their pods
+    namespaces: Option<Vec<String>>,
+    /// Only images with this state of the SBOM lookup
+    sbom: Option<SbomFilter>,
+    /// Only images from this registry
+    registry: Option<String>,
+    /// Only images whose reference contains this text
+    q: Option<String>,
+}
+
+impl From<WorkloadFilterInput> for WorkloadFilter {
+    fn from(filter: WorkloadFilterInput) -> Self {
+        Self {
+            namespace: filter
+                .namespaces
+                .filter(|namespaces| !namespaces.is_empty())
+                .map(|namespaces| namespaces.join(",")),
+            sbom: filter.sbom,
+            registry: filter.registry,
+            q: filter.q,
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The images of the workload, ordered by their reference
+    async fn images(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<WorkloadFilterInput>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<Image> {
+        let filter = filter.unwrap_or_default().into();
+        let query = WorkloadQuery {
+            offset,
+            limit,
+            ..Default::default()
+        };
+        let map = ctx.data_unchecked::<WorkloadState>();
+
+        query
+            .apply(&filter, map.get_state().await)
+            .items
+            .into_iter()
+            .map(|(image, state)| Image(image, state))
+            .collect()
+    }
+
+    /// A single image, by its reference
+    async fn image(&self, ctx: &Context<'_>, reference: String) -> Option<Image> {
+        let image = reference.parse::<ImageRef>().ok()?;
+        let map = ctx.data_unchecked::<WorkloadState>();
+        let state = map.get_state().await.remove(&image)?;
+        Some(Image(image, state))
+    }
+
+    /// The namespaces with pods, ordered by their name
+    async fn namespaces(&self, ctx: &Context<'_>) -> Vec<Namespace> {
+        let map = ctx.data_unchecked::<WorkloadState>();
+
+        let mut namespaces = BTreeMap::<String, Namespace>::new();
+        for (image, state) in map.get_state().await {
+            for pod in &state.pods {
+                let namespace =
+                    namespaces
+                        .entry(pod.namespace.clone())
+                        .or_insert_with(|| Namespace {
+                            name: pod.namespace.clone(),
+                            images: BTreeMap::new(),
+                            pods: BTreeSet::new(),
+                        });
+                namespace.pods.insert(pod.clone());
+                namespace
+                    .images
+                    .entry(image.to_string())
+                    .or_insert_with(|| Image(image.clone(), state.clone()));
+            }
+        }
+
+        namespaces.into_values().collect()
+    }
+
+    /// The pods, ordered by their namespace and name
+    async fn pods(&self, ctx: &Context<'_>, namespace: Option<String>) -> Vec<Pod> {
+        let map = ctx.data_unchecked::<WorkloadState>();
+
+        map.get_state()
+            .await
+            .values()
+            .flat_map(|state| state.pods.iter().cloned())
+            .filter(|pod| namespace.as_ref().is_none_or(|ns| &pod.namespace == ns))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(Pod)
+            .collect()
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Changes to the workload, starting with a full snapshot (`RESTART`)
+    ///
+    /// When providing the last revision received (`since`), only the changes since then are
+    /// sent, as long as those are still known.
+    async fn workload(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<WorkloadFilterInput>,
+        since: Option<u64>,
+    ) -> impl Stream<Item = WorkloadEvent> {
+        let filter = WorkloadFilter::from(filter.unwrap_or_default());
+        let map = ctx.data_unchecked::<WorkloadState>();
+        let subscription = map
+            .subscribe_with(
+                "graphql",
+                ctx.data_unchecked::<SubscriberBuffer>().0,
+                SubscribeOptions {
+                    policy: *ctx.data_unchecked::<SlowSubscriber>(),
+                    filter: filter.into_subscription_filter(),
+                    since,
+                },
+            )
+            .await;
+
+        stream::unfold(subscription, |mut subscription| async move {
+            let (revision, event) = subscription.recv_revision().await?;
+            Some((WorkloadEvent(revision, event), subscription))
+        })
+    }
+}
+
+/// The type of a change to the workload
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+enum EventKind {
+    Added,
+    Modified,
+    Removed,
+    Restart,
+}
+
+struct WorkloadEvent(u64, Arc<Event<ImageRef, data::Image>>);
+
+/// A change to the workload
+#[Object]
+impl WorkloadEvent {
+    /// The revision of the state the event leads to
+    async fn revision(&self) -> u64 {
+        self.0
+    }
+
+    async fn kind(&self) -> EventKind {
+        match &*self.1 {
+            Event::Added(..) => EventKind::Added,
+            Event::Modified(..) => EventKind::Modified,
+            Event::Removed(_) => EventKind::Removed,
+            Event::Restart(_) => EventKind::Restart,
+        }
+    }
+
+    /// The reference of the image added, modified, or removed
+    async fn reference(&self) -> Option<String> {
+        match &*self.1 {
+            Event::Added(image, _) | Event::Modified(image, _) | Event::Removed(image) => {
+                Some(image.to_string())
+            }
+            Event::Restart(_) => None,
+        }
+    }
+
+    /// The image added or modified
+    async fn image(&self) -> Option<Image> {
+        match &*self.1 {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                Some(Image(image.clone(), state.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// All images, when restarting
+    async fn images(&self) -> Option<Vec<Image>> {
+        match &*self.1 {
+            Event::Restart(state) => {
+                let mut images = state
+                    .iter()
+                    .map(|(image, state)| Image(image.clone(), state.clone()))
+                    .collect::<Vec<_>>();
+                images.sort_by_cached_key(|image| image.0.to_string());
+                Some(images)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A namespace with pods
+struct Namespace {
+    name: String,
+    images: BTreeMap<String, Image>,
+    pods: BTreeSet<data::PodRef>,
+}
+
+#[Object]
+impl Namespace {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The images used in the namespace, listing all of their pods
+    async fn images(&self) -> Vec<&Image> {
+        self.images.values().collect()
+    }
+
+    async fn pods(&self) -> Vec<Pod> {
+        self.pods.iter().cloned().map(Pod).collect()
+    }
+}
+
+/// The state of the SBOM lookup
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+enum SbomStateKind {
+    Scheduled,
+    Err,
+    Missing,
+    Found,
+}
+
+struct Image(ImageRef, data::Image);
+
+/// An image, along with the pods using it and the state of its SBOM
+#[Object]
+impl Image {
+    /// The full image reference
+    async fn reference(&self) -> String {
+        self.0.to_string()
+    }
+
+    async fn registry(&self) -> &str {
+        &self.0.registry
+    }
+
+    async fn repository(&self) -> &str {
+        &self.0.repository
+    }
+
+    async fn tag(&self) -> Option<&str> {
+        self.0.tag.as_deref()
+    }
+
+    async fn digest(&self) -> Option<&str> {
+        self.0.digest.as_deref()
+    }
+
+    async fn arch(&self) -> Option<&str> {
+        self.0.arch.as_deref()
+    }
+
+    async fn pods(&self) -> Vec<Pod> {
+        let mut pods = self.1.pods.iter().cloned().collect::<Vec<_>>();
+        pods.sort_unstable();
+        pods.into_iter().map(Pod).collect()
+    }
+
+    /// The image is only declared in the spec of the pods, but none of their containers runs it
+    async fn declared(&self) -> bool {
+        self.1.declared
+    }
+
+    /// The containers using the image, by name, kind, and namespace
+    async fn containers(&self) -> Vec<ContainerUsage<'_>> {
+        self.1.containers.iter().map(ContainerUsage).collect()
+    }
+
+    /// Batch workloads using the image in their pod template, if tracked
+    async fn jobs(&self) -> Vec<Job<'_>> {
+        let mut jobs = self.1.jobs.iter().collect::<Vec<_>>();
+        jobs.sort_unstable();
+        jobs.into_iter().map(Job).collect()
+    }
+
+    /// Nodes having the image present, if tracked
+    async fn nodes(&self) -> Vec<Node<'_>> {
+        let mut nodes = self.1.nodes.iter().collect::<Vec<_>>();
+        nodes.sort_unstable();
+        nodes.into_iter().map(Node).collect()
+    }
+
+    /// The package URL the SBOM and vulnerabilities are looked up by, if one can be created
+    async fn purl(&self) -> Option<&str> {
+        self.1.purl.as_deref()
+    }
+
+    async fn sbom_state(&self) -> SbomStateKind {
+        match &self.1.sbom {
+            data::SbomState::Scheduled => SbomStateKind::Scheduled,
+            data::SbomState::Err(_) => SbomStateKind::Err,
+            data::SbomState::Missing => SbomStateKind::Missing,
+            data::SbomState::Found(_) => SbomStateKind::Found,
+        }
+    }
+
+    /// The summary of the SBOM, if one was found
+    async fn sbom(&self) -> Option<SbomSummary<'_>> {
+        match &self.1.sbom {
+            data::SbomState::Found(summary) => Some(SbomSummary(summary)),
+            _ => None,
+        }
+    }
+
+    /// The error looking up the SBOM, if the lookup failed
+    async fn error(&self) -> Option<&str> {
+        match &self.1.sbom {
+            data::SbomState::Err(err) => Some(&err.message),
+            _ => None,
+        }
+    }
+
+    /// Number of failed attempts to retrieve the SBOM
+    async fn retry_attempts(&self) -> Option<u32> {
+        self.1.retry.as_ref().map(|retry| retry.attempts)
+    }
+
+    /// Time the next attempt to retrieve the SBOM is due
+    async fn retry_next(&self) -> Option<DateTime<Utc>> {
+        self.1.retry.as_ref().map(|retry| retry.next)
+    }
+
+    async fn vulnerabilities(&self) -> Option<Vulnerabilities<'_>> {
+        self.1.vulnerabilities.as_ref().map(Vulnerabilities)
+    }
+}
+
+struct Pod(data::PodRef);
+
+/// A pod using an image
+#[Object]
+impl Pod {
+    /// The cluster the pod is located in, when watching multiple clusters
+    async fn cluster(&self) -> Option<&str> {
+        self.0.cluster.as_deref()
+    }
+
+    async fn namespace(&self) -> &str {
+        &self.0.namespace
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The node the pod is scheduled on, if tracked
+    async fn node(&self) -> Option<&str> {
+        self.0.node.as_deref()
+    }
+
+    /// The kind of the top-level workload controlling the pod, e.g. `Deployment`
+    async fn workload_kind(&self) -> Option<&str> {
+        self.0.workload.as_ref().map(|w| w.kind.as_str())
+    }
+
+    /// The name of the top-level workload controlling the pod
+    async fn workload_name(&self) -> Option<&str> {
+        self.0.workload.as_ref().map(|w| w.name.as_str())
+    }
+}
+
+struct Job<'a>(&'a data::JobRef);
+
+/// A batch workload, using an image in its pod template
+#[Object]
+impl Job<'_> {
+    /// The cluster the job is located in, when watching multiple clusters
+    async fn cluster(&self) -> Option<&str> {
+        self.0.cluster.as_deref()
+    }
+
+    /// The kind, `Job` or `CronJob`
+    async fn kind(&self) -> &str {
+        &self.0.kind
+    }
+
+    async fn namespace(&self) -> &str {
+        &self.0.namespace
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
+struct Node<'a>(&'a data::NodeRef);
+
+/// A node, having an image present
+#[Object]
+impl Node<'_> {
+    /// The cluster the node belongs to, when watching multiple clusters
+    async fn cluster(&self) -> Option<&str> {
+        self.0.cluster.as_deref()
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
+/// The kind of a container in a pod
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+enum ContainerKind {
+    Container,
+    Init,
+    Ephemeral,
+}
+
+struct ContainerUsage<'a>(&'a data::ContainerUsage);
+
+/// Containers using an image, which share the same name and kind, in the pods of a namespace
+#[Object]
+impl ContainerUsage<'_> {
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn kind(&self) -> ContainerKind {
+        match self.0.kind {
+            data::ContainerKind::Container => ContainerKind::Container,
+            data::ContainerKind::Init => ContainerKind::Init,
+            data::ContainerKind::Ephemeral => ContainerKind::Ephemeral,
+        }
+    }
+
+    async fn namespace(&self) -> &str {
+        &self.0.namespace
+    }
+
+    /// Number of pods having such a container
+    async fn pods(&self) -> usize {
+        self.0.pods
+    }
+
+    /// Any of the containers runs the image, rather than only declaring it
+    async fn running(&self) -> bool {
+        self.0.running
+    }
+}
+
+struct SbomSummary<'a>(&'a data::SbomSummary);
+
+/// Summary of an SBOM, independent of its format
+#[Object]
+impl SbomSummary<'_> {
+    async fn format(&self) -> String {
+        self.0.format.to_string()
+    }
+
+    async fn version(&self) -> Option<&str> {
+        self.0.version.as_deref()
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    /// Number of packages (components) contained in the SBOM
+    async fn packages(&self) -> usize {
+        self.0.packages
+    }
+
+    /// Licenses of the top-level packages
+    async fn licenses(&self) -> &[String] {
+        &self.0.licenses
+    }
+
+    /// Tools which created the SBOM
+    async fn tools(&self) -> &[String] {
+        &self.0.tools
+    }
+
+    async fn created(&self) -> Option<DateTime<Utc>> {
+        self.0.created
+    }
+
+    /// The package URL the SBOM was found by
+    async fn purl(&self) -> Option<&str> {
+        self.0.purl.as_deref()
+    }
+
+    /// The instance of the SBOM source the SBOM was found at
+    async fn source(&self) -> Option<&str> {
+        self.0.source.as_deref()
+    }
+
+    /// The digest of the document itself
+    async fn digest(&self) -> Option<&str> {
+        self.0.digest.as_deref()
+    }
+
+    /// Digests of the components the document describes
+    async fn subjects(&self) -> &[String] {
+        &self.0.subjects
+    }
+
+    /// One of the described components has the digest of the image
+    async fn verified(&self) -> bool {
+        self.0.verified
+    }
+
+    /// Identifiers of the licenses of all packages
+    async fn license_ids(&self) -> &[String] {
+        &self.0.license_ids
+    }
+
+    /// The quality of the SBOM, if the source provides the document
+    async fn quality(&self) -> Option<SbomQuality<'_>> {
+        self.0.quality.as_deref().map(SbomQuality)
+    }
+}
+
+struct SbomQuality<'a>(&'a data::SbomQuality);
+
+/// The quality of an SBOM, checking for the NTIA minimum elements
+#[Object]
+impl SbomQuality<'_> {
+    /// Overall score, from 0 to 100
+    async fn score(&self) -> u8 {
+        self.0.score
+    }
+
+    async fn grade(&self) -> String {
+        self.0.grade.to_string()
+    }
+
+    async fn checks(&self) -> Vec<QualityCheck<'_>> {
+        self.0.checks.iter().map(QualityCheck).collect()
+    }
+}
+
+struct QualityCheck<'a>(&'a data::QualityCheck);
+
+/// The outcome of checking an SBOM for one of the minimum elements
+#[Object]
+impl QualityCheck<'_> {
+    async fn criterion(&self) -> String {
+        self.0.criterion.to_string()
+    }
+
+    /// Share of the packages meeting the criterion, from 0 to 100
+    async fn score(&self) -> u8 {
+        self.0.score
+    }
+}
+
+struct Vulnerabilities<'a>(&'a data::Vulnerabilities);
+
+/// Number of vulnerabilities, by severity
+#[Object]
+impl Vulnerabilities<'_> {
+    async fn critical(&self) -> usize {
+        self.0.critical
+    }
+
+    async fn high(&self) -> usize {
+        self.0.high
+    }
+
+    async fn medium(&self) -> usize {
+        self.0.medium
+    }
+
+    async fn low(&self) -> usize {
+        self.0.low
+    }
+
+    /// Vulnerabilities without a score
+    async fn unknown(&self) -> usize {
+        self.0.unknown
+    }
+
+    async fn total(&self) -> usize {
+        self.0.total()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn execute(query: String) -> async_graphql::Response {
+        schema(WorkloadState::new(16), SlowSubscriber::Wait, 16)
+            .execute(query)
+            .await
+    }
+
+    #[tokio::test]
+    async fn query() {
+        let response =
+            execute("{ images { reference pods { name } sbom { format } } }".into()).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn introspection() {
+        // as nested as the type references of the introspection query of GraphiQL
+        let query = format!(
+            "{{ __schema {{ types {{ name fields {{ name type {{ {}name{} }} }} }} }} }}",
+            "ofType { ".repeat(7),
+            " }".repeat(7)
+        );
+        let response = execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn too_deep() {
+        let query = format!(
+            "{{ __schema {{ types {{ {}name{} }} }} }}",
+            "ofType { ".repeat(MAX_DEPTH),
+            " }".repeat(MAX_DEPTH)
+        );
+        let response = execute(query).await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "Query is nested too deep.");
+    }
+
+    #[tokio::test]
+    async fn too_complex() {
+        let aliases = (0..=MAX_COMPLEXITY / 2)
+            .map(|n| format!("i{n}: images {{ reference }}"))
+            .collect::<Vec<_>>();
+        let response = execute(format!("{{ {} }}", aliases.join(" "))).await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "Query is too complex.");
+    }
+}
@@ -0,0 +1,389 @@
+//! gRPC API, mirroring the REST and websocket API
+
+use super::auth::{AuthError, Authenticator};
+use super::limit::Subscribers;
+use super::query::{self, WorkloadQuery};
+use crate::pubsub::{SlowSubscriber, SubscribeOptions};
+use crate::workload::WorkloadState;
+use anyhow::Context;
+use bommer_api::data::{self, Event, ImageRef};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::{Identity, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+// generated code, with a summary of the SBOM being much larger than the other states
+#[allow(clippy::large_enum_variant)]
+mod proto {
+    tonic::include_proto!("bommer.v1");
+}
+
+use proto::workload_service_server::{WorkloadService, WorkloadServiceServer};
+
+/// Settings of the gRPC API
+pub struct GrpcSettings {
+    pub addr: SocketAddr,
+    /// certificate and key, serving the API using TLS, as callers send their bearer tokens
+    pub tls: Option<(PathBuf, PathBuf)>,
+    pub slow_subscriber: SlowSubscriber,
+    /// Events buffered for each watch stream
+    pub buffer: usize,
+}
+
+/// serve the gRPC API, until the server fails or the shutdown token gets cancelled
+///
+/// Unlike for the HTTP server, the certificate and key are only loaded on startup.
+pub async fn run(
+    settings: GrpcSettings,
+    map: WorkloadState,
+    authenticator: Authenticator,
+    subscribers: Subscribers,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let GrpcSettings {
+        addr,
+        tls,
+        slow_subscriber,
+        buffer,
+    } = settings;
+    info!("Binding gRPC API to {addr}");
+
+    let mut server = tonic::transport::Server::builder();
+    if let Some((cert, key)) = tls {
+        let cert = std::fs::read(&cert)
+            .with_context(|| format!("Failed to read certificate: {}", cert.display()))?;
+        let key = std::fs::read(&key)
+            .with_context(|| format!("Failed to read key: {}", key.display()))?;
+        server = server
+            .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+            .context("Failed to configure TLS of the gRPC API")?;
+    }
+
+    server
+        .add_service(WorkloadServiceServer::new(Service {
+            map,
+            authenticator,
+            slow_subscriber,
+            buffer,
+            subscribers,
+            shutdown: shutdown.clone(),
+        }))
+        .serve_with_shutdown(addr, shutdown.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+struct Service {
+    map: WorkloadState,
+    authenticator: Authenticator,
+    slow_subscriber: SlowSubscriber,
+    /// Events buffered for each watch stream
+    buffer: usize,
+    /// Limits the number of concurrent watch streams
+    subscribers: Subscribers,
+    /// ends the watch streams, so that shutting down doesn't wait for them
+    shutdown: CancellationToken,
+}
+
+impl Service {
+    /// authenticate the caller, and authorize it for the path of the method (e.g. using RBAC)
+    async fn authenticate<T>(&self, request: &Request<T>, path: &str) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+
+        let result = match self.authenticator.authenticate(token).await {
+            Ok(identity) => self.authenticator.authorize(&identity, path, "get").await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(err @ (AuthError::Keys(_) | AuthError::Review(_))) => {
+                Err(Status::unavailable(err.to_string()))
+            }
+            Err(err @ AuthError::Forbidden) => Err(Status::permission_denied(err.to_string())),
+            Err(err) => Err(Status::unauthenticated(err.to_string())),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl WorkloadService for Service {
+    async fn get_workload(
+        &self,
+        request: Request<proto::GetWorkloadRequest>,
+    ) -> Result<Response<proto::GetWorkloadResponse>, Status> {
+        self.authenticate(&request, "/bommer.v1.WorkloadService/GetWorkload")
+            .await?;
+
+        let filter = request.into_inner().filter.unwrap_or_default().into();
+        let page = WorkloadQuery::default().apply(&filter, self.map.get_state().await);
+
+        Ok(Response::new(proto::GetWorkloadResponse {
+            images: page
+                .items
+                .into_iter()
+                .map(|(image, state)| entry(image, state))
+                .collect(),
+        }))
+    }
+
+    type WatchWorkloadStream = BoxStream<'static, Result<proto::WorkloadEvent, Status>>;
+
+    async fn watch_workload(
+        &self,
+        request: Request<proto::WatchWorkloadRequest>,
+    ) -> Result<Response<Self::WatchWorkloadStream>, Status> {
+        self.authenticate(&request, "/bommer.v1.WorkloadService/WatchWorkload")
+            .await?;
+
+        let permit = self
+            .subscribers
+            .acquire()
+            .ok_or_else(|| Status::resource_exhausted("Too many subscribers"))?;
+
+        let request = request.into_inner();
+        let filter = query::WorkloadFilter::from(request.filter.unwrap_or_default());
+        let subscription = self
+            .map
+            .subscribe_with(
+                "grpc",
+                self.buffer,
+                SubscribeOptions {
+                    policy: self.slow_subscriber,
+                    filter: filter.into_subscription_filter(),
+                    since: request.since,
+                },
+            )
+            .await;
+
+        // the permit is released along with the stream
+        let events = stream::unfold(
+            (subscription, permit),
+            |(mut subscription, permit)| async move {
+                let (revision, event) = subscription.recv_revision().await?;
+                Some((
+                    Ok(to_event(revision, Arc::unwrap_or_clone(event))),
+                    (subscription, permit),
+                ))
+            },
+        );
+
+        Ok(Response::new(
+            events
+                .take_until(self.shutdown.clone().cancelled_owned())
+                .boxed(),
+        ))
+    }
+}
+
+impl From<proto::WorkloadFilter> for query::WorkloadFilter {
+    fn from(filter: proto::WorkloadFilter) -> Self {
+        let sbom = filter
+            .sbom
+            .and_then(proto::SbomFilter::from_i32)
+            .and_then(|sbom| match sbom {
+                proto::SbomFilter::Unspecified => None,
+                proto::SbomFilter::Scheduled => Some(query::SbomFilter::Scheduled),
+                proto::SbomFilter::Err => Some(query::SbomFilter::Err),
+                proto::SbomFilter::Missing => Some(query::SbomFilter::Missing),
+                proto::SbomFilter::Found => Some(query::SbomFilter::Found),
+            });
+
+        Self {
+            namespace: (!filter.namespaces.is_empty()).then(|| filter.namespaces.join(",")),
+            sbom,
+            registry: filter.registry,
+            q: filter.q,
+        }
+    }
+}
+
+fn to_event(revision: u64, event: Event<ImageRef, data::Image>) -> proto::WorkloadEvent {
+    use proto::workload_event::Event as E;
+
+    let event = match event {
+        Event::Added(image, state) => E::Added(entry(image, state)),
+        Event::Modified(image, state) => E::Modified(entry(image, state)),
+        Event::Removed(image) => E::Removed(image.to_string()),
+        Event::Restart(state) => {
+            let mut images = state.into_iter().collect::<Vec<_>>();
+            images.sort_by_cached_key(|(image, _)| image.to_string());
+            E::Restart(proto::Snapshot {
+                images: images
+                    .into_iter()
+                    .map(|(image, state)| entry(image, state))
+                    .collect(),
+            })
+        }
+    };
+
+    proto::WorkloadEvent {
+        revision,
+        event: Some(event),
+    }
+}
+
+fn entry(image: ImageRef, state: data::Image) -> proto::ImageEntry {
+    let mut pods = state.pods.into_iter().collect::<Vec<_>>();
+    pods.sort_unstable();
+    let mut jobs = state.jobs.into_iter().collect::<Vec<_>>();
+    jobs.sort_unstable();
+    let mut nodes = state.nodes.into_iter().collect::<Vec<_>>();
+    nodes.sort_unstable();
+
+    proto::ImageEntry {
+        image: image.to_string(),
+        state: Some(proto::Image {
+            pods: pods.into_iter().map(pod).collect(),
+            declared: state.declared,
+            containers: state
+                .containers
+                .into_iter()
+                .map(|container| proto::ContainerUsage {
+                    name: container.name,
+                    kind: match container.kind {
+                        data::ContainerKind::Container => proto::ContainerKind::Container,
+                        data::ContainerKind::Init => proto::ContainerKind::Init,
+                        data::ContainerKind::Ephemeral => proto::ContainerKind::Ephemeral,
+                    } as i32,
+                    pods: container.pods as u64,
+                    namespace: container.namespace,
+                    running: container.running,
+                })
+                .collect(),
+            jobs: jobs
+                .into_iter()
+                .map(|job| proto::JobRef {
+                    cluster: job.cluster,
+                    kind: job.kind,
+                    namespace: job.namespace,
+                    name: job.name,
+                })
+                .collect(),
+            nodes: nodes
+                .into_iter()
+                .map(|node| proto::NodeRef {
+                    cluster: node.cluster,
+                    name: node.name,
+                })
+                .collect(),
+            purl: state.purl,
+            sbom: Some(sbom_state(state.sbom)),
+            retry: state.retry.map(|retry| proto::RetryState {
+                attempts: retry.attempts,
+                next: Some(timestamp(retry.next)),
+            }),
+            vulnerabilities: state.vulnerabilities.map(|v| proto::Vulnerabilities {
+                critical: v.critical as u64,
+                high: v.high as u64,
+                medium: v.medium as u64,
+                low: v.low as u64,
+                unknown: v.unknown as u64,
+            }),
+        }),
+    }
+}
+
+fn pod(pod: data::PodRef) -> proto::PodRef {
+    proto::PodRef {
+        cluster: pod.cluster,
+        namespace: pod.namespace,
+        name: pod.name,
+        node: pod.node,
+        workload: pod.workload.map(|workload| proto::WorkloadRef {
+            kind: workload.kind,
+            namespace: workload.namespace,
+            name: workload.name,
+        }),
+    }
+}
+
+fn sbom_state(state: data::SbomState) -> proto::SbomState {
+    use proto::sbom_state::State;
+
+    let state = match state {
+        data::SbomState::Scheduled => State::Scheduled(proto::Empty {}),
+        data::SbomState::Missing => State::Missing(proto::Empty {}),
+        data::SbomState::Err(err) => State::Err(proto::LookupError {
+            kind: match err.kind {
+                data::LookupErrorKind::Transport => proto::LookupErrorKind::Transport,
+                data::LookupErrorKind::Server => proto::LookupErrorKind::Server,
+                data::LookupErrorKind::UnexpectedStatus => proto::LookupErrorKind::UnexpectedStatus,
+                data::LookupErrorKind::InvalidReference => proto::LookupErrorKind::InvalidReference,
+                data::LookupErrorKind::InvalidSbom => proto::LookupErrorKind::InvalidSbom,
+                data::LookupErrorKind::TooLarge => proto::LookupErrorKind::TooLarge,
+            } as i32,
+            message: err.message,
+            status: err.status.map(u32::from),
+        }),
+        data::SbomState::Found(summary) => State::Found(proto::SbomSummary {
+            format: match summary.format {
+                data::SbomFormat::Spdx => proto::SbomFormat::Spdx,
+                data::SbomFormat::CycloneDx => proto::SbomFormat::CycloneDx,
+                data::SbomFormat::Unknown => proto::SbomFormat::Unknown,
+            } as i32,
+            version: summary.version,
+            name: summary.name,
+            packages: summary.packages as u64,
+            licenses: summary.licenses,
+            tools: summary.tools,
+            created: summary.created.map(timestamp),
+            purl: summary.purl,
+            source: summary.source,
+            digest: summary.digest,
+            subjects: summary.subjects,
+            verified: summary.verified,
+            license_ids: summary.license_ids,
+            quality: summary.quality.map(|q| quality(*q)),
+        }),
+    };
+
+    proto::SbomState { state: Some(state) }
+}
+
+fn quality(quality: data::SbomQuality) -> proto::SbomQuality {
+    proto::SbomQuality {
+        score: quality.score.into(),
+        grade: match quality.grade {
+            data::QualityGrade::A => proto::QualityGrade::A,
+            data::QualityGrade::B => proto::QualityGrade::B,
+            data::QualityGrade::C => proto::QualityGrade::C,
+            data::QualityGrade::D => proto::QualityGrade::D,
+            data::QualityGrade::F => proto::QualityGrade::F,
+        } as i32,
+        checks: quality
+            .checks
+            .into_iter()
+            .map(|check| proto::QualityCheck {
+                criterion: match check.criterion {
+                    data::QualityCriterion::Supplier => proto::QualityCriterion::Supplier,
+                    data::QualityCriterion::Version => proto::QualityCriterion::Version,
+                    data::QualityCriterion::Identifier => proto::QualityCriterion::Identifier,
+                    data::QualityCriterion::Dependencies => proto::QualityCriterion::Dependencies,
+                    data::QualityCriterion::Author => proto::QualityCriterion::Author,
+                    data::QualityCriterion::Timestamp => proto::QualityCriterion::Timestamp,
+                } as i32,
+                score: check.score.into(),
+            })
+            .collect(),
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
@@ -0,0 +1,45 @@
+use crate::scanner::CircuitBreaker;
+use crate::store::SyncState;
+use actix_web::{get, web, HttpResponse, Responder};
+
+/// Check if the process is alive
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "The process is alive"))
+)]
+#[get("/health/live")]
+pub async fn live() -> impl Responder {
+    HttpResponse::Ok().finish()
+}
+
+/// Check if the instance is ready, which it is once it processed the initial list of pods
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The instance is ready"),
+        (status = 503, description = "The initial sync is still pending"),
+    )
+)]
+#[get("/health/ready")]
+pub async fn ready(sync: web::Data<SyncState>) -> impl Responder {
+    match sync.is_synced() {
+        true => HttpResponse::Ok().finish(),
+        false => HttpResponse::ServiceUnavailable().body("Initial sync pending"),
+    }
+}
+
+/// Check if the SBOM source is available, or if scanning is paused by the circuit breaker
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The SBOM source is available"),
+        (status = 503, description = "The SBOM source is unavailable, scanning is paused"),
+    )
+)]
+#[get("/health/source")]
+pub async fn source(breaker: web::Data<CircuitBreaker>) -> impl Responder {
+    match breaker.is_open() {
+        false => HttpResponse::Ok().finish(),
+        true => HttpResponse::ServiceUnavailable().body("SBOM source unavailable, scanning paused"),
+    }
+}
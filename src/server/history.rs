@@ -0,0 +1,39 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use crate::scanner::ScanLog;
+use crate::workload::WorkloadState;
+use actix_web::{get, web, HttpResponse};
+use bommer_api::data::ImageRef;
+
+/// Get the recent SBOM lookups of an image
+///
+/// Reports the outcome of each lookup, including the error if it failed, along with the time an
+/// SBOM was last found. The image reference is part of the path (e.g.
+/// `/api/v1/images/quay.io/app/server@sha256:…/history`), and must be part of the current workload.
+#[utoipa::path(
+    tag = "sbom",
+    params(
+        ("image" = String, Path, description = "The reference of the image, as reported by the workload"),
+    ),
+    responses(
+        (status = 200, description = "The history of the image", body = ScanHistory),
+        (status = 400, description = "Invalid image reference"),
+        (status = 404, description = "The image isn't part of the workload"),
+    )
+)]
+#[get("/api/v1/images/{image:.+}/history")]
+pub async fn get_history(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    history: web::Data<ScanLog>,
+    image: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let image: ImageRef = image.parse()?;
+
+    if !map.get_state().await.contains_key(&image) {
+        return Err(ApiError::ImageNotFound);
+    }
+
+    // scheduled images might not have been looked up yet
+    Ok(HttpResponse::Ok().json(history.get(&image).unwrap_or_default()))
+}
@@ -0,0 +1,115 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use super::query::{LicensesQuery, WorkloadFilter};
+use crate::license::LicenseConfig;
+use crate::workload::WorkloadState;
+use actix_web::{get, web, HttpResponse};
+use bommer_api::data::{ImageLicense, ImageRef, LicenseUsage, SbomState};
+use std::collections::BTreeMap;
+
+/// Get the licenses used by the images of the workload
+///
+/// Aggregates the licenses of the packages contained in the SBOMs found, listing the images using
+/// each license, and the namespaces they are used in. Licenses configured as forbidden are
+/// flagged. The same filters as for getting the workload can be applied.
+#[utoipa::path(
+    tag = "sbom",
+    params(WorkloadFilter, LicensesQuery),
+    responses(
+        (status = 200, description = "The licenses, sorted by their identifier", body = Vec<LicenseUsage>),
+    )
+)]
+#[get("/api/v1/licenses")]
+pub async fn get_licenses(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    config: web::Data<LicenseConfig>,
+    filter: web::Query<WorkloadFilter>,
+    query: web::Query<LicensesQuery>,
+) -> HttpResponse {
+    let mut licenses = BTreeMap::<String, LicenseUsage>::new();
+
+    for (image, state) in map.get_state().await {
+        let state = match filter.apply(&image, state) {
+            Some(state) => state,
+            None => continue,
+        };
+        let summary = match &state.sbom {
+            SbomState::Found(summary) => summary,
+            _ => continue,
+        };
+
+        for license in &summary.license_ids {
+            let forbidden = config.is_forbidden(license);
+            if query.forbidden && !forbidden {
+                continue;
+            }
+
+            let usage = licenses
+                .entry(license.clone())
+                .or_insert_with(|| LicenseUsage {
+                    license: license.clone(),
+                    forbidden,
+                    images: vec![],
+                    namespaces: Default::default(),
+                });
+            usage.images.push(image.clone());
+            usage
+                .namespaces
+                .extend(state.pods.iter().map(|pod| pod.namespace.clone()));
+        }
+    }
+
+    let licenses = licenses
+        .into_values()
+        .map(|mut usage| {
+            usage.images.sort_unstable();
+            usage
+        })
+        .collect::<Vec<_>>();
+
+    HttpResponse::Ok().json(licenses)
+}
+
+/// Get the licenses used by an image
+///
+/// Lists the licenses of the packages contained in the SBOM of the image, flagging the ones
+/// configured as forbidden. Images without an SBOM have no licenses.
+#[utoipa::path(
+    tag = "sbom",
+    params(
+        ("image" = String, Path, description = "The reference of the image, as reported by the workload"),
+    ),
+    responses(
+        (status = 200, description = "The licenses of the image", body = Vec<ImageLicense>),
+        (status = 400, description = "Invalid image reference"),
+        (status = 404, description = "The image isn't part of the workload"),
+    )
+)]
+#[get("/api/v1/images/{image:.+}/licenses")]
+pub async fn get_image_licenses(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    config: web::Data<LicenseConfig>,
+    image: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let image: ImageRef = image.parse()?;
+
+    let licenses = match map.get_state().await.remove(&image) {
+        Some(state) => match state.sbom {
+            SbomState::Found(summary) => summary.license_ids,
+            _ => vec![],
+        },
+        None => return Err(ApiError::ImageNotFound),
+    };
+
+    let licenses = licenses
+        .into_iter()
+        .map(|license| ImageLicense {
+            forbidden: config.is_forbidden(&license),
+            license,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(licenses))
+}
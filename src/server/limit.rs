@@ -0,0 +1,298 @@
+use super::error::ApiError;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::{HttpResponse, ResponseError};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// number of tracked clients, after which clients which didn't use up any requests get dropped,
+/// or else the least recently seen one
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Limits")]
+pub struct LimitConfig {
+    /// Requests per second allowed for each client (by its address), not limited if not provided
+    #[arg(long, env = "RATE_LIMIT")]
+    pub rate_limit: Option<u32>,
+
+    /// Requests a client may send at once, before being limited to the rate
+    #[arg(
+        long,
+        env = "RATE_LIMIT_BURST",
+        default_value_t = 20,
+        requires = "rate_limit"
+    )]
+    pub rate_limit_burst: u32,
+
+    /// Header carrying the address of the client, set by a trusted proxy in front of the server, e.g. `X-Forwarded-For`
+    #[arg(long, env = "RATE_LIMIT_CLIENT_HEADER", value_parser = parse_header, requires = "rate_limit")]
+    pub rate_limit_client_header: Option<HeaderName>,
+
+    /// Maximum number of concurrent subscribers (websocket and gRPC streams), not limited if not provided
+    #[arg(long, env = "MAX_SUBSCRIBERS")]
+    pub max_subscribers: Option<usize>,
+}
+
+fn parse_header(value: &str) -> Result<HeaderName, String> {
+    value
+        .parse()
+        .map_err(|err| format!("Invalid header name: {err}"))
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    /// requests per second
+    rate: f64,
+    burst: f64,
+    clients: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl Buckets {
+    /// take a request from the bucket of a client, or return the time until one is available
+    fn take(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock();
+
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&client) {
+            clients.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+            // all clients are busy, still keep the number of tracked ones bounded
+            if clients.len() >= MAX_CLIENTS {
+                let oldest = clients
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(client, _)| *client);
+                if let Some(oldest) = oldest {
+                    clients.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = clients.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        match bucket.tokens >= 1.0 {
+            true => {
+                bucket.tokens -= 1.0;
+                Ok(())
+            }
+            false => Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)),
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// the address of the client of a request, `None` if it isn't known
+///
+/// Using the client header, the last address is the one the trusted proxy added. Requests
+/// without the header are taken as ones which didn't pass the proxy.
+pub fn client(req: &ServiceRequest, client_header: Option<&HeaderName>) -> Option<IpAddr> {
+    let forwarded = client_header.and_then(|header| {
+        req.headers()
+            .get(header)?
+            .to_str()
+            .ok()?
+            .rsplit(',')
+            .next()?
+            .trim()
+            .parse()
+            .ok()
+    });
+    forwarded.or_else(|| Some(req.peer_addr()?.ip()))
+}
+
+/// Limits the requests of each client, using a token bucket per client address
+///
+/// Health checks and metrics are not limited, so that probes and scrapes don't fail. Requests
+/// without a known client address (on a Unix socket, unless a client header is configured) are
+/// not limited either.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Option<Arc<Buckets>>,
+    /// header to take the client address from, rather than the peer address
+    client_header: Option<HeaderName>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &LimitConfig) -> Self {
+        Self {
+            buckets: config.rate_limit.map(|rate| {
+                Arc::new(Buckets {
+                    rate: rate.max(1) as f64,
+                    burst: config.rate_limit_burst.max(1) as f64,
+                    clients: Default::default(),
+                })
+            }),
+            client_header: config.rate_limit_client_header.clone(),
+        }
+    }
+
+    /// check if the request may pass, returning the response to reject it with otherwise
+    pub fn check(&self, req: &ServiceRequest) -> Option<HttpResponse> {
+        let buckets = self.buckets.as_ref()?;
+        let path = req.path();
+        if path.starts_with("/health/") || path == "/metrics" {
+            return None;
+        }
+
+        let retry = buckets
+            .take(client(req, self.client_header.as_ref())?)
+            .err()?;
+        metrics::increment_counter!("bommer_rate_limited_total");
+        let mut response = ApiError::RateLimited.error_response();
+        response.headers_mut().insert(
+            RETRY_AFTER,
+            HeaderValue::from(retry.as_secs_f64().ceil().max(1.0) as u64),
+        );
+        Some(response)
+    }
+}
+
+/// Limits the number of concurrent subscribers to the workload
+#[derive(Clone, Debug, Default)]
+pub struct Subscribers {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+/// A slot of a subscriber, released when dropped
+#[derive(Debug)]
+pub struct Permit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Subscribers {
+    pub fn new(config: &LimitConfig) -> Self {
+        Self {
+            semaphore: config
+                .max_subscribers
+                .map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    /// take a slot for a new subscriber, `None` if all are taken
+    pub fn acquire(&self) -> Option<Permit> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    metrics::increment_counter!("bommer_subscribers_rejected_total");
+                    return None;
+                }
+            },
+            None => None,
+        };
+        Some(Permit { _permit: permit })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::net::SocketAddr;
+
+    fn limiter(client_header: Option<&str>) -> RateLimiter {
+        RateLimiter::new(&LimitConfig {
+            rate_limit: Some(1),
+            rate_limit_burst: 1,
+            rate_limit_client_header: client_header.map(|header| parse_header(header).unwrap()),
+            max_subscribers: None,
+        })
+    }
+
+    fn request(peer: Option<&str>, forwarded: Option<&str>) -> ServiceRequest {
+        let mut req = TestRequest::get().uri("/api/v1/workload");
+        if let Some(peer) = peer {
+            req = req.peer_addr(peer.parse::<SocketAddr>().unwrap());
+        }
+        if let Some(forwarded) = forwarded {
+            req = req.insert_header(("X-Forwarded-For", forwarded));
+        }
+        req.to_srv_request()
+    }
+
+    #[tokio::test]
+    async fn by_peer_address() {
+        let limiter = limiter(None);
+        assert!(limiter
+            .check(&request(Some("10.0.0.1:1234"), None))
+            .is_none());
+        let rejected = limiter
+            .check(&request(Some("10.0.0.1:1235"), Some("192.0.2.1")))
+            .unwrap();
+        assert_eq!(rejected.status(), 429);
+        assert!(rejected.headers().contains_key(RETRY_AFTER));
+        // another client
+        assert!(limiter
+            .check(&request(Some("10.0.0.2:1234"), None))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn by_client_header() {
+        let limiter = limiter(Some("X-Forwarded-For"));
+        // all requests come from the proxy, but from different clients
+        let proxy = Some("10.0.0.1:1234");
+        assert!(limiter
+            .check(&request(proxy, Some("203.0.113.7, 192.0.2.1")))
+            .is_none());
+        assert!(limiter.check(&request(proxy, Some("192.0.2.2"))).is_none());
+        // the client can't escape the limit by adding addresses in front
+        assert!(limiter
+            .check(&request(proxy, Some("198.51.100.1, 192.0.2.1")))
+            .is_some());
+        // without the header, the peer is the client
+        assert!(limiter.check(&request(proxy, None)).is_none());
+        assert!(limiter.check(&request(proxy, None)).is_some());
+    }
+
+    #[tokio::test]
+    async fn max_clients() {
+        let buckets = Buckets {
+            rate: 1.0,
+            burst: 10.0,
+            clients: Default::default(),
+        };
+        let client = |i: usize| IpAddr::from((i as u128).to_be_bytes());
+
+        // all clients used some of their requests, so none of them is idle
+        for i in 0..MAX_CLIENTS + 10 {
+            assert!(buckets.take(client(i)).is_ok());
+        }
+
+        let clients = buckets.clients.lock();
+        assert_eq!(clients.len(), MAX_CLIENTS);
+        assert!(clients.contains_key(&client(MAX_CLIENTS + 9)));
+    }
+
+    #[tokio::test]
+    async fn unknown_client() {
+        // e.g. on a Unix socket
+        let limiter = limiter(None);
+        for _ in 0..3 {
+            assert!(limiter.check(&request(None, None)).is_none());
+        }
+
+        // a proxy on the Unix socket can provide the client
+        let proxied = self::limiter(Some("X-Forwarded-For"));
+        assert!(proxied.check(&request(None, Some("192.0.2.1"))).is_none());
+        assert!(proxied.check(&request(None, Some("192.0.2.1"))).is_some());
+    }
+}
@@ -0,0 +1,14 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use metrics_exporter_prometheus::PrometheusHandle;
+
+/// Get the metrics of the instance, in the Prometheus text format
+#[utoipa::path(
+    tag = "metrics",
+    responses((status = 200, description = "The current metrics", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn metrics(handle: web::Data<PrometheusHandle>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(handle.render())
+}
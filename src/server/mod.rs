@@ -1,84 +1,812 @@
+mod access;
+mod admin;
+mod agents;
+mod auth;
+mod deflate;
+mod error;
+mod export;
+mod graphql;
+mod grpc;
+mod health;
+mod history;
+mod licenses;
+mod limit;
+mod metrics;
+mod openapi;
+mod pods;
+mod query;
+mod review;
+mod sbom;
+mod search;
+mod tls;
+mod tokens;
 mod ws;
 
-use crate::workload::{by_ns, WorkloadState};
+pub use auth::AuthConfig;
+pub use limit::LimitConfig;
+
+use crate::aggregator::Aggregator;
+use crate::documents::Documents;
+use crate::license::LicenseConfig;
+use crate::pubsub::{SlowSubscriber, SubscribeOptions};
+use crate::scanner::{CircuitBreaker, SbomCache, ScanLog};
+use crate::search::PackageIndex;
+use crate::stats::CoverageHistory;
+use crate::store::{ImageOwner, Resync, Store, SyncState};
+use crate::workload::WorkloadState;
+use access::Access;
 use actix_cors::Cors;
-use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use std::collections::HashMap;
+use actix_web::dev::{Server, Service, ServiceResponse};
+use actix_web::http::header::{ETag, EntityTag, IfNoneMatch};
+use actix_web::middleware::{Compress, Condition};
+use actix_web::{
+    get, web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder,
+};
+use anyhow::Context;
+use auth::{Authenticator, Identity};
+use bommer_api::data::{ImageRef, SyncStatus, WorkloadStats};
+use error::ApiError;
+use futures::future::Either;
+use futures::{FutureExt, TryFutureExt};
+use limit::{RateLimiter, Subscribers};
+use metrics_exporter_prometheus::PrometheusHandle;
+use query::{
+    EventsQuery, StatsHistoryQuery, StreamQuery, SyncQuery, WorkloadFilter, WorkloadQuery,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::task::spawn_local;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, Instrument};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Server")]
 pub struct ServerConfig {
-    pub bind_addr: String,
+    /// The addresses to bind the HTTP server to, `[::]:8080` unless binding to a Unix socket
+    #[arg(long, env = "BIND_ADDR", value_delimiter = ',')]
+    pub bind_addr: Vec<String>,
+
+    /// Unix socket to bind the HTTP server to, e.g. for a sidecar proxy, never using TLS
+    #[arg(long, env = "BIND_UNIX")]
+    pub bind_unix: Option<PathBuf>,
+
+    /// Address of an internal HTTP server for the health checks, metrics and the admin API, which are then no longer
+    /// served on the public addresses. It never uses TLS.
+    #[arg(long, env = "INTERNAL_BIND_ADDR")]
+    pub internal_bind_addr: Option<String>,
+
+    /// Certificate (chain) to enable TLS, in PEM format
+    #[arg(long, env = "TLS_CERTIFICATE", requires = "tls_key")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// Private key to enable TLS, in PEM format
+    #[arg(long, env = "TLS_KEY", requires = "tls_certificate")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Interval for checking if the TLS certificate or key changed
+    #[arg(long, env = "TLS_RELOAD_INTERVAL", default_value = "1m", value_parser = humantime::parse_duration)]
+    pub tls_reload_interval: Duration,
+
+    /// Interval of sending pings to websocket clients
+    #[arg(long, env = "WS_HEARTBEAT_INTERVAL", default_value = "5s", value_parser = humantime::parse_duration)]
+    pub ws_heartbeat_interval: Duration,
+
+    /// Time after which websocket sessions get closed if the client doesn't respond to pings
+    #[arg(long, env = "WS_TIMEOUT", default_value = "20s", value_parser = humantime::parse_duration)]
+    pub ws_timeout: Duration,
+
+    /// Coalesce changes of the same image sent to websocket clients, delivering at most one per window
+    #[arg(long, env = "WS_COALESCE_WINDOW", value_parser = humantime::parse_duration)]
+    pub ws_coalesce_window: Option<Duration>,
+
+    /// Time to collect events for websocket clients requesting batches, before sending them
+    #[arg(long, env = "WS_BATCH_WINDOW", default_value = "100ms", value_parser = humantime::parse_duration)]
+    pub ws_batch_window: Duration,
+
+    /// How to handle websocket, gRPC, and GraphQL clients which can't keep up with the changes
+    ///
+    /// Waiting holds up all changes of the workload, so external clients get disconnected by default.
+    #[arg(long, env = "WS_SLOW_SUBSCRIBER", value_enum, default_value_t = SlowSubscriber::Disconnect)]
+    pub ws_slow_subscriber: SlowSubscriber,
+
+    /// Disable compressing responses (gzip, brotli, zstd) and websocket messages (permessage-deflate)
+    #[arg(long, env = "DISABLE_COMPRESSION")]
+    pub disable_compression: bool,
+
+    /// Log each request (method, path, client, status, latency) and websocket session
+    #[arg(long, env = "ACCESS_LOG")]
+    pub access_log: bool,
+
+    /// The address to bind the gRPC API to, disabled if not provided
+    #[arg(long, env = "GRPC_BIND_ADDR")]
+    pub grpc_bind_addr: Option<SocketAddr>,
+
+    #[command(flatten)]
+    pub auth: AuthConfig,
+
+    #[command(flatten)]
+    pub limits: LimitConfig,
 }
 
+/// The address to bind to, if none is configured
+const DEFAULT_BIND_ADDR: &str = "[::]:8080";
+
+/// Header carrying the total number of images, before paging
+const TOTAL_COUNT: &str = "X-Total-Count";
+
+/// Header flagging a workload served before the initial sync completed
+const PARTIAL: &str = "X-Partial";
+
+/// start a successful response of the workload, as long as the initial sync completed
+///
+/// Until then, this fails, unless the client accepts a partial workload, which then gets flagged.
+fn synced_response(sync: &SyncState, query: &SyncQuery) -> Result<HttpResponseBuilder, ApiError> {
+    let mut response = HttpResponse::Ok();
+    if !sync.is_synced() {
+        if !query.partial {
+            return Err(ApiError::NotSynced);
+        }
+        response.insert_header((PARTIAL, "true"));
+    }
+    Ok(response)
+}
+
+/// Get the state of the initial sync with the clusters
+///
+/// Until the initial sync completed, the workload is incomplete, and endpoints returning it fail
+/// with a `503`, unless the client accepts a partial workload (`partial=true`).
+#[utoipa::path(
+    tag = "workload",
+    responses(
+        (status = 200, description = "The state of the initial sync", body = SyncStatus),
+    )
+)]
+#[get("/api/v1/status")]
+async fn get_status(_identity: Identity, sync: web::Data<SyncState>) -> impl Responder {
+    HttpResponse::Ok().json(SyncStatus {
+        synced: sync.is_synced(),
+        synced_at: sync.synced_at(),
+        pending: sync.pending(),
+    })
+}
+
+/// Get the current workload, along with the SBOM state of each image
+///
+/// The images can be filtered, and are returned in a stable order, so that clients can page
+/// through them. The total number of matching images is reported in the `X-Total-Count` header.
+///
+/// The response carries the revision of the workload as its `ETag`. Polling clients can provide
+/// it using `If-None-Match`, and get a `304` as long as the workload didn't change.
+///
+/// Until the initial sync completed, this fails, unless requesting a partial workload.
+#[utoipa::path(
+    tag = "workload",
+    params(WorkloadFilter, WorkloadQuery, SyncQuery),
+    responses(
+        (status = 200, description = "Images of the workload", body = HashMap<String, Image>,
+            headers(
+                ("X-Total-Count" = usize, description = "Total number of images"),
+                ("ETag" = String, description = "The revision of the workload"),
+            )),
+        (status = 304, description = "The workload didn't change since the provided revision"),
+        (status = 503, description = "The initial sync is still pending", body = Problem,
+            headers(("Retry-After" = u64, description = "Seconds to wait before trying again"))),
+    )
+)]
 #[get("/api/v1/workload")]
-async fn get_workload(map: web::Data<WorkloadState>) -> impl Responder {
-    HttpResponse::Ok().json(map.get_state().await.into_iter().collect::<HashMap<_, _>>())
+async fn get_workload(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    sync: web::Data<SyncState>,
+    filter: web::Query<WorkloadFilter>,
+    query: web::Query<WorkloadQuery>,
+    partial: web::Query<SyncQuery>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut response = synced_response(&sync, &partial)?;
+
+    let unchanged = |revision: u64| match if_none_match.as_deref() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag(revision))),
+        None => false,
+    };
+
+    // checking the revision first, so that we don't need to copy the state
+    let revision = map.revision().await;
+    if unchanged(revision) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag(revision)))
+            .finish());
+    }
+
+    let (revision, state) = map.get_revisioned_state().await;
+    let page = query.apply(&filter, state);
+    Ok(response
+        .insert_header((TOTAL_COUNT, page.total))
+        .insert_header(ETag(etag(revision)))
+        .json(page))
+}
+
+/// Get statistics of the workload
+///
+/// Counts the images by the state of their SBOM lookup, in total, per namespace, and per
+/// registry. The same filters as for getting the workload can be applied.
+#[utoipa::path(
+    tag = "workload",
+    params(WorkloadFilter, SyncQuery),
+    responses(
+        (status = 200, description = "Statistics of the workload", body = WorkloadStats),
+        (status = 503, description = "The initial sync is still pending", body = Problem),
+    )
+)]
+#[get("/api/v1/stats")]
+async fn get_stats(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    sync: web::Data<SyncState>,
+    filter: web::Query<WorkloadFilter>,
+    partial: web::Query<SyncQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut response = synced_response(&sync, &partial)?;
+    let stats = map
+        .get_state()
+        .await
+        .into_iter()
+        .filter_map(|(image, state)| {
+            let state = filter.apply(&image, state)?;
+            Some((image, state))
+        })
+        .fold(WorkloadStats::default(), |mut stats, (image, state)| {
+            stats.add(&image, &state);
+            stats
+        });
+
+    Ok(response.json(stats))
+}
+
+/// Get the SBOM coverage of the workload over time
+///
+/// Returns the recorded samples, oldest first. Samples are recorded periodically, and only a
+/// limited number of them is kept.
+#[utoipa::path(
+    tag = "workload",
+    params(StatsHistoryQuery),
+    responses(
+        (status = 200, description = "The recorded samples", body = Vec<CoverageSample>),
+    )
+)]
+#[get("/api/v1/stats/history")]
+async fn get_stats_history(
+    _identity: Identity,
+    coverage: web::Data<CoverageHistory>,
+    query: web::Query<StatsHistoryQuery>,
+) -> impl Responder {
+    HttpResponse::Ok().json(coverage.samples(query.since))
+}
+
+/// the entity tag of a revision of the workload
+///
+/// It's a weak one, as the same revision might get compressed differently.
+fn etag(revision: u64) -> EntityTag {
+    EntityTag::new_weak(revision.to_string())
+}
+
+/// Get the recent changes to the workload
+///
+/// For clients which can't keep a websocket open, this returns the most recent changes, oldest
+/// first, each with its revision and the time it happened. Providing the revision of the last
+/// change received (`since`) returns the changes following it. If those are no longer known, the
+/// response is flagged as `expired`, and clients need to get the full workload again.
+#[utoipa::path(
+    tag = "workload",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "The recent changes, along with the current revision"),
+    )
+)]
+#[get("/api/v1/events")]
+async fn get_events(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    query: web::Query<EventsQuery>,
+) -> impl Responder {
+    HttpResponse::Ok().json(map.recent(query.since, query.limit).await)
 }
 
+/// Stream changes to the workload, using a websocket
+///
+/// The first message is a full snapshot (`restart`), followed by individual changes. The same
+/// filters as for getting the workload can be applied.
+///
+/// Each message carries the revision of the state it leads to. When reconnecting, clients can
+/// provide the last revision they received (`since`), and only receive the changes since then,
+/// as long as those are still known. Otherwise, they start with a full snapshot again.
+#[utoipa::path(
+    tag = "workload",
+    params(WorkloadFilter, StreamQuery),
+    responses(
+        (status = 101, description = "Switching to the websocket protocol"),
+    )
+)]
 #[get("/api/v1/workload_stream")]
 pub async fn workload_stream(
+    identity: Identity,
     req: HttpRequest,
     stream: web::Payload,
     map: web::Data<WorkloadState>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
-    let subscription = map.subscribe(32).await;
-    spawn_local(ws::run(subscription, session, msg_stream));
+    settings: web::Data<ws::Settings>,
+    filter: web::Query<WorkloadFilter>,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let encoding = ws::encoding(&req);
+    let permit = settings
+        .subscribers
+        .acquire()
+        .ok_or(ApiError::TooManySubscribers)?;
+    let (mut res, session, msg_stream) =
+        actix_ws::handle(&req, stream).map_err(|err| ApiError::Handshake(err.to_string()))?;
+    ws::accept_encoding(&mut res, encoding);
+    if settings.compression {
+        res = deflate::apply(&req, res);
+    }
+    let subscription = map
+        .subscribe_with(
+            "websocket",
+            settings.buffer,
+            SubscribeOptions {
+                policy: settings.slow_subscriber,
+                filter: filter.into_inner().into_subscription_filter(),
+                since: query.since,
+            },
+        )
+        .await;
+    spawn_local(access::session(
+        req.path(),
+        &identity,
+        settings.access_log,
+        ws::run(
+            subscription,
+            session,
+            msg_stream,
+            settings.get_ref().clone(),
+            encoding.unwrap_or_default(),
+            query.batch,
+            permit,
+        ),
+    ));
     Ok(res)
 }
 
+/// Stream changes to the workload of a single namespace, using a websocket
+#[utoipa::path(
+    tag = "workload",
+    params(
+        ("namespace" = String, Path, description = "The namespace to watch"),
+        StreamQuery,
+    ),
+    responses(
+        (status = 101, description = "Switching to the websocket protocol"),
+    )
+)]
 #[get("/api/v1/workload_stream/{namespace}")]
 pub async fn workload_stream_ns(
+    identity: Identity,
     req: HttpRequest,
     stream: web::Payload,
     map: web::Data<WorkloadState>,
+    settings: web::Data<ws::Settings>,
     path: web::Path<String>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let (workload, runner) = by_ns(&map, path.into_inner()).await;
-    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
-    let subscription = workload.subscribe(32).await;
-
-    // run either of them to completion
-    spawn_local(async {
-        tokio::select! {
-            _ = ws::run(subscription, session, msg_stream) => {},
-            _ = runner => {},
-        }
-    });
-
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let filter = WorkloadFilter {
+        namespace: Some(path.into_inner()),
+        ..Default::default()
+    };
+    let encoding = ws::encoding(&req);
+    let permit = settings
+        .subscribers
+        .acquire()
+        .ok_or(ApiError::TooManySubscribers)?;
+    let (mut res, session, msg_stream) =
+        actix_ws::handle(&req, stream).map_err(|err| ApiError::Handshake(err.to_string()))?;
+    ws::accept_encoding(&mut res, encoding);
+    if settings.compression {
+        res = deflate::apply(&req, res);
+    }
+    let subscription = map
+        .subscribe_with(
+            "websocket",
+            settings.buffer,
+            SubscribeOptions {
+                policy: settings.slow_subscriber,
+                filter: filter.into_subscription_filter(),
+                since: query.since,
+            },
+        )
+        .await;
+    spawn_local(access::session(
+        req.path(),
+        &identity,
+        settings.access_log,
+        ws::run(
+            subscription,
+            session,
+            msg_stream,
+            settings.get_ref().clone(),
+            encoding.unwrap_or_default(),
+            query.batch,
+            permit,
+        ),
+    ));
     Ok(res)
 }
 
-/*
-#[get("/v1/images/{namespace}")]
-async fn get_containers_ns(path: web::Path<String>, store: web::Data<Store>) -> impl Responder {
-    let ns = path.into_inner();
-    HttpResponse::Ok().json(store.get_containers_ns(&ns).await)
-}*/
+/// The state the API serves, and what it uses to serve it
+pub struct ServerContext {
+    pub map: WorkloadState,
+    pub store: Store<ImageRef, ImageOwner, ()>,
+    pub breaker: CircuitBreaker,
+    pub history: ScanLog,
+    pub coverage: CoverageHistory,
+    pub documents: Documents,
+    pub index: Option<PackageIndex>,
+    pub licenses: LicenseConfig,
+    pub aggregator: Option<Aggregator>,
+    pub cache: SbomCache,
+    pub resync: Resync,
+    pub metrics: PrometheusHandle,
+}
+
+/// serve the API, until the server fails or the shutdown token gets cancelled
+///
+/// When shutting down, websocket sessions get closed and pending requests finished.
+pub async fn run(
+    config: ServerConfig,
+    context: ServerContext,
+    subscriber_buffer: usize,
+    client: reqwest::Client,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let ServerContext {
+        map,
+        store,
+        breaker,
+        history,
+        coverage,
+        documents,
+        index,
+        licenses,
+        aggregator,
+        cache,
+        resync,
+        metrics,
+    } = context;
+
+    let authenticator = Authenticator::new(config.auth.clone(), client).await?;
+    let subscribers = Subscribers::new(&config.limits);
+    let limiter = RateLimiter::new(&config.limits);
+
+    let grpc = match config.grpc_bind_addr {
+        Some(addr) => grpc::run(
+            grpc::GrpcSettings {
+                addr,
+                tls: config.tls_certificate.clone().zip(config.tls_key.clone()),
+                slow_subscriber: config.ws_slow_subscriber,
+                buffer: subscriber_buffer,
+            },
+            map.clone(),
+            authenticator.clone(),
+            subscribers.clone(),
+            shutdown.clone(),
+        )
+        .boxed(),
+        None => futures::future::ok(()).boxed(),
+    };
 
-pub async fn run(config: ServerConfig, map: WorkloadState) -> anyhow::Result<()> {
+    let schema = web::Data::new(graphql::schema(
+        map.clone(),
+        config.ws_slow_subscriber,
+        subscriber_buffer,
+    ));
     let map = web::Data::new(map);
+    let documents = web::Data::new(documents);
+    let index = web::Data::new(index);
+    let licenses = web::Data::new(licenses);
+    let aggregator = web::Data::new(aggregator);
+    let admin = web::Data::new(admin::Admin {
+        subjects: config.auth.admin_subjects.clone(),
+        cache,
+        resync,
+    });
+    let sync = web::Data::new(store.sync_state().clone());
+    let store = web::Data::new(store);
+    let breaker = web::Data::new(breaker);
+    let history = web::Data::new(history);
+    let coverage = web::Data::new(coverage);
+    let authenticator = web::Data::new(authenticator);
+    let ws_settings = web::Data::new(ws::Settings {
+        interval: config.ws_heartbeat_interval,
+        timeout: config.ws_timeout,
+        coalesce: config.ws_coalesce_window,
+        batch: config.ws_batch_window,
+        slow_subscriber: config.ws_slow_subscriber,
+        buffer: subscriber_buffer,
+        compression: !config.disable_compression,
+        access_log: config.access_log,
+        subscribers,
+        shutdown: shutdown.clone(),
+    });
+    let metrics = web::Data::new(metrics);
+    let compression = !config.disable_compression;
+    let access_log = config.access_log;
+    let client_header = config.limits.rate_limit_client_header.clone();
+    let separate = config.internal_bind_addr.is_some();
 
-    HttpServer::new(move || {
+    let internal = match &config.internal_bind_addr {
+        Some(addr) => {
+            let map = map.clone();
+            let sync = sync.clone();
+            let breaker = breaker.clone();
+            let admin = admin.clone();
+            let authenticator = authenticator.clone();
+            let metrics = metrics.clone();
+            let client_header = client_header.clone();
+
+            info!("Binding internal server to {addr}");
+            let server = HttpServer::new(move || {
+                let client_header = client_header.clone();
+                App::new()
+                    .app_data(map.clone())
+                    .app_data(sync.clone())
+                    .app_data(breaker.clone())
+                    .app_data(admin.clone())
+                    .app_data(authenticator.clone())
+                    .app_data(metrics.clone())
+                    .app_data(web::QueryConfig::default().error_handler(ApiError::extractor))
+                    .wrap_fn(move |req, srv| {
+                        let access = Access::start(&req, access_log, client_header.as_ref());
+                        let span = access.span().clone();
+                        let response = span.in_scope(|| srv.call(req));
+                        async move {
+                            let result = response.await;
+                            access.finish(&result);
+                            result
+                        }
+                        .instrument(span)
+                    })
+                    .configure(internal_services)
+                    .default_service(web::to(|| async {
+                        Err::<HttpResponse, _>(ApiError::NotFound)
+                    }))
+            })
+            .disable_signals()
+            .bind(addr)
+            .with_context(|| format!("Failed to bind to {addr}"))?
+            .run();
+            stop_on_shutdown(&server, shutdown.clone());
+            async { Ok(server.await?) }.boxed()
+        }
+        None => futures::future::ok(()).boxed(),
+    };
+
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .send_wildcard()
             .allow_any_origin()
             .allow_any_method()
             .allow_any_header()
             .max_age(3600);
+        let limiter = limiter.clone();
+        let client_header = client_header.clone();
 
         App::new()
             .app_data(map.clone())
+            .app_data(sync.clone())
+            .app_data(store.clone())
+            .app_data(breaker.clone())
+            .app_data(history.clone())
+            .app_data(coverage.clone())
+            .app_data(documents.clone())
+            .app_data(index.clone())
+            .app_data(licenses.clone())
+            .app_data(aggregator.clone())
+            .app_data(admin.clone())
+            .app_data(authenticator.clone())
+            .app_data(ws_settings.clone())
+            .app_data(metrics.clone())
+            .app_data(schema.clone())
+            .app_data(web::QueryConfig::default().error_handler(ApiError::extractor))
+            .app_data(web::PathConfig::default().error_handler(ApiError::extractor))
+            .app_data(web::JsonConfig::default().error_handler(ApiError::extractor))
+            .wrap_fn(move |req, srv| match limiter.check(&req) {
+                None => Either::Left(srv.call(req).map_ok(ServiceResponse::map_into_left_body)),
+                Some(res) => Either::Right(futures::future::ok(
+                    req.into_response(res).map_into_right_body(),
+                )),
+            })
+            .wrap(Condition::new(compression, Compress::default()))
             .wrap(cors)
+            .wrap_fn(move |req, srv| {
+                let access = Access::start(&req, access_log, client_header.as_ref());
+                let span = access.span().clone();
+                let response = span.in_scope(|| srv.call(req));
+                async move {
+                    let result = response.await;
+                    access.finish(&result);
+                    result
+                }
+                .instrument(span)
+            })
+            .service(get_status)
             .service(get_workload)
+            .service(get_events)
+            .service(get_stats)
+            .service(get_stats_history)
             .service(workload_stream)
             .service(workload_stream_ns)
-        //.service(get_containers_ns)
+            .service(pods::get_pods)
+            .service(export::cyclonedx)
+            .service(export::spdx)
+            .service(export::csv)
+            .service(sbom::get_sbom)
+            .service(sbom::get_sbom_details)
+            .service(history::get_history)
+            .service(licenses::get_licenses)
+            .service(licenses::get_image_licenses)
+            .service(search::search)
+            .service(agents::push_update)
+            .service(graphql::graphql)
+            .service(graphql::graphql_ws)
+            .service(openapi::spec)
+            .configure(|cfg| {
+                if !separate {
+                    internal_services(cfg);
+                }
+            })
+            .default_service(web::to(|| async {
+                Err::<HttpResponse, _>(ApiError::NotFound)
+            }))
     })
-    .bind(&config.bind_addr)?
-    .run()
-    .await?;
+    // signals are handled by the caller, stopping the server through the shutdown token
+    .disable_signals();
+
+    let (tcp, unix) = listeners(&config)?;
+    let tls = tls_config(&config)?;
+    let mut server = server;
+    for listener in tcp {
+        server = match &tls {
+            Some(tls) => server.listen_rustls(listener, tls.clone())?,
+            None => server.listen(listener)?,
+        };
+    }
+    if let Some(listener) = unix {
+        server = server.listen_uds(listener)?;
+    }
+    let server = server.run();
+    stop_on_shutdown(&server, shutdown);
+
+    tokio::try_join!(async { Ok(server.await?) }, internal, grpc)?;
 
     Ok(())
 }
+
+/// the services of the internal server, which are served publicly if there is none
+fn internal_services(cfg: &mut web::ServiceConfig) {
+    cfg.service(admin::reset)
+        .service(admin::clear_cache)
+        .service(admin::resync)
+        .service(health::live)
+        .service(health::ready)
+        .service(health::source)
+        .service(metrics::metrics);
+}
+
+/// run the server of an agent, which only serves the health checks and metrics
+///
+/// With an internal server configured, those are only served on its address.
+pub async fn run_agent(
+    config: ServerConfig,
+    sync: SyncState,
+    metrics: PrometheusHandle,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let sync = web::Data::new(sync);
+    let metrics = web::Data::new(metrics);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(sync.clone())
+            .app_data(metrics.clone())
+            .service(health::live)
+            .service(health::ready)
+            .service(metrics::metrics)
+    })
+    .disable_signals();
+
+    let server = match &config.internal_bind_addr {
+        Some(addr) => {
+            info!("Binding internal server to {addr}");
+            server
+                .bind(addr)
+                .with_context(|| format!("Failed to bind to {addr}"))?
+        }
+        None => {
+            let (tcp, unix) = listeners(&config)?;
+            let tls = tls_config(&config)?;
+            let mut server = server;
+            for listener in tcp {
+                server = match &tls {
+                    Some(tls) => server.listen_rustls(listener, tls.clone())?,
+                    None => server.listen(listener)?,
+                };
+            }
+            if let Some(listener) = unix {
+                server = server.listen_uds(listener)?;
+            }
+            server
+        }
+    };
+    let server = server.run();
+    stop_on_shutdown(&server, shutdown);
+    server.await?;
+
+    Ok(())
+}
+
+/// bind the configured addresses, and the Unix socket if requested
+fn listeners(config: &ServerConfig) -> anyhow::Result<(Vec<TcpListener>, Option<UnixListener>)> {
+    let mut addrs = config.bind_addr.clone();
+    if addrs.is_empty() && config.bind_unix.is_none() {
+        addrs.push(DEFAULT_BIND_ADDR.to_string());
+    }
+
+    let tcp = addrs
+        .iter()
+        .map(|addr| {
+            info!("Binding to {addr}");
+            TcpListener::bind(addr).with_context(|| format!("Failed to bind to {addr}"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let unix = match &config.bind_unix {
+        Some(path) => {
+            info!("Binding to Unix socket {}", path.display());
+            // a socket left behind by a previous run would fail binding
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            Some(
+                UnixListener::bind(path)
+                    .with_context(|| format!("Failed to bind to {}", path.display()))?,
+            )
+        }
+        None => None,
+    };
+
+    Ok((tcp, unix))
+}
+
+/// the TLS configuration, if enabled, reloading the certificate periodically
+fn tls_config(config: &ServerConfig) -> anyhow::Result<Option<rustls::ServerConfig>> {
+    match (&config.tls_certificate, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let resolver = Arc::new(tls::ReloadingResolver::new(cert.clone(), key.clone())?);
+            tokio::spawn(resolver.clone().run(config.tls_reload_interval));
+            Ok(Some(tls::server_config(resolver)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// stop the server gracefully, once shutting down
+fn stop_on_shutdown(server: &Server, shutdown: CancellationToken) {
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+        handle.stop(true).await;
+    });
+}
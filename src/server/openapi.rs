@@ -0,0 +1,85 @@
+use actix_web::{get, HttpResponse, Responder};
+use bommer_api::data::{
+    ContainerKind, ContainerUsage, CoverageSample, Image, ImageLicense, ImageRef, JobRef,
+    LicenseUsage, LookupError, LookupErrorKind, NodeRef, PackageMatch, PodContainer, PodImages,
+    PodRef, Problem, QualityCheck, QualityCriterion, QualityGrade, RetryState, SbomDetails,
+    SbomFormat, SbomPackage, SbomQuality, SbomState, SbomStats, SbomSummary, ScanAttempt,
+    ScanHistory, ScanOutcome, SyncStatus, Vulnerabilities, WorkloadRef, WorkloadStats,
+};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        super::get_status,
+        super::get_workload,
+        super::get_events,
+        super::get_stats,
+        super::get_stats_history,
+        super::workload_stream,
+        super::workload_stream_ns,
+        super::pods::get_pods,
+        super::export::cyclonedx,
+        super::export::spdx,
+        super::export::csv,
+        super::sbom::get_sbom,
+        super::sbom::get_sbom_details,
+        super::history::get_history,
+        super::licenses::get_licenses,
+        super::licenses::get_image_licenses,
+        super::search::search,
+        super::agents::push_update,
+        super::admin::reset,
+        super::admin::clear_cache,
+        super::admin::resync,
+        super::graphql::graphql,
+        super::graphql::graphql_ws,
+        super::health::live,
+        super::health::ready,
+        super::health::source,
+        super::metrics::metrics,
+    ),
+    components(schemas(
+        ContainerKind,
+        ContainerUsage,
+        CoverageSample,
+        Image,
+        ImageLicense,
+        ImageRef,
+        JobRef,
+        LicenseUsage,
+        LookupError,
+        LookupErrorKind,
+        NodeRef,
+        PackageMatch,
+        PodContainer,
+        PodImages,
+        PodRef,
+        Problem,
+        QualityCheck,
+        QualityCriterion,
+        QualityGrade,
+        RetryState,
+        ScanAttempt,
+        ScanHistory,
+        ScanOutcome,
+        SbomDetails,
+        SbomPackage,
+        SbomQuality,
+        SbomState,
+        SbomFormat,
+        SbomStats,
+        SbomSummary,
+        SyncStatus,
+        Vulnerabilities,
+        WorkloadRef,
+        WorkloadStats
+    ))
+)]
+pub struct ApiDoc;
+
+/// Get the OpenAPI specification of the API
+#[get("/openapi.json")]
+pub async fn spec() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
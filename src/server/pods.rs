@@ -0,0 +1,83 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use super::query::{PodsQuery, SyncQuery};
+use super::synced_response;
+use crate::store::{ImageOwner, Store, SyncState};
+use crate::workload::WorkloadState;
+use actix_web::{get, web, HttpResponse};
+use bommer_api::data::{ImageRef, PodContainer, PodImages, PodRef};
+use std::collections::BTreeMap;
+
+/// Get the pods of a namespace, along with the images of their containers
+///
+/// This is the inverse of the workload, listing each pod with the images its containers use, and
+/// the state of their SBOM lookup. Pods are sorted by name, their containers by kind and name.
+#[utoipa::path(
+    tag = "workload",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the pods"),
+        PodsQuery,
+        SyncQuery,
+    ),
+    responses(
+        (status = 200, description = "Pods of the namespace", body = Vec<PodImages>),
+        (status = 503, description = "The initial sync is still pending", body = Problem),
+    )
+)]
+#[get("/api/v1/namespaces/{namespace}/pods")]
+pub async fn get_pods(
+    _identity: Identity,
+    store: web::Data<Store<ImageRef, ImageOwner, ()>>,
+    map: web::Data<WorkloadState>,
+    sync: web::Data<SyncState>,
+    path: web::Path<String>,
+    query: web::Query<PodsQuery>,
+    partial: web::Query<SyncQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut response = synced_response(&sync, &partial)?;
+    let namespace = path.into_inner();
+
+    let owners = store
+        .owners_matching(|owner| match owner {
+            ImageOwner::Container(owner) => {
+                owner.pod.namespace == namespace
+                    && query
+                        .cluster
+                        .as_ref()
+                        .is_none_or(|cluster| owner.pod.cluster.as_ref() == Some(cluster))
+            }
+            ImageOwner::Job(_) | ImageOwner::Node(_) => false,
+        })
+        .await;
+    let state = map.get_state().await;
+
+    let mut pods = BTreeMap::<PodRef, Vec<PodContainer>>::new();
+    let owners = owners
+        .into_iter()
+        .filter_map(|(owner, images)| match owner {
+            ImageOwner::Container(owner) => Some((owner, images)),
+            ImageOwner::Job(_) | ImageOwner::Node(_) => None,
+        });
+    for (owner, images) in owners {
+        let containers = pods.entry(owner.pod).or_default();
+        for image in images {
+            containers.push(PodContainer {
+                name: owner.container.clone(),
+                kind: owner.kind,
+                sbom: state.get(&image).map(|image| image.sbom.clone()),
+                image,
+                declared: !owner.running,
+            });
+        }
+    }
+
+    let pods = pods
+        .into_iter()
+        .map(|(pod, mut containers)| {
+            containers.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+            PodImages { pod, containers }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(response.json(pods))
+}
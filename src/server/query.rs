@@ -0,0 +1,313 @@
+use crate::pubsub::Filter;
+use bommer_api::data::{Image, ImageRef, SbomState};
+use chrono::{DateTime, Utc};
+use serde::ser::{Serialize, Serializer};
+
+/// Property to sort images by
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    /// The image reference
+    #[default]
+    Image,
+    /// The first namespace the image is used in
+    Namespace,
+    /// The state of the SBOM lookup
+    SbomState,
+}
+
+/// State of the SBOM lookup to filter by
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema, async_graphql::Enum,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum SbomFilter {
+    Scheduled,
+    Err,
+    Missing,
+    Found,
+}
+
+impl SbomFilter {
+    fn matches(&self, state: &SbomState) -> bool {
+        matches!(
+            (self, state),
+            (Self::Scheduled, SbomState::Scheduled)
+                | (Self::Err, SbomState::Err(_))
+                | (Self::Missing, SbomState::Missing)
+                | (Self::Found, SbomState::Found(_))
+        )
+    }
+}
+
+/// Query parameters for filtering the workload
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WorkloadFilter {
+    /// Only images used in these (comma separated) namespaces, along with only their pods,
+    /// containers, and jobs
+    pub namespace: Option<String>,
+    /// Only images with this state of the SBOM lookup
+    #[param(inline)]
+    pub sbom: Option<SbomFilter>,
+    /// Only images from this registry
+    pub registry: Option<String>,
+    /// Only images whose reference contains this text
+    pub q: Option<String>,
+}
+
+/// Query parameters for listing the pods of a namespace
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PodsQuery {
+    /// Only pods of this cluster, when watching multiple clusters
+    pub cluster: Option<String>,
+}
+
+/// Query parameters for getting the coverage history
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsHistoryQuery {
+    /// Only samples recorded after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for getting the licenses of the workload
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LicensesQuery {
+    /// Only licenses which are configured as forbidden
+    #[serde(default)]
+    pub forbidden: bool,
+}
+
+/// Query parameters for streaming the workload
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    /// Resume after this revision, only receiving the changes since then if possible
+    pub since: Option<u64>,
+    /// Receive arrays of events, collected over a short window, rather than individual events
+    #[serde(default)]
+    pub batch: bool,
+}
+
+/// Query parameters for polling the recent changes of the workload
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQuery {
+    /// Only changes after this revision, the latest changes if not provided
+    pub since: Option<u64>,
+    /// Maximum number of changes to return
+    #[serde(default = "default_events_limit")]
+    #[param(default = 100)]
+    pub limit: usize,
+}
+
+fn default_events_limit() -> usize {
+    100
+}
+
+/// Query parameters of endpoints which wait for the initial sync
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SyncQuery {
+    /// Return the workload before the initial sync completed, flagged as partial, instead of failing
+    #[serde(default)]
+    pub partial: bool,
+}
+
+/// Query parameters for paging through the workload
+#[derive(Clone, Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WorkloadQuery {
+    /// Property to sort by, ties are broken by the image reference
+    #[serde(default)]
+    #[param(inline)]
+    pub sort: SortKey,
+    /// Number of images to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of images to return, all if not provided
+    pub limit: Option<usize>,
+}
+
+/// A page of images, in the requested order
+pub struct Page {
+    /// The total number of matching images, before paging
+    pub total: usize,
+    pub items: Vec<(ImageRef, Image)>,
+}
+
+impl WorkloadQuery {
+    /// filter and sort the state, and select the requested page
+    pub fn apply(&self, filter: &WorkloadFilter, state: im::HashMap<ImageRef, Image>) -> Page {
+        let mut items = state
+            .into_iter()
+            .filter_map(|(image, state)| {
+                let state = filter.apply(&image, state)?;
+                Some((image, state))
+            })
+            .collect::<Vec<_>>();
+        let total = items.len();
+
+        match self.sort {
+            SortKey::Image => items.sort_by_cached_key(|(image, _)| image.to_string()),
+            SortKey::Namespace => items.sort_by_cached_key(|(image, state)| {
+                let namespace = state.pods.iter().map(|pod| pod.namespace.clone()).min();
+                (namespace, image.to_string())
+            }),
+            SortKey::SbomState => {
+                items.sort_by_cached_key(|(image, state)| (rank(&state.sbom), image.to_string()))
+            }
+        }
+
+        let items = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Page { total, items }
+    }
+}
+
+impl WorkloadFilter {
+    fn is_empty(&self) -> bool {
+        self.namespace.is_none()
+            && self.sbom.is_none()
+            && self.registry.is_none()
+            && self.q.is_none()
+    }
+
+    /// apply the filters to an image, `None` if it doesn't match
+    pub fn apply(&self, image: &ImageRef, mut state: Image) -> Option<Image> {
+        if let Some(registry) = &self.registry {
+            if &image.registry != registry {
+                return None;
+            }
+        }
+
+        if let Some(sbom) = &self.sbom {
+            if !sbom.matches(&state.sbom) {
+                return None;
+            }
+        }
+
+        if let Some(q) = &self.q {
+            if !image.to_string().contains(q.as_str()) {
+                return None;
+            }
+        }
+
+        if let Some(namespace) = &self.namespace {
+            let matches = |ns: &str| namespace.split(',').any(|n| n == ns);
+            state.pods.retain(|pod| matches(&pod.namespace));
+            state
+                .containers
+                .retain(|container| matches(&container.namespace));
+            state.declared = Image::declared_by(&state.containers);
+            state.jobs.retain(|job| matches(&job.namespace));
+            // nodes aren't namespaced
+            state.nodes.clear();
+            if state.pods.is_empty() && state.jobs.is_empty() {
+                return None;
+            }
+        }
+
+        Some(state)
+    }
+
+    /// turn into a filter for subscriptions, `None` if it doesn't filter anything
+    pub fn into_subscription_filter(self) -> Option<Filter<ImageRef, Image>> {
+        match self.is_empty() {
+            true => None,
+            false => Some(Filter::new(move |image, state| self.apply(image, state))),
+        }
+    }
+}
+
+/// serializes as a JSON object, keeping the order of the page
+impl Serialize for Page {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.items.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+/// the order of SBOM states, following their lifecycle
+fn rank(state: &SbomState) -> u8 {
+    match state {
+        SbomState::Scheduled => 0,
+        SbomState::Err(_) => 1,
+        SbomState::Missing => 2,
+        SbomState::Found(_) => 3,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::{ContainerKind, ContainerUsage, NodeRef, PodRef};
+
+    fn pod(namespace: &str, name: &str) -> PodRef {
+        PodRef {
+            cluster: None,
+            namespace: namespace.into(),
+            name: name.into(),
+            node: None,
+            workload: None,
+        }
+    }
+
+    fn container(name: &str, namespace: &str, pods: usize, running: bool) -> ContainerUsage {
+        ContainerUsage {
+            name: name.into(),
+            kind: ContainerKind::Container,
+            namespace: namespace.into(),
+            pods,
+            running,
+        }
+    }
+
+    #[test]
+    fn filter_namespace() {
+        let image: ImageRef = "quay.io/example/app:1.0".parse().unwrap();
+        // running in one namespace, but stuck pulling the image in the other
+        let state = Image {
+            pods: [pod("a", "app-1"), pod("a", "app-2"), pod("b", "app-1")].into(),
+            containers: vec![
+                container("app", "a", 2, true),
+                container("app", "b", 1, false),
+            ],
+            nodes: [NodeRef {
+                cluster: None,
+                name: "node-1".into(),
+            }]
+            .into(),
+            ..Image::new(SbomState::Scheduled)
+        };
+        let filter = |namespace: &str| WorkloadFilter {
+            namespace: Some(namespace.into()),
+            ..Default::default()
+        };
+
+        let filtered = filter("b").apply(&image, state.clone()).unwrap();
+        assert_eq!(
+            filtered,
+            Image {
+                pods: [pod("b", "app-1")].into(),
+                declared: true,
+                containers: vec![container("app", "b", 1, false)],
+                ..Image::new(SbomState::Scheduled)
+            }
+        );
+
+        let filtered = filter("a,c").apply(&image, state.clone()).unwrap();
+        assert_eq!(filtered.pods, [pod("a", "app-1"), pod("a", "app-2")].into());
+        assert!(!filtered.declared);
+        assert_eq!(filtered.containers, vec![container("app", "a", 2, true)]);
+        assert!(filtered.nodes.is_empty());
+
+        assert_eq!(filter("c").apply(&image, state), None);
+    }
+}
@@ -0,0 +1,173 @@
+use super::auth::{AuthError, Identity, KubernetesUser};
+use k8s_openapi::api::authentication::v1::{TokenReview, TokenReviewSpec};
+use k8s_openapi::api::authorization::v1::{
+    NonResourceAttributes, SubjectAccessReview, SubjectAccessReviewSpec,
+};
+use kube::api::PostParams;
+use kube::{Api, Client};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// time the outcome of a review is cached, so that not every request causes another one
+const REVIEW_TTL: Duration = Duration::from_secs(60);
+
+/// maximum number of cached reviews, of each kind
+const MAX_REVIEWS: usize = 10_000;
+
+/// Review outcomes, expiring after [`REVIEW_TTL`]
+struct Reviews<K, V> {
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> Reviews<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: Default::default(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock();
+        let (value, reviewed) = entries.get(key)?;
+        (reviewed.elapsed() < REVIEW_TTL).then(|| value.clone())
+    }
+
+    fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock();
+        if entries.len() >= MAX_REVIEWS {
+            entries.retain(|_, (_, reviewed)| reviewed.elapsed() < REVIEW_TTL);
+        }
+        if entries.len() < MAX_REVIEWS {
+            entries.insert(key, (value, Instant::now()));
+        }
+    }
+}
+
+/// Authenticates and authorizes callers using the API of the cluster bommer runs in
+///
+/// Tokens (e.g. of service accounts) are validated using a `TokenReview`. Access to a path of the
+/// API is checked using a `SubjectAccessReview` for a non-resource URL, so that it can be granted
+/// using RBAC roles.
+pub struct KubernetesAuth {
+    tokens: Api<TokenReview>,
+    access: Api<SubjectAccessReview>,
+    audiences: Option<Vec<String>>,
+    users: Reviews<[u8; 32], Option<KubernetesUser>>,
+    permissions: Reviews<(KubernetesUser, String, String), bool>,
+}
+
+impl KubernetesAuth {
+    pub fn new(client: Client, audiences: Vec<String>) -> Self {
+        Self {
+            tokens: Api::all(client.clone()),
+            access: Api::all(client),
+            audiences: (!audiences.is_empty()).then_some(audiences),
+            users: Reviews::new(),
+            permissions: Reviews::new(),
+        }
+    }
+
+    /// validate a token, returning the user it belongs to
+    pub async fn authenticate(&self, token: &str) -> Result<Identity, AuthError> {
+        let key = Sha256::digest(token.as_bytes()).into();
+        let user = match self.users.get(&key) {
+            Some(user) => user,
+            None => {
+                let user = self.review_token(token).await?;
+                self.users.insert(key, user.clone());
+                user
+            }
+        };
+
+        user.map(Identity::Kubernetes).ok_or(AuthError::Rejected)
+    }
+
+    async fn review_token(&self, token: &str) -> Result<Option<KubernetesUser>, AuthError> {
+        let review = TokenReview {
+            spec: TokenReviewSpec {
+                audiences: self.audiences.clone(),
+                token: Some(token.to_string()),
+            },
+            ..Default::default()
+        };
+        let status = self
+            .tokens
+            .create(&PostParams::default(), &review)
+            .await?
+            .status
+            .unwrap_or_default();
+
+        if status.authenticated != Some(true) {
+            debug!(error = ?status.error, "Token rejected");
+            return Ok(None);
+        }
+
+        Ok(status.user.and_then(|user| {
+            Some(KubernetesUser {
+                username: user.username?,
+                uid: user.uid,
+                groups: user.groups.unwrap_or_default(),
+                extra: user.extra.unwrap_or_default(),
+            })
+        }))
+    }
+
+    /// check if the user may access a path of the API, using a verb (e.g. `get`)
+    pub async fn authorize(
+        &self,
+        user: &KubernetesUser,
+        path: &str,
+        verb: &str,
+    ) -> Result<(), AuthError> {
+        let key = (user.clone(), path.to_string(), verb.to_string());
+        let allowed = match self.permissions.get(&key) {
+            Some(allowed) => allowed,
+            None => {
+                let allowed = self.review_access(user, path, verb).await?;
+                self.permissions.insert(key, allowed);
+                allowed
+            }
+        };
+
+        match allowed {
+            true => Ok(()),
+            false => Err(AuthError::Forbidden),
+        }
+    }
+
+    async fn review_access(
+        &self,
+        user: &KubernetesUser,
+        path: &str,
+        verb: &str,
+    ) -> Result<bool, AuthError> {
+        let review = SubjectAccessReview {
+            spec: SubjectAccessReviewSpec {
+                user: Some(user.username.clone()),
+                uid: user.uid.clone(),
+                groups: Some(user.groups.clone()),
+                extra: Some(user.extra.clone()),
+                non_resource_attributes: Some(NonResourceAttributes {
+                    path: Some(path.to_string()),
+                    verb: Some(verb.to_string()),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let status = self
+            .access
+            .create(&PostParams::default(), &review)
+            .await?
+            .status
+            .unwrap_or_default();
+
+        debug!(user = user.username, path, verb, allowed = status.allowed, reason = ?status.reason, "Reviewed access");
+        Ok(status.allowed)
+    }
+}
@@ -0,0 +1,122 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use crate::documents::Documents;
+use crate::export;
+use crate::sbom;
+use crate::workload::WorkloadState;
+use actix_web::{get, web, HttpResponse};
+use bommer_api::data::{ImageRef, SbomDetails, SbomFormat, SbomPackage, SbomState, SbomSummary};
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// Query parameters for getting an SBOM
+#[derive(Clone, Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SbomQuery {
+    /// The reference of the image, as reported by the workload
+    pub image: String,
+}
+
+/// Get the SBOM document of an image
+///
+/// Only images of the current workload are supported. As bommer only keeps a summary, the
+/// document is served from the document store if configured, or fetched from the SBOM source
+/// again.
+#[utoipa::path(
+    tag = "sbom",
+    params(SbomQuery),
+    responses(
+        (status = 200, description = "The SBOM document, as SPDX or CycloneDX (JSON)"),
+        (status = 400, description = "Invalid image reference"),
+        (status = 404, description = "The image isn't part of the workload, or the source has no document"),
+        (status = 502, description = "Failed to fetch the document from the SBOM source"),
+    )
+)]
+#[get("/api/v1/sbom")]
+pub async fn get_sbom(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    documents: web::Data<Documents>,
+    query: web::Query<SbomQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let image: ImageRef = query.image.parse()?;
+    let document = document(&map, &documents, &image).await?;
+
+    let content_type = match sbom::detect(&document) {
+        Ok(SbomFormat::Spdx) => export::spdx::CONTENT_TYPE,
+        Ok(SbomFormat::CycloneDx) => export::cyclonedx::CONTENT_TYPE,
+        _ => "application/json",
+    };
+
+    Ok(HttpResponse::Ok().content_type(content_type).body(document))
+}
+
+/// Get the details of the SBOM of an image
+///
+/// Instead of the document, this returns its summary and the packages it contains, independent
+/// of the format of the SBOM. The same restrictions as for getting the document apply.
+#[utoipa::path(
+    tag = "sbom",
+    params(SbomQuery),
+    responses(
+        (status = 200, description = "The details of the SBOM", body = SbomDetails),
+        (status = 400, description = "Invalid image reference"),
+        (status = 404, description = "The image isn't part of the workload, or the source has no document"),
+        (status = 422, description = "The document isn't a supported SBOM"),
+        (status = 502, description = "Failed to fetch the document from the SBOM source"),
+    )
+)]
+#[get("/api/v1/sbom/details")]
+pub async fn get_sbom_details(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    documents: web::Data<Documents>,
+    query: web::Query<SbomQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let image: ImageRef = query.image.parse()?;
+    let document = document(&map, &documents, &image).await?;
+
+    let summary = sbom::parse(&document).map_err(|err| ApiError::InvalidSbom(err.to_string()))?;
+    let summary = SbomSummary {
+        verified: sbom::verify(&summary, &image),
+        ..summary
+    };
+    let packages =
+        sbom::packages(&document).map_err(|err| ApiError::InvalidSbom(err.to_string()))?;
+
+    let roots = packages.roots.iter().collect::<HashSet<_>>();
+    let packages = packages
+        .packages
+        .iter()
+        .map(|package| SbomPackage {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            purl: package.purl.clone(),
+            licenses: package.licenses.clone(),
+            top_level: roots.contains(&package.id),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(SbomDetails { summary, packages }))
+}
+
+/// fetch the document of an image, which must be part of the workload
+async fn document(
+    map: &WorkloadState,
+    documents: &Documents,
+    image: &ImageRef,
+) -> Result<Bytes, ApiError> {
+    let summary = match map.get_state().await.get(image) {
+        Some(state) => match &state.sbom {
+            SbomState::Found(summary) => Some(summary.clone()),
+            _ => None,
+        },
+        None => return Err(ApiError::ImageNotFound),
+    };
+
+    documents
+        .get(image, summary.as_ref())
+        .await
+        .map_err(|err| ApiError::Upstream(err.to_string()))?
+        .ok_or(ApiError::DocumentNotFound)
+}
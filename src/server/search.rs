@@ -0,0 +1,90 @@
+use super::auth::Identity;
+use super::error::ApiError;
+use crate::search::{Error, PackageIndex};
+use crate::workload::WorkloadState;
+use actix_web::{get, web, HttpResponse};
+use bommer_api::data::PackageMatch;
+use packageurl::PackageUrl;
+use std::str::FromStr;
+
+/// Query parameters for searching images by package
+#[derive(Clone, Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// The package URL to look up, e.g. `pkg:maven/org.apache.logging.log4j/log4j-core`. Matches all versions, unless it includes one
+    pub purl: Option<String>,
+    /// A full-text query, searching the name and licenses of packages, e.g. `log4j` or `license:GPL`
+    pub q: Option<String>,
+    /// Maximum number of packages to return
+    #[serde(default = "default_limit")]
+    #[param(default = 1000)]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    1000
+}
+
+/// Search the images of the workload containing a package
+///
+/// Requires the package index to be enabled, which indexes the packages of the SBOMs found.
+/// Packages are either looked up by their package URL (`purl`), or using a full-text query (`q`).
+/// Images are returned along with the matching packages, and the pods using them.
+#[utoipa::path(
+    tag = "sbom",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "The images containing the package", body = Vec<PackageMatch>),
+        (status = 400, description = "Invalid package URL or query, or neither provided"),
+        (status = 404, description = "The package index isn't enabled"),
+    )
+)]
+#[get("/api/v1/search")]
+pub async fn search(
+    _identity: Identity,
+    map: web::Data<WorkloadState>,
+    index: web::Data<Option<PackageIndex>>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let index = index
+        .as_ref()
+        .as_ref()
+        .ok_or(ApiError::Disabled("The package index is not enabled"))?;
+
+    let found = match (&query.purl, &query.q) {
+        (Some(purl), None) => {
+            let purl = PackageUrl::from_str(purl)
+                .map_err(|err| ApiError::InvalidRequest(format!("Invalid package URL: {err}")))?;
+            index.lookup(&purl, query.limit)
+        }
+        (None, Some(q)) => index.query(q, query.limit),
+        _ => {
+            return Err(ApiError::InvalidRequest(
+                "Either a package URL or a query is required".into(),
+            ))
+        }
+    }
+    .map_err(|err| match err {
+        Error::Query(_) => ApiError::InvalidRequest(err.to_string()),
+        Error::Index(_) => ApiError::Internal(err.to_string()),
+    })?;
+
+    let state = map.get_state().await;
+    let matches = found
+        .into_iter()
+        .filter_map(|(image, packages)| {
+            // the index might be ahead of the workload
+            let state = state.get(&image)?;
+            let mut pods = state.pods.iter().cloned().collect::<Vec<_>>();
+            pods.sort_unstable();
+            Some(PackageMatch {
+                namespaces: pods.iter().map(|pod| pod.namespace.clone()).collect(),
+                image,
+                packages,
+                pods,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(matches))
+}
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Context};
+use parking_lot::RwLock;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// A certificate resolver, which re-loads the certificate once the files change
+pub struct ReloadingResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<Current>,
+}
+
+struct Current {
+    key: Arc<CertifiedKey>,
+    modified: Option<SystemTime>,
+}
+
+impl ReloadingResolver {
+    pub fn new(cert: PathBuf, key: PathBuf) -> anyhow::Result<Self> {
+        let modified = last_modified(&cert, &key);
+        let certified = load(&cert, &key)?;
+
+        Ok(Self {
+            cert,
+            key,
+            current: RwLock::new(Current {
+                key: Arc::new(certified),
+                modified,
+            }),
+        })
+    }
+
+    /// check if the files changed, and re-load them if they did
+    pub fn reload(&self) {
+        let modified = last_modified(&self.cert, &self.key);
+        if modified == self.current.read().modified {
+            return;
+        }
+
+        match load(&self.cert, &self.key) {
+            Ok(key) => {
+                info!("Reloaded TLS certificate");
+                *self.current.write() = Current {
+                    key: Arc::new(key),
+                    modified,
+                };
+            }
+            Err(err) => {
+                // keep the current one, maybe we caught the files while being written
+                warn!("Failed to reload TLS certificate: {err:#}");
+            }
+        }
+    }
+
+    /// periodically check for changes
+    pub async fn run(self: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            self.reload();
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().key.clone())
+    }
+}
+
+/// the most recent modification time of either file
+fn last_modified(cert: &Path, key: &Path) -> Option<SystemTime> {
+    [cert, key]
+        .into_iter()
+        .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+fn load(cert: &Path, key: &Path) -> anyhow::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        std::fs::File::open(cert).with_context(|| format!("Failed to open {}", cert.display()))?,
+    ))?
+    .into_iter()
+    .map(Certificate)
+    .collect::<Vec<_>>();
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(
+        std::fs::File::open(key).with_context(|| format!("Failed to open {}", key.display()))?,
+    ))?
+    .into_iter()
+    .find_map(|item| match item {
+        rustls_pemfile::Item::RSAKey(key)
+        | rustls_pemfile::Item::PKCS8Key(key)
+        | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+        _ => None,
+    })
+    .ok_or_else(|| anyhow!("No private key found in {}", key.display()))?;
+
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| anyhow!("Unsupported private key type"))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// create the TLS server configuration
+pub fn server_config(resolver: Arc<ReloadingResolver>) -> rustls::ServerConfig {
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
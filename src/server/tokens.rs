@@ -0,0 +1,128 @@
+use anyhow::Context;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// minimum time between two checks of the token file for changes
+const MIN_RELOAD: Duration = Duration::from_secs(30);
+
+/// What a static API token grants access to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Scope {
+    /// Reading the workload
+    Read,
+    /// Reading the workload, and using the admin API
+    Admin,
+}
+
+/// A static API token, as configured in the token file
+#[derive(Debug, serde::Deserialize)]
+struct Entry {
+    /// A name for the token, identifying the caller
+    name: String,
+    scope: Scope,
+    token: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub name: String,
+    pub scope: Scope,
+}
+
+struct Loaded {
+    /// tokens by their hash, so that looking them up doesn't leak them through timing
+    tokens: HashMap<[u8; 32], ApiToken>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// Static API tokens, read from a file (e.g. mounted from a Secret)
+///
+/// The file is a JSON array of tokens, each with a `name`, a `scope` (`read` or `admin`), and the
+/// `token` itself. It is re-read when it changes, so that tokens can be rotated without a restart.
+pub struct ApiTokens {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+impl ApiTokens {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let tokens = load(&path)?;
+        info!("Loaded {} API tokens from {}", tokens.len(), path.display());
+
+        Ok(Self {
+            loaded: RwLock::new(Loaded {
+                tokens,
+                modified: modified(&path),
+                checked: Instant::now(),
+            }),
+            path,
+        })
+    }
+
+    /// look up a token, re-reading the file first if it changed
+    pub fn get(&self, token: &str) -> Option<ApiToken> {
+        self.reload();
+        self.loaded.read().tokens.get(&hash(token)).cloned()
+    }
+
+    fn reload(&self) {
+        if self.loaded.read().checked.elapsed() < MIN_RELOAD {
+            return;
+        }
+
+        let mut loaded = self.loaded.write();
+        loaded.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified == loaded.modified {
+            return;
+        }
+
+        // keep the current tokens if the new file is broken
+        match load(&self.path) {
+            Ok(tokens) => {
+                info!(
+                    "Reloaded {} API tokens from {}",
+                    tokens.len(),
+                    self.path.display()
+                );
+                loaded.tokens = tokens;
+                loaded.modified = modified;
+            }
+            Err(err) => warn!("Failed to reload API tokens: {err:#}"),
+        }
+    }
+}
+
+fn hash(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load(path: &Path) -> anyhow::Result<HashMap<[u8; 32], ApiToken>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let entries: Vec<Entry> = serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            (
+                hash(&entry.token),
+                ApiToken {
+                    name: entry.name,
+                    scope: entry.scope,
+                },
+            )
+        })
+        .collect())
+}
@@ -1,21 +1,103 @@
-use crate::pubsub::Subscription;
+use super::limit::{Permit, Subscribers};
+use crate::pubsub::{SlowSubscriber, Subscription};
+use actix_web::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use actix_web::{HttpRequest, HttpResponse};
 use actix_ws::{CloseCode, CloseReason, Message};
-use bommer_api::data::{Event, Image, ImageRef, SbomState};
+use bommer_api::codec::{Encoding, Frame};
+use bommer_api::data::{Event, Image, ImageRef};
 use futures::StreamExt;
+use serde::Serialize;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::{interval, Instant};
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, Span};
 
-const HEARTBEAT: Duration = Duration::from_secs(5);
-const TIMEOUT: Duration = Duration::from_secs(20);
+/// maximum number of events sent in a single batch, sending it early once reached
+const MAX_BATCH: usize = 1024;
+
+/// An event, serialized the same way as [`bommer_api::data::RevisionedEvent`]
+///
+/// It keeps the event shared with other subscribers, rather than cloning it for serializing it.
+#[derive(Serialize)]
+struct Revisioned {
+    revision: u64,
+    #[serde(flatten)]
+    event: Arc<Event<ImageRef, Image>>,
+}
+
+/// Settings of websocket sessions
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Interval of sending pings
+    pub interval: Duration,
+    /// Time after which a session without any response from the client gets closed
+    pub timeout: Duration,
+    /// Window for coalescing events of the same image, if enabled
+    pub coalesce: Option<Duration>,
+    /// Window for collecting events into a batch, for clients requesting batches
+    pub batch: Duration,
+    /// How to handle clients which can't keep up
+    pub slow_subscriber: SlowSubscriber,
+    /// Events buffered for each session
+    pub buffer: usize,
+    /// Compress messages, if the client supports it
+    pub compression: bool,
+    /// Log when sessions start and end
+    pub access_log: bool,
+    /// Limits the number of concurrent sessions
+    pub subscribers: Subscribers,
+    /// Cancelled when shutting down, closing all sessions
+    pub shutdown: CancellationToken,
+}
+
+/// the encoding requested by the client, if it requested a supported subprotocol
+///
+/// Requesting none of the supported ones doesn't fail the handshake, the client gets JSON. As
+/// no protocol gets confirmed, the client can tell, or fail the connection on its own.
+pub fn encoding(req: &HttpRequest) -> Option<Encoding> {
+    req.headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()
+        .and_then(Encoding::negotiate)
+}
+
+/// confirm the subprotocol, if the client requested a supported one
+pub fn accept_encoding(res: &mut HttpResponse, encoding: Option<Encoding>) {
+    if let Some(encoding) = encoding {
+        res.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(encoding.protocol()),
+        );
+    }
+}
 
 pub async fn run(
-    mut subscription: Subscription<ImageRef, Image>,
+    subscription: Subscription<ImageRef, Image>,
     mut session: actix_ws::Session,
     mut msg_stream: actix_ws::MessageStream,
+    settings: Settings,
+    encoding: Encoding,
+    // send arrays of events, rather than individual ones
+    batch: bool,
+    // released when the session ends
+    _permit: Permit,
 ) {
-    let close_reason: Option<CloseReason> = {
+    let mut subscription = match settings.coalesce {
+        Some(window) => subscription.coalesce(window, settings.buffer),
+        None => subscription,
+    };
+
+    let close_reason: Option<Option<CloseReason>> = {
         let mut last_heartbeat = Instant::now();
-        let mut interval = interval(HEARTBEAT);
+        let mut interval = interval(settings.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // events collected for the next batch, sent once the window started by the first one ends
+        let mut pending = Vec::new();
+        let flush = sleep(settings.batch);
+        tokio::pin!(flush);
 
         loop {
             tokio::select! {
@@ -23,81 +105,141 @@ pub async fn run(
                     match msg {
                         None => {
                             // stream ended
-                            break None;
+                            break Some(None);
                         }
                         Some(Err(err)) => {
-                            break Some(CloseReason{
+                            break Some(Some(CloseReason{
                                 code: CloseCode::Error,
                                 description: Some(err.to_string()),
-                            })
+                            }))
                         },
                         Some(Ok(Message::Close(reason))) => {
                             // mirror reason
-                            break reason;
+                            break Some(reason);
                         }
                         Some(Ok(Message::Nop)) => {
                         },
                         Some(Ok(Message::Ping(data))) => {
                             last_heartbeat = Instant::now();
-                            let _ =  session.pong(&data).await;
+                            if session.pong(&data).await.is_err() {
+                                break None;
+                            }
                         }
                         Some(Ok(Message::Pong(_)))=> {
                             last_heartbeat = Instant::now();
                         }
                         Some(Ok(Message::Text(_) | Message::Binary(_))) => {
-                            break Some((CloseCode::Protocol, "Must not send data").into());
+                            break Some(Some((CloseCode::Protocol, "Must not send data").into()));
                         }
                         Some(Ok(Message::Continuation(_))) => {
                         }
                     }
                 },
-                evt = subscription.recv() => {
+                evt = subscription.recv_revision() => {
                     match evt {
-                        None => break Some(CloseCode::Restart.into()),
-                        Some(evt) => {
-                            if let Err(err) = handle_evt(&mut session, evt).await {
-                                break Some((CloseCode::Error, err.to_string()).into());
+                        None => break Some(Some(CloseCode::Restart.into())),
+                        Some((revision, event)) if batch => {
+                            if pending.is_empty() {
+                                flush.as_mut().reset(Instant::now() + settings.batch);
+                            }
+                            pending.push(Revisioned { revision, event });
+                            if pending.len() >= MAX_BATCH {
+                                if let Err(err) = send(&mut session, encoding, &std::mem::take(&mut pending)).await {
+                                    break Some(Some((CloseCode::Error, err.to_string()).into()));
+                                }
                             }
                         }
+                        Some((revision, event)) => {
+                            if let Err(err) = send(&mut session, encoding, &Revisioned { revision, event }).await {
+                                break Some(Some((CloseCode::Error, err.to_string()).into()));
+                            }
+                        }
+                    }
+                }
+                _ = &mut flush, if !pending.is_empty() => {
+                    if let Err(err) = send(&mut session, encoding, &std::mem::take(&mut pending)).await {
+                        break Some(Some((CloseCode::Error, err.to_string()).into()));
                     }
                 }
+                _ = settings.shutdown.cancelled() => {
+                    break Some(Some((CloseCode::Away, "Server shutting down").into()));
+                }
                 _  = interval.tick() => {
-                    if Instant::now() - last_heartbeat > TIMEOUT {
-                        break None;
+                    if last_heartbeat.elapsed() > settings.timeout {
+                        debug!("Closing websocket session, client didn't respond in time");
+                        break Some(Some((CloseCode::Policy, "Heartbeat timeout").into()));
                     }
 
                     // we still have time to send one
-                    let _ = session.ping(b"").await;
+                    if session.ping(b"").await.is_err() {
+                        // session is already closed
+                        break None;
+                    }
                 }
             }
         }
     };
 
-    let _ = session.close(close_reason).await;
+    // stop receiving events right away, rather than when closing the session has finished
+    drop(subscription);
+
+    if let Some(Some(reason)) = &close_reason {
+        Span::current().record("close", tracing::field::debug(reason.code));
+    }
+
+    if let Some(close_reason) = close_reason {
+        let _ = session.close(close_reason).await;
+    }
 }
 
-async fn handle_evt(
+async fn send<T: Serialize>(
     session: &mut actix_ws::Session,
-    mut evt: Event<ImageRef, Image>,
+    encoding: Encoding,
+    msg: &T,
 ) -> anyhow::Result<()> {
-    match &mut evt {
-        Event::Added(_, state) | Event::Modified(_, state) => {
-            strip_sbom(&mut state.sbom);
-        }
-        Event::Restart(state) => {
-            for state in &mut state.values_mut() {
-                strip_sbom(&mut state.sbom);
-            }
-        }
-        _ => {}
+    match encoding.encode(msg)? {
+        Frame::Text(text) => session.text(text).await?,
+        Frame::Binary(data) => session.binary(data).await?,
     }
-    session.text(serde_json::to_string(&evt)?).await?;
 
     Ok(())
 }
 
-fn strip_sbom(mut sbom: &mut SbomState) {
-    if let SbomState::Found(sbom) = &mut sbom {
-        sbom.data.clear();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn requested(protocols: Option<&str>) -> Option<Encoding> {
+        let mut req = TestRequest::default();
+        if let Some(protocols) = protocols {
+            req = req.insert_header((SEC_WEBSOCKET_PROTOCOL, protocols));
+        }
+        encoding(&req.to_http_request())
+    }
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(requested(None), None);
+        assert_eq!(
+            requested(Some("bommer.v1.msgpack, bommer.v1.json")),
+            Some(Encoding::MessagePack)
+        );
+        assert_eq!(requested(Some("bommer.v1.json")), Some(Encoding::Json));
+        // falls back to JSON, without confirming a protocol
+        assert_eq!(requested(Some("bommer.v2.cbor")), None);
+    }
+
+    #[test]
+    fn accept() {
+        let mut res = HttpResponse::Ok().finish();
+        accept_encoding(&mut res, None);
+        assert!(res.headers().get(SEC_WEBSOCKET_PROTOCOL).is_none());
+
+        accept_encoding(&mut res, Some(Encoding::MessagePack));
+        assert_eq!(
+            res.headers().get(SEC_WEBSOCKET_PROTOCOL).unwrap(),
+            "bommer.v1.msgpack"
+        );
     }
 }
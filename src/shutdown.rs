@@ -0,0 +1,50 @@
+//! Shutting down gracefully, once the process got asked to terminate.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Shutdown")]
+pub struct ShutdownConfig {
+    /// Time to wait for draining the scanner, closing sessions and persisting the state, before exiting anyway
+    #[arg(long, env = "SHUTDOWN_TIMEOUT", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub shutdown_timeout: Duration,
+}
+
+/// cancel the token once SIGTERM or SIGINT is received
+pub async fn on_signal(shutdown: CancellationToken) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term = signal(SignalKind::terminate())?;
+        let mut int = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = term.recv() => info!("Received SIGTERM, shutting down"),
+            _ = int.recv() => info!("Received SIGINT, shutting down"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        info!("Received Ctrl-C, shutting down");
+    }
+
+    shutdown.cancel();
+
+    Ok(())
+}
+
+/// run a task until shutting down, for tasks which don't need to clean up
+pub async fn until<F>(shutdown: &CancellationToken, task: F) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    tokio::select! {
+        result = task => result,
+        _ = shutdown.cancelled() => Ok(()),
+    }
+}
@@ -0,0 +1,347 @@
+//! Persisting the workload state, so that a restart doesn't need to look up all SBOMs again.
+//!
+//! The state is kept in an embedded database (sled), which gets updated with every change of the
+//! workload. Along with the images, the state of the pod watchers is persisted, allowing them to
+//! resume watching instead of listing all pods again.
+
+use crate::store::{Checkpoints, WatchState};
+use crate::workload::WorkloadState;
+use anyhow::Context;
+use bommer_api::data::{Event, Image, ImageRef};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// version of the format of the stored state
+const VERSION: u32 = 2;
+
+/// key of the version, in the default tree
+const VERSION_KEY: &str = "version";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Snapshot")]
+pub struct SnapshotConfig {
+    /// Directory of the database to persist the state to, and load it from on startup
+    #[arg(long, env = "STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Interval of persisting the progress of the pod watchers
+    #[arg(long, env = "STATE_SAVE_INTERVAL", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub state_save_interval: Duration,
+}
+
+/// The persisted state
+#[derive(Clone, Debug, Default)]
+pub struct State {
+    pub images: im::HashMap<ImageRef, Image>,
+    pub watches: Vec<WatchState>,
+}
+
+impl SnapshotConfig {
+    /// open the database, if enabled
+    pub fn open(&self) -> anyhow::Result<Persistence> {
+        let database = match &self.state_dir {
+            Some(path) => Some(
+                Database::new(
+                    sled::open(path)
+                        .with_context(|| format!("Failed to open state {}", path.display()))?,
+                )
+                .with_context(|| format!("Failed to initialize state {}", path.display()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Persistence {
+            database,
+            save_interval: self.state_save_interval,
+        })
+    }
+}
+
+/// Access to the persisted state, a no-op if persistence isn't enabled
+#[derive(Clone)]
+pub struct Persistence {
+    database: Option<Database>,
+    save_interval: Duration,
+}
+
+impl Persistence {
+    /// load the persisted state, empty if there is none, or it can't be used
+    pub fn load(&self) -> State {
+        let Some(database) = &self.database else {
+            return Default::default();
+        };
+
+        match database.load() {
+            Ok(state) => {
+                info!("Loaded state of {} images", state.images.len());
+                state
+            }
+            Err(err) => {
+                warn!("Ignoring persisted state: {err:#}");
+                Default::default()
+            }
+        }
+    }
+
+    /// persist the current progress of the watchers, and make sure everything is written
+    pub async fn flush(&self, watches: &Checkpoints) -> anyhow::Result<()> {
+        let Some(database) = &self.database else {
+            return Ok(());
+        };
+
+        database.save_watches(&watches.get().await, &mut HashMap::new())?;
+        database.db.flush_async().await?;
+        info!("Persisted state");
+
+        Ok(())
+    }
+
+    /// persist every change of the workload, and periodically the progress of the watchers
+    pub async fn run(self, map: WorkloadState, watches: Checkpoints) -> anyhow::Result<()> {
+        let Some(database) = self.database else {
+            return futures::future::pending().await;
+        };
+
+        let mut interval = tokio::time::interval(self.save_interval);
+        let mut sub = map.subscribe("snapshot", None).await;
+        // the last persisted state of each watcher
+        let mut saved = HashMap::new();
+
+        loop {
+            tokio::select! {
+                evt = sub.recv() => match evt {
+                    Some(evt) => {
+                        if let Err(err) = database.apply(&evt) {
+                            // start over with the full state
+                            warn!("Failed to persist change: {err:#}");
+                            sub = map.subscribe("snapshot", None).await;
+                        }
+                    }
+                    None => {
+                        // the subscription got dropped, we start over with the full state
+                        sub = map.subscribe("snapshot", None).await;
+                    }
+                },
+                _ = interval.tick() => {
+                    if let Err(err) = database.save_watches(&watches.get().await, &mut saved) {
+                        // try again next time
+                        warn!("Failed to persist the state of the watchers: {err:#}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The database, with one tree holding the images, and one holding the watchers
+#[derive(Clone)]
+struct Database {
+    db: sled::Db,
+    /// images, by their reference, with their JSON encoded state
+    images: sled::Tree,
+    /// watchers, by their fingerprint, with their JSON encoded state
+    watches: sled::Tree,
+}
+
+impl Database {
+    /// open the trees, dropping a state of a different version
+    fn new(db: sled::Db) -> anyhow::Result<Self> {
+        let images = db.open_tree("images")?;
+        let watches = db.open_tree("watches")?;
+
+        let version = db
+            .get(VERSION_KEY)?
+            .and_then(|version| Some(u32::from_be_bytes(version.as_ref().try_into().ok()?)));
+        if version != Some(VERSION) {
+            if let Some(version) = version {
+                warn!("Dropping persisted state of unsupported version: {version}");
+            }
+            images.clear()?;
+            watches.clear()?;
+            db.insert(VERSION_KEY, &VERSION.to_be_bytes())?;
+        }
+
+        Ok(Self {
+            db,
+            images,
+            watches,
+        })
+    }
+
+    fn load(&self) -> anyhow::Result<State> {
+        let mut images = im::HashMap::new();
+        for entry in self.images.iter() {
+            let (key, value) = entry?;
+            let key = String::from_utf8_lossy(&key);
+            match key
+                .parse::<ImageRef>()
+                .map_err(anyhow::Error::from)
+                .and_then(|image| Ok((image, serde_json::from_slice(&value)?)))
+            {
+                Ok((image, state)) => {
+                    images.insert(image, state);
+                }
+                Err(err) => warn!("Ignoring persisted image {key}: {err}"),
+            }
+        }
+
+        let watches = self
+            .watches
+            .iter()
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(State { images, watches })
+    }
+
+    /// apply a change of the workload, a restart replaces all images
+    fn apply(&self, evt: &Event<ImageRef, Image>) -> anyhow::Result<()> {
+        match evt {
+            Event::Added(image, state) | Event::Modified(image, state) => {
+                self.images
+                    .insert(image.to_string(), serde_json::to_vec(state)?)?;
+            }
+            Event::Removed(image) => {
+                self.images.remove(image.to_string())?;
+            }
+            Event::Restart(state) => {
+                let mut batch = sled::Batch::default();
+                for key in self.images.iter().keys() {
+                    let key = key?;
+                    let gone = std::str::from_utf8(&key)
+                        .ok()
+                        .and_then(|key| key.parse().ok())
+                        .is_none_or(|image| !state.contains_key(&image));
+                    if gone {
+                        batch.remove(key);
+                    }
+                }
+                for (image, state) in state {
+                    batch.insert(image.to_string().as_bytes(), serde_json::to_vec(state)?);
+                }
+                // a batch is applied atomically
+                self.images.apply_batch(batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// persist the state of the watchers which changed since the last time
+    fn save_watches(
+        &self,
+        watches: &[WatchState],
+        saved: &mut HashMap<String, Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        let mut current = HashMap::with_capacity(watches.len());
+        for watch in watches {
+            let value = serde_json::to_vec(watch)?;
+            if saved.get(&watch.id) != Some(&value) {
+                batch.insert(watch.id.as_bytes(), value.as_slice());
+            }
+            current.insert(watch.id.clone(), value);
+        }
+        for key in self.watches.iter().keys() {
+            let key = key?;
+            if !current.contains_key(String::from_utf8_lossy(&key).as_ref()) {
+                batch.remove(key);
+            }
+        }
+        self.watches.apply_batch(batch)?;
+        *saved = current;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bommer_api::data::SbomState;
+
+    fn database() -> Database {
+        Database::new(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn changes() {
+        let database = database();
+        let first: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        let second: ImageRef = "quay.io/example/second:1.0".parse().unwrap();
+
+        database
+            .apply(&Event::Restart(im::HashMap::from_iter([
+                (first.clone(), Image::new(SbomState::Scheduled)),
+                (second.clone(), Image::new(SbomState::Scheduled)),
+            ])))
+            .unwrap();
+        database
+            .apply(&Event::Modified(
+                first.clone(),
+                Image::new(SbomState::Missing),
+            ))
+            .unwrap();
+        database.apply(&Event::Removed(second.clone())).unwrap();
+
+        let state = database.load().unwrap();
+        assert_eq!(
+            state.images,
+            im::HashMap::from_iter([(first.clone(), Image::new(SbomState::Missing))])
+        );
+
+        // a restart replaces everything
+        database
+            .apply(&Event::Restart(im::HashMap::from_iter([(
+                second.clone(),
+                Image::new(SbomState::Missing),
+            )])))
+            .unwrap();
+        let state = database.load().unwrap();
+        assert_eq!(
+            state.images,
+            im::HashMap::from_iter([(second, Image::new(SbomState::Missing))])
+        );
+    }
+
+    #[test]
+    fn watches() {
+        let database = database();
+        let watch = |id: &str| WatchState {
+            id: id.into(),
+            resource_version: "1".into(),
+            containers: vec![],
+        };
+
+        let mut saved = HashMap::new();
+        database
+            .save_watches(&[watch("a"), watch("b")], &mut saved)
+            .unwrap();
+        database.save_watches(&[watch("b")], &mut saved).unwrap();
+
+        let ids = database
+            .load()
+            .unwrap()
+            .watches
+            .into_iter()
+            .map(|watch| watch.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn version() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let database = Database::new(db.clone()).unwrap();
+        let image_ref: ImageRef = "quay.io/example/first:1.0".parse().unwrap();
+        database
+            .apply(&Event::Added(image_ref, Image::new(SbomState::Missing)))
+            .unwrap();
+
+        // a different version gets dropped
+        db.insert(VERSION_KEY, &1u32.to_be_bytes()).unwrap();
+        let database = Database::new(db).unwrap();
+        assert!(database.load().unwrap().images.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+mod purl;
+
+pub use purl::PurlConfig;
+
+use crate::http;
+use bommer_api::data::{ImageRef, LookupError, SbomSummary, Vulnerabilities};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+use std::sync::Arc;
+
+/// The backend to look up SBOMs from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceKind {
+    Bombastic,
+    Guac,
+    DependencyTrack,
+}
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "SBOM source")]
+pub struct SourceConfig {
+    /// The backend to look up SBOMs from
+    #[arg(
+        long = "sbom-source",
+        env = "SBOM_SOURCE",
+        value_enum,
+        default_value_t = SourceKind::Bombastic
+    )]
+    pub kind: SourceKind,
+
+    /// Maximum size of SBOM documents, larger ones are rejected while downloading them
+    #[arg(long, env = "MAX_SBOM_SIZE", default_value = "64MiB", value_parser = http::parse_size)]
+    pub max_sbom_size: u64,
+
+    #[command(flatten)]
+    pub purl: PurlConfig,
+}
+
+/// An SBOM, as found by a source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sbom {
+    pub summary: SbomSummary,
+    /// Vulnerabilities, if the source reports them along with the SBOM
+    pub vulnerabilities: Option<Vulnerabilities>,
+    /// The document, if the source fetched it for the lookup
+    pub document: Option<Bytes>,
+}
+
+impl From<SbomSummary> for Sbom {
+    fn from(summary: SbomSummary) -> Self {
+        Self {
+            summary,
+            vulnerabilities: None,
+            document: None,
+        }
+    }
+}
+
+/// A source of SBOMs
+///
+/// Looks up the SBOM of an image. If the source doesn't know the image, that's not an error,
+/// but `None`.
+#[async_trait::async_trait]
+pub trait SbomSource: Send + Sync {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError>;
+
+    /// fetch the full SBOM document of an image, if the source can provide it
+    async fn document(&self, _image: &ImageRef) -> Result<Option<Bytes>, LookupError> {
+        Ok(None)
+    }
+}
+
+/// Looks up SBOMs from a fallback source, when the primary source doesn't have one
+pub struct FallbackSource {
+    pub primary: Arc<dyn SbomSource>,
+    pub fallback: Arc<dyn SbomSource>,
+}
+
+#[async_trait::async_trait]
+impl SbomSource for FallbackSource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        match self.primary.lookup(image).await? {
+            Some(sbom) => Ok(Some(sbom)),
+            None => self.fallback.lookup(image).await,
+        }
+    }
+
+    async fn document(&self, image: &ImageRef) -> Result<Option<Bytes>, LookupError> {
+        match self.primary.document(image).await? {
+            Some(document) => Ok(Some(document)),
+            None => self.fallback.document(image).await,
+        }
+    }
+}
+
+/// Looks up SBOMs from several instances of a source, e.g. a staging and a production instance
+///
+/// The SBOM of the first instance in order which knows the image is used. Instances are either
+/// queried one after the other, stopping at the first one finding an SBOM, or all at once. An
+/// error of an instance is only reported if none of the instances finds an SBOM.
+pub struct AggregateSource {
+    pub sources: Vec<Arc<dyn SbomSource>>,
+    pub parallel: bool,
+}
+
+impl AggregateSource {
+    async fn first<'a, T, F, Fut>(&'a self, f: F) -> Result<Option<T>, LookupError>
+    where
+        F: Fn(&'a dyn SbomSource) -> Fut + Send + Sync,
+        Fut: Future<Output = Result<Option<T>, LookupError>> + Send + 'a,
+        T: Send + 'a,
+    {
+        let mut results = match self.parallel {
+            true => {
+                let results =
+                    futures::future::join_all(self.sources.iter().map(|source| f(source.as_ref())))
+                        .await;
+                stream::iter(results).boxed()
+            }
+            false => stream::iter(&self.sources)
+                .then(|source| f(source.as_ref()))
+                .boxed(),
+        };
+
+        let mut error = None;
+        while let Some(result) = results.next().await {
+            match result {
+                Ok(Some(found)) => return Ok(Some(found)),
+                Ok(None) => {}
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SbomSource for AggregateSource {
+    async fn lookup(&self, image: &ImageRef) -> Result<Option<Sbom>, LookupError> {
+        self.first(|source| source.lookup(image)).await
+    }
+
+    async fn document(&self, image: &ImageRef) -> Result<Option<Bytes>, LookupError> {
+        self.first(|source| source.document(image)).await
+    }
+}
@@ -0,0 +1,164 @@
+use bommer_api::data::{ImageRef, LookupError, LookupErrorKind};
+use packageurl::PackageUrl;
+use std::collections::HashSet;
+
+/// The shape of a package URL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Shape {
+    purl_type: PurlType,
+    repository_url: bool,
+    /// Use the tag as version, instead of the digest
+    by_tag: bool,
+    tag_qualifier: bool,
+}
+
+/// The type of package URLs to create for images
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PurlType {
+    /// `pkg:oci/<name>@<digest>`, using the last segment of the repository as name
+    #[default]
+    Oci,
+    /// `pkg:docker/<namespace>/<name>@<digest>`, using the full repository
+    Docker,
+}
+
+/// How to create the package URLs of images, which SBOMs and vulnerabilities are looked up by
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Package URLs")]
+pub struct PurlConfig {
+    /// Type of the package URLs of images
+    #[arg(long, env = "PURL_TYPE", value_enum, default_value_t)]
+    pub purl_type: PurlType,
+
+    /// Add the `repository_url` qualifier: the registry and repository for `oci`, the registry for `docker`
+    #[arg(long, env = "PURL_REPOSITORY_URL")]
+    pub purl_repository_url: bool,
+
+    /// Add the `tag` qualifier, for images having a tag
+    #[arg(long, env = "PURL_TAG")]
+    pub purl_tag: bool,
+
+    /// Try alternative package URLs when looking up SBOMs, if the configured one doesn't match
+    #[arg(long, env = "PURL_FALLBACK")]
+    pub purl_fallback: bool,
+}
+
+impl PurlConfig {
+    fn shape(&self) -> Shape {
+        Shape {
+            purl_type: self.purl_type,
+            repository_url: self.purl_repository_url,
+            by_tag: false,
+            tag_qualifier: self.purl_tag,
+        }
+    }
+
+    /// create the package URL of an image, which requires a digest
+    pub fn purl(&self, image: &ImageRef) -> Result<PackageUrl<'static>, LookupError> {
+        build(image, self.shape())
+    }
+
+    /// the package URLs to try when looking up an image, in order
+    ///
+    /// The configured package URL comes first. With fallbacks enabled, it's followed by the
+    /// digest-based alternatives (the other type, with or without the `repository_url` qualifier),
+    /// and finally by the tag-based ones. Fails if no package URL could be created at all.
+    pub fn candidates(&self, image: &ImageRef) -> Result<Vec<PackageUrl<'static>>, LookupError> {
+        let configured = self.shape();
+        if !self.purl_fallback {
+            return Ok(vec![build(image, configured)?]);
+        }
+
+        let other = match configured.purl_type {
+            PurlType::Oci => PurlType::Docker,
+            PurlType::Docker => PurlType::Oci,
+        };
+
+        let mut shapes = Vec::new();
+        for by_tag in [false, true] {
+            for purl_type in [configured.purl_type, other] {
+                for repository_url in [configured.repository_url, !configured.repository_url] {
+                    shapes.push(Shape {
+                        purl_type,
+                        repository_url,
+                        by_tag,
+                        tag_qualifier: configured.tag_qualifier && !by_tag,
+                    });
+                }
+            }
+        }
+
+        let mut first_err = None;
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for shape in shapes {
+            match build(image, shape) {
+                Ok(purl) => {
+                    if seen.insert(purl.to_string()) {
+                        result.push(purl);
+                    }
+                }
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+
+        match (result.is_empty(), first_err) {
+            (true, Some(err)) => Err(err),
+            _ => Ok(result),
+        }
+    }
+}
+
+fn build(image: &ImageRef, shape: Shape) -> Result<PackageUrl<'static>, LookupError> {
+    let version = match shape.by_tag {
+        false => image.digest.as_ref().filter(|d| d.starts_with("sha256:")),
+        true => image.tag.as_ref(),
+    }
+    .ok_or_else(|| LookupError {
+        kind: LookupErrorKind::InvalidReference,
+        message: format!("Unable to create PURL for: {image}"),
+        status: None,
+    })?;
+
+    let invalid = |err: packageurl::Error| LookupError {
+        kind: LookupErrorKind::InvalidReference,
+        message: err.to_string(),
+        status: None,
+    };
+
+    let (namespace, name) = match image.repository.rsplit_once('/') {
+        Some((namespace, name)) => (Some(namespace), name),
+        None => (None, image.repository.as_str()),
+    };
+
+    let mut purl = match shape.purl_type {
+        PurlType::Oci => PackageUrl::new("oci", name.to_string()).map_err(invalid)?,
+        PurlType::Docker => {
+            let mut purl = PackageUrl::new("docker", name.to_string()).map_err(invalid)?;
+            if let Some(namespace) = namespace {
+                purl.with_namespace(namespace.to_string());
+            }
+            purl
+        }
+    };
+    purl.with_version(version.clone());
+
+    if shape.repository_url {
+        let repository_url = match shape.purl_type {
+            PurlType::Oci => format!("{}/{}", image.registry, image.repository),
+            PurlType::Docker => image.registry.clone(),
+        };
+        purl.add_qualifier("repository_url", repository_url)
+            .map_err(invalid)?;
+    }
+    if let Some(arch) = &image.arch {
+        purl.add_qualifier("arch", arch.clone()).map_err(invalid)?;
+    }
+    if let Some(tag) = image.tag.as_ref().filter(|_| shape.tag_qualifier) {
+        purl.add_qualifier("tag", tag.clone()).map_err(invalid)?;
+    }
+
+    Ok(purl)
+}
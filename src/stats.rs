@@ -0,0 +1,144 @@
+//! Recording the SBOM coverage over time, so that it can be charted.
+
+use crate::store::SyncState;
+use crate::workload::WorkloadState;
+use anyhow::Context;
+use bommer_api::data::{CoverageSample, SbomStats};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Coverage history")]
+pub struct StatsConfig {
+    /// Interval of recording the SBOM coverage
+    #[arg(long, env = "COVERAGE_HISTORY_INTERVAL", default_value = "5m", value_parser = humantime::parse_duration)]
+    pub coverage_history_interval: Duration,
+
+    /// Number of recorded samples to keep, zero disables recording
+    #[arg(long, env = "COVERAGE_HISTORY", default_value_t = 288)]
+    pub coverage_history: usize,
+
+    /// File to persist the recorded samples to, and load them from on startup
+    #[arg(long, env = "COVERAGE_HISTORY_FILE")]
+    pub coverage_history_file: Option<PathBuf>,
+}
+
+/// The SBOM coverage of the workload over time
+///
+/// Samples are kept in a ring, dropping the oldest one once the configured number is reached.
+#[derive(Clone, Debug)]
+pub struct CoverageHistory {
+    config: StatsConfig,
+    samples: Arc<Mutex<VecDeque<CoverageSample>>>,
+}
+
+impl CoverageHistory {
+    /// create a new history, starting with the persisted samples, if any
+    pub fn new(config: StatsConfig) -> Self {
+        let mut samples = match &config.coverage_history_file {
+            Some(path) if path.exists() => match load(path) {
+                Ok(samples) => {
+                    info!(
+                        "Loaded {} coverage samples from {}",
+                        samples.len(),
+                        path.display()
+                    );
+                    samples
+                }
+                Err(err) => {
+                    warn!("Ignoring coverage history file {}: {err:#}", path.display());
+                    Default::default()
+                }
+            },
+            _ => VecDeque::new(),
+        };
+
+        // the number of samples to keep might have been lowered in the meantime
+        while samples.len() > config.coverage_history {
+            samples.pop_front();
+        }
+
+        Self {
+            config,
+            samples: Arc::new(Mutex::new(samples)),
+        }
+    }
+
+    /// the recorded samples, oldest first, only those after `since` if provided
+    pub fn samples(&self, since: Option<DateTime<Utc>>) -> Vec<CoverageSample> {
+        self.samples
+            .lock()
+            .iter()
+            .filter(|sample| since.is_none_or(|since| sample.timestamp > since))
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, sample: CoverageSample) {
+        let mut samples = self.samples.lock();
+        while samples.len() >= self.config.coverage_history {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// record the coverage periodically, if enabled
+    pub async fn run(self, map: WorkloadState, sync: SyncState) -> anyhow::Result<()> {
+        if self.config.coverage_history == 0 {
+            return futures::future::pending().await;
+        }
+
+        let mut interval = tokio::time::interval(self.config.coverage_history_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            // until synced, we only have a partial view of the workload
+            if !sync.is_synced() {
+                debug!("Skipping coverage sample, the pod watchers have not synced yet");
+                continue;
+            }
+
+            let mut coverage = SbomStats::default();
+            for image in map.get_state().await.values() {
+                coverage.add(&image.sbom);
+            }
+
+            self.record(CoverageSample {
+                timestamp: Utc::now(),
+                coverage,
+            });
+
+            if let Some(path) = &self.config.coverage_history_file {
+                let path = path.clone();
+                let samples = self.samples.lock().clone();
+                if let Err(err) = tokio::task::spawn_blocking(move || save(&path, &samples)).await?
+                {
+                    warn!("Failed to persist coverage history: {err:#}");
+                }
+            }
+        }
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<VecDeque<CoverageSample>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// write to a temporary file first, so that a crash never leaves a partial file behind
+fn save(path: &Path, samples: &VecDeque<CoverageSample>) -> anyhow::Result<()> {
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, serde_json::to_vec(samples)?)
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+    std::fs::rename(&temp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
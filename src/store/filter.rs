@@ -0,0 +1,486 @@
+use bommer_api::data::ImageRef;
+use k8s_openapi::api::core::v1::Pod;
+use kube::ResourceExt;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Filter for pods which should be tracked
+#[derive(Clone, Debug, Default)]
+pub struct PodFilter {
+    /// namespaces to include, all if empty
+    pub include_namespaces: HashSet<String>,
+    /// namespaces to exclude
+    pub exclude_namespaces: HashSet<String>,
+    /// label selector pods must match
+    pub label_selector: Option<LabelSelector>,
+}
+
+impl PodFilter {
+    /// create a filter which only accepts a single namespace
+    pub fn namespace(namespace: impl Into<String>) -> Self {
+        Self {
+            include_namespaces: HashSet::from_iter([namespace.into()]),
+            ..Default::default()
+        }
+    }
+
+    /// check if the namespace is accepted by the filter
+    pub fn matches_namespace(&self, namespace: &str) -> bool {
+        if self.exclude_namespaces.contains(namespace) {
+            return false;
+        }
+
+        self.include_namespaces.is_empty() || self.include_namespaces.contains(namespace)
+    }
+
+    /// check if the pod is accepted by the filter
+    pub fn matches(&self, pod: &Pod) -> bool {
+        let namespace = match pod.namespace() {
+            Some(namespace) => self.matches_namespace(&namespace),
+            None => false,
+        };
+
+        namespace
+            && self
+                .label_selector
+                .as_ref()
+                .map(|selector| selector.matches(pod.labels()))
+                .unwrap_or(true)
+    }
+
+    /// check if the pod template of a workload is accepted by the filter
+    ///
+    /// The label selector is evaluated against the labels of the template, which are the labels
+    /// the pods will get.
+    pub fn matches_template(
+        &self,
+        namespace: &str,
+        labels: Option<&BTreeMap<String, String>>,
+    ) -> bool {
+        self.matches_namespace(namespace)
+            && self
+                .label_selector
+                .as_ref()
+                .map(|selector| selector.matches(labels.unwrap_or(&BTreeMap::new())))
+                .unwrap_or(true)
+    }
+
+    /// create a field selector, evaluating the exclusions server side
+    pub fn field_selector(&self) -> Option<String> {
+        if self.exclude_namespaces.is_empty() {
+            return None;
+        }
+
+        let mut excludes = self.exclude_namespaces.iter().collect::<Vec<_>>();
+        excludes.sort_unstable();
+
+        Some(
+            excludes
+                .into_iter()
+                .map(|ns| format!("metadata.namespace!={ns}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid label selector: {0}")]
+pub struct LabelSelectorError(String);
+
+/// A label selector, in the Kubernetes string representation
+///
+/// The selector is passed on to the watcher, and also evaluated locally, so that we can re-check
+/// pods which we receive as part of a full re-list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::Equals(key, value) => labels.get(key) == Some(value),
+            Self::NotEquals(key, value) => labels.get(key) != Some(value),
+            Self::In(key, values) => labels.get(key).map(|v| values.contains(v)) == Some(true),
+            Self::NotIn(key, values) => labels.get(key).map(|v| values.contains(v)) != Some(true),
+            Self::Exists(key) => labels.contains_key(key),
+            Self::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl LabelSelector {
+    /// check if the labels match the selector
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+}
+
+/// split the requirements of a selector, ignoring commas inside of value sets
+fn split_requirements(s: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&s[start..]);
+
+    result
+}
+
+fn parse_set(s: &str) -> Option<Vec<String>> {
+    let s = s.trim().strip_prefix('(')?.strip_suffix(')')?;
+    Some(s.split(',').map(|v| v.trim().to_string()).collect())
+}
+
+impl FromStr for Requirement {
+    type Err = LabelSelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let err = || LabelSelectorError(s.to_string());
+
+        if let Some((key, value)) = s.split_once("!=") {
+            return Ok(Self::NotEquals(key.trim().into(), value.trim().into()));
+        }
+        if let Some((key, value)) = s.split_once("==").or_else(|| s.split_once('=')) {
+            return Ok(Self::Equals(key.trim().into(), value.trim().into()));
+        }
+        if let Some((key, values)) = s.split_once(" notin ") {
+            return Ok(Self::NotIn(
+                key.trim().into(),
+                parse_set(values).ok_or_else(err)?,
+            ));
+        }
+        if let Some((key, values)) = s.split_once(" in ") {
+            return Ok(Self::In(
+                key.trim().into(),
+                parse_set(values).ok_or_else(err)?,
+            ));
+        }
+        if let Some(key) = s.strip_prefix('!') {
+            return Ok(Self::NotExists(key.trim().into()));
+        }
+
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(err());
+        }
+
+        Ok(Self::Exists(s.into()))
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Equals(key, value) => write!(f, "{key}={value}"),
+            Self::NotEquals(key, value) => write!(f, "{key}!={value}"),
+            Self::In(key, values) => write!(f, "{key} in ({})", values.join(",")),
+            Self::NotIn(key, values) => write!(f, "{key} notin ({})", values.join(",")),
+            Self::Exists(key) => write!(f, "{key}"),
+            Self::NotExists(key) => write!(f, "!{key}"),
+        }
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = LabelSelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let requirements = split_requirements(s)
+            .into_iter()
+            .filter(|r| !r.trim().is_empty())
+            .map(Requirement::from_str)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { requirements })
+    }
+}
+
+impl Display for LabelSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, r) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            Display::fmt(r, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Filter for images which should be tracked
+///
+/// Patterns are matched against the name of the image, without tag and digest (e.g.
+/// `registry.k8s.io/pause`). Images from Docker Hub use their canonical name, like
+/// `docker.io/library/nginx`.
+#[derive(Clone, Debug, Default)]
+pub struct ImageFilter {
+    /// patterns of images to include, all if empty
+    pub include: Vec<ImagePattern>,
+    /// patterns of images to exclude
+    pub exclude: Vec<ImagePattern>,
+}
+
+impl ImageFilter {
+    /// check if the image is accepted by the filter
+    pub fn matches(&self, image: &ImageRef) -> bool {
+        let name = format!("{}/{}", image.registry, image.repository);
+
+        if self.exclude.iter().any(|pattern| pattern.matches(&name)) {
+            return false;
+        }
+
+        self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(&name))
+    }
+}
+
+/// A pattern of image names
+///
+/// By default, the pattern is a glob, where `*` matches any sequence of characters (including
+/// `/`) and `?` a single one. With a `regex:` prefix, the rest is a regular expression, which must
+/// match the full name.
+#[derive(Clone, Debug)]
+pub struct ImagePattern(Regex);
+
+impl ImagePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+
+    /// the regular expression the pattern got translated to
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl FromStr for ImagePattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = match s.strip_prefix("regex:") {
+            Some(regex) => regex.to_string(),
+            None => s
+                .split('*')
+                .map(|part| {
+                    part.split('?')
+                        .map(regex::escape)
+                        .collect::<Vec<_>>()
+                        .join(".")
+                })
+                .collect::<Vec<_>>()
+                .join(".*"),
+        };
+
+        Ok(Self(Regex::new(&format!("^(?:{pattern})$"))?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pattern(s: &str) -> ImagePattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn pattern_star() {
+        let p = pattern("quay.io/*");
+        assert!(p.matches("quay.io/example/app"));
+        assert!(p.matches("quay.io/"));
+        // `*` crosses path segments
+        assert!(pattern("*/library/nginx").matches("docker.io/library/nginx"));
+        assert!(pattern("quay.io/*/app").matches("quay.io/a/b/app"));
+        assert!(!p.matches("docker.io/quay.io/example"));
+    }
+
+    #[test]
+    fn pattern_question_mark() {
+        let p = pattern("quay.io/app-?");
+        assert!(p.matches("quay.io/app-1"));
+        assert!(!p.matches("quay.io/app-"));
+        assert!(!p.matches("quay.io/app-12"));
+        assert!(pattern("quay.io/*-??").matches("quay.io/example/app-12"));
+    }
+
+    #[test]
+    fn pattern_metacharacters() {
+        // a dot only matches a dot
+        let p = pattern("quay.io/example");
+        assert!(p.matches("quay.io/example"));
+        assert!(!p.matches("quayXio/example"));
+
+        for name in [
+            "quay.io/a+b",
+            "quay.io/(a)",
+            "quay.io/[a]",
+            "quay.io/a|b",
+            "quay.io/^a$",
+        ] {
+            assert!(pattern(name).matches(name), "{name}");
+        }
+        assert!(!pattern("quay.io/a+b").matches("quay.io/aab"));
+        assert!(!pattern("quay.io/a|b").matches("quay.io/a"));
+        assert!(!pattern("quay.io/[a]").matches("quay.io/a"));
+    }
+
+    #[test]
+    fn pattern_anchored() {
+        let p = pattern("example/app");
+        assert!(p.matches("example/app"));
+        assert!(!p.matches("quay.io/example/app"));
+        assert!(!p.matches("example/app-2"));
+
+        // the whole regex is anchored, not only its first alternative
+        let p = pattern("regex:quay\\.io/.*|docker\\.io/library/nginx");
+        assert!(p.matches("quay.io/example"));
+        assert!(p.matches("docker.io/library/nginx"));
+        assert!(!p.matches("docker.io/library/nginx-extra"));
+        assert!(!p.matches("mirror.example.com/quay.io/example"));
+    }
+
+    #[test]
+    fn pattern_regex() {
+        let p = pattern("regex:quay\\.io/example/app-[0-9]+");
+        assert!(p.matches("quay.io/example/app-12"));
+        assert!(!p.matches("quay.io/example/app-x"));
+        assert!("regex:quay.io/(".parse::<ImagePattern>().is_err());
+    }
+
+    #[test]
+    fn image_filter() {
+        let filter = ImageFilter {
+            include: vec![pattern("quay.io/*"), pattern("docker.io/library/*")],
+            exclude: vec![pattern("quay.io/internal/*")],
+        };
+        let matches = |image: &str| filter.matches(&image.parse().unwrap());
+
+        assert!(matches("quay.io/example/app:1.0"));
+        // matched by its canonical name
+        assert!(matches("nginx:1.25"));
+        // exclusions take precedence
+        assert!(!matches("quay.io/internal/app:1.0"));
+        assert!(!matches("ghcr.io/example/app:1.0"));
+        // the tag isn't part of the name
+        assert!(!ImageFilter {
+            include: vec![pattern("quay.io/example/app:1.0")],
+            exclude: vec![],
+        }
+        .matches(&"quay.io/example/app:1.0".parse().unwrap()));
+    }
+
+    fn labels(labels: &[(&str, &str)]) -> BTreeMap<String, String> {
+        labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn label_selector_parse() {
+        let selector: LabelSelector =
+            "app=web, tier != db,env in (prod, staging),team notin (a),canary,!legacy"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            selector.requirements,
+            vec![
+                Requirement::Equals("app".into(), "web".into()),
+                Requirement::NotEquals("tier".into(), "db".into()),
+                Requirement::In("env".into(), vec!["prod".into(), "staging".into()]),
+                Requirement::NotIn("team".into(), vec!["a".into()]),
+                Requirement::Exists("canary".into()),
+                Requirement::NotExists("legacy".into()),
+            ]
+        );
+        // normalized, and parsing it again gives the same selector
+        assert_eq!(
+            selector.to_string(),
+            "app=web,tier!=db,env in (prod,staging),team notin (a),canary,!legacy"
+        );
+        assert_eq!(
+            selector.to_string().parse::<LabelSelector>().unwrap(),
+            selector
+        );
+
+        assert_eq!(
+            "app==web".parse::<LabelSelector>().unwrap().requirements,
+            vec![Requirement::Equals("app".into(), "web".into())]
+        );
+        // empty selects everything
+        assert!("".parse::<LabelSelector>().unwrap().requirements.is_empty());
+    }
+
+    #[test]
+    fn label_selector_invalid() {
+        for selector in ["env in prod", "env in (prod", "app web", "a,,b c"] {
+            assert!(selector.parse::<LabelSelector>().is_err(), "{selector}");
+        }
+    }
+
+    #[test]
+    fn label_selector_matches() {
+        let selector: LabelSelector = "app=web,env in (prod,staging),!legacy".parse().unwrap();
+        assert!(selector.matches(&labels(&[("app", "web"), ("env", "prod")])));
+        assert!(!selector.matches(&labels(&[("app", "web"), ("env", "dev")])));
+        assert!(!selector.matches(&labels(&[("app", "web")])));
+        assert!(!selector.matches(&labels(&[
+            ("app", "web"),
+            ("env", "prod"),
+            ("legacy", "true"),
+        ])));
+
+        // negations match missing labels
+        let selector: LabelSelector = "tier!=db,team notin (a,b)".parse().unwrap();
+        assert!(selector.matches(&labels(&[])));
+        assert!(selector.matches(&labels(&[("tier", "web"), ("team", "c")])));
+        assert!(!selector.matches(&labels(&[("tier", "db")])));
+        assert!(!selector.matches(&labels(&[("team", "b")])));
+    }
+
+    #[test]
+    fn pod_filter() {
+        let filter = PodFilter {
+            include_namespaces: Default::default(),
+            exclude_namespaces: ["kube-system".to_string(), "default".to_string()].into(),
+            label_selector: Some("app".parse().unwrap()),
+        };
+        assert!(filter.matches_namespace("example"));
+        assert!(!filter.matches_namespace("kube-system"));
+        assert_eq!(
+            filter.field_selector().as_deref(),
+            Some("metadata.namespace!=default,metadata.namespace!=kube-system")
+        );
+        assert!(filter.matches_template("example", Some(&labels(&[("app", "web")]))));
+        assert!(!filter.matches_template("example", None));
+
+        let filter = PodFilter::namespace("example");
+        assert!(filter.matches_namespace("example"));
+        assert!(!filter.matches_namespace("other"));
+        assert_eq!(filter.field_selector(), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Normalization of the image information reported by container statuses.
+//!
+//! Depending on the container runtime, the fields `image` and `imageID` are filled quite
+//! differently (see: `docs/image_id.md`). We try to turn them into a canonical, digest based
+//! reference (`registry/repository@sha256:…`). If that's not possible, we fall back to whatever
+//! reference we have, so that the image still shows up.
+//!
+//! Containers which didn't report an image ID (yet) only declare their image. Whether those count
+//! is up to the caller, see [`declared`].
+
+use bommer_api::data::ImageRef;
+
+/// prefixes some runtimes add to the image ID
+const PREFIXES: &[&str] = &[
+    "docker-pullable://",
+    "docker://",
+    "containerd://",
+    "cri-o://",
+];
+
+/// normalize the `image` and `imageID` of a container status, `None` without an image ID
+pub fn normalize(image: &str, image_id: &str) -> Option<ImageRef> {
+    if image_id.is_empty() {
+        return None;
+    }
+
+    let image = parse(image);
+    let image_id = parse(image_id);
+
+    // kind may swap the fields, so we take whichever is a usable digest reference
+    let digest_ref = [&image_id, &image]
+        .into_iter()
+        .flatten()
+        .find_map(to_digest_ref);
+
+    // fall back to the best reference we have, preferring the configured image
+    digest_ref.or(image).or(image_id)
+}
+
+/// the image a container only declares, from its spec or a status without an image ID
+pub fn declared(image: &str) -> Option<ImageRef> {
+    parse(image)
+}
+
+/// parse a reference, `None` for a bare digest, as that doesn't name a repository
+fn parse(value: &str) -> Option<ImageRef> {
+    let value = strip_prefix(value);
+    if value.starts_with("sha256:") {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn strip_prefix(value: &str) -> &str {
+    PREFIXES
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix))
+        .unwrap_or(value)
+}
+
+/// turn `name[:tag]@digest` into a canonical `name@digest`
+fn to_digest_ref(image: &ImageRef) -> Option<ImageRef> {
+    let digest = image.digest.as_ref().filter(|d| d.starts_with("sha256:"))?;
+
+    // kind reports images loaded from an archive as "import-<date>", that's not a real repository
+    if image.registry == "docker.io" && image.repository.starts_with("library/import-") {
+        return None;
+    }
+
+    Some(image.with_digest(digest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: &str = "sha256:34e8724e0f47e31eb2ec3279ac398b657db5f60f167426ee73138e2e84af6486";
+
+    fn assert_normalized(image: &str, image_id: &str, expected: Option<&str>) {
+        assert_eq!(
+            normalize(image, image_id),
+            expected.map(|s| s.parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn classic() {
+        assert_normalized(
+            "gcr.io/kubebuilder/kube-rbac-proxy:v0.8.0",
+            &format!("gcr.io/kubebuilder/kube-rbac-proxy@{DIGEST}"),
+            Some(&format!("gcr.io/kubebuilder/kube-rbac-proxy@{DIGEST}")),
+        );
+    }
+
+    #[test]
+    fn by_digest() {
+        let image = format!("quay.io/openshift-release-dev/ocp-v4.0-art-dev@{DIGEST}");
+        assert_normalized(&image, &image, Some(&image));
+    }
+
+    #[test]
+    fn docker_pullable() {
+        assert_normalized(
+            "nginx:1.25",
+            &format!("docker-pullable://nginx@{DIGEST}"),
+            Some(&format!("docker.io/library/nginx@{DIGEST}")),
+        );
+    }
+
+    #[test]
+    fn docker_local_id() {
+        // docker only knows the local image ID, which isn't a manifest digest
+        assert_normalized(
+            "quay.io/foo/bar:1",
+            &format!("docker://{DIGEST}"),
+            Some("quay.io/foo/bar:1"),
+        );
+    }
+
+    #[test]
+    fn containerd() {
+        assert_normalized(
+            "docker.io/foo/bar:latest",
+            &format!("docker.io/foo/bar@{DIGEST}"),
+            Some(&format!("docker.io/foo/bar@{DIGEST}")),
+        );
+    }
+
+    #[test]
+    fn cri_o_with_port() {
+        assert_normalized(
+            "registry.local:5000/foo/bar:1.0",
+            &format!("registry.local:5000/foo/bar@{DIGEST}"),
+            Some(&format!("registry.local:5000/foo/bar@{DIGEST}")),
+        );
+    }
+
+    #[test]
+    fn kind_swapped() {
+        assert_normalized(
+            DIGEST,
+            &format!("docker.io/kindest/kindnetd:v20230330-48f316cd@{DIGEST}"),
+            Some(&format!("docker.io/kindest/kindnetd@{DIGEST}")),
+        );
+    }
+
+    #[test]
+    fn kind_digest_only() {
+        assert_normalized(
+            "registry.k8s.io/coredns/coredns:v1.9.3",
+            DIGEST,
+            Some("registry.k8s.io/coredns/coredns:v1.9.3"),
+        );
+    }
+
+    #[test]
+    fn kind_import() {
+        assert_normalized(
+            "registry.k8s.io/kube-apiserver:v1.26.3",
+            &format!("docker.io/library/import-2023-03-30@{DIGEST}"),
+            Some("registry.k8s.io/kube-apiserver:v1.26.3"),
+        );
+    }
+
+    #[test]
+    fn kind_bare_digests() {
+        assert_normalized(DIGEST, DIGEST, None);
+        assert_normalized(DIGEST, &format!("docker://{DIGEST}"), None);
+    }
+
+    #[test]
+    fn empty() {
+        assert_normalized("", "", None);
+    }
+
+    #[test]
+    fn without_image_id() {
+        // e.g. still pulling the image
+        assert_normalized("quay.io/foo/bar:1", "", None);
+        assert_eq!(
+            declared("quay.io/foo/bar:1"),
+            Some("quay.io/foo/bar:1".parse().unwrap())
+        );
+        assert_eq!(declared(DIGEST), None);
+        assert_eq!(declared(""), None);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::store::pods::spec_containers;
+use crate::store::{image_id, ImageFilter, ImageOwner, PodFilter, Store};
+use bommer_api::data::{ImageRef, JobRef};
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use k8s_openapi::api::batch::v1::{CronJob, Job, JobSpec};
+use k8s_openapi::Resource as _;
+use kube::{runtime::watcher, Resource, ResourceExt};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+pub type JobStream<K> = BoxStream<'static, Result<watcher::Event<K>, watcher::Error>>;
+
+/// A source of batch workloads
+///
+/// Jobs and cron jobs own the images of their pod template, no matter if they currently have any
+/// pods. Jobs created by a cron job are covered by the cron job.
+pub struct JobSource {
+    /// The cluster the jobs are located in, `None` for the default cluster
+    pub cluster: Option<String>,
+    /// Filter applied to the jobs, evaluating the label selector against their pod templates.
+    ///
+    /// Like for pods, it also defines the scope of the source.
+    pub filter: PodFilter,
+    /// Filter applied to the images of the jobs
+    pub images: ImageFilter,
+    /// The stream of cron job events
+    pub cron_jobs: JobStream<CronJob>,
+    /// The stream of job events
+    pub jobs: JobStream<Job>,
+}
+
+/// An event of either stream
+enum JobEvent {
+    CronJob(Box<watcher::Event<CronJob>>),
+    Job(Box<watcher::Event<Job>>),
+}
+
+pub(super) async fn run(
+    store: Store<ImageRef, ImageOwner, ()>,
+    source: JobSource,
+) -> anyhow::Result<()> {
+    let JobSource {
+        cluster,
+        filter,
+        images,
+        cron_jobs,
+        jobs,
+    } = source;
+
+    let mut stream = futures::stream::select(
+        cron_jobs.map_ok(|evt| JobEvent::CronJob(Box::new(evt))),
+        jobs.map_ok(|evt| JobEvent::Job(Box::new(evt))),
+    );
+
+    let source = Source {
+        store: &store,
+        cluster: &cluster,
+        filter: &filter,
+        images: &images,
+    };
+
+    // the source is synced once both streams listed their resources
+    let mut listed = HashSet::new();
+    let mut synced = false;
+
+    while let Some(evt) = stream.try_next().await? {
+        match evt {
+            JobEvent::CronJob(evt) => match *evt {
+                watcher::Event::Applied(cron_job) => {
+                    source.apply(&cron_job, cron_job_spec(&cron_job)).await;
+                }
+                watcher::Event::Deleted(cron_job) => {
+                    source.delete(&cron_job).await;
+                }
+                watcher::Event::Restarted(cron_jobs) => {
+                    let jobs = cron_jobs
+                        .iter()
+                        .map(|cron_job| (cron_job, cron_job_spec(cron_job)));
+                    source.reset(CronJob::KIND, jobs).await;
+                    listed.insert(CronJob::KIND);
+                }
+            },
+            JobEvent::Job(evt) => match *evt {
+                watcher::Event::Applied(job) => match is_scheduled(&job) {
+                    false => source.apply(&job, job.spec.as_ref()).await,
+                    true => source.delete(&job).await,
+                },
+                watcher::Event::Deleted(job) => {
+                    source.delete(&job).await;
+                }
+                watcher::Event::Restarted(jobs) => {
+                    let jobs = jobs
+                        .iter()
+                        .filter(|job| !is_scheduled(job))
+                        .map(|job| (job, job.spec.as_ref()));
+                    source.reset(Job::KIND, jobs).await;
+                    listed.insert(Job::KIND);
+                }
+            },
+        }
+
+        if !synced && listed.len() == 2 {
+            synced = true;
+            store.sync.mark_synced();
+        }
+    }
+
+    Ok(())
+}
+
+struct Source<'a> {
+    store: &'a Store<ImageRef, ImageOwner, ()>,
+    cluster: &'a Option<String>,
+    filter: &'a PodFilter,
+    images: &'a ImageFilter,
+}
+
+impl Source<'_> {
+    fn owner<K>(&self, resource: &K) -> Option<JobRef>
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        Some(JobRef {
+            cluster: self.cluster.clone(),
+            kind: K::kind(&()).to_string(),
+            namespace: resource.namespace()?,
+            name: resource.meta().name.clone()?,
+        })
+    }
+
+    /// the images of a job spec, `None` if the job doesn't match the filter
+    fn images(&self, job: &JobRef, spec: Option<&JobSpec>) -> Option<HashSet<ImageRef>> {
+        let template = &spec?.template;
+        let labels = template.metadata.as_ref().and_then(|m| m.labels.as_ref());
+        if !self.filter.matches_template(&job.namespace, labels) {
+            return None;
+        }
+
+        Some(
+            template
+                .spec
+                .clone()
+                .into_iter()
+                .flat_map(spec_containers)
+                .filter_map(|(_, _, image)| image_id::declared(&image?))
+                .filter(|image| self.images.matches(image))
+                .collect(),
+        )
+    }
+
+    async fn apply<K>(&self, resource: &K, spec: Option<&JobSpec>)
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        let owner = match self.owner(resource) {
+            Some(owner) => owner,
+            None => return,
+        };
+
+        match self
+            .images(&owner, spec)
+            .filter(|images| !images.is_empty())
+        {
+            Some(images) => {
+                debug!(event = "applied", job = %owner, images = images.len(), "Job applied");
+                self.store
+                    .apply(ImageOwner::Job(owner), images, |_| (), |_, v| v)
+                    .await;
+            }
+            None => {
+                self.store.delete(&ImageOwner::Job(owner), |_, v| v).await;
+            }
+        }
+    }
+
+    async fn delete<K>(&self, resource: &K)
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        if let Some(owner) = self.owner(resource) {
+            debug!(event = "deleted", job = %owner, "Job deleted");
+            self.store.delete(&ImageOwner::Job(owner), |_, v| v).await;
+        }
+    }
+
+    /// replace all jobs of a kind
+    async fn reset<'r, K, I>(&self, kind: &str, resources: I)
+    where
+        K: Resource<DynamicType = ()> + 'r,
+        I: IntoIterator<Item = (&'r K, Option<&'r JobSpec>)>,
+    {
+        let mut state = HashMap::new();
+
+        for (resource, spec) in resources {
+            if let Some(owner) = self.owner(resource) {
+                if let Some(images) = self.images(&owner, spec).filter(|i| !i.is_empty()) {
+                    state.insert(ImageOwner::Job(owner), images);
+                }
+            }
+        }
+
+        debug!(
+            event = "restarted",
+            cluster = ?self.cluster,
+            kind,
+            jobs = state.len(),
+            "Jobs re-listed"
+        );
+
+        self.store
+            .reset_scoped(
+                |owner| match owner {
+                    ImageOwner::Job(job) => {
+                        &job.cluster == self.cluster
+                            && job.kind == kind
+                            && self.filter.matches_namespace(&job.namespace)
+                    }
+                    _ => false,
+                },
+                state,
+                |_| (),
+            )
+            .await;
+    }
+}
+
+fn cron_job_spec(cron_job: &CronJob) -> Option<&JobSpec> {
+    cron_job.spec.as_ref()?.job_template.spec.as_ref()
+}
+
+/// check if the job was created by a cron job, which covers it
+fn is_scheduled(job: &Job) -> bool {
+    job.owner_references()
+        .iter()
+        .any(|owner| owner.kind == CronJob::KIND)
+}
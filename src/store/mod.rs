@@ -1,34 +1,72 @@
+mod filter;
+mod image_id;
+mod jobs;
+mod node;
+mod node_images;
+mod owner;
 mod pods;
+mod remote;
+mod sharded;
+mod sync;
+mod watch;
+mod workload;
 
-use crate::pubsub::{State, Subscription};
+use sharded::ShardedState;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-pub use pods::image_store;
+pub use filter::{ImageFilter, ImagePattern, LabelSelector, PodFilter};
+pub use jobs::JobSource;
+pub use node::NodeResolver;
+pub use node_images::NodeImageSource;
+pub use owner::{ContainerOwner, ImageOwner};
+pub use pods::{image_store, PodSource};
+pub use sharded::ShardedSubscription;
+pub use sync::SyncState;
+pub use watch::{
+    pod_watcher, resyncing, Checkpoint, Checkpoints, Fingerprint, PodEvent, Resync, WatchState,
+};
+pub use workload::WorkloadResolver;
 
+/// number of shards the owners, and the images, are distributed over
+const SHARDS: usize = 16;
+
+/// Owners located in a namespace, which the store uses to distribute them over its shards
+pub trait Namespaced {
+    /// the namespace of the owner, `None` if it isn't namespaced
+    fn namespace(&self) -> Option<&str>;
+}
+
+/// The images of all owners, along with the state of each image
+///
+/// The owners are sharded by their namespace, so that changes in different namespaces don't
+/// contend for the same lock. The state of the images is sharded by the image, so that owners
+/// of different namespaces only contend for it when they share an image. Reading the state
+/// doesn't need any of the locks of the owners.
 #[derive(Clone)]
 pub struct Store<K, O, V>
 where
     K: Clone + Debug + Eq + Hash,
-    O: Clone + Debug + Eq + Hash,
+    O: Clone + Debug + Eq + Hash + Namespaced,
     V: Clone + Debug + PartialEq,
 {
-    inner: Arc<RwLock<Inner<K, O, V>>>,
+    shards: Arc<[RwLock<Shard<K, O, V>>]>,
+    state: ShardedState<K, Owned<O, V>>,
+    sync: SyncState,
 }
 
 impl<K, O, V> Default for Store<K, O, V>
 where
-    K: Clone + Debug + Eq + Hash,
-    O: Clone + Debug + Eq + Hash,
-    V: Clone + Debug + PartialEq,
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    O: Clone + Debug + Eq + Hash + Namespaced + Send + Sync + 'static,
+    V: Clone + Debug + PartialEq + Send + Sync + 'static,
 {
     fn default() -> Self {
-        Self {
-            inner: Default::default(),
-        }
+        Self::with_sync(Default::default())
     }
 }
 
@@ -55,7 +93,8 @@ where
     }
 }
 
-pub struct Inner<K, O, V>
+/// The owners of some namespaces
+struct Shard<K, O, V>
 where
     K: Clone + Debug + Eq + Hash,
     O: Clone + Debug + Eq + Hash,
@@ -66,25 +105,11 @@ where
     /// This is mainly needed to figure out how to clean up a pod which got removed.
     pods: HashMap<O, HashSet<K>>,
 
-    /// listeners
-    state: State<K, Owned<O, V>>,
+    /// the state of all images, shared by all shards
+    state: ShardedState<K, Owned<O, V>>,
 }
 
-impl<K, O, V> Default for Inner<K, O, V>
-where
-    K: Clone + Debug + Eq + Hash,
-    O: Clone + Debug + Eq + Hash,
-    V: Clone + Debug + PartialEq,
-{
-    fn default() -> Self {
-        Self {
-            pods: Default::default(),
-            state: Default::default(),
-        }
-    }
-}
-
-impl<K, O, V> Inner<K, O, V>
+impl<K, O, V> Shard<K, O, V>
 where
     K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
     O: Clone + Debug + Eq + Hash + Send + Sync + 'static,
@@ -157,29 +182,155 @@ where
             }
         }
     }
-
-    /// full reset of the state
-    async fn reset(&mut self, images: HashMap<K, Owned<O, V>>, pods: HashMap<O, HashSet<K>>) {
-        self.pods = pods;
-        self.state.set_state(images).await;
-    }
 }
 
 impl<K, O, V> Store<K, O, V>
 where
     K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
-    O: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    O: Clone + Debug + Eq + Hash + Namespaced + Send + Sync + 'static,
     V: Clone + Debug + PartialEq + Send + Sync + 'static,
 {
-    #[allow(unused)]
-    pub async fn get_state(&self) -> HashMap<K, Owned<O, V>> {
-        self.inner.read().await.state.get_state().await
+    /// create a new store, expecting the provided number of sources to synchronize
+    pub fn new(sources: usize) -> Self {
+        Self::with_sync(SyncState::new(sources))
+    }
+
+    fn with_sync(sync: SyncState) -> Self {
+        let state = ShardedState::new(SHARDS);
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    RwLock::new(Shard {
+                        pods: Default::default(),
+                        state: state.clone(),
+                    })
+                })
+                .collect(),
+            state,
+            sync,
+        }
+    }
+
+    /// the synchronization state of the store
+    pub fn sync_state(&self) -> &SyncState {
+        &self.sync
+    }
+
+    /// the index of the shard of an owner
+    fn index(&self, owner: &O) -> usize {
+        let mut hasher = DefaultHasher::new();
+        owner.namespace().hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    /// the shard of an owner
+    fn shard(&self, owner: &O) -> &RwLock<Shard<K, O, V>> {
+        &self.shards[self.index(owner)]
+    }
+
+    /// add or modify an owner
+    async fn apply<I, A>(&self, owner: O, keys: HashSet<K>, initial: I, apply: A)
+    where
+        I: Fn(&K) -> V,
+        A: Fn(&K, V) -> V,
+    {
+        self.shard(&owner)
+            .write()
+            .await
+            .apply(owner, keys, initial, apply)
+            .await;
+    }
+
+    /// delete an owner
+    async fn delete<A>(&self, owner: &O, apply: A)
+    where
+        A: Fn(&K, V) -> V,
+    {
+        self.shard(owner).write().await.delete(owner, apply).await;
+    }
+
+    /// reset the state of all owners in scope, keeping owners outside the scope
+    ///
+    /// Images which are still present keep their current state, new images get the initial state.
+    /// All shards are locked meanwhile, as this replaces the full state.
+    pub(crate) async fn reset_scoped<S, I>(
+        &self,
+        scope: S,
+        pods: HashMap<O, HashSet<K>>,
+        initial: I,
+    ) where
+        S: Fn(&O) -> bool,
+        I: Fn(&K) -> V,
+    {
+        // always lock the shards in the same order
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            shards.push(shard.write().await);
+        }
+
+        for shard in &mut shards {
+            shard.pods.retain(|owner, _| !scope(owner));
+        }
+        for (owner, keys) in pods {
+            let index = self.index(&owner);
+            shards[index].pods.insert(owner, keys);
+        }
+
+        let current = self.state.get_state().await;
+        let mut images = im::HashMap::<K, Owned<O, V>>::new();
+
+        for (owner, keys) in shards.iter().flat_map(|shard| shard.pods.iter()) {
+            for key in keys {
+                images
+                    .entry(key.clone())
+                    .or_insert_with(|| Owned {
+                        owners: Default::default(),
+                        state: current
+                            .get(key)
+                            .map(|current| current.state.clone())
+                            .unwrap_or_else(|| initial(key)),
+                    })
+                    .owners
+                    .insert(owner.clone());
+            }
+        }
+
+        self.state.set_state(images).await;
+    }
+
+    /// the current state of all keys
+    pub async fn get_state(&self) -> im::HashMap<K, Owned<O, V>> {
+        self.state.get_state().await
+    }
+
+    /// the keys of all owners
+    pub async fn owners(&self) -> HashMap<O, HashSet<K>> {
+        self.owners_matching(|_| true).await
+    }
+
+    /// the keys of the owners matching a predicate
+    pub async fn owners_matching(&self, f: impl Fn(&O) -> bool) -> HashMap<O, HashSet<K>> {
+        let mut result = HashMap::new();
+        for shard in self.shards.iter() {
+            result.extend(
+                shard
+                    .read()
+                    .await
+                    .pods
+                    .iter()
+                    .filter(|(owner, _)| f(owner))
+                    .map(|(owner, keys)| (owner.clone(), keys.clone())),
+            );
+        }
+        result
     }
 
+    /// subscribe to the state of the images, starting with the full state
     pub async fn subscribe(
         &self,
+        name: &'static str,
         buffer: impl Into<Option<usize>>,
-    ) -> Subscription<K, Owned<O, V>> {
-        self.inner.read().await.state.subscribe(buffer).await
+    ) -> ShardedSubscription<K, Owned<O, V>> {
+        self.state.subscribe(name, buffer).await
     }
 }
@@ -0,0 +1,54 @@
+use k8s_openapi::api::core::v1::Node;
+use kube::{Api, Client, ResourceExt};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::debug;
+
+const ARCH_LABEL: &str = "kubernetes.io/arch";
+
+/// Resolves the architecture of nodes
+///
+/// The architecture of a node doesn't change, so we cache it for as long as we run.
+#[derive(Clone)]
+pub struct NodeResolver {
+    client: Client,
+    cache: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl NodeResolver {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            cache: Default::default(),
+        }
+    }
+
+    /// get the architecture of a node, `None` if it can't be determined
+    pub async fn arch(&self, node: &str) -> Option<String> {
+        if let Some(arch) = self.cache.lock().get(node) {
+            return Some(arch.clone());
+        }
+
+        let api: Api<Node> = Api::all(self.client.clone());
+        let node = match api.get_opt(node).await {
+            Ok(node) => node?,
+            Err(err) => {
+                // we might not have permission to read nodes
+                debug!("Failed to look up node {node}: {err}");
+                return None;
+            }
+        };
+
+        let arch = node
+            .status
+            .as_ref()
+            .and_then(|status| status.node_info.as_ref())
+            .map(|info| info.architecture.clone())
+            .or_else(|| node.labels().get(ARCH_LABEL).cloned())?;
+
+        self.cache.lock().insert(node.name_any(), arch.clone());
+
+        Some(arch)
+    }
+}
@@ -0,0 +1,132 @@
+use crate::store::{image_id, ImageFilter, ImageOwner, Store};
+use bommer_api::data::{ImageRef, NodeRef};
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use k8s_openapi::api::core::v1::Node;
+use kube::{runtime::watcher, ResourceExt};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+/// A source of images present on nodes
+///
+/// Nodes report the images they pulled, no matter if a pod currently uses them. The kubelet only
+/// reports a limited number of images per node, the largest ones first.
+pub struct NodeImageSource {
+    /// The cluster the nodes are part of, `None` for the default cluster
+    pub cluster: Option<String>,
+    /// Filter applied to the images of the nodes
+    pub images: ImageFilter,
+    /// Record the architecture of the node with its images
+    pub arch: bool,
+    /// The stream of node events
+    pub stream: BoxStream<'static, Result<watcher::Event<Node>, watcher::Error>>,
+}
+
+pub(super) async fn run(
+    store: Store<ImageRef, ImageOwner, ()>,
+    source: NodeImageSource,
+) -> anyhow::Result<()> {
+    let NodeImageSource {
+        cluster,
+        images: filter,
+        arch,
+        mut stream,
+    } = source;
+
+    let mut synced = false;
+
+    while let Some(evt) = stream.try_next().await? {
+        match evt {
+            watcher::Event::Applied(node) => {
+                let name = node.name_any();
+                let owner = to_owner(&cluster, &node);
+                let images = images_from_node(&filter, arch, node);
+                debug!(event = "applied", node = %name, images = images.len(), "Node applied");
+
+                match images.is_empty() {
+                    false => store.apply(owner, images, |_| (), |_, v| v).await,
+                    true => store.delete(&owner, |_, v| v).await,
+                }
+            }
+            watcher::Event::Deleted(node) => {
+                let owner = to_owner(&cluster, &node);
+                debug!(event = "deleted", node = %node.name_any(), "Node deleted");
+                store.delete(&owner, |_, v| v).await;
+            }
+            watcher::Event::Restarted(nodes) => {
+                let state = nodes
+                    .into_iter()
+                    .map(|node| {
+                        let owner = to_owner(&cluster, &node);
+                        (owner, images_from_node(&filter, arch, node))
+                    })
+                    .filter(|(_, images)| !images.is_empty())
+                    .collect::<HashMap<_, _>>();
+
+                debug!(
+                    event = "restarted",
+                    cluster = ?cluster,
+                    nodes = state.len(),
+                    "Nodes re-listed"
+                );
+
+                store
+                    .reset_scoped(
+                        |owner| matches!(owner, ImageOwner::Node(node) if node.cluster == cluster),
+                        state,
+                        |_| (),
+                    )
+                    .await;
+
+                if !synced {
+                    synced = true;
+                    store.sync.mark_synced();
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn to_owner(cluster: &Option<String>, node: &Node) -> ImageOwner {
+    ImageOwner::Node(NodeRef {
+        cluster: cluster.clone(),
+        name: node.name_any(),
+    })
+}
+
+/// the images present on a node
+///
+/// Each image is reported with all of its names, which may contain the digest as well as tags.
+fn images_from_node(filter: &ImageFilter, arch: bool, node: Node) -> HashSet<ImageRef> {
+    let status = match node.status {
+        Some(status) => status,
+        None => return HashSet::new(),
+    };
+
+    let arch = status
+        .node_info
+        .map(|info| info.architecture)
+        .filter(|_| arch);
+
+    status
+        .images
+        .into_iter()
+        .flatten()
+        .filter_map(|image| {
+            let names = image.names.unwrap_or_default();
+            let digest = names.iter().find(|name| name.contains('@'));
+            let name = names.iter().find(|name| !name.contains('@')).or(digest)?;
+            match digest {
+                Some(digest) => image_id::normalize(name, digest),
+                None => image_id::declared(name),
+            }
+        })
+        .filter(|image| filter.matches(image))
+        .map(|image| ImageRef {
+            arch: arch.clone().or(image.arch),
+            ..image
+        })
+        .collect()
+}
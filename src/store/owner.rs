@@ -0,0 +1,58 @@
+use super::Namespaced;
+use bommer_api::data::{ContainerKind, JobRef, NodeRef, PodRef};
+
+/// An owner of images in the store
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageOwner {
+    /// A container of a pod, running or declaring an image
+    Container(ContainerOwner),
+    /// A batch workload, declaring images in its pod template
+    Job(JobRef),
+    /// A node, having images present
+    Node(NodeRef),
+}
+
+impl ImageOwner {
+    /// the cluster the owner is located in, `None` when running against a single cluster
+    pub fn cluster(&self) -> Option<&str> {
+        match self {
+            Self::Container(owner) => owner.pod.cluster.as_deref(),
+            Self::Job(job) => job.cluster.as_deref(),
+            Self::Node(node) => node.cluster.as_deref(),
+        }
+    }
+
+    /// move the owner to another cluster
+    pub fn with_cluster(mut self, cluster: &str) -> Self {
+        let current = match &mut self {
+            Self::Container(owner) => &mut owner.pod.cluster,
+            Self::Job(job) => &mut job.cluster,
+            Self::Node(node) => &mut node.cluster,
+        };
+        *current = Some(cluster.to_string());
+        self
+    }
+}
+
+impl Namespaced for ImageOwner {
+    fn namespace(&self) -> Option<&str> {
+        match self {
+            Self::Container(owner) => Some(&owner.pod.namespace),
+            Self::Job(job) => Some(&job.namespace),
+            Self::Node(_) => None,
+        }
+    }
+}
+
+/// A container of a pod, using an image
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerOwner {
+    pub pod: PodRef,
+    /// The name of the container
+    pub container: String,
+    pub kind: ContainerKind,
+    /// If the container runs the image, or only declares it
+    pub running: bool,
+}
@@ -1,138 +1,669 @@
-use crate::store::{Owned, Store};
-use bommer_api::data::{ImageRef, PodRef};
+use crate::registry::DigestResolver;
+use crate::store::{
+    image_id, jobs, node_images, Checkpoint, ContainerOwner, ImageFilter, ImageOwner, JobSource,
+    NodeImageSource, NodeResolver, PodEvent, PodFilter, Store, WatchState, WorkloadResolver,
+};
+use bommer_api::data::{ContainerKind, ImageRef, PodRef, WorkloadRef};
+use futures::future::join_all;
 use futures::{Stream, TryStreamExt};
-use k8s_openapi::api::core::v1::{ContainerStatus, Pod};
+use k8s_openapi::api::core::v1::{ContainerStatus, Pod, PodSpec};
 use kube::{runtime::watcher, Resource, ResourceExt};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::pin;
+use tracing::debug;
 
+/// A source of pods
+pub struct PodSource<S> {
+    /// The cluster the pods are located in, `None` for the default cluster
+    pub cluster: Option<String>,
+    /// Filter applied to the pods of the stream.
+    ///
+    /// It also defines the scope of the source, so that a restart of one watcher only resets its
+    /// own pods.
+    pub filter: PodFilter,
+    /// Filter applied to the images of the pods
+    pub images: ImageFilter,
+    /// Resolver for the workloads owning the pods
+    pub resolver: WorkloadResolver,
+    /// Resolver for the architecture of nodes, if enabled
+    pub nodes: Option<NodeResolver>,
+    /// Resolver for images without a usable (platform specific) digest, if enabled
+    pub digests: Option<DigestResolver>,
+    /// Also track images of containers which didn't start yet, from the spec of the pod
+    pub pending: bool,
+    /// Ignore finished pods of jobs, as the jobs themselves are tracked
+    pub collapse_jobs: bool,
+    /// The progress of the watcher, for resuming it later on
+    pub checkpoint: Checkpoint,
+    /// The persisted state, if the stream resumes watching
+    pub resume: Option<WatchState>,
+    /// The stream of watcher events
+    pub stream: S,
+}
+
+/// create an image store, fed by pod watchers, and optionally by job and node watchers
+///
+/// The store is synced once all watchers, and the `external` sources (e.g. agents pushing their
+/// workload), are synced. External sources need to mark the store as synced on their own.
 pub fn image_store<S>(
-    stream: S,
+    pods: Vec<PodSource<S>>,
+    jobs: Vec<JobSource>,
+    nodes: Vec<NodeImageSource>,
+    external: usize,
 ) -> (
-    Store<ImageRef, PodRef, ()>,
+    Store<ImageRef, ImageOwner, ()>,
     impl Future<Output = anyhow::Result<()>>,
 )
 where
-    S: Stream<Item = Result<watcher::Event<Pod>, watcher::Error>>,
+    S: Stream<Item = Result<PodEvent, watcher::Error>>,
 {
-    let store = Store::<ImageRef, PodRef, ()>::default();
-    let runner = {
-        let store = store.clone();
-        async move { run(store, stream).await }
+    let store =
+        Store::<ImageRef, ImageOwner, ()>::new(pods.len() + jobs.len() + nodes.len() + external);
+
+    let pods = pods
+        .into_iter()
+        .map(|source| run(store.clone(), source))
+        .collect::<Vec<_>>();
+    let jobs = jobs
+        .into_iter()
+        .map(|source| jobs::run(store.clone(), source))
+        .collect::<Vec<_>>();
+    let nodes = nodes
+        .into_iter()
+        .map(|source| node_images::run(store.clone(), source))
+        .collect::<Vec<_>>();
+
+    let runner = async move {
+        futures::future::try_join3(
+            futures::future::try_join_all(pods),
+            futures::future::try_join_all(jobs),
+            futures::future::try_join_all(nodes),
+        )
+        .await?;
+        Ok(())
     };
 
     (store, runner)
 }
 
-async fn run<S>(store: Store<ImageRef, PodRef, ()>, stream: S) -> anyhow::Result<()>
+/// namespace and name of a pod
+type PodName = (String, String);
+
+async fn run<S>(store: Store<ImageRef, ImageOwner, ()>, source: PodSource<S>) -> anyhow::Result<()>
 where
-    S: Stream<Item = Result<watcher::Event<Pod>, watcher::Error>>,
+    S: Stream<Item = Result<PodEvent, watcher::Error>>,
 {
+    let PodSource {
+        cluster,
+        filter,
+        images: image_filter,
+        resolver,
+        nodes,
+        digests,
+        pending,
+        collapse_jobs,
+        checkpoint,
+        resume,
+        stream,
+    } = source;
+
+    let scope = |owner: &ImageOwner| match owner {
+        ImageOwner::Container(owner) => {
+            owner.pod.cluster == cluster && filter.matches_namespace(&owner.pod.namespace)
+        }
+        _ => false,
+    };
+
     let mut stream = pin!(stream);
 
-    while let Some(evt) = stream.try_next().await? {
+    // The containers we handed out, by pod. As the workload is part of the pod reference, we need
+    // to remember what we resolved it to, in order to delete the pod later on.
+    let mut owners = HashMap::<PodName, HashSet<ContainerOwner>>::new();
+    // Synced with the first full list of pods. When resuming, there is no such list, unless the
+    // resource version expired. Instead, the first bookmark confirms that the watch caught up with
+    // all changes since the persisted state.
+    let mut synced = false;
+
+    if let Some(resume) = resume {
+        // continue with the pods we had, the watcher will report what changed in the meantime
+        let mut state = HashMap::<_, HashSet<_>>::new();
+        for (owner, image) in resume.containers {
+            owners
+                .entry((owner.pod.namespace.clone(), owner.pod.name.clone()))
+                .or_default()
+                .insert(owner.clone());
+            state
+                .entry(ImageOwner::Container(owner))
+                .or_default()
+                .insert(image);
+        }
+
+        debug!(
+            event = "resumed",
+            ?cluster,
+            pods = owners.len(),
+            resource_version = resume.resource_version,
+            "Pods resumed"
+        );
+
+        store.reset_scoped(scope, state, |_| ()).await;
+    }
+
+    while let Some(PodEvent {
+        event,
+        resource_version,
+    }) = stream.try_next().await?
+    {
+        let evt = match event {
+            Some(evt) => evt,
+            None => {
+                // bookmark
+                checkpoint.set(resource_version);
+                if !synced {
+                    debug!(?cluster, "Resumed watch caught up");
+                    synced = true;
+                    store.sync.mark_synced();
+                }
+                continue;
+            }
+        };
+
         match evt {
             watcher::Event::Applied(pod) => {
-                let pod_ref = match to_key(&pod) {
-                    Some(pod_ref) => pod_ref,
+                let name = match to_name(&pod) {
+                    Some(name) => name,
                     None => continue,
                 };
 
-                let images = images_from_pod(pod);
+                if !filter.matches(&pod) {
+                    // the pod might have matched before, e.g. when its labels got changed
+                    if let Some(current) = owners.remove(&name) {
+                        delete(&store, &current).await;
+                    }
+                    continue;
+                }
+
+                let workload = resolver.resolve(&pod).await;
+                if collapse_jobs && is_finished_job(&pod, &workload) {
+                    // covered by the job, and it won't run again
+                    if let Some(current) = owners.remove(&name) {
+                        delete(&store, &current).await;
+                    }
+                    continue;
+                }
+
+                let pod_ref = to_key(&cluster, name.clone(), &pod, workload);
+                let images = images_from_pod(&nodes, &image_filter, pending, &pod_ref, pod).await;
+                let images = resolve_digests(&digests, images).await;
 
-                store
-                    .inner
-                    .write()
-                    .await
-                    .apply(pod_ref, images, |_| (), |_, v| v)
-                    .await;
+                debug!(
+                    event = "applied",
+                    namespace = %pod_ref.namespace,
+                    pod = %pod_ref.name,
+                    containers = images.len(),
+                    "Pod applied"
+                );
+
+                let current = owners
+                    .insert(name, images.keys().cloned().collect())
+                    .unwrap_or_default();
+
+                for owner in current
+                    .into_iter()
+                    .filter(|owner| !images.contains_key(owner))
+                {
+                    store.delete(&ImageOwner::Container(owner), |_, v| v).await;
+                }
+                for (owner, image) in images {
+                    store
+                        .apply(
+                            ImageOwner::Container(owner),
+                            HashSet::from([image]),
+                            |_| (),
+                            |_, v| v,
+                        )
+                        .await;
+                }
             }
             watcher::Event::Deleted(pod) => {
-                if let Some(pod_ref) = to_key(&pod) {
-                    store.inner.write().await.delete(&pod_ref, |_, v| v).await;
+                if let Some((name, current)) =
+                    to_name(&pod).and_then(|name| owners.remove_entry(&name))
+                {
+                    debug!(
+                        event = "deleted",
+                        namespace = %name.0,
+                        pod = %name.1,
+                        "Pod deleted"
+                    );
+                    delete(&store, &current).await;
                 }
             }
             watcher::Event::Restarted(pods) => {
-                let (images, pods) = to_state(pods);
-                store.inner.write().await.reset(images, pods).await;
+                // take the chance to drop outdated owners
+                resolver.clear();
+
+                let mut state = HashMap::new();
+                owners.clear();
+
+                for pod in pods.into_iter().filter(|pod| filter.matches(pod)) {
+                    if let Some(name) = to_name(&pod) {
+                        let workload = resolver.resolve(&pod).await;
+                        if collapse_jobs && is_finished_job(&pod, &workload) {
+                            continue;
+                        }
+                        let pod_ref = to_key(&cluster, name.clone(), &pod, workload);
+                        let images =
+                            images_from_pod(&nodes, &image_filter, pending, &pod_ref, pod).await;
+                        let images = resolve_digests(&digests, images).await;
+                        owners.insert(name, images.keys().cloned().collect());
+                        state.extend(images.into_iter().map(|(owner, image)| {
+                            (ImageOwner::Container(owner), HashSet::from([image]))
+                        }));
+                    }
+                }
+
+                debug!(
+                    event = "restarted",
+                    ?cluster,
+                    pods = owners.len(),
+                    "Pods re-listed"
+                );
+
+                store.reset_scoped(scope, state, |_| ()).await;
+
+                if !synced {
+                    synced = true;
+                    store.sync.mark_synced();
+                }
             }
         }
+
+        checkpoint.set(resource_version);
     }
 
     Ok(())
 }
 
-fn to_state(
-    pods: Vec<Pod>,
-) -> (
-    HashMap<ImageRef, Owned<PodRef, ()>>,
-    HashMap<PodRef, HashSet<ImageRef>>,
-) {
-    let mut by_images: HashMap<ImageRef, Owned<PodRef, ()>> = Default::default();
-    let mut by_pods = HashMap::new();
-
-    for pod in pods {
-        let pod_ref = match to_key(&pod) {
-            Some(pod_ref) => pod_ref,
-            None => continue,
-        };
+/// get the namespace and name of a pod
+fn to_name(pod: &Pod) -> Option<PodName> {
+    match (pod.namespace(), pod.meta().name.clone()) {
+        (Some(namespace), Some(name)) => Some((namespace, name)),
+        _ => None,
+    }
+}
 
-        let images = images_from_pod(pod);
-        for image in &images {
-            by_images
-                .entry(image.clone())
-                .or_default()
-                .owners
-                .insert(pod_ref.clone());
-        }
+/// create a key for a pod
+fn to_key(
+    cluster: &Option<String>,
+    (namespace, name): PodName,
+    pod: &Pod,
+    workload: Option<WorkloadRef>,
+) -> PodRef {
+    PodRef {
+        cluster: cluster.clone(),
+        namespace,
+        name,
+        node: node_name(pod),
+        workload,
+    }
+}
+
+/// get the name of the node a pod is scheduled on
+fn node_name(pod: &Pod) -> Option<String> {
+    pod.spec.as_ref().and_then(|spec| spec.node_name.clone())
+}
+
+/// check if the pod is finished, and belongs to a job
+fn is_finished_job(pod: &Pod, workload: &Option<WorkloadRef>) -> bool {
+    let finished = matches!(
+        pod.status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref()),
+        Some("Succeeded" | "Failed")
+    );
+    let job =
+        matches!(workload, Some(workload) if workload.kind == "Job" || workload.kind == "CronJob");
+
+    finished && job
+}
 
-        by_pods.insert(pod_ref, images);
+/// delete the containers of a pod
+async fn delete(store: &Store<ImageRef, ImageOwner, ()>, owners: &HashSet<ContainerOwner>) {
+    for owner in owners {
+        store
+            .delete(&ImageOwner::Container(owner.clone()), |_, v| v)
+            .await;
     }
+}
 
-    (by_images, by_pods)
+/// resolve images without a (platform specific) digest, if enabled
+async fn resolve_digests(
+    digests: &Option<DigestResolver>,
+    images: HashMap<ContainerOwner, ImageRef>,
+) -> HashMap<ContainerOwner, ImageRef> {
+    match digests {
+        Some(digests) => join_all(
+            images
+                .into_iter()
+                .map(|(owner, image)| async move { (owner, digests.resolve(image).await) }),
+        )
+        .await
+        .into_iter()
+        .collect(),
+        None => images,
+    }
 }
 
-/// create a key for a pod
-fn to_key(pod: &Pod) -> Option<PodRef> {
-    match (pod.namespace(), pod.meta().name.clone()) {
-        (Some(namespace), Some(name)) => Some(PodRef { namespace, name }),
+/// collect the images of all containers of a pod which pass the filter, along with the
+/// architecture of its node if enabled
+///
+/// Containers which reported an image ID run the image. With `pending`, other containers are
+/// taken as only declaring it: those which didn't report an image ID (e.g. still pulling their
+/// image), and those without a status, like those of a pod which isn't scheduled yet, taken from
+/// the spec of the pod.
+async fn images_from_pod(
+    nodes: &Option<NodeResolver>,
+    filter: &ImageFilter,
+    pending: bool,
+    pod_ref: &PodRef,
+    pod: Pod,
+) -> HashMap<ContainerOwner, ImageRef> {
+    let arch = match (nodes, node_name(&pod)) {
+        (Some(nodes), Some(node)) => nodes.arch(&node).await,
         _ => None,
+    };
+
+    let statuses = pod.status.into_iter().flat_map(|s| {
+        let statuses = |statuses: Option<Vec<ContainerStatus>>, kind| {
+            statuses.into_iter().flatten().map(move |c| (kind, c))
+        };
+        statuses(s.container_statuses, ContainerKind::Container)
+            .chain(statuses(s.init_container_statuses, ContainerKind::Init))
+            .chain(statuses(
+                s.ephemeral_container_statuses,
+                ContainerKind::Ephemeral,
+            ))
+    });
+
+    let owner = |container: String, kind, running| ContainerOwner {
+        pod: pod_ref.clone(),
+        container,
+        kind,
+        running,
+    };
+
+    let mut images = HashMap::new();
+
+    for (kind, status) in statuses {
+        let image = match status.image_id.is_empty() {
+            false => {
+                image_id::normalize(&status.image, &status.image_id).map(|image| (image, true))
+            }
+            true if pending => image_id::declared(&status.image).map(|image| (image, false)),
+            true => None,
+        };
+        if let Some((image, running)) = image {
+            images.insert(owner(status.name, kind, running), image);
+        }
+    }
+
+    if pending {
+        let containers = pod.spec.into_iter().flat_map(spec_containers);
+
+        // container names are unique within a pod, across all kinds
+        let started = images
+            .keys()
+            .map(|owner| owner.container.clone())
+            .collect::<HashSet<_>>();
+
+        for (kind, name, image) in containers {
+            if started.contains(&name) {
+                continue;
+            }
+            if let Some(image) = image.and_then(|image| image_id::declared(&image)) {
+                images.insert(owner(name, kind, false), image);
+            }
+        }
     }
-}
 
-/// collect all container images from a pod
-fn images_from_pod(pod: Pod) -> HashSet<ImageRef> {
-    pod.status
+    images
         .into_iter()
-        .flat_map(|s| {
-            s.container_statuses
-                .into_iter()
-                .flat_map(|c| c.into_iter().flat_map(to_container_id))
-                .chain(
-                    s.init_container_statuses
-                        .into_iter()
-                        .flat_map(|ic| ic.into_iter().flat_map(to_container_id)),
-                )
-                .chain(
-                    s.ephemeral_container_statuses
-                        .into_iter()
-                        .flat_map(|ic| ic.into_iter().flat_map(to_container_id)),
-                )
+        .filter(|(_, image)| filter.matches(image))
+        .map(|(owner, image)| {
+            let image = ImageRef {
+                arch: arch.clone().or(image.arch),
+                ..image
+            };
+            (owner, image)
         })
         .collect()
 }
 
-pub fn to_container_id(container: ContainerStatus) -> Option<ImageRef> {
-    if container.image_id.is_empty() {
-        return None;
+/// the containers of a pod spec, along with their kind and image
+pub(super) fn spec_containers(
+    spec: PodSpec,
+) -> impl Iterator<Item = (ContainerKind, String, Option<String>)> {
+    spec.containers
+        .into_iter()
+        .map(|c| (ContainerKind::Container, c.name, c.image))
+        .chain(
+            spec.init_containers
+                .into_iter()
+                .flatten()
+                .map(|c| (ContainerKind::Init, c.name, c.image)),
+        )
+        .chain(
+            spec.ephemeral_containers
+                .into_iter()
+                .flatten()
+                .map(|c| (ContainerKind::Ephemeral, c.name, c.image)),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::Fingerprint;
+    use bommer_api::data::PodRef;
+    use futures::StreamExt;
+    use http::{Request, Response, StatusCode};
+    use hyper::Body;
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    /// a client failing all requests, which the tests must not need
+    fn client() -> kube::Client {
+        let service = tower::service_fn(|_: Request<Body>| async {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            Ok::<_, Infallible>(response)
+        });
+        kube::Client::new(service, "default")
     }
 
-    // FIXME: we need some more magic here, as kubernetes has weird ideas on filling the fields image and imageId.
-    // see: docs/image_id.md
+    fn pod(name: &str) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "namespace": "default", "name": name },
+            "status": {
+                "containerStatuses": [{
+                    "name": "main",
+                    "image": format!("quay.io/example/{name}:1.0"),
+                    "imageID": format!("quay.io/example/{name}@sha256:34e8724e0f47e31eb2ec3279ac398b657db5f60f167426ee73138e2e84af6486"),
+                    "ready": true,
+                    "restartCount": 0,
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn event(event: Option<watcher::Event<Pod>>, resource_version: &str) -> PodEvent {
+        PodEvent {
+            event,
+            resource_version: resource_version.into(),
+        }
+    }
 
-    // FIXME: this won't work on kind, and maybe others, as they generate broken image ID values
-    Some(ImageRef(container.image_id))
+    /// a pod watcher, possibly resuming, receiving the events
+    fn source(
+        resume: Option<WatchState>,
+        events: Vec<PodEvent>,
+    ) -> PodSource<impl Stream<Item = Result<PodEvent, watcher::Error>>> {
+        let fingerprint = Fingerprint {
+            cluster: None,
+            namespace: None,
+            label_selector: None,
+            field_selector: None,
+            include_images: vec![],
+            exclude_images: vec![],
+            node_arch: false,
+            pending: false,
+            resolve_digests: false,
+            collapse_jobs: false,
+        };
+        PodSource {
+            cluster: None,
+            filter: PodFilter::default(),
+            images: ImageFilter::default(),
+            resolver: WorkloadResolver::new(client()),
+            nodes: None,
+            digests: None,
+            pending: false,
+            collapse_jobs: false,
+            checkpoint: Checkpoint::new(&fingerprint, PodFilter::default()),
+            resume,
+            // keep the stream open, like a watcher would
+            stream: futures::stream::iter(events.into_iter().map(Ok))
+                .chain(futures::stream::pending()),
+        }
+    }
 
-    // ImageRef(format!("{} / {}", container.image, container.image_id))
+    /// a persisted state, having pod `a`
+    fn resumed() -> WatchState {
+        let owner = ContainerOwner {
+            pod: PodRef {
+                cluster: None,
+                namespace: "default".into(),
+                name: "a".into(),
+                workload: None,
+                node: None,
+            },
+            container: "main".into(),
+            kind: ContainerKind::Container,
+            running: true,
+        };
+        WatchState {
+            id: String::new(),
+            resource_version: "10".into(),
+            containers: vec![(owner, "quay.io/example/a:1.0".parse().unwrap())],
+        }
+    }
+
+    /// run the source until all events are processed, returning the store and its repositories
+    async fn images(
+        source: PodSource<impl Stream<Item = Result<PodEvent, watcher::Error>>>,
+    ) -> (Store<ImageRef, ImageOwner, ()>, Vec<String>) {
+        let (store, runner) = image_store(vec![source], vec![], vec![], 0);
+        let _ = tokio::time::timeout(Duration::from_millis(100), runner).await;
+        let mut images = store
+            .get_state()
+            .await
+            .keys()
+            .map(|image| image.repository.clone())
+            .collect::<Vec<_>>();
+        images.sort();
+        (store, images)
+    }
+
+    #[tokio::test]
+    async fn synced_by_list() {
+        let (store, images) = images(source(
+            None,
+            vec![event(Some(watcher::Event::Restarted(vec![pod("a")])), "10")],
+        ))
+        .await;
+        assert!(store.sync_state().is_synced());
+        assert_eq!(images, vec!["example/a"]);
+    }
+
+    #[tokio::test]
+    async fn resumed_not_synced() {
+        let (store, images) = images(source(
+            Some(resumed()),
+            vec![event(Some(watcher::Event::Applied(pod("b"))), "11")],
+        ))
+        .await;
+        // serving what we had, but the watch might not have caught up yet
+        assert!(!store.sync_state().is_synced());
+        assert_eq!(images, vec!["example/a", "example/b"]);
+    }
+
+    #[tokio::test]
+    async fn pending_declared() {
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": { "namespace": "default", "name": "app" },
+            "spec": {
+                "containers": [
+                    { "name": "main", "image": "quay.io/example/main:1.0" },
+                    { "name": "sidecar", "image": "quay.io/example/sidecar:1.0" },
+                ],
+            },
+            "status": {
+                "containerStatuses": [{
+                    "name": "main",
+                    "image": "quay.io/example/main:1.0",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 0,
+                    "state": { "waiting": { "reason": "ImagePullBackOff" } },
+                }],
+            },
+        }))
+        .unwrap();
+        let pod_ref = PodRef {
+            cluster: None,
+            namespace: "default".into(),
+            name: "app".into(),
+            workload: None,
+            node: None,
+        };
+        let filter = ImageFilter::default();
+        let images = |pending| images_from_pod(&None, &filter, pending, &pod_ref, pod.clone());
+
+        // only running images by default
+        assert!(images(false).await.is_empty());
+
+        let mut declared = images(true)
+            .await
+            .into_iter()
+            .map(|(owner, image)| (owner.container, owner.running, image.to_string()))
+            .collect::<Vec<_>>();
+        declared.sort();
+        assert_eq!(
+            declared,
+            vec![
+                ("main".into(), false, "quay.io/example/main:1.0".into()),
+                (
+                    "sidecar".into(),
+                    false,
+                    "quay.io/example/sidecar:1.0".into()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resumed_synced_by_bookmark() {
+        let (store, images) = images(source(
+            Some(resumed()),
+            vec![
+                event(Some(watcher::Event::Deleted(pod("a"))), "11"),
+                event(None, "12"),
+            ],
+        ))
+        .await;
+        assert!(store.sync_state().is_synced());
+        assert!(images.is_empty());
+    }
 }
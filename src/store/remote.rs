@@ -0,0 +1,30 @@
+use crate::store::{ImageOwner, Store};
+use bommer_api::data::ImageRef;
+use std::collections::{HashMap, HashSet};
+
+/// Feeding the store with the state pushed by agents, instead of watching a cluster
+impl Store<ImageRef, ImageOwner, ()> {
+    /// replace all owners of a cluster, keeping those of other clusters
+    pub async fn reset_cluster(
+        &self,
+        cluster: &str,
+        owners: HashMap<ImageOwner, HashSet<ImageRef>>,
+    ) {
+        self.reset_scoped(|owner| owner.cluster() == Some(cluster), owners, |_| ())
+            .await;
+    }
+
+    /// add or modify owners, and delete the removed ones
+    pub async fn update_owners(
+        &self,
+        applied: HashMap<ImageOwner, HashSet<ImageRef>>,
+        removed: Vec<ImageOwner>,
+    ) {
+        for owner in removed {
+            self.delete(&owner, |_, v| v).await;
+        }
+        for (owner, images) in applied {
+            self.apply(owner, images, |_| (), |_, v| v).await;
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use crate::pubsub::State;
+use bommer_api::data::Event;
+use futures::stream::{self, BoxStream, SelectAll};
+use futures::{FutureExt, StreamExt};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// A state, sharded by its keys
+///
+/// Each shard has its own lock, so that changes of different keys don't contend for the same
+/// one. Subscribers still see a single state, see [`ShardedSubscription`].
+pub struct ShardedState<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    shards: Arc<[State<K, V>]>,
+    /// held exclusively while replacing the state of all shards
+    reset: Arc<RwLock<()>>,
+}
+
+impl<K, V> Clone for ShardedState<K, V>
+where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+            reset: self.reset.clone(),
+        }
+    }
+}
+
+impl<K, V> ShardedState<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + PartialEq + Send + Sync + 'static,
+{
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| State::default()).collect(),
+            reset: Default::default(),
+        }
+    }
+
+    /// the index of the shard of a key
+    fn index(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    /// a snapshot of the current state of all shards
+    pub async fn get_state(&self) -> im::HashMap<K, V> {
+        let mut state = im::HashMap::new();
+        for shard in self.shards.iter() {
+            state = state.union(shard.get_state().await);
+        }
+        state
+    }
+
+    /// replace the full state, each shard reporting a restart with its part of it
+    pub async fn set_state(&self, state: im::HashMap<K, V>) {
+        let _reset = self.reset.write().await;
+        let mut parts = vec![im::HashMap::new(); self.shards.len()];
+        for (key, value) in state {
+            parts[self.index(&key)].insert(key, value);
+        }
+        for (shard, part) in self.shards.iter().zip(parts) {
+            shard.set_state(part).await;
+        }
+    }
+
+    /// mutate the state of a key, only locking its shard
+    pub async fn mutate_state<F>(&self, key: K, f: F)
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        self.shards[self.index(&key)].mutate_state(key, f).await;
+    }
+
+    /// subscribe to the changes of all shards
+    pub async fn subscribe(
+        &self,
+        name: &'static str,
+        buffer: impl Into<Option<usize>>,
+    ) -> ShardedSubscription<K, V> {
+        let buffer = buffer.into();
+        // all shards start with the state of the same reset
+        let _reset = self.reset.read().await;
+        let mut events = stream::select_all(Vec::<BoxStream<_>>::new());
+        for (index, shard) in self.shards.iter().enumerate() {
+            let sub = shard.subscribe(name, buffer).await;
+            events.push(
+                stream::unfold(Some(sub), move |sub| async move {
+                    let mut sub = sub?;
+                    match sub.recv().await {
+                        Some(evt) => Some(((index, Some(evt)), Some(sub))),
+                        // report the end, once
+                        None => Some(((index, None), None)),
+                    }
+                })
+                .boxed(),
+            );
+        }
+
+        ShardedSubscription {
+            events,
+            parts: vec![None; self.shards.len()],
+            reset: self.reset.clone(),
+        }
+    }
+}
+
+/// A subscription to all shards of a [`ShardedState`]
+///
+/// It starts with a `Restart` of the full state, once all shards reported their initial state.
+/// A later `Restart` of a shard gets reported as a `Restart` of the full state too, so
+/// subscribers can keep treating it as replacing everything they know. The restarts of all
+/// shards caused by replacing the full state are reported as a single one, so that subscribers
+/// never see the state of only some of the shards replaced. The subscription ends once the
+/// subscription of any shard ends.
+pub struct ShardedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + Send + Sync + 'static,
+{
+    #[allow(clippy::type_complexity)]
+    events: SelectAll<BoxStream<'static, (usize, Option<Arc<Event<K, V>>>)>>,
+    /// the state of each shard, `None` until it reported its initial state
+    parts: Vec<Option<im::HashMap<K, V>>>,
+    reset: Arc<RwLock<()>>,
+}
+
+impl<K, V> ShardedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Debug + Send + Sync + 'static,
+{
+    /// receive the next event, `None` if the subscription ended
+    pub async fn recv(&mut self) -> Option<Arc<Event<K, V>>> {
+        loop {
+            let (index, evt) = self.events.next().await?;
+            let evt = evt?;
+
+            let started = self.parts.iter().all(Option::is_some);
+            self.apply(index, &evt);
+
+            if !matches!(*evt, Event::Restart(_)) {
+                match self.parts.iter().all(Option::is_some) {
+                    true if started => return Some(evt),
+                    // still waiting for the initial state of some shards
+                    _ => continue,
+                }
+            }
+
+            // wait for a reset of all shards to complete, and take in what it brought. Keep
+            // draining the shards meanwhile, as the reset can't complete while their queues are full.
+            let reset = self.reset.clone();
+            let done = reset.read();
+            tokio::pin!(done);
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut done => break,
+                    next = self.events.next() => {
+                        let (index, evt) = next?;
+                        let evt = evt?;
+                        self.apply(index, &evt);
+                    }
+                }
+            }
+            while let Some(next) = self.events.next().now_or_never() {
+                let (index, evt) = next?;
+                let evt = evt?;
+                self.apply(index, &evt);
+            }
+
+            if self.parts.iter().all(Option::is_some) {
+                return Some(Arc::new(Event::Restart(im::HashMap::unions(
+                    self.parts.iter().flatten().cloned(),
+                ))));
+            }
+        }
+    }
+
+    /// apply an event to the state of its shard
+    fn apply(&mut self, index: usize, evt: &Event<K, V>) {
+        // a shard only reports changes after its initial restart
+        let part = self.parts[index].get_or_insert_with(Default::default);
+        match evt {
+            Event::Added(key, value) | Event::Modified(key, value) => {
+                part.insert(key.clone(), value.clone());
+            }
+            Event::Removed(key) => {
+                part.remove(key);
+            }
+            Event::Restart(state) => {
+                *part = state.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    async fn next(sub: &mut ShardedSubscription<u32, u32>) -> Event<u32, u32> {
+        let evt = tokio::time::timeout(Duration::from_secs(1), sub.recv())
+            .await
+            .expect("no event")
+            .expect("subscription ended");
+        Arc::unwrap_or_clone(evt)
+    }
+
+    #[tokio::test]
+    async fn initial_restart() {
+        let state = ShardedState::new(4);
+        for key in 0..16u32 {
+            state.mutate_state(key, |_| Some(key * 2)).await;
+        }
+
+        let expected = (0..16u32)
+            .map(|key| (key, key * 2))
+            .collect::<im::HashMap<_, _>>();
+        assert_eq!(state.get_state().await, expected);
+
+        // a single restart, with the state of all shards
+        let mut sub = state.subscribe("test", None).await;
+        assert!(matches!(next(&mut sub).await, Event::Restart(state) if state == expected));
+
+        state.mutate_state(3, |_| Some(1)).await;
+        assert!(matches!(next(&mut sub).await, Event::Modified(3, 1)));
+        state.mutate_state(5, |_| None).await;
+        assert!(matches!(next(&mut sub).await, Event::Removed(5)));
+        state.mutate_state(20, |_| Some(1)).await;
+        assert!(matches!(next(&mut sub).await, Event::Added(20, 1)));
+    }
+
+    #[tokio::test]
+    async fn full_restart() {
+        let state = ShardedState::new(4);
+        let mut sub = state.subscribe("test", None).await;
+        assert!(matches!(next(&mut sub).await, Event::Restart(state) if state.is_empty()));
+
+        state.mutate_state(1, |_| Some(1)).await;
+        assert!(matches!(next(&mut sub).await, Event::Added(1, 1)));
+
+        // all shards restart, reported as a single restart
+        let replaced = (10..20u32)
+            .map(|key| (key, key))
+            .collect::<im::HashMap<_, _>>();
+        state.set_state(replaced.clone()).await;
+        assert!(matches!(next(&mut sub).await, Event::Restart(state) if state == replaced));
+        assert_eq!(state.get_state().await, replaced);
+
+        state.mutate_state(1, |_| Some(1)).await;
+        assert!(matches!(next(&mut sub).await, Event::Added(1, 1)));
+    }
+
+    #[tokio::test]
+    async fn full_restart_with_full_queue() {
+        let state = ShardedState::new(2);
+        let first = (0..).find(|key| state.index(key) == 0).unwrap();
+        let key = (0..).find(|key| state.index(key) == 1).unwrap();
+
+        let mut sub = state.subscribe("test", 1).await;
+        assert!(matches!(next(&mut sub).await, Event::Restart(state) if state.is_empty()));
+        state.mutate_state(first, |_| Some(1)).await;
+        assert!(matches!(next(&mut sub).await, Event::Added(k, 1) if k == first));
+
+        // fill the queue of the second shard, so that the reset has to wait for it
+        state.mutate_state(key, |_| Some(1)).await;
+        let replaced = (10..20u32)
+            .map(|key| (key, key))
+            .collect::<im::HashMap<_, _>>();
+        let received = async {
+            // the subscriber sees the reset, without getting disconnected
+            loop {
+                match next(&mut sub).await {
+                    Event::Restart(state) => break state,
+                    evt => assert!(matches!(evt, Event::Added(k, 1) if k == key)),
+                }
+            }
+        };
+        let (_, received) = tokio::join!(state.set_state(replaced.clone()), received);
+        assert_eq!(received, replaced);
+
+        state.mutate_state(key, |_| Some(2)).await;
+        assert!(matches!(next(&mut sub).await, Event::Added(k, 2) if k == key));
+    }
+}
@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Tracks the initial synchronization of the pod sources
+///
+/// A source is synchronized once it processed its first full list of pods (the first `Restarted`
+/// event of the watcher). Until then, the store only has a partial view of the workload.
+#[derive(Clone, Debug)]
+pub struct SyncState {
+    pending: Arc<AtomicUsize>,
+    /// when the last source got synchronized
+    synced_at: Arc<watch::Sender<Option<DateTime<Utc>>>>,
+}
+
+impl Default for SyncState {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl SyncState {
+    pub fn new(sources: usize) -> Self {
+        let synced_at = (sources == 0).then(Utc::now);
+        Self {
+            pending: Arc::new(AtomicUsize::new(sources)),
+            synced_at: Arc::new(watch::Sender::new(synced_at)),
+        }
+    }
+
+    /// mark one of the sources as synchronized
+    pub(crate) fn mark_synced(&self) {
+        let previous = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                pending.checked_sub(1)
+            });
+        if previous == Ok(1) {
+            self.synced_at.send_replace(Some(Utc::now()));
+        }
+    }
+
+    /// check if all sources are synchronized
+    pub fn is_synced(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) == 0
+    }
+
+    /// the number of sources which didn't synchronize yet
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// when all sources were synchronized, `None` while still pending
+    pub fn synced_at(&self) -> Option<DateTime<Utc>> {
+        *self.synced_at.borrow()
+    }
+
+    /// wait until all sources are synchronized
+    pub async fn synced(&self) {
+        let mut synced_at = self.synced_at.subscribe();
+        // the sender is owned by ourselves, so it can't be dropped
+        let _ = synced_at.wait_for(Option::is_some).await;
+    }
+}
@@ -0,0 +1,572 @@
+//! Watching pods, tracking the resource version so that watching can be resumed after a restart.
+
+use crate::store::{ContainerOwner, ImageOwner, PodFilter, Store};
+use bommer_api::data::ImageRef;
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{ListParams, WatchEvent, WatchParams};
+use kube::core::ErrorResponse;
+use kube::runtime::watcher;
+use kube::{Api, ResourceExt};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+/// Requests watchers to list their resources again, e.g. when the store drifted due to missed
+/// events
+#[derive(Clone, Debug)]
+pub struct Resync {
+    tx: Arc<watch::Sender<u64>>,
+}
+
+impl Default for Resync {
+    fn default() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl Resync {
+    /// request all watchers to list again
+    pub fn request(&self) {
+        self.tx.send_modify(|generation| *generation += 1);
+    }
+}
+
+/// re-create a watcher each time a resync is requested
+///
+/// Watchers start with a full list, so re-creating them emits a restart of their state.
+pub fn resyncing<S, F>(resync: &Resync, mut f: F) -> impl Stream<Item = S::Item> + Send
+where
+    F: FnMut() -> S + Send + 'static,
+    S: Stream + Send + 'static,
+    S::Item: Send,
+{
+    let rx = resync.tx.subscribe();
+    let stream = f().boxed();
+    stream::unfold((stream, rx, f), |(mut stream, mut rx, mut f)| async move {
+        loop {
+            tokio::select! {
+                item = stream.next() => return Some((item?, (stream, rx, f))),
+                Ok(()) = rx.changed() => {
+                    info!("Resync requested, listing again");
+                    stream = f().boxed();
+                }
+            }
+        }
+    })
+}
+
+/// An event of a pod watcher
+#[derive(Clone, Debug)]
+pub struct PodEvent {
+    /// The change, `None` if the watch only progressed (a bookmark)
+    pub event: Option<watcher::Event<Pod>>,
+    /// The resource version to resume watching from, once the event got processed
+    pub resource_version: String,
+}
+
+enum State {
+    /// Pods need to be listed
+    Empty,
+    /// Watching needs to be (re-)started from a resource version
+    Listed { resource_version: String },
+    /// Watching
+    Watching {
+        resource_version: String,
+        stream: BoxStream<'static, kube::Result<WatchEvent<Pod>>>,
+    },
+}
+
+/// watch pods, starting with a full list unless there is a resource version to resume from
+///
+/// Works like the [`watcher`] of `kube`, but requests bookmarks and reports the resource version
+/// along with each event. If the resource version is too old, pods are listed again instead of
+/// failing.
+pub fn pod_watcher(
+    api: Api<Pod>,
+    config: watcher::Config,
+    resume: Option<String>,
+) -> impl Stream<Item = Result<PodEvent, watcher::Error>> + Send {
+    let state = match resume {
+        Some(resource_version) => State::Listed { resource_version },
+        None => State::Empty,
+    };
+
+    stream::unfold(
+        (api, config, state),
+        |(api, config, mut state)| async move {
+            loop {
+                let (result, next) = step(&api, &config, state).await;
+                state = next;
+                if let Some(result) = result {
+                    return Some((result, (api, config, state)));
+                }
+            }
+        },
+    )
+}
+
+/// progress the watcher a single step, without a result if the caller should simply continue
+async fn step(
+    api: &Api<Pod>,
+    config: &watcher::Config,
+    state: State,
+) -> (Option<Result<PodEvent, watcher::Error>>, State) {
+    match state {
+        State::Empty => {
+            let params = ListParams {
+                label_selector: config.label_selector.clone(),
+                field_selector: config.field_selector.clone(),
+                timeout: config.timeout,
+                ..Default::default()
+            };
+            match api.list(&params).await {
+                Ok(list) => match list.metadata.resource_version {
+                    Some(resource_version) => (
+                        Some(Ok(PodEvent {
+                            event: Some(watcher::Event::Restarted(list.items)),
+                            resource_version: resource_version.clone(),
+                        })),
+                        State::Listed { resource_version },
+                    ),
+                    None => (Some(Err(watcher::Error::NoResourceVersion)), State::Empty),
+                },
+                Err(err) => (
+                    Some(Err(watcher::Error::InitialListFailed(err))),
+                    State::Empty,
+                ),
+            }
+        }
+        State::Listed { resource_version } => {
+            let params = WatchParams {
+                label_selector: config.label_selector.clone(),
+                field_selector: config.field_selector.clone(),
+                timeout: config.timeout,
+                bookmarks: true,
+            };
+            match api.watch(&params, &resource_version).await {
+                Ok(stream) => (
+                    None,
+                    State::Watching {
+                        resource_version,
+                        stream: stream.boxed(),
+                    },
+                ),
+                Err(kube::Error::Api(ErrorResponse { code: 410, .. })) => {
+                    info!("Resource version {resource_version} expired, listing pods again");
+                    (None, State::Empty)
+                }
+                Err(err) => (
+                    Some(Err(watcher::Error::WatchStartFailed(err))),
+                    State::Listed { resource_version },
+                ),
+            }
+        }
+        State::Watching {
+            resource_version,
+            mut stream,
+        } => match stream.next().await {
+            Some(Ok(WatchEvent::Added(pod) | WatchEvent::Modified(pod))) => {
+                let resource_version = pod.resource_version().unwrap_or(resource_version);
+                (
+                    Some(Ok(PodEvent {
+                        event: Some(watcher::Event::Applied(pod)),
+                        resource_version: resource_version.clone(),
+                    })),
+                    State::Watching {
+                        resource_version,
+                        stream,
+                    },
+                )
+            }
+            Some(Ok(WatchEvent::Deleted(pod))) => {
+                let resource_version = pod.resource_version().unwrap_or(resource_version);
+                (
+                    Some(Ok(PodEvent {
+                        event: Some(watcher::Event::Deleted(pod)),
+                        resource_version: resource_version.clone(),
+                    })),
+                    State::Watching {
+                        resource_version,
+                        stream,
+                    },
+                )
+            }
+            Some(Ok(WatchEvent::Bookmark(bookmark))) => {
+                let resource_version = bookmark.metadata.resource_version;
+                (
+                    Some(Ok(PodEvent {
+                        event: None,
+                        resource_version: resource_version.clone(),
+                    })),
+                    State::Watching {
+                        resource_version,
+                        stream,
+                    },
+                )
+            }
+            Some(Ok(WatchEvent::Error(err))) if err.code == 410 => {
+                info!("Resource version {resource_version} expired, listing pods again");
+                (None, State::Empty)
+            }
+            Some(Ok(WatchEvent::Error(err))) => (
+                Some(Err(watcher::Error::WatchError(err))),
+                State::Watching {
+                    resource_version,
+                    stream,
+                },
+            ),
+            Some(Err(err)) => (
+                Some(Err(watcher::Error::WatchFailed(err))),
+                State::Listed { resource_version },
+            ),
+            None => {
+                debug!("Watch ended, re-starting from {resource_version}");
+                (None, State::Listed { resource_version })
+            }
+        },
+    }
+}
+
+/// Everything influencing the images reported by a pod watcher
+///
+/// A watcher only resumes from a state which was collected with the same fingerprint. Anything
+/// added to the configuration of watchers, which changes what they report, must be added here.
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fingerprint {
+    pub cluster: Option<String>,
+    /// The namespace, when only watching a single one
+    pub namespace: Option<String>,
+    pub label_selector: Option<String>,
+    pub field_selector: Option<String>,
+    /// The regular expressions of the image patterns to include
+    pub include_images: Vec<String>,
+    /// The regular expressions of the image patterns to exclude
+    pub exclude_images: Vec<String>,
+    pub node_arch: bool,
+    pub pending: bool,
+    pub resolve_digests: bool,
+    pub collapse_jobs: bool,
+}
+
+impl Fingerprint {
+    /// the identifier of the fingerprint, which is stable across versions
+    fn id(&self) -> String {
+        // serializing a struct keeps the order of the fields
+        let data = serde_json::to_vec(self).expect("serializing a fingerprint can't fail");
+        hex::encode(Sha256::digest(data))
+    }
+}
+
+/// The progress of a pod watcher
+///
+/// Identifies the watcher by the fingerprint of its configuration, so that a changed configuration
+/// doesn't resume from a state which was collected with different settings.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    id: String,
+    cluster: Option<String>,
+    filter: PodFilter,
+    resource_version: Arc<Mutex<Option<String>>>,
+}
+
+impl Checkpoint {
+    /// create a new checkpoint, for a watcher scoped by the filter
+    pub fn new(fingerprint: &Fingerprint, filter: PodFilter) -> Self {
+        Self {
+            id: fingerprint.id(),
+            cluster: fingerprint.cluster.clone(),
+            filter,
+            resource_version: Default::default(),
+        }
+    }
+
+    /// check if the owner was reported by this watcher
+    fn contains(&self, owner: &ContainerOwner) -> bool {
+        owner.pod.cluster == self.cluster && self.filter.matches_namespace(&owner.pod.namespace)
+    }
+
+    /// the state persisted for this watcher, if any
+    pub fn find<'a>(&self, states: &'a [WatchState]) -> Option<&'a WatchState> {
+        states.iter().find(|state| state.id == self.id)
+    }
+
+    pub(crate) fn set(&self, resource_version: String) {
+        *self.resource_version.lock() = Some(resource_version);
+    }
+
+    fn get(&self) -> Option<String> {
+        self.resource_version.lock().clone()
+    }
+}
+
+/// The persisted state of a pod watcher
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchState {
+    /// The fingerprint of the configuration of the watcher
+    pub id: String,
+    /// The resource version to resume watching from
+    pub resource_version: String,
+    /// The containers of the pods seen by the watcher, along with their images
+    pub containers: Vec<(ContainerOwner, ImageRef)>,
+}
+
+/// The checkpoints of all pod watchers of a store
+#[derive(Clone)]
+pub struct Checkpoints {
+    store: Store<ImageRef, ImageOwner, ()>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Checkpoints {
+    pub fn new(store: Store<ImageRef, ImageOwner, ()>, checkpoints: Vec<Checkpoint>) -> Self {
+        Self { store, checkpoints }
+    }
+
+    /// the current state of all watchers which made some progress
+    ///
+    /// The resource version is taken before the containers, so that the state might already
+    /// contain changes after the resource version, which are simply applied again when resuming.
+    pub async fn get(&self) -> Vec<WatchState> {
+        let resource_versions = self
+            .checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.get())
+            .collect::<Vec<_>>();
+        let owners = self.store.owners().await;
+
+        let mut result = Vec::with_capacity(self.checkpoints.len());
+
+        for (checkpoint, resource_version) in self.checkpoints.iter().zip(resource_versions) {
+            let resource_version = match resource_version {
+                Some(resource_version) => resource_version,
+                None => continue,
+            };
+
+            let containers = owners
+                .iter()
+                .filter_map(|(owner, images)| match owner {
+                    ImageOwner::Container(owner) if checkpoint.contains(owner) => {
+                        Some((owner, images))
+                    }
+                    _ => None,
+                })
+                .flat_map(|(owner, images)| {
+                    images.iter().map(|image| (owner.clone(), image.clone()))
+                })
+                .collect();
+
+            result.push(WatchState {
+                id: checkpoint.id.clone(),
+                resource_version,
+                containers,
+            });
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::TryStreamExt;
+    use http::{Request, Response};
+    use hyper::Body;
+    use serde_json::json;
+    use std::convert::Infallible;
+
+    fn pod(name: &str, resource_version: &str) -> serde_json::Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "namespace": "default",
+                "name": name,
+                "resourceVersion": resource_version,
+            },
+        })
+    }
+
+    /// A fake API server, listing pods at resource version 10, and watching from there
+    ///
+    /// Watching from an older resource version fails, as it expired. Returns the client, along
+    /// with the requests it received.
+    fn api() -> (Api<Pod>, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let query = request.uri().query().unwrap_or_default().to_string();
+            recorded.lock().push(query.clone());
+            async move {
+                let body = if !query.contains("watch=true") {
+                    json!({
+                        "apiVersion": "v1",
+                        "kind": "PodList",
+                        "metadata": { "resourceVersion": "10" },
+                        "items": [pod("a", "9")],
+                    })
+                    .to_string()
+                } else if query.contains("resourceVersion=10") {
+                    [
+                        json!({ "type": "ADDED", "object": pod("b", "11") }),
+                        json!({
+                            "type": "BOOKMARK",
+                            "object": {
+                                "apiVersion": "v1",
+                                "kind": "Pod",
+                                "metadata": { "resourceVersion": "12" },
+                            },
+                        }),
+                    ]
+                    .map(|event| format!("{event}\n"))
+                    .concat()
+                } else {
+                    json!({
+                        "type": "ERROR",
+                        "object": {
+                            "apiVersion": "v1",
+                            "kind": "Status",
+                            "status": "Failure",
+                            "reason": "Expired",
+                            "message": "too old resource version",
+                            "code": 410,
+                        },
+                    })
+                    .to_string()
+                };
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }
+        });
+
+        (Api::all(kube::Client::new(service, "default")), requests)
+    }
+
+    /// the first events of a watcher, as names of the pods, or the resource version of bookmarks
+    async fn events(resume: Option<&str>, count: usize) -> Vec<String> {
+        let (api, _) = api();
+        pod_watcher(api, Default::default(), resume.map(str::to_string))
+            .take(count)
+            .map_ok(|evt| match evt.event {
+                Some(watcher::Event::Restarted(pods)) => format!(
+                    "restarted({}) at {}",
+                    pods.iter()
+                        .map(|pod| pod.name_any())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    evt.resource_version
+                ),
+                Some(watcher::Event::Applied(pod)) => {
+                    format!("applied({}) at {}", pod.name_any(), evt.resource_version)
+                }
+                Some(watcher::Event::Deleted(pod)) => {
+                    format!("deleted({}) at {}", pod.name_any(), evt.resource_version)
+                }
+                None => format!("bookmark at {}", evt.resource_version),
+            })
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn list_and_watch() {
+        assert_eq!(
+            events(None, 3).await,
+            vec!["restarted(a) at 10", "applied(b) at 11", "bookmark at 12"]
+        );
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        assert_eq!(
+            events(Some("10"), 2).await,
+            vec!["applied(b) at 11", "bookmark at 12"]
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_expired() {
+        assert_eq!(
+            events(Some("5"), 2).await,
+            vec!["restarted(a) at 10", "applied(b) at 11"]
+        );
+    }
+
+    #[tokio::test]
+    async fn bookmarks_requested() {
+        let (api, requests) = api();
+        let _ = pod_watcher(api, Default::default(), Some("10".into()))
+            .boxed()
+            .next()
+            .await;
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("allowWatchBookmarks=true"));
+    }
+
+    fn fingerprint() -> Fingerprint {
+        Fingerprint {
+            cluster: Some("cluster".into()),
+            namespace: None,
+            label_selector: Some("app=example".into()),
+            field_selector: None,
+            include_images: vec![],
+            exclude_images: vec!["^(?:registry\\.k8s\\.io/pause)$".into()],
+            node_arch: false,
+            pending: false,
+            resolve_digests: false,
+            collapse_jobs: true,
+        }
+    }
+
+    #[test]
+    fn fingerprint_stable() {
+        // changing the identifier drops the persisted state of all users
+        assert_eq!(
+            fingerprint().id(),
+            hex::encode(Sha256::digest(
+                r#"{"cluster":"cluster","namespace":null,"labelSelector":"app=example","fieldSelector":null,"includeImages":[],"excludeImages":["^(?:registry\\.k8s\\.io/pause)$"],"nodeArch":false,"pending":false,"resolveDigests":false,"collapseJobs":true}"#
+            ))
+        );
+    }
+
+    #[test]
+    fn fingerprint_changes() {
+        let id = fingerprint().id();
+        let changed = [
+            Fingerprint {
+                namespace: Some("default".into()),
+                ..fingerprint()
+            },
+            Fingerprint {
+                label_selector: None,
+                ..fingerprint()
+            },
+            Fingerprint {
+                exclude_images: vec![],
+                ..fingerprint()
+            },
+            Fingerprint {
+                pending: true,
+                ..fingerprint()
+            },
+            Fingerprint {
+                collapse_jobs: false,
+                ..fingerprint()
+            },
+        ];
+        for fingerprint in changed {
+            assert_ne!(fingerprint.id(), id, "{fingerprint:?}");
+        }
+    }
+}
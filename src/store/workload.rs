@@ -0,0 +1,99 @@
+use bommer_api::data::WorkloadRef;
+use k8s_openapi::api::apps::v1::ReplicaSet;
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::{Api, Client, Resource, ResourceExt};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Resolves the top-level workload controlling a pod
+///
+/// Pods are typically controlled by an intermediate resource (like a `ReplicaSet` or `Job`),
+/// which itself is controlled by the resource the user actually created (like a `Deployment` or
+/// `CronJob`). We follow the chain of controller owner references for those intermediate kinds,
+/// and cache the results, as pods of the same workload share their owners.
+#[derive(Clone)]
+pub struct WorkloadResolver {
+    client: Client,
+    cache: Arc<Mutex<HashMap<WorkloadRef, WorkloadRef>>>,
+}
+
+impl WorkloadResolver {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            cache: Default::default(),
+        }
+    }
+
+    /// drop all cached resolutions
+    pub fn clear(&self) {
+        self.cache.lock().clear();
+    }
+
+    /// resolve the workload of a pod, `None` if the pod isn't controlled by anything
+    pub async fn resolve(&self, pod: &Pod) -> Option<WorkloadRef> {
+        let namespace = pod.namespace()?;
+        let owner = controller(pod.owner_references(), &namespace)?;
+
+        if let Some(result) = self.cache.lock().get(&owner) {
+            return Some(result.clone());
+        }
+
+        let result = match self.lookup(&owner).await {
+            Ok(Some(result)) => result,
+            Ok(None) => owner.clone(),
+            Err(err) => {
+                // we might not have permission to look up the owner, so stick with what we know
+                debug!("Failed to resolve owner of {owner}: {err}");
+                return Some(owner);
+            }
+        };
+
+        self.cache.lock().insert(owner, result.clone());
+
+        Some(result)
+    }
+
+    /// look up the controller of an intermediate owner
+    async fn lookup(&self, owner: &WorkloadRef) -> Result<Option<WorkloadRef>, kube::Error> {
+        match owner.kind.as_str() {
+            "ReplicaSet" => self.lookup_controller::<ReplicaSet>(owner).await,
+            "Job" => self.lookup_controller::<Job>(owner).await,
+            _ => Ok(None),
+        }
+    }
+
+    async fn lookup_controller<K>(
+        &self,
+        owner: &WorkloadRef,
+    ) -> Result<Option<WorkloadRef>, kube::Error>
+    where
+        K: Resource<Scope = k8s_openapi::NamespaceResourceScope>
+            + Clone
+            + std::fmt::Debug
+            + serde::de::DeserializeOwned,
+        K::DynamicType: Default,
+    {
+        let api: Api<K> = Api::namespaced(self.client.clone(), &owner.namespace);
+        Ok(api
+            .get_opt(&owner.name)
+            .await?
+            .and_then(|resource| controller(resource.owner_references(), &owner.namespace)))
+    }
+}
+
+/// find the controlling owner in a list of owner references
+fn controller(owners: &[OwnerReference], namespace: &str) -> Option<WorkloadRef> {
+    owners
+        .iter()
+        .find(|owner| owner.controller == Some(true))
+        .map(|owner| WorkloadRef {
+            kind: owner.kind.clone(),
+            namespace: namespace.to_string(),
+            name: owner.name.clone(),
+        })
+}
@@ -0,0 +1,88 @@
+use crate::bombastic::{TokenError, TokenProvider};
+use crate::http;
+use bommer_api::data::Vulnerabilities;
+use packageurl::PackageUrl;
+use reqwest::{StatusCode, Url};
+use url::ParseError;
+
+/// maximum number of advisories we request for a single image
+const MAX_ADVISORIES: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct VexinationSource {
+    url: Url,
+    client: reqwest::Client,
+    tokens: TokenProvider,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to build URL: {0}")]
+    Url(#[from] ParseError),
+    #[error("Request error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("Failed to acquire token: {0}")]
+    Token(#[from] TokenError),
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SearchResult {
+    result: Vec<SearchHit>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SearchHit {
+    document: Advisory,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Advisory {
+    /// The highest CVSS score of the vulnerabilities covered by the advisory
+    #[serde(default)]
+    cvss_max: Option<f64>,
+}
+
+impl VexinationSource {
+    pub fn new(url: Url, tokens: TokenProvider, client: reqwest::Client) -> Self {
+        Self {
+            url,
+            client,
+            tokens,
+        }
+    }
+
+    /// look up the advisories affecting a package, and count them by severity
+    pub async fn lookup_vulnerabilities(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Vulnerabilities, Error> {
+        let mut request = self
+            .client
+            .get(http::join(&self.url, "api/v1/vex/search")?)
+            .query(&[
+                ("q", format!(r#"affected:"{purl}""#)),
+                ("limit", MAX_ADVISORIES.to_string()),
+            ]);
+
+        if let Some(token) = self.tokens.token().await? {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let result: SearchResult = response.json().await?;
+
+        let mut vulnerabilities = Vulnerabilities::default();
+        for hit in result.result {
+            vulnerabilities.add(hit.document.cvss_max);
+        }
+
+        Ok(vulnerabilities)
+    }
+}
@@ -0,0 +1,20 @@
+mod client;
+
+pub use client::VexinationSource;
+
+use url::Url;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(next_help_heading = "Vexination")]
+pub struct VexinationConfig {
+    /// The base URL of the vexination instance, vulnerabilities are not being looked up if absent
+    ///
+    /// Vexination uses the same credentials as bombastic.
+    #[arg(
+        id = "vexination_url",
+        value_name = "URL",
+        long = "vexination-url",
+        env = "VEXINATION_URL"
+    )]
+    pub url: Option<Url>,
+}
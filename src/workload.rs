@@ -1,14 +1,21 @@
 use crate::pubsub::State;
-use bommer_api::data::{Event, Image, ImageRef};
-use std::future::Future;
+use bommer_api::data::{Image, ImageRef};
 use std::ops::Deref;
-use tracing::log;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct WorkloadState {
     state: State<ImageRef, Image>,
 }
 
+impl WorkloadState {
+    /// create an empty workload, using a default buffer for subscriptions which don't request one
+    pub fn new(default_buffer: usize) -> Self {
+        Self {
+            state: State::with_buffer(default_buffer),
+        }
+    }
+}
+
 impl Deref for WorkloadState {
     type Target = State<ImageRef, Image>;
 
@@ -16,67 +23,3 @@ impl Deref for WorkloadState {
         &self.state
     }
 }
-
-pub async fn by_ns(
-    source: &WorkloadState,
-    namespace: impl Into<String>,
-) -> (WorkloadState, impl Future<Output = anyhow::Result<()>>) {
-    let workload = WorkloadState::default();
-    let mut sub = source.subscribe(None).await;
-
-    let runner = {
-        let workload = workload.clone();
-        let namespace = namespace.into();
-        async move {
-            while let Some(evt) = sub.recv().await {
-                match evt {
-                    Event::Added(image_ref, image) => {
-                        workload
-                            .mutate_state(image_ref, |_current| {
-                                Some(Image {
-                                    sbom: image.sbom,
-                                    pods: image
-                                        .pods
-                                        .into_iter()
-                                        .filter(|pod| pod.namespace == namespace)
-                                        .collect(),
-                                })
-                            })
-                            .await;
-                    }
-                    Event::Removed(image_ref) => {
-                        workload.remove_state(image_ref).await;
-                    }
-                    Event::Modified(image_ref, image) => {
-                        workload
-                            .mutate_state(image_ref, |mut current| {
-                                if let Some(state) = &mut current {
-                                    state.pods = image
-                                        .pods
-                                        .into_iter()
-                                        .filter(|pod| pod.namespace == namespace)
-                                        .collect();
-                                    state.sbom = image.sbom;
-                                }
-
-                                current
-                            })
-                            .await;
-                    }
-                    Event::Restart(mut state) => {
-                        for s in state.values_mut() {
-                            s.pods.retain(|pod| pod.namespace == namespace);
-                        }
-                        state.retain(|_, v| !v.pods.is_empty());
-                        workload.set_state(state).await;
-                    }
-                }
-            }
-            log::info!("Lost subscription, re-trying...");
-
-            Ok(())
-        }
-    };
-
-    (workload, runner)
-}